@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::{fs};
 
 use std::path::Path;
@@ -17,16 +17,24 @@ use bincode::{Encode, Decode};
 use crate::projects::{Person, ProjectMetadata, ProjectSettings, Section, SectionOrToc};
 use crate::projects::api::ApiError;
 use crate::settings::Settings;
+use crate::data_storage::legacy::{OldProjectDataV2, OldProjectDataV3, OldProjectDataV4, OldProjectDataV5, OldProjectDataV6, OldSectionOrToc};
+use crate::projects::comments::Comment;
+use crate::projects::suggestions::Suggestion;
+use crate::projects::workflow::WorkflowStatus;
+use crate::mail::notifications::NotificationPreferences;
+use crate::webhooks::{DeliveryLogEntry, Webhook};
 use hayagriva::types::*;
 use reqwest::Url;
 
 use unic_langid_impl::LanguageIdentifier;
 
+mod legacy;
+
 /// Storage for small data like users, passwords and login attempts
 ///
 /// This data is stored in memory permanently and doesn't get unloaded
 pub struct DataStorage{
-    pub data: RwLock<InnerDataStorageV2>,
+    pub data: RwLock<InnerDataStorageV5>,
     file_locked: AtomicBool,
 }
 
@@ -52,6 +60,64 @@ pub struct InnerDataStorageV2{
     pub templates: HashMap<uuid::Uuid, Arc<RwLock<ProjectTemplateV2>>>
 }
 
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub struct InnerDataStorageV3{
+    /// HashMap with users, id as HashMap keys
+    #[bincode(with_serde)]
+    pub login_data: HashMap<uuid::Uuid, Arc<RwLock<User>>>,
+    #[bincode(with_serde)]
+    pub persons: HashMap<uuid::Uuid, Arc<RwLock<Person>>>,
+    #[bincode(with_serde)]
+    pub templates: HashMap<uuid::Uuid, Arc<RwLock<ProjectTemplateV2>>>,
+    /// Email notification preferences by user id, users without entry use the default preferences
+    #[bincode(with_serde)]
+    pub notification_preferences: HashMap<uuid::Uuid, NotificationPreferences>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub struct InnerDataStorageV4{
+    /// HashMap with users, id as HashMap keys
+    #[bincode(with_serde)]
+    pub login_data: HashMap<uuid::Uuid, Arc<RwLock<User>>>,
+    #[bincode(with_serde)]
+    pub persons: HashMap<uuid::Uuid, Arc<RwLock<Person>>>,
+    #[bincode(with_serde)]
+    pub templates: HashMap<uuid::Uuid, Arc<RwLock<ProjectTemplateV2>>>,
+    /// Email notification preferences by user id, users without entry use the default preferences
+    #[bincode(with_serde)]
+    pub notification_preferences: HashMap<uuid::Uuid, NotificationPreferences>,
+    /// Contributors: user id -> linked person id
+    ///
+    /// Linked users only get access to the sections the person is credited on as author, see [crate::projects::contributors]
+    #[bincode(with_serde)]
+    pub contributors: HashMap<uuid::Uuid, uuid::Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub struct InnerDataStorageV5{
+    /// HashMap with users, id as HashMap keys
+    #[bincode(with_serde)]
+    pub login_data: HashMap<uuid::Uuid, Arc<RwLock<User>>>,
+    #[bincode(with_serde)]
+    pub persons: HashMap<uuid::Uuid, Arc<RwLock<Person>>>,
+    #[bincode(with_serde)]
+    pub templates: HashMap<uuid::Uuid, Arc<RwLock<ProjectTemplateV2>>>,
+    /// Email notification preferences by user id, users without entry use the default preferences
+    #[bincode(with_serde)]
+    pub notification_preferences: HashMap<uuid::Uuid, NotificationPreferences>,
+    /// Contributors: user id -> linked person id
+    ///
+    /// Linked users only get access to the sections the person is credited on as author, see [crate::projects::contributors]
+    #[bincode(with_serde)]
+    pub contributors: HashMap<uuid::Uuid, uuid::Uuid>,
+    /// Outgoing webhooks, global and per project
+    #[bincode(with_serde)]
+    pub webhooks: HashMap<uuid::Uuid, Webhook>,
+    /// Delivery log by webhook id, newest attempt last
+    #[bincode(with_serde)]
+    pub webhook_deliveries: HashMap<uuid::Uuid, VecDeque<DeliveryLogEntry>>,
+}
+
 impl From<InnerDataStorageV1> for InnerDataStorageV2{
     fn from(value: InnerDataStorageV1) -> Self {
         println!("Migrating data storage from V1 to V2. You have to migrate your templates manually. Your old templates where moved to data/templates-old"); // TODO: move
@@ -63,14 +129,55 @@ impl From<InnerDataStorageV1> for InnerDataStorageV2{
     }
 }
 
+impl From<InnerDataStorageV2> for InnerDataStorageV3{
+    fn from(value: InnerDataStorageV2) -> Self {
+        InnerDataStorageV3{
+            login_data: value.login_data,
+            persons: value.persons,
+            templates: value.templates,
+            notification_preferences: HashMap::new(),
+        }
+    }
+}
+
+impl From<InnerDataStorageV3> for InnerDataStorageV4{
+    fn from(value: InnerDataStorageV3) -> Self {
+        InnerDataStorageV4{
+            login_data: value.login_data,
+            persons: value.persons,
+            templates: value.templates,
+            notification_preferences: value.notification_preferences,
+            contributors: HashMap::new(),
+        }
+    }
+}
+
+impl From<InnerDataStorageV4> for InnerDataStorageV5{
+    fn from(value: InnerDataStorageV4) -> Self {
+        InnerDataStorageV5{
+            login_data: value.login_data,
+            persons: value.persons,
+            templates: value.templates,
+            notification_preferences: value.notification_preferences,
+            contributors: value.contributors,
+            webhooks: HashMap::new(),
+            webhook_deliveries: HashMap::new(),
+        }
+    }
+}
+
 impl DataStorage{
     /// Creates a new empty [DataStorage]
     pub fn new() -> Self {
         DataStorage {
-            data: RwLock::new(InnerDataStorageV2{
+            data: RwLock::new(InnerDataStorageV5{
                 login_data: Default::default(),
                 persons: Default::default(),
                 templates: Default::default(),
+                notification_preferences: Default::default(),
+                contributors: Default::default(),
+                webhooks: Default::default(),
+                webhook_deliveries: Default::default(),
             }),
             file_locked: Default::default(),
         }
@@ -105,6 +212,34 @@ impl DataStorage{
         }
     }
 
+    /// Get user by id
+    pub fn get_user_by_id(&self, uuid: &uuid::Uuid) -> Option<Arc<RwLock<User>>>{
+        self.data.read().unwrap().login_data.get(uuid).map(Arc::clone)
+    }
+
+    /// Get the notification preferences of a user, default preferences if the user never changed them
+    pub fn get_notification_preferences(&self, user_id: &uuid::Uuid) -> NotificationPreferences{
+        self.data.read().unwrap().notification_preferences.get(user_id).cloned().unwrap_or_default()
+    }
+
+    pub fn set_notification_preferences(&self, user_id: uuid::Uuid, preferences: NotificationPreferences){
+        self.data.write().unwrap().notification_preferences.insert(user_id, preferences);
+    }
+
+    /// Returns the person a contributor is linked to, [None] for staff users
+    pub fn get_contributor_person(&self, user_id: &uuid::Uuid) -> Option<uuid::Uuid>{
+        self.data.read().unwrap().contributors.get(user_id).cloned()
+    }
+
+    /// Links a user to a person and makes them a contributor, [None] removes the link
+    pub fn set_contributor_person(&self, user_id: uuid::Uuid, person_id: Option<uuid::Uuid>){
+        let mut data = self.data.write().unwrap();
+        match person_id{
+            Some(person_id) => data.contributors.insert(user_id, person_id),
+            None => data.contributors.remove(&user_id),
+        };
+    }
+
     /// Get person by id
     /// Returns a [Person] as [Arc<RwLock<Person>>] if the person exists
     pub fn get_person(&self, uuid: &uuid::Uuid) -> Option<Arc<RwLock<Person>>>{
@@ -206,13 +341,61 @@ impl DataStorage{
                 }
 
                 match bincode::decode_from_std_read::<InnerDataStorageV1, _, _>(&mut file, bincode::config::standard()) {
-                    Ok(data) => return Ok(InnerDataStorageV2::from(data)),
+                    Ok(data) => return Ok(InnerDataStorageV5::from(InnerDataStorageV4::from(InnerDataStorageV3::from(InnerDataStorageV2::from(data))))),
                     Err(e) => {
                         eprintln!("bincode decode error while loading data storage with version {} into memory: {}.", version, e);
                         return Err(())
                     },
                 };
             }else if *version == 2 {
+                // Load data format without notification preferences
+                let mut file = match std::fs::File::open(format!("{}/{}", &path, file_path)) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        eprintln!("io error while loading data file into memory: {}", e);
+                        return Err(())
+                    },
+                };
+                match bincode::decode_from_std_read::<InnerDataStorageV2, _, _>(&mut file, bincode::config::standard()) {
+                    Ok(data) => return Ok(InnerDataStorageV5::from(InnerDataStorageV4::from(InnerDataStorageV3::from(data)))),
+                    Err(e) => {
+                        eprintln!("bincode decode error while loading data storage with version {} into memory: {}.", version, e);
+                        return Err(())
+                    },
+                };
+            }else if *version == 3 {
+                // Load data format without contributors
+                let mut file = match std::fs::File::open(format!("{}/{}", &path, file_path)) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        eprintln!("io error while loading data file into memory: {}", e);
+                        return Err(())
+                    },
+                };
+                match bincode::decode_from_std_read::<InnerDataStorageV3, _, _>(&mut file, bincode::config::standard()) {
+                    Ok(data) => return Ok(InnerDataStorageV5::from(InnerDataStorageV4::from(data))),
+                    Err(e) => {
+                        eprintln!("bincode decode error while loading data storage with version {} into memory: {}.", version, e);
+                        return Err(())
+                    },
+                };
+            }else if *version == 4 {
+                // Load data format without webhooks
+                let mut file = match std::fs::File::open(format!("{}/{}", &path, file_path)) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        eprintln!("io error while loading data file into memory: {}", e);
+                        return Err(())
+                    },
+                };
+                match bincode::decode_from_std_read::<InnerDataStorageV4, _, _>(&mut file, bincode::config::standard()) {
+                    Ok(data) => return Ok(InnerDataStorageV5::from(data)),
+                    Err(e) => {
+                        eprintln!("bincode decode error while loading data storage with version {} into memory: {}.", version, e);
+                        return Err(())
+                    },
+                };
+            }else if *version == 5 {
                 // Load new project format
                 let mut file = match std::fs::File::open(format!("{}/{}", &path, file_path)) {
                     Ok(file) => file,
@@ -262,7 +445,7 @@ impl DataStorage{
 
         // Save login data
         let cpy = self.data.read().unwrap().clone();
-        let path = format!("{}/data.2.bincode", settings.data_path);
+        let path = format!("{}/data.5.bincode", settings.data_path);
 
         match rocket::tokio::task::spawn_blocking(move || {
             let mut file = match std::fs::File::create(path) {
@@ -613,6 +796,86 @@ impl ProjectStorage {
                             },
                         };
                     }else if *version == 2 {
+                        // Load project format without version counters
+                        let mut file = match std::fs::File::open(format!("{}/{}", &npath, project_path)) {
+                            Ok(file) => file,
+                            Err(e) => {
+                                eprintln!("io error while loading project file into memory: {}", e);
+                                return Err(())
+                            },
+                        };
+                        match bincode::decode_from_std_read::<OldProjectDataV2, _, _>(&mut file, bincode::config::standard()) {
+                            Ok(project) => return Ok(ProjectDataV2::from(project)),
+                            Err(e) => {
+                                eprintln!("bincode decode error while loading project file with version {} into memory: {}.", version, e);
+                                return Err(())
+                            },
+                        };
+                    }else if *version == 3 {
+                        // Load project format without comments
+                        let mut file = match std::fs::File::open(format!("{}/{}", &npath, project_path)) {
+                            Ok(file) => file,
+                            Err(e) => {
+                                eprintln!("io error while loading project file into memory: {}", e);
+                                return Err(())
+                            },
+                        };
+                        match bincode::decode_from_std_read::<OldProjectDataV3, _, _>(&mut file, bincode::config::standard()) {
+                            Ok(project) => return Ok(ProjectDataV2::from(project)),
+                            Err(e) => {
+                                eprintln!("bincode decode error while loading project file with version {} into memory: {}.", version, e);
+                                return Err(())
+                            },
+                        };
+                    }else if *version == 4 {
+                        // Load project format without suggestions
+                        let mut file = match std::fs::File::open(format!("{}/{}", &npath, project_path)) {
+                            Ok(file) => file,
+                            Err(e) => {
+                                eprintln!("io error while loading project file into memory: {}", e);
+                                return Err(())
+                            },
+                        };
+                        match bincode::decode_from_std_read::<OldProjectDataV4, _, _>(&mut file, bincode::config::standard()) {
+                            Ok(project) => return Ok(ProjectDataV2::from(project)),
+                            Err(e) => {
+                                eprintln!("bincode decode error while loading project file with version {} into memory: {}.", version, e);
+                                return Err(())
+                            },
+                        };
+                    }else if *version == 5 {
+                        // Load project format without editorial workflow
+                        let mut file = match std::fs::File::open(format!("{}/{}", &npath, project_path)) {
+                            Ok(file) => file,
+                            Err(e) => {
+                                eprintln!("io error while loading project file into memory: {}", e);
+                                return Err(())
+                            },
+                        };
+                        match bincode::decode_from_std_read::<OldProjectDataV5, _, _>(&mut file, bincode::config::standard()) {
+                            Ok(project) => return Ok(ProjectDataV2::from(project)),
+                            Err(e) => {
+                                eprintln!("bincode decode error while loading project file with version {} into memory: {}.", version, e);
+                                return Err(())
+                            },
+                        };
+                    }else if *version == 6 {
+                        // Load project format without task assignments
+                        let mut file = match std::fs::File::open(format!("{}/{}", &npath, project_path)) {
+                            Ok(file) => file,
+                            Err(e) => {
+                                eprintln!("io error while loading project file into memory: {}", e);
+                                return Err(())
+                            },
+                        };
+                        match bincode::decode_from_std_read::<OldProjectDataV6, _, _>(&mut file, bincode::config::standard()) {
+                            Ok(project) => return Ok(ProjectDataV2::from(project)),
+                            Err(e) => {
+                                eprintln!("bincode decode error while loading project file with version {} into memory: {}.", version, e);
+                                return Err(())
+                            },
+                        };
+                    }else if *version == 7 {
                         // Load new project format
                         let mut file = match std::fs::File::open(format!("{}/{}", &npath, project_path)) {
                             Ok(file) => file,
@@ -650,7 +913,13 @@ impl ProjectStorage {
         match res{
             Ok(project) => {
                 match project {
-                    Ok(project) => {
+                    Ok(mut project) => {
+                        // Repair content blocks that were stored with a wrong block type by older versions
+                        let repaired = project.repair_block_types();
+                        if repaired > 0{
+                            println!("Repaired block types of {} content blocks in project {}.", repaired, uuid);
+                        }
+
                         println!("Loaded project, inserting into memory storage.");
                         if let Some(tproject) = self.projects.write().unwrap().get_mut(uuid){
                                 // Update last edited to current time, so the project doesn't get unloaded immediately
@@ -737,7 +1006,7 @@ impl ProjectStorage {
             }
         }
 
-        let version = "2"; //TODO: auto detect latest version
+        let version = "7"; //TODO: auto detect latest version
 
         // Encode project data with bincode and save to disk
         let path = format!("{}/projects/{}/project.{}.bincode", settings.data_path, uuid, version);
@@ -796,7 +1065,7 @@ pub struct OldProjectData {
     pub last_interaction: u64,
     pub metadata: Option<ProjectMetadata>,
     pub settings: Option<ProjectSettings>,
-    pub sections: Vec<SectionOrToc>,
+    pub sections: Vec<OldSectionOrToc>,
     #[bincode(with_serde)]
     pub bibliography: HashMap<String, OldBibEntry>
 }
@@ -813,7 +1082,21 @@ pub struct ProjectDataV2 {
     pub settings: Option<ProjectSettings>,
     pub sections: Vec<SectionOrToc>,
     #[bincode(with_serde)]
-    pub bibliography: HashMap<String, BibEntryV2> //TODO: add prefix & suffix support
+    pub bibliography: HashMap<String, BibEntryV2>, //TODO: add prefix & suffix support
+    /// Incremented on every change of the project metadata
+    ///
+    /// Sent to clients as ETag, so concurrent edits can be detected
+    #[serde(default)]
+    pub version: u64,
+    /// Review comments on content blocks, see [crate::projects::comments]
+    #[serde(default)]
+    pub comments: Vec<Comment>,
+    /// Pending suggested changes on content blocks, see [crate::projects::suggestions]
+    #[serde(default)]
+    pub suggestions: Vec<Suggestion>,
+    /// Editorial workflow state of the whole project, see [crate::projects::workflow]
+    #[serde(default)]
+    pub workflow: WorkflowStatus,
 }
 
 impl From<OldProjectData> for ProjectDataV2{
@@ -825,8 +1108,107 @@ impl From<OldProjectData> for ProjectDataV2{
             last_interaction: value.last_interaction,
             metadata: value.metadata,
             settings: value.settings,
-            sections: value.sections,
+            sections: value.sections.into_iter().map(|section| section.into()).collect(),
             bibliography: value.bibliography.iter().map(|(k, v)| (k.clone(), v.clone().into())).collect(),
+            version: 0,
+            comments: vec![],
+            suggestions: vec![],
+            workflow: Default::default(),
+        }
+    }
+}
+
+impl From<OldProjectDataV2> for ProjectDataV2{
+    fn from(value: OldProjectDataV2) -> Self {
+        ProjectDataV2{
+            name: value.name,
+            description: value.description,
+            template_id: value.template_id,
+            last_interaction: value.last_interaction,
+            metadata: value.metadata,
+            settings: value.settings,
+            sections: value.sections.into_iter().map(|section| section.into()).collect(),
+            bibliography: value.bibliography,
+            version: 0,
+            comments: vec![],
+            suggestions: vec![],
+            workflow: Default::default(),
+        }
+    }
+}
+
+impl From<OldProjectDataV6> for ProjectDataV2{
+    fn from(value: OldProjectDataV6) -> Self {
+        ProjectDataV2{
+            name: value.name,
+            description: value.description,
+            template_id: value.template_id,
+            last_interaction: value.last_interaction,
+            metadata: value.metadata,
+            settings: value.settings,
+            sections: value.sections.into_iter().map(|section| section.into()).collect(),
+            bibliography: value.bibliography,
+            version: value.version,
+            comments: value.comments,
+            suggestions: value.suggestions,
+            workflow: value.workflow,
+        }
+    }
+}
+
+impl From<OldProjectDataV5> for ProjectDataV2{
+    fn from(value: OldProjectDataV5) -> Self {
+        ProjectDataV2{
+            name: value.name,
+            description: value.description,
+            template_id: value.template_id,
+            last_interaction: value.last_interaction,
+            metadata: value.metadata,
+            settings: value.settings,
+            sections: value.sections.into_iter().map(|section| section.into()).collect(),
+            bibliography: value.bibliography,
+            version: value.version,
+            comments: value.comments,
+            suggestions: value.suggestions,
+            workflow: Default::default(),
+        }
+    }
+}
+
+impl From<OldProjectDataV4> for ProjectDataV2{
+    fn from(value: OldProjectDataV4) -> Self {
+        ProjectDataV2{
+            name: value.name,
+            description: value.description,
+            template_id: value.template_id,
+            last_interaction: value.last_interaction,
+            metadata: value.metadata,
+            settings: value.settings,
+            sections: value.sections.into_iter().map(|section| section.into()).collect(),
+            bibliography: value.bibliography,
+            version: value.version,
+            comments: value.comments,
+            suggestions: vec![],
+            workflow: Default::default(),
+        }
+    }
+}
+
+impl From<OldProjectDataV3> for ProjectDataV2{
+    fn from(value: OldProjectDataV3) -> Self {
+        ProjectDataV2{
+            name: value.name,
+            description: value.description,
+            template_id: value.template_id,
+            last_interaction: value.last_interaction,
+            metadata: value.metadata,
+            settings: value.settings,
+            sections: value.sections.into_iter().map(|section| section.into()).collect(),
+            bibliography: value.bibliography,
+            version: value.version,
+            comments: vec![],
+            suggestions: vec![],
+            workflow: Default::default(),
         }
     }
 }
@@ -1517,6 +1899,73 @@ impl BibEntryV2 {
 }
 
 impl ProjectDataV2 {
+    /// Marks the project metadata as changed by incrementing the project version
+    pub fn increment_version(&mut self){
+        self.version += 1;
+    }
+
+    /// Finds a section (or subsection) by its id
+    pub fn find_section(&self, section_id: &uuid::Uuid) -> Option<&Section> {
+        fn find_in<'a>(sections: &'a Vec<Section>, section_id: &uuid::Uuid) -> Option<&'a Section> {
+            for section in sections{
+                if section.id == Some(*section_id){
+                    return Some(section);
+                }
+                if let Some(section) = find_in(&section.sub_sections, section_id){
+                    return Some(section);
+                }
+            }
+            None
+        }
+
+        for section in self.sections.iter(){
+            if let SectionOrToc::Section(section) = section{
+                if section.id == Some(*section_id){
+                    return Some(section);
+                }
+                if let Some(section) = find_in(&section.sub_sections, section_id){
+                    return Some(section);
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the path (ids of all parent sections and the section itself) of a section
+    pub fn find_section_path(&self, section_id: &uuid::Uuid) -> Option<Vec<uuid::Uuid>> {
+        fn find_in(section: &Section, section_id: &uuid::Uuid, path: &mut Vec<uuid::Uuid>) -> bool {
+            path.push(section.id.unwrap_or_default());
+            if section.id == Some(*section_id) || section.sub_sections.iter().any(|sub_section| find_in(sub_section, section_id, path)){
+                return true;
+            }
+            path.pop();
+            false
+        }
+
+        let mut path = vec![];
+        for section in self.sections.iter(){
+            if let SectionOrToc::Section(section) = section{
+                if find_in(section, section_id, &mut path){
+                    return Some(path);
+                }
+            }
+        }
+        None
+    }
+
+    /// Repairs the block types of all content blocks in all sections
+    ///
+    /// Returns the number of blocks that had to be repaired
+    pub fn repair_block_types(&mut self) -> usize {
+        let mut repaired = 0;
+        for section in self.sections.iter_mut(){
+            if let SectionOrToc::Section(section) = section{
+                repaired += section.repair_block_types();
+            }
+        }
+        repaired
+    }
+
     // TODO migrate to using path instead of the id and searching for it
     pub fn remove_section(&mut self, section_to_remove_id: &uuid::Uuid) -> Option<Section> {
         let pos = self.sections.iter().position(|section| match section {
@@ -2002,9 +2451,24 @@ mod tests {
             file_lock_timeout: 10,
             backup_to_file_interval: 120,
             max_rendering_threads: 10,
+            rendering_timeout: 600,
+            rendering_queue: Default::default(),
+            section_cache_size: 0,
+            preview_cache_size: 0,
             max_import_threads: 2,
             chromium_path: None,
+            pdf_renderer: crate::settings::PdfRendererKind::Mock,
             zotero_translation_server: "https://translation-server.anghenfil.de".to_string(),
+            html_sanitizer: crate::settings::HtmlSanitizerSettings{
+                allowed_elements: Default::default(),
+                generic_attributes: vec![],
+                url_schemes: vec![],
+                html_attributes: vec![],
+            },
+            workflow: Default::default(),
+            mail: None,
+            webhooks: Default::default(),
+            rendering_history: Default::default(),
         }
     }
 
@@ -2020,6 +2484,10 @@ mod tests {
             settings: None,
             sections: vec![],
             bibliography: Default::default(),
+            version: 0,
+            comments: vec![],
+            suggestions: vec![],
+            workflow: Default::default(),
         };
         let settings = generate_settings();
         let mut project_storage = ProjectStorage::new();
@@ -0,0 +1,233 @@
+//! Data structures of older project file versions
+//!
+//! bincode doesn't support adding fields to existing structs, so older project files have to be decoded into
+//! these structs and converted to the current data structures afterwards.
+
+use std::collections::HashMap;
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use crate::data_storage::BibEntryV2;
+use crate::projects::comments::Comment;
+use crate::projects::suggestions::Suggestion;
+use crate::projects::workflow::WorkflowStatus;
+use crate::projects::{NewContentBlock, ProjectMetadata, ProjectSettings, Section, SectionMetadata, SectionOrToc};
+
+/// Project data as stored in project files with version 2 (before the version counter was added)
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub struct OldProjectDataV2 {
+    pub name: String,
+    pub description: Option<String>,
+    #[bincode(with_serde)]
+    pub template_id: uuid::Uuid,
+    pub last_interaction: u64,
+    pub metadata: Option<ProjectMetadata>,
+    pub settings: Option<ProjectSettings>,
+    pub sections: Vec<OldSectionOrToc>,
+    #[bincode(with_serde)]
+    pub bibliography: HashMap<String, BibEntryV2>
+}
+
+/// Project data as stored in project files with version 3 (before comments were added)
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub struct OldProjectDataV3 {
+    pub name: String,
+    pub description: Option<String>,
+    #[bincode(with_serde)]
+    pub template_id: uuid::Uuid,
+    pub last_interaction: u64,
+    pub metadata: Option<ProjectMetadata>,
+    pub settings: Option<ProjectSettings>,
+    pub sections: Vec<OldSectionOrTocV3>,
+    #[bincode(with_serde)]
+    pub bibliography: HashMap<String, BibEntryV2>,
+    pub version: u64,
+}
+
+/// Project data as stored in project files with version 4 (before suggestions were added)
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub struct OldProjectDataV4 {
+    pub name: String,
+    pub description: Option<String>,
+    #[bincode(with_serde)]
+    pub template_id: uuid::Uuid,
+    pub last_interaction: u64,
+    pub metadata: Option<ProjectMetadata>,
+    pub settings: Option<ProjectSettings>,
+    pub sections: Vec<OldSectionOrTocV3>,
+    #[bincode(with_serde)]
+    pub bibliography: HashMap<String, BibEntryV2>,
+    pub version: u64,
+    pub comments: Vec<Comment>,
+}
+
+/// Project data as stored in project files with version 5 (before the editorial workflow was added)
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub struct OldProjectDataV5 {
+    pub name: String,
+    pub description: Option<String>,
+    #[bincode(with_serde)]
+    pub template_id: uuid::Uuid,
+    pub last_interaction: u64,
+    pub metadata: Option<ProjectMetadata>,
+    pub settings: Option<ProjectSettings>,
+    pub sections: Vec<OldSectionOrTocV3>,
+    #[bincode(with_serde)]
+    pub bibliography: HashMap<String, BibEntryV2>,
+    pub version: u64,
+    pub comments: Vec<Comment>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+/// Section or toc as stored in project files with version 1 and 2
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub enum OldSectionOrToc{
+    Section(Box<OldSection>),
+    Toc,
+}
+
+/// Section as stored in project files with version 1 and 2 (before the version counter was added)
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub struct OldSection{
+    #[bincode(with_serde)]
+    pub id: Option<uuid::Uuid>,
+    pub css_classes: Vec<String>,
+    pub sub_sections: Vec<OldSection>,
+    pub children: Vec<NewContentBlock>,
+    pub visible_in_toc: bool,
+    pub metadata: SectionMetadata,
+}
+
+/// Project data as stored in project files with version 6 (before task assignments were added)
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub struct OldProjectDataV6 {
+    pub name: String,
+    pub description: Option<String>,
+    #[bincode(with_serde)]
+    pub template_id: uuid::Uuid,
+    pub last_interaction: u64,
+    pub metadata: Option<ProjectMetadata>,
+    pub settings: Option<ProjectSettings>,
+    pub sections: Vec<OldSectionOrTocV6>,
+    #[bincode(with_serde)]
+    pub bibliography: HashMap<String, BibEntryV2>,
+    pub version: u64,
+    pub comments: Vec<Comment>,
+    pub suggestions: Vec<Suggestion>,
+    pub workflow: WorkflowStatus,
+}
+
+/// Section or toc as stored in project files with version 3 to 5
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub enum OldSectionOrTocV3{
+    Section(Box<OldSectionV3>),
+    Toc,
+}
+
+/// Section as stored in project files with version 3 to 5 (before the editorial workflow was added)
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub struct OldSectionV3{
+    #[bincode(with_serde)]
+    pub id: Option<uuid::Uuid>,
+    pub css_classes: Vec<String>,
+    pub sub_sections: Vec<OldSectionV3>,
+    pub children: Vec<NewContentBlock>,
+    pub visible_in_toc: bool,
+    pub metadata: SectionMetadata,
+    pub version: u64,
+}
+
+/// Section or toc as stored in project files with version 6
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub enum OldSectionOrTocV6{
+    Section(Box<OldSectionV6>),
+    Toc,
+}
+
+/// Section as stored in project files with version 6 (before task assignments were added)
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
+pub struct OldSectionV6{
+    #[bincode(with_serde)]
+    pub id: Option<uuid::Uuid>,
+    pub css_classes: Vec<String>,
+    pub sub_sections: Vec<OldSectionV6>,
+    pub children: Vec<NewContentBlock>,
+    pub visible_in_toc: bool,
+    pub metadata: SectionMetadata,
+    pub version: u64,
+    pub workflow: WorkflowStatus,
+}
+
+impl From<OldSectionOrToc> for SectionOrToc{
+    fn from(value: OldSectionOrToc) -> Self {
+        match value{
+            OldSectionOrToc::Section(section) => SectionOrToc::Section((*section).into()),
+            OldSectionOrToc::Toc => SectionOrToc::Toc,
+        }
+    }
+}
+
+impl From<OldSection> for Section{
+    fn from(value: OldSection) -> Self {
+        Section{
+            id: value.id,
+            css_classes: value.css_classes,
+            sub_sections: value.sub_sections.into_iter().map(|section| section.into()).collect(),
+            children: value.children,
+            visible_in_toc: value.visible_in_toc,
+            metadata: value.metadata,
+            version: 0,
+            workflow: Default::default(),
+            assignment: Default::default(),
+        }
+    }
+}
+
+impl From<OldSectionOrTocV3> for SectionOrToc{
+    fn from(value: OldSectionOrTocV3) -> Self {
+        match value{
+            OldSectionOrTocV3::Section(section) => SectionOrToc::Section((*section).into()),
+            OldSectionOrTocV3::Toc => SectionOrToc::Toc,
+        }
+    }
+}
+
+impl From<OldSectionV3> for Section{
+    fn from(value: OldSectionV3) -> Self {
+        Section{
+            id: value.id,
+            css_classes: value.css_classes,
+            sub_sections: value.sub_sections.into_iter().map(|section| section.into()).collect(),
+            children: value.children,
+            visible_in_toc: value.visible_in_toc,
+            metadata: value.metadata,
+            version: value.version,
+            workflow: Default::default(),
+            assignment: Default::default(),
+        }
+    }
+}
+
+impl From<OldSectionOrTocV6> for SectionOrToc{
+    fn from(value: OldSectionOrTocV6) -> Self {
+        match value{
+            OldSectionOrTocV6::Section(section) => SectionOrToc::Section((*section).into()),
+            OldSectionOrTocV6::Toc => SectionOrToc::Toc,
+        }
+    }
+}
+
+impl From<OldSectionV6> for Section{
+    fn from(value: OldSectionV6) -> Self {
+        Section{
+            id: value.id,
+            css_classes: value.css_classes,
+            sub_sections: value.sub_sections.into_iter().map(|section| section.into()).collect(),
+            children: value.children,
+            visible_in_toc: value.visible_in_toc,
+            metadata: value.metadata,
+            version: value.version,
+            workflow: value.workflow,
+            assignment: Default::default(),
+        }
+    }
+}
@@ -1,12 +1,22 @@
+use std::sync::Arc;
 use rocket::fs::NamedFile;
 use rocket::http::Status;
 use rocket::State;
+use crate::export::history::RenderingHistory;
+use crate::projects::contributors::Access;
 use crate::session::session_guard::Session;
-use crate::settings::Settings;
 
 #[get("/download/renderings/<id>")]
-pub async fn download_rendering(id: String, settings: &State<Settings>, _session: Session) -> Result<NamedFile, Status> {
-    let path = format!("{}/temp/{}/output.pdf", settings.data_path, id);
+pub async fn download_rendering(id: String, session: Session, access: Access, history: &State<Arc<RenderingHistory>>) -> Result<NamedFile, Status> {
+    let rendering_id = uuid::Uuid::parse_str(&id).map_err(|_| Status::NotFound)?;
+    let record = history.get(&rendering_id).ok_or(Status::NotFound)?;
+
+    // Contributors only get their own renderings
+    if !access.is_staff() && record.requested_by != session.user_id{
+        return Err(Status::NotFound);
+    }
+
+    let path = history.artifact_path(&record, "output.pdf").ok_or(Status::NotFound)?;
     let file = NamedFile::open(path).await.map_err(|_| Status::NotFound)?;
     Ok(file)
-}
\ No newline at end of file
+}
@@ -0,0 +1,432 @@
+//! Persistent history of renderings and their output files.
+//!
+//! When a rendering is done, its output files are moved out of the temp directory (which is cleared at boot)
+//! into `{data_path}/renderings/<project_id>/<rendering_id>`. A [RenderingRecord] per rendering is kept in
+//! `{data_path}/renderings/<project_id>/history.1.bincode`.
+//! The stage log of the rendering is kept as `log.json` next to the output files.
+//! Output files and logs are deleted according to [RenderingHistorySettings], the records themselves are kept.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use bincode::{Decode, Encode};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::data_storage::ExportType;
+use crate::export::page_map::PageMap;
+use crate::export::rendering_log::StageLog;
+use crate::export::rendering_manager::{RenderingOptions, RenderingStatus};
+use crate::export::scope::RenderingScope;
+use crate::settings::{RenderingHistorySettings, Settings};
+
+/// Files which are kept from the working directory of a rendering
+const ARTIFACTS: [&str; 1] = ["output.pdf"];
+
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug)]
+pub struct RenderingArtifact{
+    pub file_name: String,
+    /// Path relative to the data path
+    pub path: String,
+    /// Size in bytes
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug)]
+pub struct RenderingRecord{
+    #[bincode(with_serde)]
+    pub id: uuid::Uuid,
+    #[bincode(with_serde)]
+    pub project_id: uuid::Uuid,
+    /// User who started the rendering
+    #[bincode(with_serde)]
+    pub requested_by: uuid::Uuid,
+    #[bincode(with_serde)]
+    pub requested_at: NaiveDateTime,
+    #[bincode(with_serde)]
+    pub finished_at: NaiveDateTime,
+    #[bincode(with_serde)]
+    pub template_id: uuid::Uuid,
+    pub export_type: ExportType,
+    pub options: RenderingOptions,
+    /// Final status of the rendering
+    pub status: RenderingStatus,
+    pub error: Option<String>,
+    /// Empty if the rendering produced no output or the output was removed by the retention policy
+    pub artifacts: Vec<RenderingArtifact>,
+    /// Page count and start pages of the sections, if the PDF could be parsed
+    pub page_map: Option<PageMap>,
+}
+
+/// Rendering options are stored as JSON, so new options with a serde default don't need a new history format
+///
+/// bincode (also with `#[bincode(with_serde)]`) encodes structs by position and can't decode records without the new fields.
+impl Encode for RenderingOptions{
+    fn encode<E: bincode::enc::Encoder>(&self, encoder: &mut E) -> Result<(), bincode::error::EncodeError> {
+        let json = serde_json::to_string(self).map_err(|e| bincode::error::EncodeError::OtherString(e.to_string()))?;
+        json.encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for RenderingOptions{
+    fn decode<D: bincode::de::Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, bincode::error::DecodeError> {
+        let json = String::decode(decoder)?;
+        serde_json::from_str(&json).map_err(|e| bincode::error::DecodeError::OtherString(e.to_string()))
+    }
+}
+
+bincode::impl_borrow_decode!(RenderingOptions);
+
+pub struct RenderingHistory{
+    data_path: String,
+    retention: RenderingHistorySettings,
+    /// Records per project, oldest first
+    records: RwLock<HashMap<uuid::Uuid, Vec<RenderingRecord>>>,
+}
+
+impl RenderingHistory{
+    pub fn new(settings: &Settings) -> RenderingHistory{
+        RenderingHistory{
+            data_path: settings.data_path.clone(),
+            retention: settings.rendering_history.clone(),
+            records: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Loads the history from disk and starts the worker which applies the retention policy every hour
+    pub fn start(settings: &Settings) -> Arc<RenderingHistory>{
+        let history = Arc::new(RenderingHistory::new(settings));
+        history.load_from_disk();
+
+        let history_cpy = history.clone();
+        tokio::spawn(async move {
+            loop{
+                let history = history_cpy.clone();
+                if let Err(e) = tokio::task::spawn_blocking(move || history.apply_retention()).await{
+                    eprintln!("Couldn't apply rendering retention policy: {}", e);
+                }
+                tokio::time::sleep(Duration::from_secs(3600)).await;
+            }
+        });
+
+        history
+    }
+
+    fn base_dir(&self) -> PathBuf{
+        Path::new(&self.data_path).join("renderings")
+    }
+
+    fn history_file(&self, project_id: &uuid::Uuid) -> PathBuf{
+        self.base_dir().join(project_id.to_string()).join("history.1.bincode")
+    }
+
+    fn read_history_file(&self, project_id: &uuid::Uuid) -> Option<Result<Vec<RenderingRecord>, bincode::error::DecodeError>>{
+        let mut file = fs::File::open(self.history_file(project_id)).ok()?;
+        Some(bincode::decode_from_std_read::<Vec<RenderingRecord>, _, _>(&mut file, bincode::config::standard()))
+    }
+
+    fn load_from_disk(&self){
+        let entries = match fs::read_dir(self.base_dir()){
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                eprintln!("Couldn't read rendering history: {}", e);
+                return;
+            }
+        };
+
+        let mut records = self.records.write().unwrap();
+        for entry in entries.flatten(){
+            let project_id = match uuid::Uuid::parse_str(&entry.file_name().to_string_lossy()){
+                Ok(project_id) => project_id,
+                Err(_) => continue,
+            };
+            match self.read_history_file(&project_id){
+                Some(Ok(project_records)) => {
+                    records.insert(project_id, project_records);
+                },
+                Some(Err(e)) => eprintln!("Couldn't decode rendering history of project {}: {}", project_id, e),
+                None => continue,
+            }
+        }
+    }
+
+    fn save(&self, project_id: &uuid::Uuid, records: &[RenderingRecord]) -> std::io::Result<()>{
+        let path = self.history_file(project_id);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut file = fs::File::create(path)?;
+        bincode::encode_into_std_write(records, &mut file, bincode::config::standard())
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        Ok(())
+    }
+
+    fn rendering_dir(&self, record: &RenderingRecord) -> PathBuf{
+        self.base_dir().join(record.project_id.to_string()).join(record.id.to_string())
+    }
+
+    /// Moves the output files of a finished rendering from its working directory into the store and adds the record
+    pub fn add(&self, mut record: RenderingRecord, working_dir: &Path, log: &[StageLog]) -> std::io::Result<RenderingRecord>{
+        let relative_dir = Path::new("renderings").join(record.project_id.to_string()).join(record.id.to_string());
+        let target_dir = Path::new(&self.data_path).join(&relative_dir);
+        fs::create_dir_all(&target_dir)?;
+        fs::write(target_dir.join("log.json"), serde_json::to_string(log)?)?;
+
+        for file_name in ARTIFACTS{
+            let source = working_dir.join(file_name);
+            if !source.exists(){
+                continue;
+            }
+            let target = target_dir.join(file_name);
+            if fs::rename(&source, &target).is_err(){
+                // Temp and output directory might be on different file systems
+                fs::copy(&source, &target)?;
+            }
+            record.artifacts.push(RenderingArtifact{
+                file_name: file_name.to_string(),
+                path: relative_dir.join(file_name).to_string_lossy().to_string(),
+                size: fs::metadata(&target)?.len(),
+            });
+        }
+
+        let project_id = record.project_id;
+        let mut records = self.records.write().unwrap();
+        let project_records = records.entry(project_id).or_default();
+        project_records.push(record);
+        self.expire(project_records);
+        self.save(&project_id, project_records)?;
+        Ok(project_records.last().unwrap().clone())
+    }
+
+    /// Returns the renderings of a project, newest first
+    pub fn list(&self, project_id: &uuid::Uuid) -> Vec<RenderingRecord>{
+        self.records.read().unwrap().get(project_id)
+            .map(|records| records.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, rendering_id: &uuid::Uuid) -> Option<RenderingRecord>{
+        self.records.read().unwrap().values()
+            .flat_map(|records| records.iter())
+            .find(|record| record.id == *rendering_id)
+            .cloned()
+    }
+
+    /// Returns the page map of the latest finished rendering of the whole project
+    pub fn latest_page_map(&self, project_id: &uuid::Uuid) -> Option<PageMap>{
+        self.records.read().unwrap().get(project_id)?.iter().rev()
+            .filter(|record| matches!(record.status, RenderingStatus::Finished) && record.options.scope == RenderingScope::Project)
+            .find_map(|record| record.page_map.clone())
+    }
+
+    /// Returns the stage log of a rendering, None if it was removed by the retention policy
+    pub fn get_log(&self, record: &RenderingRecord) -> Option<Vec<StageLog>>{
+        let log = fs::read_to_string(self.rendering_dir(record).join("log.json")).ok()?;
+        serde_json::from_str(&log).ok()
+    }
+
+    /// Absolute path of an output file of a rendering, if it's still there
+    pub fn artifact_path(&self, record: &RenderingRecord, file_name: &str) -> Option<PathBuf>{
+        record.artifacts.iter()
+            .find(|artifact| artifact.file_name == file_name)
+            .map(|artifact| Path::new(&self.data_path).join(&artifact.path))
+    }
+
+    /// Deletes output files and logs of renderings which are too old or exceed the number of renderings kept per project
+    ///
+    /// Returns true if any records were changed.
+    fn expire(&self, records: &mut [RenderingRecord]) -> bool{
+        let now = chrono::Utc::now().naive_utc();
+        let max_age = chrono::Duration::days(self.retention.max_age as i64);
+        let keep = self.retention.keep_per_project;
+
+        let mut changed = false;
+        for (index, record) in records.iter_mut().rev().enumerate(){
+            let too_many = keep > 0 && index >= keep;
+            let too_old = self.retention.max_age > 0 && now - record.finished_at > max_age;
+            let dir = self.rendering_dir(record);
+            if (too_many || too_old) && dir.exists(){
+                if let Err(e) = fs::remove_dir_all(&dir){
+                    if e.kind() != std::io::ErrorKind::NotFound{
+                        eprintln!("Couldn't remove output of rendering {}: {}", record.id, e);
+                        continue;
+                    }
+                }
+                record.artifacts.clear();
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Applies the retention policy to all projects
+    pub fn apply_retention(&self){
+        let mut records = self.records.write().unwrap();
+        for (project_id, project_records) in records.iter_mut(){
+            if self.expire(project_records){
+                if let Err(e) = self.save(project_id, project_records){
+                    eprintln!("Couldn't save rendering history of project {}: {}", project_id, e);
+                }
+            }
+        }
+    }
+}
+
+pub mod api{
+    use std::sync::Arc;
+    use rocket::serde::json::Json;
+    use rocket::State;
+    use crate::export::history::{RenderingHistory, RenderingRecord};
+    use crate::export::page_map::PageMap;
+    use crate::projects::api::{ApiError, ApiResult};
+    use crate::projects::contributors::Access;
+    use crate::session::session_guard::{Session, StaffSession};
+
+    /// GET /api/projects/<project_id>/renderings
+    /// Lists the past renderings of a project, newest first. Contributors only see their own renderings.
+    #[get("/api/projects/<project_id>/renderings")]
+    pub async fn list_renderings(project_id: String, session: Session, access: Access, history: &State<Arc<RenderingHistory>>) -> Json<ApiResult<Vec<RenderingRecord>>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let records = history.list(&project_id).into_iter()
+            .filter(|record| access.is_staff() || record.requested_by == session.user_id)
+            .collect();
+        ApiResult::new_data(records)
+    }
+
+    /// GET /api/renderings/<render_id>/pages
+    /// Page count and start pages of the sections of a finished rendering
+    #[get("/api/renderings/<render_id>/pages")]
+    pub async fn get_rendering_pages(render_id: String, session: Session, access: Access, history: &State<Arc<RenderingHistory>>) -> Json<ApiResult<PageMap>>{
+        let render_id = match uuid::Uuid::parse_str(&render_id) {
+            Ok(render_id) => render_id,
+            Err(e) => {
+                eprintln!("Couldn't parse render id: {}", e);
+                return ApiResult::new_error(ApiError::NotFound);
+            },
+        };
+
+        let record = match history.get(&render_id){
+            Some(record) => record,
+            None => return ApiResult::new_error(ApiError::NotFound),
+        };
+
+        // Contributors only see their own renderings
+        if !access.is_staff() && record.requested_by != session.user_id{
+            return ApiResult::new_error(ApiError::NotFound);
+        }
+
+        match record.page_map{
+            Some(page_map) => ApiResult::new_data(page_map),
+            None => ApiResult::new_error(ApiError::NotFound)
+        }
+    }
+
+    /// GET /api/projects/<project_id>/pages
+    /// Page count and start pages of the sections from the latest finished rendering of the whole project,
+    /// e.g. for the table of contents on the web, ONIX and Crossref page ranges
+    #[get("/api/projects/<project_id>/pages")]
+    pub async fn get_project_pages(project_id: String, _session: StaffSession, history: &State<Arc<RenderingHistory>>) -> Json<ApiResult<PageMap>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        match history.latest_page_map(&project_id){
+            Some(page_map) => ApiResult::new_data(page_map),
+            None => ApiResult::new_error(ApiError::NotFound)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn record(project_id: uuid::Uuid, age_days: i64) -> RenderingRecord{
+        let finished_at = chrono::Utc::now().naive_utc() - chrono::Duration::days(age_days);
+        RenderingRecord{
+            id: uuid::Uuid::new_v4(),
+            project_id,
+            requested_by: uuid::Uuid::new_v4(),
+            requested_at: finished_at,
+            finished_at,
+            template_id: uuid::Uuid::new_v4(),
+            export_type: ExportType::PDF,
+            options: RenderingOptions::default(),
+            status: RenderingStatus::Finished,
+            error: None,
+            artifacts: vec![],
+            page_map: None,
+        }
+    }
+
+    #[test]
+    fn test_retention(){
+        let data_path = std::env::temp_dir().join(format!("rendering_history_{}", uuid::Uuid::new_v4()));
+        let history = RenderingHistory{
+            data_path: data_path.to_string_lossy().to_string(),
+            retention: RenderingHistorySettings{ max_age: 30, keep_per_project: 2 },
+            records: RwLock::new(HashMap::new()),
+        };
+        let project_id = uuid::Uuid::new_v4();
+
+        let mut ids = vec![];
+        for age in [40, 3, 2, 1]{
+            let working_dir = data_path.join("temp").join(uuid::Uuid::new_v4().to_string());
+            fs::create_dir_all(&working_dir).unwrap();
+            fs::write(working_dir.join("output.pdf"), "%PDF").unwrap();
+            let record = history.add(record(project_id, age), &working_dir, &[]).unwrap();
+            assert!(record.artifacts.iter().all(|artifact| artifact.size == 4));
+            assert!(!working_dir.join("output.pdf").exists());
+            ids.push(record.id);
+        }
+
+        // Newest first, only the two newest renderings keep their output
+        let records = history.list(&project_id);
+        assert_eq!(records.iter().map(|record| record.id).collect::<Vec<_>>(), ids.iter().rev().cloned().collect::<Vec<_>>());
+        assert_eq!(records.iter().map(|record| record.artifacts.len()).collect::<Vec<_>>(), vec![1, 1, 0, 0]);
+        assert!(history.artifact_path(&records[0], "output.pdf").unwrap().exists());
+        assert!(!data_path.join("renderings").join(project_id.to_string()).join(ids[0].to_string()).exists());
+
+        // Records survive a restart
+        let reloaded = RenderingHistory{
+            data_path: history.data_path.clone(),
+            retention: history.retention.clone(),
+            records: RwLock::new(HashMap::new()),
+        };
+        reloaded.load_from_disk();
+        assert_eq!(reloaded.list(&project_id).len(), 4);
+        assert_eq!(reloaded.get(&ids[3]).unwrap().artifacts.len(), 1);
+        assert!(reloaded.get_log(&reloaded.get(&ids[3]).unwrap()).unwrap().is_empty());
+        assert!(reloaded.get_log(&reloaded.get(&ids[0]).unwrap()).is_none());
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn test_options_are_stored_as_json(){
+        let options = RenderingOptions{
+            scope: RenderingScope::Sections { paths: vec![uuid::Uuid::new_v4().to_string()] },
+            watermark: Some("DRAFT".to_string()),
+            ..Default::default()
+        };
+        let encoded = bincode::encode_to_vec(&options, bincode::config::standard()).unwrap();
+        let (decoded, _): (RenderingOptions, usize) = bincode::decode_from_slice(&encoded, bincode::config::standard()).unwrap();
+        assert_eq!(decoded, options);
+
+        // Options stored before an option was added get its default value
+        let encoded = bincode::encode_to_vec("{\"review\":true}", bincode::config::standard()).unwrap();
+        let (decoded, _): (RenderingOptions, usize) = bincode::decode_from_slice(&encoded, bincode::config::standard()).unwrap();
+        assert_eq!(decoded, RenderingOptions{ review: true, ..Default::default() });
+    }
+}
@@ -0,0 +1,315 @@
+//! Live HTML preview of a project with its template, without building a PDF.
+//!
+//! The preview runs [prepare_project] and the `main` template of the project's template. For previews of a single
+//! section, templates can provide a `section` template, which gets the prepared section as `section` and the prepared
+//! project as `project`; without it, `main` is rendered with only that section in scope.
+//! Relative links in the html (template assets and project uploads) resolve to the asset route next to it.
+//!
+//! The latest preview of each project and section is cached, up to `preview_cache_size` previews. The cache key covers
+//! the rendered project data, the persons it references and the template files, so any edit invalidates the preview.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use sha2::{Digest, Sha256};
+use crate::data_storage::{DataStorage, ProjectDataV2};
+use crate::export::preprocessing::{load_templates, prepare_project};
+use crate::export::process::CancellationToken;
+use crate::export::rendering_manager::{RenderingError, RenderingManager, RenderingOptions};
+use crate::export::scope::RenderingScope;
+use crate::export::section_cache::collect_persons;
+use crate::projects::SectionOrToc;
+
+struct PreviewEntry{
+    /// Hash of everything the preview depends on, see [preview_key]
+    key: String,
+    html: Arc<String>,
+    /// Value of [PreviewState::clock] when the entry was used last, the least recently used entry is evicted first
+    last_used: u64,
+}
+
+struct PreviewState{
+    /// Latest preview per project and section path
+    entries: HashMap<(uuid::Uuid, Option<String>), PreviewEntry>,
+    /// Incremented on every lookup
+    clock: u64,
+}
+
+pub struct HtmlPreviewCache{
+    /// Maximum number of cached previews, 0 disables the cache
+    capacity: usize,
+    state: Mutex<PreviewState>,
+}
+
+impl HtmlPreviewCache{
+    pub fn new(capacity: usize) -> HtmlPreviewCache{
+        HtmlPreviewCache{
+            capacity,
+            state: Mutex::new(PreviewState{
+                entries: HashMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    /// Returns the preview of the project (or one of its sections), rendering it if the cached one is outdated
+    pub fn get_or_render(&self, project: ProjectDataV2, project_id: uuid::Uuid, section: Option<String>, rendering_manager: &RenderingManager) -> Result<Arc<String>, RenderingError>{
+        let key = preview_key(&project, &section, &rendering_manager.data_storage, &rendering_manager.settings.data_path);
+        self.get_or_insert((project_id, section.clone()), key, || render_preview(project, section, rendering_manager))
+    }
+
+    /// Returns the cached preview if its key matches, otherwise renders and caches it
+    fn get_or_insert<F: FnOnce() -> Result<String, RenderingError>>(&self, preview: (uuid::Uuid, Option<String>), key: String, render: F) -> Result<Arc<String>, RenderingError>{
+        if self.capacity == 0{
+            return render().map(Arc::new);
+        }
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.clock += 1;
+            let clock = state.clock;
+            if let Some(entry) = state.entries.get_mut(&preview){
+                if entry.key == key{
+                    entry.last_used = clock;
+                    return Ok(entry.html.clone());
+                }
+            }
+        }
+
+        // Render without holding the lock, other previews may use the cache meanwhile
+        let html = Arc::new(render()?);
+
+        let mut state = self.state.lock().unwrap();
+        if state.entries.len() >= self.capacity && !state.entries.contains_key(&preview){
+            let oldest = state.entries.iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(preview, _)| preview.clone());
+            if let Some(oldest) = oldest{
+                state.entries.remove(&oldest);
+            }
+        }
+        let last_used = state.clock;
+        state.entries.insert(preview, PreviewEntry{ key, html: html.clone(), last_used });
+        Ok(html)
+    }
+}
+
+/// Runs prepare_project and the handlebars templates, returns the html
+pub fn render_preview(project: ProjectDataV2, section: Option<String>, rendering_manager: &RenderingManager) -> Result<String, RenderingError>{
+    let template_id = project.template_id;
+    let options = RenderingOptions{
+        scope: match &section{
+            Some(path) => RenderingScope::Sections { paths: vec![path.clone()] },
+            None => RenderingScope::Project,
+        },
+        ..Default::default()
+    };
+    let prepared_project = prepare_project(project, rendering_manager.data_storage.clone(), rendering_manager.csl_data.clone(), &rendering_manager.settings, &options, &rendering_manager.section_cache, &CancellationToken::default())?;
+
+    let missing_values = Arc::new(Mutex::new(Vec::new()));
+    let handlebars = load_templates(&rendering_manager.settings, template_id, missing_values)?;
+
+    let res = match prepared_project.data.first(){
+        Some(prepared_section) if section.is_some() && handlebars.has_template("section") => {
+            handlebars.render("section", &serde_json::json!({"section": prepared_section, "project": &prepared_project}))
+        }
+        _ => handlebars.render("main", &prepared_project),
+    };
+    res.map_err(|e| {
+        eprintln!("Couldn't render preview: {}", e);
+        RenderingError::IoError(e.to_string())
+    })
+}
+
+/// Hash of everything the preview depends on
+fn preview_key(project: &ProjectDataV2, section: &Option<String>, data_storage: &DataStorage, data_path: &str) -> String{
+    // last_interaction changes on every access of the project, so only the rendered fields are part of the key.
+    // Maps are sorted by serde_json::Value, so the key doesn't depend on the order of the bibliography.
+    let content = serde_json::json!({
+        "name": project.name,
+        "description": project.description,
+        "template_id": project.template_id,
+        "metadata": project.metadata,
+        "settings": project.settings,
+        "sections": project.sections,
+        "bibliography": project.bibliography,
+    });
+    let mut hasher = Sha256::new();
+    hasher.update(content.to_string().as_bytes());
+    hasher.update(section.as_deref().unwrap_or_default().as_bytes());
+
+    let mut persons = vec![];
+    if let Some(metadata) = &project.metadata{
+        persons.extend(metadata.authors.iter().flatten());
+        persons.extend(metadata.editors.iter().flatten());
+    }
+    for section in project.sections.iter(){
+        if let SectionOrToc::Section(section) = section{
+            collect_persons(section, &mut persons);
+        }
+    }
+    for person_id in persons{
+        if let Some(person) = data_storage.get_person(&person_id){
+            hasher.update(serde_json::to_string(&*person.read().unwrap()).unwrap_or_default().as_bytes());
+        }
+    }
+
+    // Template files are edited in place, their modification times cover changes
+    let mut files = vec![];
+    collect_file_times(&Path::new(data_path).join("templates").join(project.template_id.to_string()), &mut files);
+    files.sort();
+    for file in files{
+        hasher.update(file.as_bytes());
+    }
+
+    hex::encode(hasher.finalize())
+}
+
+fn collect_file_times(dir: &Path, files: &mut Vec<String>){
+    let entries = match fs::read_dir(dir){
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten(){
+        let path = entry.path();
+        if path.is_dir(){
+            collect_file_times(&path, files);
+        }else if let Ok(metadata) = entry.metadata(){
+            let modified = metadata.modified().ok()
+                .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|modified| modified.as_nanos())
+                .unwrap_or_default();
+            files.push(format!("{}:{}:{}", path.to_string_lossy(), metadata.len(), modified));
+        }
+    }
+}
+
+pub mod api{
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use rocket::fs::NamedFile;
+    use rocket::http::{ContentType, Status};
+    use rocket::State;
+    use crate::data_storage::ProjectStorage;
+    use crate::export::html_preview::HtmlPreviewCache;
+    use crate::export::rendering_manager::RenderingManager;
+    use crate::export::scope::RenderingScope;
+    use crate::projects::api::ApiError;
+    use crate::session::session_guard::StaffSession;
+    use crate::settings::Settings;
+
+    fn error_status(e: ApiError) -> Status{
+        match e{
+            ApiError::NotFound => Status::NotFound,
+            ApiError::BadRequest(_) => Status::BadRequest,
+            ApiError::Unauthorized => Status::Unauthorized,
+            _ => Status::InternalServerError,
+        }
+    }
+
+    /// GET /api/projects/<project_id>/preview/index.html?<section>
+    /// HTML preview of the project with its template, optionally only of the section with the content path `section`
+    #[get("/api/projects/<project_id>/preview/index.html?<section>")]
+    pub async fn html_preview(project_id: String, section: Option<String>, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, rendering_manager: &State<Arc<RenderingManager>>, preview_cache: &State<Arc<HtmlPreviewCache>>) -> Result<(ContentType, String), (Status, String)>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return Err((Status::NotFound, "Project not found".to_string()));
+            },
+        };
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.read().unwrap().clone(),
+            Err(_) => return Err((Status::NotFound, "Project not found".to_string())),
+        };
+
+        if let Some(section) = &section{
+            let scope = RenderingScope::Sections { paths: vec![section.clone()] };
+            if let Err(e) = scope.validate(&project.sections){
+                return Err((error_status(e), "Section not found".to_string()));
+            }
+        }
+
+        // Preparing and rendering blocks, like a rendering
+        let rendering_manager = Arc::clone(rendering_manager);
+        let preview_cache = Arc::clone(preview_cache);
+        let result = rocket::tokio::task::spawn_blocking(move || preview_cache.get_or_render(project, project_id, section, &rendering_manager)).await;
+
+        match result{
+            Ok(Ok(html)) => Ok((ContentType::HTML, html.to_string())),
+            Ok(Err(e)) => Err((Status::InternalServerError, e.to_string())),
+            Err(e) => {
+                eprintln!("Preview of project {} crashed: {}", project_id, e);
+                Err((Status::InternalServerError, "Preview failed".to_string()))
+            }
+        }
+    }
+
+    /// GET /api/projects/<project_id>/preview/<file..>
+    /// Template assets and project uploads referenced by the html preview, uploads take precedence like in renderings
+    #[get("/api/projects/<project_id>/preview/<file..>", rank = 2)]
+    pub async fn preview_asset(project_id: String, file: PathBuf, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Result<NamedFile, Status>{
+        let project_id = uuid::Uuid::parse_str(&project_id).map_err(|_| Status::NotFound)?;
+        let template_id = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.read().unwrap().template_id,
+            Err(_) => return Err(Status::NotFound),
+        };
+
+        let upload = PathBuf::from(format!("{}/projects/{}/uploads", settings.data_path, project_id)).join(&file);
+        if let Ok(upload) = NamedFile::open(upload).await{
+            return Ok(upload);
+        }
+        let asset = PathBuf::from(format!("{}/templates/{}/output", settings.data_path, template_id)).join(&file);
+        NamedFile::open(asset).await.map_err(|_| Status::NotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use crate::data_storage::ProjectStorage;
+    use crate::settings::Settings;
+    use super::*;
+
+    #[rocket::tokio::test]
+    async fn test_preview_is_cached_between_requests(){
+        let mut settings = Settings::new().unwrap();
+        settings.data_path = std::env::temp_dir().join(format!("html_preview_{}", uuid::Uuid::new_v4())).to_string_lossy().to_string();
+        fs::create_dir_all(format!("{}/projects", settings.data_path)).unwrap();
+        let project_storage = ProjectStorage::new();
+        let data_storage = DataStorage::new();
+        let project_id = project_storage.insert_project(ProjectDataV2{
+            name: "Preview".to_string(),
+            description: None,
+            template_id: uuid::Uuid::new_v4(),
+            last_interaction: 0,
+            metadata: None,
+            settings: None,
+            sections: vec![],
+            bibliography: HashMap::new(),
+            version: 0,
+            comments: vec![],
+            suggestions: vec![],
+            workflow: Default::default(),
+        }, &settings).await.unwrap();
+
+        let cache = HtmlPreviewCache::new(2);
+        let renders = AtomicUsize::new(0);
+        for _ in 0..2{
+            let project = project_storage.get_project(&project_id, &settings).await.unwrap().read().unwrap().clone();
+            let key = preview_key(&project, &None, &data_storage, &settings.data_path);
+            cache.get_or_insert((project_id, None), key, || {
+                renders.fetch_add(1, Ordering::SeqCst);
+                Ok("<html></html>".to_string())
+            }).unwrap();
+            // get_project updates last_interaction, which is stored in seconds
+            rocket::tokio::time::sleep(Duration::from_millis(1100)).await;
+        }
+        assert_eq!(renders.load(Ordering::SeqCst), 1);
+
+        let _ = fs::remove_dir_all(&settings.data_path);
+    }
+}
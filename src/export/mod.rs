@@ -1,14 +1,29 @@
 use serde::{Deserialize, Serialize};
+use crate::export::proof::PreparedProof;
+use crate::export::scope::RenderingScope;
 use crate::projects::{BlockType, Identifier, Keyword, Language, License, Person, ProjectSettings};
 
 pub mod preprocessing;
 pub mod rendering_manager;
+pub mod process;
+pub mod renderers;
+pub mod section_cache;
+pub mod scope;
+pub mod html_preview;
+pub mod proof;
+pub mod page_map;
+pub mod history;
+pub mod rendering_log;
 pub mod download;
 
 #[derive(Serialize, Deserialize)]
 pub struct PreparedProject{
     pub metadata: PreparedMetadata,
     pub settings: Option<ProjectSettings>,
+    /// Part of the book which is rendered, e.g. templates can skip the title pages for chapter previews
+    pub scope: RenderingScope,
+    /// Watermark, proof stamp and line numbers, see [proof]
+    pub proof: PreparedProof,
     pub data: Vec<PreparedSection>,
 }
 
@@ -89,7 +104,7 @@ pub struct TocEntry{
     pub children: Vec<TocEntry>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PreparedSection{
     pub id: uuid::Uuid,
     pub sub_sections: Vec<PreparedSection>,
@@ -99,14 +114,14 @@ pub struct PreparedSection{
     pub endnotes: Vec<PreparedEndnote>
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PreparedEndnote{
     pub num: usize,
     pub id: uuid::Uuid,
     pub content: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PreparedSectionMetadata{
     pub title: String,
     pub subtitle: Option<String>,
@@ -118,15 +133,19 @@ pub struct PreparedSectionMetadata{
     pub lang: PreparedLanguage,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PreparedLanguage{
     pub de: bool,
     pub en: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct PreparedContentBlock{
     pub id: String,
     pub block_type: BlockType,
     pub html: String,
+    /// Title of a box or attribution of an epigraph
+    pub caption: Option<String>,
+    /// Rendered nested blocks of container blocks (box, epigraph, abstract)
+    pub children: Vec<PreparedContentBlock>,
 }
\ No newline at end of file
@@ -0,0 +1,325 @@
+//! Page count and start pages of the sections of a rendered PDF.
+//!
+//! After the PDF build, the output is parsed to find the page on which each section starts, for the table of
+//! contents on the web, ONIX and the first and last page of chapters registered with Crossref.
+//! Start pages are taken from named destinations containing the id of the section (the engines create them for
+//! elements with an id which are link targets, e.g. `<section id="{{id}}">` with an entry in the ToC), otherwise from
+//! the PDF outline, whose entries are matched with the section titles.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use bincode::{Decode, Encode};
+use lopdf::{Document, Object, ObjectId};
+use serde::{Deserialize, Serialize};
+use crate::export::PreparedSection;
+
+/// Upper limit of visited outline items and name tree nodes, guards against cycles in broken files
+const MAX_NODES: usize = 100_000;
+
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, PartialEq)]
+pub struct PageMap{
+    pub page_count: u32,
+    /// Sections whose start page was found, in book order
+    pub sections: Vec<SectionPage>,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, PartialEq)]
+pub struct SectionPage{
+    #[bincode(with_serde)]
+    pub section_id: uuid::Uuid,
+    pub title: String,
+    /// 0 for top level sections
+    pub level: u32,
+    pub first_page: u32,
+    /// Page before the next section on the same or a higher level starts, the last page of the book for the last one
+    pub last_page: u32,
+}
+
+/// Section of the rendered project, as it's searched for in the PDF
+#[derive(Clone, Debug)]
+pub struct SectionOutline{
+    pub section_id: uuid::Uuid,
+    pub title: String,
+    pub level: u32,
+}
+
+/// Lists the sections of a prepared project with their sub sections, in book order
+pub fn section_outline(sections: &[PreparedSection]) -> Vec<SectionOutline>{
+    let mut outline = vec![];
+    collect_outline(sections, 0, &mut outline);
+    outline
+}
+
+fn collect_outline(sections: &[PreparedSection], level: u32, outline: &mut Vec<SectionOutline>){
+    for section in sections{
+        outline.push(SectionOutline{
+            section_id: section.id,
+            title: section.metadata.title.clone(),
+            level,
+        });
+        collect_outline(&section.sub_sections, level + 1, outline);
+    }
+}
+
+/// Parses the PDF and determines the page count and the start pages of the sections
+pub fn read_page_map(pdf: &Path, sections: &[SectionOutline]) -> Result<PageMap, String>{
+    let document = Document::load(pdf).map_err(|e| format!("Couldn't parse {}: {}", pdf.to_string_lossy(), e))?;
+    let page_numbers: HashMap<ObjectId, u32> = document.get_pages().into_iter().map(|(number, id)| (id, number)).collect();
+
+    let destinations = named_destinations(&document, &page_numbers);
+    let outline = outline_entries(&document, &page_numbers, &destinations);
+    Ok(build_page_map(page_numbers.len() as u32, sections, &destinations, &outline))
+}
+
+/// Matches the sections with the destinations and outline entries found in the PDF
+fn build_page_map(page_count: u32, sections: &[SectionOutline], destinations: &HashMap<String, u32>, outline: &[(String, u32)]) -> PageMap{
+    let mut used_outline = vec![false; outline.len()];
+    let mut found: Vec<(&SectionOutline, u32)> = vec![];
+    for section in sections{
+        let id = section.section_id.to_string();
+        let by_destination = destinations.iter()
+            .filter(|(name, _)| name.contains(&id))
+            .map(|(_, page)| *page)
+            .min();
+        let page = by_destination.or_else(|| {
+            let title = normalize_title(&section.title);
+            let index = outline.iter().enumerate()
+                .position(|(index, (entry, _))| !used_outline[index] && normalize_title(entry) == title)?;
+            used_outline[index] = true;
+            Some(outline[index].1)
+        });
+        if let Some(page) = page{
+            found.push((section, page));
+        }
+    }
+
+    let sections = found.iter().enumerate().map(|(index, (section, first_page))| {
+        let next = found[index + 1..].iter().find(|(next, _)| next.level <= section.level).map(|(_, page)| *page);
+        SectionPage{
+            section_id: section.section_id,
+            title: normalize_whitespace(&strip_soft_hyphens(&section.title)),
+            level: section.level,
+            first_page: *first_page,
+            last_page: match next{
+                Some(next) => next.saturating_sub(1).max(*first_page),
+                None => page_count.max(*first_page),
+            },
+        }
+    }).collect();
+
+    PageMap{
+        page_count,
+        sections,
+    }
+}
+
+fn strip_soft_hyphens(title: &str) -> String{
+    title.replace('\u{00ad}', "").replace("&shy;", "")
+}
+
+fn normalize_whitespace(title: &str) -> String{
+    title.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Title without soft hyphens, markup and case, outline entries are plain text
+fn normalize_title(title: &str) -> String{
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in strip_soft_hyphens(title).chars(){
+        match c{
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    normalize_whitespace(&text).to_lowercase()
+}
+
+/// Follows references, returns the object itself otherwise
+fn resolve<'a>(document: &'a Document, object: &'a Object) -> &'a Object{
+    match object{
+        Object::Reference(id) => document.get_object(*id).unwrap_or(object),
+        _ => object,
+    }
+}
+
+fn catalog(document: &Document) -> Option<&lopdf::Dictionary>{
+    match resolve(document, document.trailer.get(b"Root").ok()?){
+        Object::Dictionary(catalog) => Some(catalog),
+        _ => None,
+    }
+}
+
+fn dictionary_entry<'a>(document: &'a Document, dictionary: &'a lopdf::Dictionary, key: &[u8]) -> Option<&'a Object>{
+    dictionary.get(key).ok().map(|object| resolve(document, object))
+}
+
+/// Text strings are UTF-16BE with a byte order mark or PDFDocEncoding, which matches Latin-1 for letters
+fn decode_text(bytes: &[u8]) -> String{
+    if bytes.starts_with(&[0xFE, 0xFF]){
+        let units: Vec<u16> = bytes[2..].chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+        String::from_utf16_lossy(&units)
+    }else{
+        bytes.iter().map(|byte| *byte as char).collect()
+    }
+}
+
+/// Page of an explicit destination (`[page /XYZ left top zoom]`) or a destination dictionary (`<< /D [...] >>`)
+fn destination_page(document: &Document, destination: &Object, page_numbers: &HashMap<ObjectId, u32>) -> Option<u32>{
+    match resolve(document, destination){
+        Object::Array(destination) => match destination.first()?{
+            Object::Reference(page) => page_numbers.get(page).copied(),
+            _ => None,
+        },
+        Object::Dictionary(destination) => destination_page(document, destination.get(b"D").ok()?, page_numbers),
+        _ => None,
+    }
+}
+
+/// Named destinations with their page, from the name tree of the catalog (PDF 1.2) and the older `/Dests` dictionary
+fn named_destinations(document: &Document, page_numbers: &HashMap<ObjectId, u32>) -> HashMap<String, u32>{
+    let mut destinations = HashMap::new();
+    let catalog = match catalog(document){
+        Some(catalog) => catalog,
+        None => return destinations,
+    };
+
+    if let Some(Object::Dictionary(dests)) = dictionary_entry(document, catalog, b"Dests"){
+        for (name, destination) in dests.iter(){
+            if let Some(page) = destination_page(document, destination, page_numbers){
+                destinations.insert(decode_text(name), page);
+            }
+        }
+    }
+
+    if let Some(Object::Dictionary(names)) = dictionary_entry(document, catalog, b"Names"){
+        if let Some(Object::Dictionary(root)) = dictionary_entry(document, names, b"Dests"){
+            let mut nodes = vec![root];
+            let mut visited = 0;
+            while let Some(node) = nodes.pop(){
+                visited += 1;
+                if visited > MAX_NODES{
+                    break;
+                }
+                if let Some(Object::Array(entries)) = dictionary_entry(document, node, b"Names"){
+                    for pair in entries.chunks_exact(2){
+                        let name = match resolve(document, &pair[0]){
+                            Object::String(name, _) => decode_text(name),
+                            _ => continue,
+                        };
+                        if let Some(page) = destination_page(document, &pair[1], page_numbers){
+                            destinations.insert(name, page);
+                        }
+                    }
+                }
+                if let Some(Object::Array(kids)) = dictionary_entry(document, node, b"Kids"){
+                    for kid in kids{
+                        if let Object::Dictionary(kid) = resolve(document, kid){
+                            nodes.push(kid);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    destinations
+}
+
+/// Titles and pages of the outline (bookmarks) in document order
+fn outline_entries(document: &Document, page_numbers: &HashMap<ObjectId, u32>, destinations: &HashMap<String, u32>) -> Vec<(String, u32)>{
+    let mut entries = vec![];
+    let outlines = match catalog(document).and_then(|catalog| dictionary_entry(document, catalog, b"Outlines")){
+        Some(Object::Dictionary(outlines)) => outlines,
+        _ => return entries,
+    };
+
+    // Depth first, the next sibling is visited after the children
+    let mut visited = HashSet::new();
+    let mut stack: Vec<ObjectId> = vec![];
+    if let Ok(Object::Reference(first)) = outlines.get(b"First"){
+        stack.push(*first);
+    }
+    while let Some(id) = stack.pop(){
+        if !visited.insert(id) || visited.len() > MAX_NODES{
+            continue;
+        }
+        let item = match document.get_object(id){
+            Ok(Object::Dictionary(item)) => item,
+            _ => continue,
+        };
+
+        let title = match dictionary_entry(document, item, b"Title"){
+            Some(Object::String(title, _)) => decode_text(title),
+            _ => String::new(),
+        };
+        let page = item_page(document, item, page_numbers, destinations);
+        if let Some(page) = page{
+            entries.push((title, page));
+        }
+
+        if let Ok(Object::Reference(next)) = item.get(b"Next"){
+            stack.push(*next);
+        }
+        if let Ok(Object::Reference(first)) = item.get(b"First"){
+            stack.push(*first);
+        }
+    }
+    entries
+}
+
+/// Page of an outline item, its destination is either given directly or as GoTo action
+fn item_page(document: &Document, item: &lopdf::Dictionary, page_numbers: &HashMap<ObjectId, u32>, destinations: &HashMap<String, u32>) -> Option<u32>{
+    let destination = match dictionary_entry(document, item, b"Dest"){
+        Some(destination) => destination,
+        None => match dictionary_entry(document, item, b"A"){
+            Some(Object::Dictionary(action)) => dictionary_entry(document, action, b"D")?,
+            _ => return None,
+        },
+    };
+    match destination{
+        Object::String(name, _) => destinations.get(&decode_text(name)).copied(),
+        Object::Name(name) => destinations.get(&decode_text(name)).copied(),
+        destination => destination_page(document, destination, page_numbers),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn section(title: &str, level: u32) -> SectionOutline{
+        SectionOutline{
+            section_id: uuid::Uuid::new_v4(),
+            title: title.to_string(),
+            level,
+        }
+    }
+
+    #[test]
+    fn test_build_page_map(){
+        let intro = section("Intro\u{00ad}duction", 0);
+        let first = section("Chapter 1", 0);
+        let sub = section("Chapter <em>1.1</em>", 1);
+        let missing = section("Not in the PDF", 0);
+        let last = section("Chapter 2", 0);
+        let sections = vec![intro.clone(), first.clone(), sub.clone(), missing, last.clone()];
+
+        let destinations = HashMap::from([(format!("section-{}", first.section_id), 5), (last.section_id.to_string(), 12)]);
+        let outline = vec![("Introduction".to_string(), 3), ("Chapter 1".to_string(), 5), ("chapter  1.1".to_string(), 8)];
+        let page_map = build_page_map(20, &sections, &destinations, &outline);
+
+        assert_eq!(page_map.page_count, 20);
+        let pages: Vec<(&str, u32, u32)> = page_map.sections.iter().map(|section| (section.title.as_str(), section.first_page, section.last_page)).collect();
+        assert_eq!(pages, vec![("Introduction", 3, 4), ("Chapter 1", 5, 11), ("Chapter <em>1.1</em>", 8, 11), ("Chapter 2", 12, 20)]);
+        assert_eq!(page_map.sections[1].section_id, first.section_id);
+        assert_eq!(page_map.sections[2].section_id, sub.section_id);
+    }
+
+    #[test]
+    fn test_decode_text(){
+        assert_eq!(decode_text(&[0xFE, 0xFF, 0x00, 0x4B, 0x00, 0xFC]), "Kü");
+        assert_eq!(decode_text(b"Intro"), "Intro");
+    }
+}
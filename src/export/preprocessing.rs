@@ -2,91 +2,111 @@ use std::collections::HashMap;
 use std::fs;
 use std::io::Cursor;
 use std::path::Path;
-use std::process::Command;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, OnceLock};
 use handlebars::{Context, DirectorySourceOptions, Handlebars, Helper, HelperResult, JsonRender, Output, RenderContext, RenderError, RenderErrorReason};
 use hyphenation::{Hyphenator, Load, Standard};
 use image::{ImageOutputFormat, Luma};
 use regex::Regex;
 use rocket::form::validate::Contains;
 use qrcode::QrCode;
+use syntect::html::{ClassedHTMLGenerator, ClassStyle};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
 use base64::prelude::*;
 use hayagriva::{BibliographyDriver, BibliographyRequest, BufWriteFormat, CitationItem, CitationRequest};
 use hayagriva::citationberg::{LocaleCode};
 use crate::data_storage::{DataStorage, ProjectDataV2};
 use crate::export::{PreparedContentBlock, PreparedEndnote, PreparedLanguage, PreparedLicense, PreparedMetadata, PreparedProject, PreparedSection, PreparedSectionMetadata};
-use crate::export::rendering_manager::RenderingError;
-use crate::projects::{BlockData, Language, NewContentBlock, Section, SectionOrToc};
+use crate::export::process::CancellationToken;
+use crate::export::rendering_log::{RenderingLog, RenderingStage};
+use crate::export::rendering_manager::{RenderingError, RenderingOptions};
+use crate::export::proof::{inject_proof, PreparedProof};
+use crate::export::scope::mark_out_of_scope_links;
+use crate::export::section_cache::PreparedSectionCache;
+use crate::projects::{find_content_block, BlockData, Language, NewContentBlock, Section, SectionOrToc};
+use crate::projects::comments::{build_threads, CommentThread};
+use crate::projects::suggestions::{mark_suggestions, AttributedSuggestion};
 use crate::settings::Settings;
 use crate::utils::csl::CslData;
+use crate::utils::html_sanitizer::HtmlSanitizer;
+
+pub fn render_project(prepared_project: PreparedProject, project_id: uuid::Uuid, template_id: uuid::Uuid, temp_dir: &Path, settings: &Settings, token: &CancellationToken, log: &RenderingLog) -> Result<(), RenderingError>{
+    // Template variables which couldn't be resolved, collected while rendering
+    let missing_values: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
 
-pub fn render_project(prepared_project: PreparedProject, project_id: uuid::Uuid, template_id: uuid::Uuid, temp_dir: &Path, settings: &Settings) -> Result<(), RenderingError>{
     // Load templates
-    let mut handlebars = Handlebars::new();
-    match handlebars.register_templates_directory(Path::new(&format!("{}/templates/{}/templates", settings.data_path, template_id)), DirectorySourceOptions::default()){
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("Couldn't load templates for export: {}", e);
-            return Err(RenderingError::ErrorLoadingTemplate(e.to_string()));
+    let handlebars = log.stage(RenderingStage::TemplateLoad, |stage| {
+        let handlebars = load_templates(settings, template_id, missing_values.clone())?;
+        if !handlebars.has_template("main"){
+            stage.warn("No main template found".to_string());
+        }
+        stage.stdout = format!("Loaded templates: {}", handlebars.get_templates().keys().cloned().collect::<Vec<String>>().join(", "));
+        Ok(handlebars)
+    })?;
+
+    log.stage(RenderingStage::AssetCopy, |stage| {
+        // Copy output folder contents to working folder
+        if let Err(e) =  crate::utils::fs_copy_recursive::copy_dir_all(format!("{}/templates/{}/output", settings.data_path, template_id), temp_dir){
+            eprintln!("Couldn't copy template to output directory: {}", e);
+            return Err(RenderingError::ErrorCopyingTemplate(e.to_string()));
         }
-    }
-
-    // Add custom handler for qr codes
-    handlebars.register_helper("qrcode", Box::new(handlebars_qrcode_helper));
-
-    // Copy output folder contents to working folder
-    if let Err(e) =  crate::utils::fs_copy_recursive::copy_dir_all(format!("{}/templates/{}/output", settings.data_path, template_id), temp_dir){
-        eprintln!("Couldn't copy template to output directory: {}", e);
-        return Err(RenderingError::ErrorCopyingTemplate(e.to_string()));
-    }
 
-    // Copy uploads to working folder
-    if let Err(e) =  crate::utils::fs_copy_recursive::copy_dir_all(format!("{}/projects/{}/uploads", settings.data_path, project_id), temp_dir){
-        if e.kind() != std::io::ErrorKind::NotFound { // No uploads folder found, that's okay
-            eprintln!("Couldn't copy uploads to output directory: {}", e);
-            return Err(RenderingError::ErrorCopyingUploads(e.to_string()));
+        // Copy uploads to working folder
+        if let Err(e) =  crate::utils::fs_copy_recursive::copy_dir_all(format!("{}/projects/{}/uploads", settings.data_path, project_id), temp_dir){
+            if e.kind() != std::io::ErrorKind::NotFound { // No uploads folder found, that's okay
+                eprintln!("Couldn't copy uploads to output directory: {}", e);
+                return Err(RenderingError::ErrorCopyingUploads(e.to_string()));
+            }
+            stage.stdout = "Project has no uploads".to_string();
         }
-    }
+        Ok(())
+    })?;
 
-    let res = match handlebars.render("main", &prepared_project){
-        Ok(res) => res,
-        Err(e) => {
-            eprintln!("Couldn't render template: {}", e);
+    log.stage(RenderingStage::HtmlRender, |stage| {
+        let res = handlebars.render("main", &prepared_project);
+        for warning in missing_values.lock().unwrap().drain(..){
+            stage.warn(warning);
+        }
+        let res = match res{
+            Ok(res) => inject_proof(res, &prepared_project.proof),
+            Err(e) => {
+                eprintln!("Couldn't render template: {}", e);
+                return Err(RenderingError::IoError(e.to_string()));
+            }
+        };
+        if let Err(e) =  fs::write(temp_dir.join("index.html"), res){
+            eprintln!("Couldn't write rendered template to file: {}", e);
             return Err(RenderingError::IoError(e.to_string()));
         }
-    };
-    if let Err(e) =  fs::write(temp_dir.join("index.html"), res){
-        eprintln!("Couldn't write rendered template to file: {}", e);
-        return Err(RenderingError::IoError(e.to_string()));
-    }
+        Ok(())
+    })?;
 
-    let mut args = vec!["build", "index.html", "-o", "output.pdf"];
+    token.check()?;
 
-    let path = settings.chromium_path.clone();
-    let path_str = path.as_deref();
-    if let Some(path_str) = path_str {
-        args.push("--executable-browser");
-        args.push(path_str);
-        args.push("--timeout");
-        args.push("480000");
-    }
+    let renderer = settings.pdf_renderer.renderer();
+    log.stage(RenderingStage::PdfBuild, |stage| renderer.render(temp_dir, settings, token, stage))
+}
 
-    let output =  Command::new("vivliostyle").current_dir(temp_dir).args(args).output();
-    match output{
-        Ok(out) => {
-            if out.status.success(){
-                Ok(())
-            }else{
-                let out = String::from_utf8_lossy(&out.stderr);
-                eprintln!("Export failed: {}", out);
-                Err(RenderingError::VivliostyleError(out.to_string()))
-            }
-        }
-        Err(e) => {
-            println!("Couldn't run vivliostyle: {}", e);
-            Err(RenderingError::VivliostyleError(e.to_string()))
-        }
+/// Loads the handlebars templates of a template with the helpers available to them
+///
+/// Template variables which can't be resolved are rendered as empty string and collected in `missing_values`.
+pub fn load_templates(settings: &Settings, template_id: uuid::Uuid, missing_values: Arc<Mutex<Vec<String>>>) -> Result<Handlebars<'static>, RenderingError>{
+    let mut handlebars = Handlebars::new();
+    if let Err(e) = handlebars.register_templates_directory(Path::new(&format!("{}/templates/{}/templates", settings.data_path, template_id)), DirectorySourceOptions::default()){
+        eprintln!("Couldn't load templates for export: {}", e);
+        return Err(RenderingError::ErrorLoadingTemplate(e.to_string()));
     }
+
+    // Add custom handler for qr codes
+    handlebars.register_helper("qrcode", Box::new(handlebars_qrcode_helper));
+
+    // Called by handlebars for values which are missing in the data, they're rendered as empty string
+    handlebars.register_helper("helperMissing", Box::new(move |h: &Helper, _: &Handlebars, _: &Context, rc: &mut RenderContext, _: &mut dyn Output| -> HelperResult {
+        let template = rc.get_current_template_name().cloned().unwrap_or_default();
+        missing_values.lock().unwrap().push(format!("Missing value or helper \"{}\" in template {}", h.name(), template));
+        Ok(())
+    }));
+    Ok(handlebars)
 }
 
 fn handlebars_qrcode_helper(h: &Helper, _: &Handlebars, _: &Context, _rc: &mut RenderContext, out: &mut dyn Output) -> HelperResult{
@@ -118,8 +138,31 @@ fn handlebars_qrcode_helper(h: &Helper, _: &Handlebars, _: &Context, _rc: &mut R
     Ok(())
 }
 
-pub fn prepare_project(project_data: ProjectDataV2, data_storage: Arc<DataStorage>, csl_data: Arc<CslData>) -> Result<PreparedProject, RenderingError>{
+pub fn prepare_project(project_data: ProjectDataV2, data_storage: Arc<DataStorage>, csl_data: Arc<CslData>, settings: &Settings, options: &RenderingOptions, cache: &PreparedSectionCache, token: &CancellationToken) -> Result<PreparedProject, RenderingError>{
+    let proof = PreparedProof::new(options, &project_data);
     let citation_bib = render_citations(&project_data, csl_data);
+    token.check()?;
+    let sanitizer = HtmlSanitizer::new(&settings.html_sanitizer);
+
+    // Comments are only part of review exports
+    let comments: Vec<CommentThread> = if options.review{
+        build_threads(&project_data.comments).into_iter().filter(|thread| !thread.comment.resolved).collect()
+    }else{
+        vec![]
+    };
+
+    // Pending suggestions are only marked in exports with tracked changes
+    let suggestions: Vec<AttributedSuggestion> = if options.show_changes{
+        project_data.suggestions.iter().map(|suggestion| {
+            let author_name = match data_storage.get_user_by_id(&suggestion.author){
+                Some(user) => user.read().unwrap().name.clone(),
+                None => String::new(),
+            };
+            AttributedSuggestion{ suggestion: suggestion.clone(), author_name }
+        }).collect()
+    }else{
+        vec![]
+    };
 
     let metadata = match project_data.metadata{
         Some(metadata) => metadata,
@@ -161,15 +204,31 @@ pub fn prepare_project(project_data: ProjectDataV2, data_storage: Arc<DataStorag
         None
     };
 
-    let mut data = vec![];
-    for section in project_data.sections{
+    // Authors and editors of the book include those of sections outside the rendering scope
+    for section in project_data.sections.iter(){
         if let SectionOrToc::Section(section) = section{
-            data.push(render_section(section, data_storage.clone(), &citation_bib))
+            add_remaining_authors_editors_from_section(section, &data_storage, &mut authors, &mut editors);
         }
     }
 
-    for section in data.iter() {
-        add_remaining_authors_editors_from_section(section,&mut authors, &mut editors);
+    // Review exports depend on comments and suggestions, their sections are always prepared from scratch
+    let use_cache = !options.review && !options.show_changes;
+
+    let (sections, outside_scope) = options.scope.select(project_data.sections);
+    let mut data = vec![];
+    for section in sections{
+        // Big volumes take a while to prepare, so cancellation and the timeout are checked per section
+        token.check()?;
+        let mut prepared = if use_cache{
+            let key = PreparedSectionCache::key(&section, &project_data.template_id, &data_storage, &citation_bib);
+            cache.get_or_prepare(key, || render_section(section, data_storage.clone(), &citation_bib, &sanitizer, &comments, &suggestions))
+        }else{
+            render_section(section, data_storage.clone(), &citation_bib, &sanitizer, &comments, &suggestions)
+        };
+        if !outside_scope.is_empty(){
+            mark_out_of_scope_links(&mut prepared, &outside_scope);
+        }
+        data.push(prepared);
     }
 
     // Sort authors and editors by last name
@@ -200,27 +259,35 @@ pub fn prepare_project(project_data: ProjectDataV2, data_storage: Arc<DataStorag
     Ok(PreparedProject{
         metadata,
         settings: project_data.settings,
+        scope: options.scope.clone(),
+        proof,
         data,
     })
 }
 
-fn add_remaining_authors_editors_from_section(section: &PreparedSection, authors: &mut Vec<crate::projects::Person>, editors: &mut Vec<crate::projects::Person>){
+fn add_remaining_authors_editors_from_section(section: &Section, data_storage: &DataStorage, authors: &mut Vec<crate::projects::Person>, editors: &mut Vec<crate::projects::Person>){
     for author in section.metadata.authors.iter(){
-        if !authors.contains(author){
-            authors.push(author.clone());
+        if let Some(author) = data_storage.get_person(author){
+            let author = author.read().unwrap().clone();
+            if !authors.contains(&author){
+                authors.push(author);
+            }
         }
     }
     for editor in section.metadata.editors.iter(){
-        if !editors.contains(editor){
-            editors.push(editor.clone());
+        if let Some(editor) = data_storage.get_person(editor){
+            let editor = editor.read().unwrap().clone();
+            if !editors.contains(&editor){
+                editors.push(editor);
+            }
         }
     }
     for sub_section in section.sub_sections.iter(){
-        add_remaining_authors_editors_from_section(sub_section, authors, editors);
+        add_remaining_authors_editors_from_section(sub_section, data_storage, authors, editors);
     }
 }
 
-pub fn render_section(section: Section, data_storage: Arc<DataStorage>, citation_bib: &HashMap<String, String>) -> PreparedSection{
+pub fn render_section(section: Section, data_storage: Arc<DataStorage>, citation_bib: &HashMap<String, String>, sanitizer: &HtmlSanitizer, comments: &[CommentThread], suggestions: &[AttributedSuggestion]) -> PreparedSection{
     let published = match section.metadata.published{
         Some(date) => Some(date.format("%d.%m.%Y").to_string()),
         None => None
@@ -292,13 +359,27 @@ pub fn render_section(section: Section, data_storage: Arc<DataStorage>, citation
     // Store all endnote contents for this section. They will be rendered at the end of the section based on their order in the storage
     let mut endnote_storage: Vec<(uuid::Uuid, String)> = vec![];
 
-    for content_block in section.children{
-        content.push(render_content_block(content_block, &mut endnote_storage, &dict, &citation_bib));
+    // Sanitize again, in case the allow-list changed since the content was stored
+    let mut children = section.children;
+    sanitizer.sanitize_content_blocks(&mut children);
+
+    for content_block in children{
+        // Comments are only passed for review exports, comments on nested blocks are shown after their container
+        let threads: Vec<&CommentThread> = comments.iter()
+            .filter(|thread| Some(thread.comment.section_id) == section.id && find_content_block(std::slice::from_ref(&content_block), &thread.comment.block_id).is_some())
+            .collect();
+
+        let mut prepared_block = render_content_block(content_block, &mut endnote_storage, &dict, &citation_bib, suggestions);
+        if !threads.is_empty(){
+            prepared_block.html.push_str(&render_comment_threads(&threads, &data_storage));
+        }
+
+        content.push(prepared_block);
     }
 
     let mut sub_sections = vec![];
     for sub_section in section.sub_sections{
-        sub_sections.push(render_section(sub_section, data_storage.clone(), &citation_bib));
+        sub_sections.push(render_section(sub_section, data_storage.clone(), &citation_bib, sanitizer, comments, suggestions));
     }
 
     let mut endnotes = vec![];
@@ -317,13 +398,20 @@ pub fn render_section(section: Section, data_storage: Arc<DataStorage>, citation
     }
 }
 
-pub fn render_content_block(block: NewContentBlock, endnote_storage: &mut Vec<(uuid::Uuid, String)>, dict: &Standard, citation_bib: &HashMap<String, String>) -> PreparedContentBlock{
+pub fn render_content_block(mut block: NewContentBlock, endnote_storage: &mut Vec<(uuid::Uuid, String)>, dict: &Standard, citation_bib: &HashMap<String, String>, suggestions: &[AttributedSuggestion]) -> PreparedContentBlock{
+    // Suggestions are only passed for exports with tracked changes
+    if !suggestions.is_empty(){
+        mark_suggestions(&mut block, suggestions);
+    }
     let css_classes_raw = block.css_classes.join(" ");
     let css_classes = if block.css_classes.len() > 0{
         format!(" class='{}'", block.css_classes.join(" "))
     }else{
         String::new()
     };
+    let block_type = block.data.block_type();
+    let mut caption = None;
+    let mut children = vec![];
     let data: String = match block.data{
         BlockData::Paragraph {text} => {
             format!("<p{}>{}</p>", css_classes, render_text(text, endnote_storage, dict, citation_bib))
@@ -352,22 +440,129 @@ pub fn render_content_block(block: NewContentBlock, endnote_storage: &mut Vec<(u
             // We use filename since all images are copied from te uploads directory to our temporary working dir and file.url represents the public url
             format!("<img src=\"{}\" alt=\"{}\" {}/>", file.filename, caption.unwrap_or_default(), css_classes)
         }
+        BlockData::Math { latex } => {
+            format!("<div class=\"math-block {}\">{}</div>", css_classes_raw, render_math(&latex, latex2mathml::DisplayStyle::Block))
+        }
+        BlockData::Code { code, language } => {
+            // Code is neither hyphenated nor run through render_text, whitespace has to stay untouched
+            match language{
+                Some(language) => format!("<pre class=\"code-block {}\" data-language=\"{}\"><code class=\"language-{}\">{}</code></pre>", css_classes_raw, escape_html(&language), escape_html(&language), highlight_code(&code, &language)),
+                None => format!("<pre class=\"code-block {}\"><code>{}</code></pre>", css_classes_raw, escape_html(&code))
+            }
+        }
+        BlockData::Box { title, children: nested } => {
+            let title = render_text(title, endnote_storage, dict, citation_bib);
+            children = render_nested_blocks(nested, endnote_storage, dict, citation_bib, suggestions);
+            let content: String = children.iter().map(|child| child.html.as_str()).collect();
+            let res = format!("<aside class=\"box {}\"><div class=\"box-title\">{}</div>{}</aside>", css_classes_raw, title, content);
+            caption = Some(title);
+            res
+        }
+        BlockData::Epigraph { attribution, children: nested } => {
+            let attribution = render_text(attribution, endnote_storage, dict, citation_bib);
+            children = render_nested_blocks(nested, endnote_storage, dict, citation_bib, suggestions);
+            let content: String = children.iter().map(|child| child.html.as_str()).collect();
+            let res = format!("<blockquote class=\"epigraph {}\">{}<footer class=\"epigraph-attribution\">{}</footer></blockquote>", css_classes_raw, content, attribution);
+            caption = Some(attribution);
+            res
+        }
+        BlockData::Abstract { children: nested } => {
+            children = render_nested_blocks(nested, endnote_storage, dict, citation_bib, suggestions);
+            let content: String = children.iter().map(|child| child.html.as_str()).collect();
+            format!("<section class=\"abstract {}\">{}</section>", css_classes_raw, content)
+        }
     };
     PreparedContentBlock{
         id: block.id,
-        block_type: block.block_type,
-        html: data
+        block_type,
+        html: data,
+        caption,
+        children,
     }
 }
 
-fn escape_html(text: &str) -> String{
+/// Renders review comment threads as an aside, which is placed after the commented block
+fn render_comment_threads(threads: &[&CommentThread], data_storage: &DataStorage) -> String{
+    let render_comment = |comment: &crate::projects::comments::Comment, class: &str| -> String{
+        let author = match data_storage.get_user_by_id(&comment.author){
+            Some(user) => user.read().unwrap().name.clone(),
+            None => "Unknown user".to_string(),
+        };
+        format!("<div class=\"{}\"><span class=\"review-comment-author\">{}</span> <span class=\"review-comment-date\">{}</span><p>{}</p></div>",
+                class, escape_html(&author), comment.created.format("%d.%m.%Y %H:%M"), escape_html(&comment.content))
+    };
+
+    let mut res = String::from("<aside class=\"review-comments\">");
+    for thread in threads{
+        res.push_str("<div class=\"review-comment-thread\">");
+        res.push_str(&render_comment(&thread.comment, "review-comment"));
+        for reply in thread.replies.iter(){
+            res.push_str(&render_comment(reply, "review-comment review-comment-reply"));
+        }
+        res.push_str("</div>");
+    }
+    res.push_str("</aside>");
+    res
+}
+
+/// Renders the nested blocks of a container block
+fn render_nested_blocks(blocks: Vec<NewContentBlock>, endnote_storage: &mut Vec<(uuid::Uuid, String)>, dict: &Standard, citation_bib: &HashMap<String, String>, suggestions: &[AttributedSuggestion]) -> Vec<PreparedContentBlock>{
+    blocks.into_iter().map(|block| render_content_block(block, endnote_storage, dict, citation_bib, suggestions)).collect()
+}
+
+pub(crate) fn escape_html(text: &str) -> String{
     text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;")
 }
-fn unescape_html(text: &str) -> String{
+pub(crate) fn unescape_html(text: &str) -> String{
     text.replace("&amp;", "&").replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"")
 }
 
+/// Renders a LaTeX formula to MathML
+///
+/// If the formula can't be parsed, the escaped LaTeX source is returned instead, so the export doesn't fail
+pub fn render_math(latex: &str, display: latex2mathml::DisplayStyle) -> String{
+    match latex2mathml::latex_to_mathml(latex, display){
+        Ok(mathml) => mathml,
+        Err(e) => {
+            eprintln!("Couldn't render formula '{}': {}", latex, e);
+            format!("<code class=\"math-error\">{}</code>", escape_html(latex))
+        }
+    }
+}
+
+/// Highlights code with syntect, using css classes prefixed with `hl-` instead of inline styles
+///
+/// Templates opt in to syntax highlighting by styling these classes.
+/// If the language is unknown, the escaped code is returned without highlighting
+pub fn highlight_code(code: &str, language: &str) -> String{
+    static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+    let syntax_set = SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines);
+
+    let syntax = match syntax_set.find_syntax_by_token(language){
+        Some(syntax) => syntax,
+        None => return escape_html(code)
+    };
+
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, syntax_set, ClassStyle::SpacedPrefixed { prefix: "hl-" });
+    for line in LinesWithEndings::from(code){
+        if let Err(e) = generator.parse_html_for_line_which_includes_newline(line){
+            eprintln!("Couldn't highlight code block with language {}: {}", language, e);
+            return escape_html(code)
+        }
+    }
+    generator.finalize()
+}
+
 pub fn render_text(text: String, endnote_storage: &mut Vec<(uuid::Uuid, String)>, dict: &Standard, citation_bib: &HashMap<String, String>) -> String{
+    // Step Zero: Render inline formulas and replace them with placeholders, so they aren't touched by the hyphenation
+    let re_math = Regex::new(r#"<math-inline data-latex="([^"]*)">.*?</math-inline>"#).unwrap();
+    let mut formulas: Vec<String> = vec![];
+    let text = re_math.replace_all(&text, |caps: &regex::Captures| {
+        let latex = caps.get(1).map_or("", |m| m.as_str());
+        formulas.push(render_math(&unescape_html(latex), latex2mathml::DisplayStyle::Inline));
+        format!("<math-placeholder-{}/>", formulas.len()-1)
+    }).to_string();
+
     let re: Regex = Regex::new(r#"<span(?:[^>]*?\bnote-type="([^"]+)")?(?:[^>]*?\bnote-content="([^"]+)")?[^>]*>.*?</span>"#).unwrap(); //TODO: DO NOT RECOMPILE REGEX, it's bad for performance
     let re3 = Regex::new(r#"<citation data-key="([^"]*)">C</citation>"#).unwrap();
 
@@ -445,7 +640,13 @@ pub fn render_text(text: String, endnote_storage: &mut Vec<(uuid::Uuid, String)>
             }
         }
     });
-    hyphenate_text(res3.to_string(), dict)
+    let mut res = hyphenate_text(res3.to_string(), dict);
+
+    // Insert rendered formulas again
+    for (i, formula) in formulas.iter().enumerate(){
+        res = res.replace(&format!("<math-placeholder-{}/>", i), formula);
+    }
+    res
 }
 
 pub fn render_citations(project: &ProjectDataV2, csl_data: Arc<CslData>) -> HashMap<String, String>{
@@ -555,4 +756,30 @@ mod tests {
         let hyphenated = hyphenate_text(text.to_string(), &dict);
         assert_eq!(hyphenated, "Grund\u{ad}stücks\u{ad}ver\u{ad}kehrs\u{ad}ge\u{ad}neh\u{ad}mi\u{ad}gungs\u{ad}zu\u{ad}stän\u{ad}dig\u{ad}keits\u{ad}über\u{ad}tra\u{ad}gungs\u{ad}ver\u{ad}ord\u{ad}nung");
     }
+
+    #[test]
+    fn test_inline_math_is_rendered_and_not_hyphenated(){
+        let dict = Standard::from_embedded(hyphenation::Language::German1996).unwrap();
+        let mut endnotes = vec![];
+        let text = r#"Es gilt <math-inline data-latex="\mathrm{Verfassungsbeschwerde}">x</math-inline> immer"#;
+        let rendered = render_text(text.to_string(), &mut endnotes, &dict, &HashMap::new());
+        assert!(rendered.contains("<math"));
+        assert!(!rendered.contains("math-inline"));
+        assert!(!rendered.contains("Ver\u{ad}fas\u{ad}sung"));
+    }
+
+    #[test]
+    fn test_code_block_is_escaped_and_not_hyphenated(){
+        let dict = Standard::from_embedded(hyphenation::Language::German1996).unwrap();
+        let mut endnotes = vec![];
+        let block = NewContentBlock{
+            id: "code".to_string(),
+            block_type: crate::projects::BlockType::Code,
+            data: BlockData::Code { code: "if a < b {\n    Grundstücksverkehrsgenehmigung();\n}".to_string(), language: None },
+            css_classes: vec![],
+            revision_id: None,
+        };
+        let rendered = render_content_block(block, &mut endnotes, &dict, &HashMap::new(), &[]);
+        assert_eq!(rendered.html, "<pre class=\"code-block \"><code>if a &lt; b {\n    Grundstücksverkehrsgenehmigung();\n}</code></pre>");
+    }
 }
\ No newline at end of file
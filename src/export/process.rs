@@ -0,0 +1,131 @@
+//! Child process management for renderings.
+//!
+//! External tools (the pdf engines and the browsers they start) run in their own process group, so a cancelled or
+//! timed out rendering can kill the whole process tree instead of leaving browsers behind.
+
+use std::io::Read;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::export::rendering_manager::RenderingError;
+
+/// Shared between a rendering and the API to abort it
+#[derive(Default)]
+pub struct CancellationToken{
+    cancelled: AtomicBool,
+    deadline: OnceLock<Instant>,
+}
+
+impl CancellationToken{
+    pub fn cancel(&self){
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool{
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Starts the wall-clock limit of the rendering, 0 means no limit
+    pub fn start_timer(&self, timeout: u64){
+        if timeout > 0{
+            let _ = self.deadline.set(Instant::now() + Duration::from_secs(timeout));
+        }
+    }
+
+    /// Returns an error if the rendering should stop
+    pub fn check(&self) -> Result<(), RenderingError>{
+        if self.is_cancelled(){
+            return Err(RenderingError::Cancelled);
+        }
+        match self.deadline.get(){
+            Some(deadline) if Instant::now() >= *deadline => Err(RenderingError::TimedOut),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Runs the command and collects its output like [Command::output]
+///
+/// The process tree is killed as soon as the token is cancelled or its deadline has passed.
+/// Errors spawning or waiting for the process are returned as [RenderingError::IoError].
+pub fn run_command(command: &mut Command, token: &CancellationToken) -> Result<Output, RenderingError>{
+    command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    let mut child = command.spawn().map_err(|e| RenderingError::IoError(e.to_string()))?;
+
+    // Drain the pipes in the background, otherwise a chatty child blocks on a full pipe
+    let stdout = read_to_end(child.stdout.take());
+    let stderr = read_to_end(child.stderr.take());
+
+    let status = loop{
+        match child.try_wait(){
+            Ok(Some(status)) => break status,
+            Ok(None) => {},
+            Err(e) => {
+                kill_tree(&mut child);
+                return Err(RenderingError::IoError(e.to_string()));
+            }
+        }
+        if let Err(e) = token.check(){
+            kill_tree(&mut child);
+            return Err(e);
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    Ok(Output{
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn read_to_end<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>>{
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe{
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// Kills the child and all processes in its process group and reaps the child
+fn kill_tree(child: &mut Child){
+    #[cfg(unix)]
+    unsafe{
+        // The child is the leader of its process group, see run_command
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(all(test, unix))]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_timed_out_process_tree_is_killed(){
+        let token = CancellationToken::default();
+        token.start_timer(1);
+
+        let started = Instant::now();
+        let result = run_command(Command::new("sh").args(["-c", "sleep 30 & sleep 30"]), &token);
+        assert!(matches!(result, Err(RenderingError::TimedOut)));
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let token = CancellationToken::default();
+        let output = run_command(Command::new("sh").args(["-c", "echo out; echo err >&2"]), &token).unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "out\n");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "err\n");
+    }
+}
@@ -0,0 +1,171 @@
+//! Draft watermark, proof stamp and line numbers of proof renderings.
+//!
+//! They are available to templates as `proof` and injected into the rendered html as well, so every template
+//! supports them without changes. The injected elements are fixed positioned, which repeats them on every page.
+
+use std::sync::OnceLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::data_storage::ProjectDataV2;
+use crate::export::preprocessing::escape_html;
+use crate::export::rendering_manager::RenderingOptions;
+
+/// Highest line number of a paragraph, longer paragraphs continue without numbers
+const MAX_LINE_NUMBERS: usize = 200;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PreparedProof{
+    pub watermark: Option<String>,
+    /// Text of the proof stamp, e.g. "Proof 2024-05-02 14:03 UTC, revision 3f2a9c1e", None if no stamp was requested
+    pub stamp: Option<String>,
+    pub rendered_at: String,
+    /// Short hash of the project content, identifies the state of the content the proof was made of
+    pub revision: String,
+    pub line_numbers: bool,
+}
+
+impl PreparedProof{
+    pub fn new(options: &RenderingOptions, project_data: &ProjectDataV2) -> PreparedProof{
+        let rendered_at = chrono::Utc::now().format("%Y-%m-%d %H:%M UTC").to_string();
+        let revision = project_revision(project_data);
+        PreparedProof{
+            watermark: options.watermark.clone().filter(|watermark| !watermark.trim().is_empty()),
+            stamp: options.proof_stamp.then(|| format!("Proof {}, revision {}", rendered_at, revision)),
+            rendered_at,
+            revision,
+            line_numbers: options.line_numbers,
+        }
+    }
+
+    fn is_empty(&self) -> bool{
+        self.watermark.is_none() && self.stamp.is_none() && !self.line_numbers
+    }
+}
+
+fn project_revision(project_data: &ProjectDataV2) -> String{
+    // Only the content, bookkeeping like last_interaction would give identical content a new revision.
+    // Maps are sorted by serde_json::Value, so the order of the bibliography doesn't matter.
+    let content = serde_json::json!({
+        "name": project_data.name,
+        "metadata": project_data.metadata,
+        "sections": project_data.sections,
+        "bibliography": project_data.bibliography,
+    });
+    let hash = Sha256::digest(content.to_string().as_bytes());
+    hex::encode(hash)[..8].to_string()
+}
+
+/// Adds styles and elements of watermark, stamp and line numbers to the rendered html
+pub fn inject_proof(html: String, proof: &PreparedProof) -> String{
+    if proof.is_empty(){
+        return html;
+    }
+
+    let mut style = String::from("<style>\n");
+    let mut elements = String::new();
+    if let Some(watermark) = &proof.watermark{
+        style.push_str(".proof-watermark{position: fixed; top: 50%; left: 50%; transform: translate(-50%, -50%) rotate(-45deg); font-family: sans-serif; font-size: 72pt; color: rgba(200, 0, 0, 0.15); white-space: nowrap; pointer-events: none; z-index: 1000;}\n");
+        elements.push_str(&format!("<div class=\"proof-watermark\">{}</div>", escape_html(watermark)));
+    }
+    if let Some(stamp) = &proof.stamp{
+        style.push_str(".proof-stamp{position: fixed; top: 0; right: 0; font-family: sans-serif; font-size: 7pt; color: #c00; z-index: 1000;}\n");
+        elements.push_str(&format!("<div class=\"proof-stamp\">{}</div>", escape_html(stamp)));
+    }
+    if proof.line_numbers{
+        // Numbers are restarted in every paragraph, the column of numbers is cut off at the height of the paragraph.
+        // They are shown by an own element, so styles of the template on the paragraphs (e.g. p::before) still apply.
+        let numbers: Vec<String> = (1..=MAX_LINE_NUMBERS).map(|number| number.to_string()).collect();
+        style.push_str("p[data-proof-lines]{position: relative;}\n");
+        style.push_str("span.proof-line-numbers{position: absolute; top: 0; left: -3em; width: 2.5em; height: 100%; overflow: hidden; text-align: right; color: #888; font-style: normal; font-weight: normal;}\n");
+        style.push_str(&format!("span.proof-line-numbers::before{{content: \"{}\"; white-space: pre;}}\n", numbers.join("\\A ")));
+    }
+    style.push_str("</style>\n");
+
+    static HEAD_END: OnceLock<Regex> = OnceLock::new();
+    static BODY_START: OnceLock<Regex> = OnceLock::new();
+    static PARAGRAPH_START: OnceLock<Regex> = OnceLock::new();
+    let head_end = HEAD_END.get_or_init(|| Regex::new(r"(?i)</head>").unwrap());
+    let body_start = BODY_START.get_or_init(|| Regex::new(r"(?i)<body[^>]*>").unwrap());
+    let paragraph_start = PARAGRAPH_START.get_or_init(|| Regex::new(r"(?i)<p(\s[^>]*)?>").unwrap());
+
+    let html = match proof.line_numbers{
+        true => paragraph_start.replace_all(&html, "<p data-proof-lines${1}><span class=\"proof-line-numbers\" aria-hidden=\"true\"></span>").to_string(),
+        false => html,
+    };
+    let html = match head_end.find(&html){
+        Some(head) => format!("{}{}{}", &html[..head.start()], style, &html[head.start()..]),
+        None => format!("{}{}", style, html),
+    };
+    match body_start.find(&html){
+        Some(body) => format!("{}{}{}", &html[..body.end()], elements, &html[body.end()..]),
+        None => format!("{}{}", elements, html),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_inject_proof(){
+        let proof = PreparedProof{
+            watermark: Some("DRAFT <1>".to_string()),
+            stamp: Some("Proof 2024-05-02 14:03 UTC, revision 3f2a9c1e".to_string()),
+            rendered_at: "2024-05-02 14:03 UTC".to_string(),
+            revision: "3f2a9c1e".to_string(),
+            line_numbers: true,
+        };
+        let html = inject_proof("<html><head><title>Book</title></head><body class=\"book\"><p>Text</p></body></html>".to_string(), &proof);
+
+        let style = html.find("<style>").unwrap();
+        assert!(style > html.find("<title>").unwrap() && style < html.find("</head>").unwrap());
+        assert!(html.contains("<body class=\"book\"><div class=\"proof-watermark\">DRAFT &lt;1&gt;</div><div class=\"proof-stamp\">Proof 2024-05-02 14:03 UTC, revision 3f2a9c1e</div><p data-proof-lines><span class=\"proof-line-numbers\" aria-hidden=\"true\"></span>Text</p>"));
+        assert!(html.contains("span.proof-line-numbers::before{content: \"1\\A 2\\A 3"));
+
+        let nothing = PreparedProof{ watermark: None, stamp: None, line_numbers: false, ..proof };
+        assert_eq!(inject_proof("<p>Text</p>".to_string(), &nothing), "<p>Text</p>");
+    }
+
+    #[test]
+    fn test_line_numbers_keep_paragraph_styles_of_template(){
+        let proof = PreparedProof{
+            watermark: None,
+            stamp: None,
+            rendered_at: "2024-05-02 14:03 UTC".to_string(),
+            revision: "3f2a9c1e".to_string(),
+            line_numbers: true,
+        };
+        let template_style = "<style>p::before{content: \"§ \";} p.noindent{position: static;}</style>";
+        let html = inject_proof(format!("<html><head>{}</head><body><p class=\"noindent\">Text</p><pre>Code</pre></body></html>", template_style), &proof);
+
+        // The template's own rules are kept and not overridden by global paragraph rules
+        assert!(html.contains(template_style));
+        let injected = &html[html.rfind("<style>").unwrap()..html.find("</head>").unwrap()];
+        assert!(!injected.contains("p::before") && !injected.contains("\np{"));
+        assert!(html.contains("<p data-proof-lines class=\"noindent\"><span class=\"proof-line-numbers\" aria-hidden=\"true\"></span>Text</p><pre>Code</pre>"));
+    }
+
+    #[test]
+    fn test_revision_only_depends_on_content(){
+        let project = ProjectDataV2{
+            name: "Book".to_string(),
+            description: None,
+            template_id: uuid::Uuid::new_v4(),
+            last_interaction: 0,
+            metadata: None,
+            settings: None,
+            sections: vec![],
+            bibliography: Default::default(),
+            version: 0,
+            comments: vec![],
+            suggestions: vec![],
+            workflow: Default::default(),
+        };
+        let accessed = ProjectDataV2{ last_interaction: 1714658580, ..project.clone() };
+        assert_eq!(project_revision(&project), project_revision(&accessed));
+
+        let renamed = ProjectDataV2{ name: "Other book".to_string(), ..project.clone() };
+        assert_ne!(project_revision(&project), project_revision(&renamed));
+    }
+}
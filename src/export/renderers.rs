@@ -0,0 +1,185 @@
+//! Engines turning the rendered `index.html` of a rendering into `output.pdf`.
+//!
+//! The engine is selected with [Settings::pdf_renderer]. All engines run in the working directory of the
+//! rendering, which contains `index.html`, the template assets and the project uploads.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use crate::export::process::{run_command, CancellationToken};
+use crate::export::rendering_log::StageLog;
+use crate::export::rendering_manager::RenderingError;
+use crate::settings::{PdfRendererKind, Settings};
+
+/// Timeout passed to the browser based engines in ms, the rendering itself is limited by [Settings::rendering_timeout]
+const BROWSER_TIMEOUT: &str = "480000";
+
+pub trait PdfRenderer: Send + Sync{
+    /// Name shown in logs
+    fn name(&self) -> &'static str;
+
+    /// Converts `index.html` in the working directory into `output.pdf`
+    ///
+    /// Output of external tools is written to the stage log.
+    fn render(&self, working_dir: &Path, settings: &Settings, token: &CancellationToken, stage: &mut StageLog) -> Result<(), RenderingError>;
+}
+
+impl PdfRendererKind{
+    pub fn renderer(&self) -> Box<dyn PdfRenderer>{
+        match self{
+            PdfRendererKind::Vivliostyle => Box::new(VivliostyleRenderer),
+            PdfRendererKind::PagedJs => Box::new(PagedJsRenderer),
+            PdfRendererKind::Chromium => Box::new(ChromiumRenderer),
+            PdfRendererKind::Mock => Box::new(MockRenderer),
+        }
+    }
+}
+
+/// Runs an engine and stores its output in the stage log, `to_error` creates the engine specific error
+fn run_engine(name: &str, command: &mut Command, token: &CancellationToken, stage: &mut StageLog, to_error: fn(String) -> RenderingError) -> Result<(), RenderingError>{
+    match run_command(command, token){
+        Ok(out) => {
+            stage.stdout = String::from_utf8_lossy(&out.stdout).to_string();
+            stage.stderr = String::from_utf8_lossy(&out.stderr).to_string();
+            if out.status.success(){
+                Ok(())
+            }else{
+                eprintln!("Export with {} failed: {}", name, stage.stderr);
+                Err(to_error(stage.stderr.clone()))
+            }
+        }
+        Err(RenderingError::IoError(e)) => {
+            println!("Couldn't run {}: {}", name, e);
+            Err(to_error(e))
+        }
+        Err(e) => {
+            println!("{} was stopped: {}", name, e);
+            Err(e)
+        }
+    }
+}
+
+/// Vivliostyle CLI, which drives Chromium (from [Settings::chromium_path] or its bundled one)
+pub struct VivliostyleRenderer;
+
+impl PdfRenderer for VivliostyleRenderer{
+    fn name(&self) -> &'static str {
+        "vivliostyle"
+    }
+
+    fn render(&self, working_dir: &Path, settings: &Settings, token: &CancellationToken, stage: &mut StageLog) -> Result<(), RenderingError> {
+        let mut args = vec!["build", "index.html", "-o", "output.pdf"];
+        if let Some(path) = settings.chromium_path.as_deref(){
+            args.push("--executable-browser");
+            args.push(path);
+            args.push("--timeout");
+            args.push(BROWSER_TIMEOUT);
+        }
+
+        run_engine(self.name(), Command::new("vivliostyle").current_dir(working_dir).args(args), token, stage, RenderingError::VivliostyleError)
+    }
+}
+
+/// pagedjs-cli, which drives Chromium through puppeteer
+pub struct PagedJsRenderer;
+
+impl PdfRenderer for PagedJsRenderer{
+    fn name(&self) -> &'static str {
+        "pagedjs-cli"
+    }
+
+    fn render(&self, working_dir: &Path, settings: &Settings, token: &CancellationToken, stage: &mut StageLog) -> Result<(), RenderingError> {
+        let mut command = Command::new("pagedjs-cli");
+        command.current_dir(working_dir).args(["index.html", "-o", "output.pdf", "--timeout", BROWSER_TIMEOUT]);
+        if let Some(path) = settings.chromium_path.as_deref().filter(|path| !path.is_empty()){
+            command.env("PUPPETEER_EXECUTABLE_PATH", path);
+        }
+
+        run_engine(self.name(), &mut command, token, stage, RenderingError::RendererError)
+    }
+}
+
+/// Chromium's own print to pdf, without any paged media polyfill
+///
+/// Only supports what Chromium supports of CSS paged media, e.g. no running headers and no page references.
+/// Useful for simple templates or where no node tooling is available.
+pub struct ChromiumRenderer;
+
+impl PdfRenderer for ChromiumRenderer{
+    fn name(&self) -> &'static str {
+        "chromium"
+    }
+
+    fn render(&self, working_dir: &Path, settings: &Settings, token: &CancellationToken, stage: &mut StageLog) -> Result<(), RenderingError> {
+        let executable = settings.chromium_path.as_deref().filter(|path| !path.is_empty()).unwrap_or("chromium");
+        let index = working_dir.join("index.html").canonicalize().map_err(|e| RenderingError::IoError(e.to_string()))?;
+
+        let mut command = Command::new(executable);
+        command.current_dir(working_dir).args([
+            "--headless",
+            "--disable-gpu",
+            "--no-pdf-header-footer",
+            "--print-to-pdf=output.pdf",
+            &format!("file://{}", index.to_string_lossy()),
+        ]);
+
+        run_engine(self.name(), &mut command, token, stage, RenderingError::RendererError)
+    }
+}
+
+/// Writes a fixed single page pdf without running any browser, for tests and development setups
+pub struct MockRenderer;
+
+/// Smallest pdf the usual viewers open: one empty A4 page
+const MOCK_PDF: &str = "%PDF-1.4
+1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj
+2 0 obj << /Type /Pages /Kids [3 0 R] /Count 1 >> endobj
+3 0 obj << /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] >> endobj
+trailer << /Root 1 0 R >>
+%%EOF
+";
+
+impl PdfRenderer for MockRenderer{
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn render(&self, working_dir: &Path, _settings: &Settings, token: &CancellationToken, stage: &mut StageLog) -> Result<(), RenderingError> {
+        token.check()?;
+        if !working_dir.join("index.html").exists(){
+            return Err(RenderingError::RendererError("index.html is missing".to_string()));
+        }
+        fs::write(working_dir.join("output.pdf"), MOCK_PDF).map_err(|e| RenderingError::IoError(e.to_string()))?;
+        stage.stdout = "Wrote mock output.pdf".to_string();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::export::rendering_log::{RenderingLog, RenderingStage};
+
+    #[test]
+    fn test_mock_renderer(){
+        let working_dir = std::env::temp_dir().join(format!("mock_renderer_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&working_dir).unwrap();
+        let settings = Settings::new().unwrap();
+        let token = CancellationToken::default();
+        let renderer = PdfRendererKind::Mock.renderer();
+        let log = RenderingLog::default();
+
+        let result = log.stage(RenderingStage::PdfBuild, |stage| renderer.render(&working_dir, &settings, &token, stage));
+        assert!(matches!(result, Err(RenderingError::RendererError(_))));
+
+        fs::write(working_dir.join("index.html"), "<html></html>").unwrap();
+        log.stage(RenderingStage::PdfBuild, |stage| renderer.render(&working_dir, &settings, &token, stage)).unwrap();
+        assert!(fs::read_to_string(working_dir.join("output.pdf")).unwrap().starts_with("%PDF"));
+
+        token.cancel();
+        let result = log.stage(RenderingStage::PdfBuild, |stage| renderer.render(&working_dir, &settings, &token, stage));
+        assert!(matches!(result, Err(RenderingError::Cancelled)));
+
+        fs::remove_dir_all(working_dir).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+//! Per-stage log of a rendering: timings, warnings and the output of external tools.
+//!
+//! Template authors use it to debug their templates without shell access, see
+//! `GET /api/renderings/<render_id>/log`.
+
+use std::sync::Mutex;
+use std::time::Instant;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::export::rendering_manager::RenderingError;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum RenderingStage{
+    /// Converting the project data (citations, notes, sanitizing)
+    Prepare,
+    TemplateLoad,
+    /// Copying the template output folder and the project uploads
+    AssetCopy,
+    HtmlRender,
+    PdfBuild,
+    /// Reading the page count and the start pages of the sections from the PDF
+    PageMap,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StageLog{
+    pub stage: RenderingStage,
+    pub started_at: NaiveDateTime,
+    pub duration_ms: u64,
+    pub success: bool,
+    pub error: Option<String>,
+    /// E.g. template variables which couldn't be resolved
+    pub warnings: Vec<String>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl StageLog{
+    /// Adds a warning, repeated warnings (e.g. from loops in templates) are only kept once
+    pub fn warn(&mut self, warning: String){
+        if !self.warnings.contains(&warning){
+            self.warnings.push(warning);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RenderingLog{
+    stages: Mutex<Vec<StageLog>>,
+}
+
+impl RenderingLog{
+    /// Runs a stage of the rendering and logs its duration and result
+    pub fn stage<T, F: FnOnce(&mut StageLog) -> Result<T, RenderingError>>(&self, stage: RenderingStage, f: F) -> Result<T, RenderingError>{
+        let started = Instant::now();
+        let mut entry = StageLog{
+            stage,
+            started_at: chrono::Utc::now().naive_utc(),
+            duration_ms: 0,
+            success: false,
+            error: None,
+            warnings: vec![],
+            stdout: String::new(),
+            stderr: String::new(),
+        };
+
+        let result = f(&mut entry);
+        entry.duration_ms = started.elapsed().as_millis() as u64;
+        entry.success = result.is_ok();
+        if let Err(e) = &result{
+            entry.error = Some(e.to_string());
+        }
+        self.stages.lock().unwrap().push(entry);
+        result
+    }
+
+    /// Returns the stages which are done so far
+    pub fn stages(&self) -> Vec<StageLog>{
+        self.stages.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_stage_log(){
+        let log = RenderingLog::default();
+        let value = log.stage(RenderingStage::HtmlRender, |stage| {
+            stage.warn("Missing value title".to_string());
+            stage.warn("Missing value title".to_string());
+            Ok(1)
+        });
+        assert_eq!(value.unwrap(), 1);
+        let result: Result<(), RenderingError> = log.stage(RenderingStage::PdfBuild, |_| Err(RenderingError::TimedOut));
+        assert!(result.is_err());
+
+        let stages = log.stages();
+        assert_eq!(stages.len(), 2);
+        assert!(stages[0].success);
+        assert_eq!(stages[0].warnings, vec!["Missing value title".to_string()]);
+        assert!(!stages[1].success);
+        assert_eq!(stages[1].error, Some("Rendering timed out".to_string()));
+    }
+}
@@ -3,13 +3,25 @@ use std::{error, fmt, mem};
 use std::path::Path;
 use std::sync::{Arc, RwLock};
 use std::sync::atomic::AtomicU64;
-use serde::Serialize;
-use crate::data_storage::{DataStorage, ProjectDataV2};
+use bincode::{Decode, Encode};
+use rocket::tokio::sync::Notify;
+use serde::{Deserialize, Serialize};
+use crate::data_storage::{DataStorage, ExportType, ProjectDataV2, ProjectStorage};
+use crate::export::history::{RenderingHistory, RenderingRecord};
+use crate::export::page_map::{read_page_map, section_outline, PageMap};
 use crate::export::preprocessing::{prepare_project, render_project};
-use crate::settings::Settings;
+use crate::export::process::CancellationToken;
+use crate::export::scope::RenderingScope;
+use crate::export::section_cache::PreparedSectionCache;
+use crate::export::rendering_log::{RenderingLog, RenderingStage, StageLog};
+use crate::mail::Mailer;
+use crate::mail::notifications::{notify, Notification};
+use crate::settings::{RenderingQueueSettings, Settings};
 use crate::utils::csl::CslData;
+use crate::utils::job_events::{JobEvent, JobProgress};
+use crate::webhooks::{WebhookEvent, WebhookManager};
 
-#[derive(Default, Clone, Serialize)]
+#[derive(Default, Clone, Debug, Serialize, Deserialize, Encode, Decode)]
 pub enum RenderingStatus{
     #[default]
     Queued,
@@ -17,6 +29,65 @@ pub enum RenderingStatus{
     Running,
     Finished,
     Failed(RenderingError),
+    /// Cancelled by a user
+    Cancelled,
+    /// Killed after exceeding [Settings::rendering_timeout]
+    TimedOut,
+}
+
+impl RenderingStatus{
+    /// True if the rendering is done, either successfully or not
+    pub fn is_final(&self) -> bool{
+        matches!(self, RenderingStatus::Finished | RenderingStatus::Failed(_) | RenderingStatus::Cancelled | RenderingStatus::TimedOut)
+    }
+}
+
+/// Options for a single rendering, sent with the render request
+/// Stored in the rendering history as JSON, see [crate::export::history]
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RenderingOptions{
+    /// Review export: open comment threads are rendered next to the commented blocks
+    #[serde(default)]
+    pub review: bool,
+    /// Pending suggestions are rendered as tracked changes (ins/del elements)
+    #[serde(default)]
+    pub show_changes: bool,
+    /// Final export, only allowed if all sections are in the final workflow state
+    #[serde(default)]
+    pub final_export: bool,
+    /// Part of the book to render, e.g. {"type": "sections", "paths": ["<section_id>"]} for a chapter preview
+    #[serde(default)]
+    pub scope: RenderingScope,
+    /// Text shown diagonally on every page, e.g. "DRAFT"
+    #[serde(default)]
+    pub watermark: Option<String>,
+    /// Stamp with render date and revision of the content on every page
+    #[serde(default)]
+    pub proof_stamp: bool,
+    /// Numbers the lines of every paragraph, for copy-edit rounds
+    #[serde(default)]
+    pub line_numbers: bool,
+    /// Writes the page count of the PDF into the project metadata, only for renderings of the whole project
+    #[serde(default)]
+    pub update_page_count: bool,
+}
+
+/// Queued renderings with a higher priority start first, previews of some sections are quick and shouldn't wait for books
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum RenderingPriority{
+    /// Renderings with a [RenderingScope] other than the whole project
+    Preview,
+    #[default]
+    Full,
+}
+
+impl RenderingPriority{
+    pub fn of(options: &RenderingOptions) -> RenderingPriority{
+        match options.scope{
+            RenderingScope::Project => RenderingPriority::Full,
+            _ => RenderingPriority::Preview,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -25,17 +96,79 @@ pub struct RenderingRequest{
     pub status: RenderingStatus,
     pub project_id: uuid::Uuid,
     pub project_data: Option<ProjectDataV2>,
+    pub options: RenderingOptions,
+    pub priority: RenderingPriority,
+    pub project_name: String,
+    pub template_id: uuid::Uuid,
+    /// User who started the rendering, gets notified when it's done
+    pub requested_by: uuid::Uuid,
+    pub requested_at: chrono::NaiveDateTime,
+    /// Status transitions and log lines for the event stream
+    pub events: JobProgress,
+    /// Stops the rendering and its child processes
+    pub cancellation: Arc<CancellationToken>,
+    /// Timings, warnings and tool output of the stages
+    pub log: Arc<RenderingLog>,
+    /// Page count and start pages of the sections, once the PDF is built
+    pub page_map: Option<PageMap>,
+}
+
+impl RenderingRequest{
+    /// Sets the status and publishes it to the event stream
+    pub fn set_status(&mut self, status: RenderingStatus){
+        self.events.status(&status, status.is_final());
+        self.status = status;
+    }
+
+    /// Subscribes to the event stream, starting with the current status
+    pub fn subscribe(&self) -> (Vec<JobEvent>, rocket::tokio::sync::broadcast::Receiver<JobEvent>){
+        let status = serde_json::to_value(&self.status).unwrap_or_default();
+        self.events.subscribe(vec![JobEvent::Status { status, finished: self.status.is_final() }])
+    }
 }
 
 pub struct RenderingManager{
     pub settings: Settings,
+    /// Projects whose page count is updated by renderings
+    pub project_storage: Arc<ProjectStorage>,
     pub data_storage: Arc<DataStorage>,
     pub csl_data: Arc<CslData>,
+    pub mailer: Arc<Mailer>,
+    pub webhooks: Arc<WebhookManager>,
+    /// Finished renderings, they are removed from the archive once they are recorded here
+    pub history: Arc<RenderingHistory>,
+    /// Prepared sections of previous renderings, unchanged sections are reused
+    pub section_cache: PreparedSectionCache,
+    /// Renderings which are running
+    ///
+    /// Never lock it while holding the lock of [RenderingManager::rendering_requests] or the other way round.
     pub requests_archive: RwLock<HashMap<uuid::Uuid, RwLock<RenderingRequest>>>,
+    /// Queued renderings, ordered by priority and then by the time they were requested (unless reordered by staff)
     pub rendering_requests: RwLock<VecDeque<RwLock<RenderingRequest>>>,
+    /// Cancellation tokens of all queued and running renderings
+    ///
+    /// Covers renderings while the worker moves them from the queue to the archive. Only locked briefly, without
+    /// taking any other lock meanwhile.
+    cancellations: RwLock<HashMap<uuid::Uuid, Arc<CancellationToken>>>,
+    /// Wakes the worker when a rendering was queued or is done
+    queue_changed: Notify,
+}
+
+/// Running or queued rendering, for the queue overview
+#[derive(Serialize, Debug)]
+pub struct QueueEntry{
+    pub rendering_id: uuid::Uuid,
+    pub project_id: uuid::Uuid,
+    pub project_name: String,
+    pub requested_by: uuid::Uuid,
+    pub requested_at: chrono::NaiveDateTime,
+    pub priority: RenderingPriority,
+    pub status: RenderingStatus,
+    /// Position in the queue, None if the rendering is running
+    pub position: Option<usize>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
 pub enum RenderingError{
     NoProjectData,
     ProjectMetadataMissing,
@@ -44,6 +177,10 @@ pub enum RenderingError{
     ErrorCopyingTemplate(String),
     IoError(String),
     ErrorCopyingUploads(String),
+    Cancelled,
+    TimedOut,
+    /// Error of a pdf engine other than Vivliostyle
+    RendererError(String),
 }
 
 impl fmt::Display for RenderingError{
@@ -56,6 +193,9 @@ impl fmt::Display for RenderingError{
             RenderingError::ErrorCopyingTemplate(ref e) => write!(f, "Error copying template files: {}", e),
             RenderingError::IoError(ref e) => write!(f, "I/O Error occurred: {}", e),
             RenderingError::ErrorCopyingUploads(ref e) => write!(f, "Error copying uploads: {}", e),
+            RenderingError::Cancelled => write!(f, "Rendering was cancelled"),
+            RenderingError::TimedOut => write!(f, "Rendering timed out"),
+            RenderingError::RendererError(ref e) => write!(f, "PDF renderer error: {}", e),
         }
     }
 }
@@ -69,22 +209,35 @@ impl error::Error for RenderingError {
             RenderingError::VivliostyleError(_) => None,
             RenderingError::ErrorCopyingTemplate(_) => None,
             RenderingError::IoError(_) => None,
-            RenderingError::ErrorCopyingUploads(_) => None
+            RenderingError::ErrorCopyingUploads(_) => None,
+            RenderingError::Cancelled => None,
+            RenderingError::TimedOut => None,
+            RenderingError::RendererError(_) => None,
         }
     }
 }
 
 impl RenderingManager{
-    pub fn start(settings: Settings, data_storage: Arc<DataStorage>, csl_data: Arc<CslData>) -> Arc<RenderingManager>{
-        let rendering_manager = RenderingManager{
+    pub fn new(settings: Settings, project_storage: Arc<ProjectStorage>, data_storage: Arc<DataStorage>, csl_data: Arc<CslData>, mailer: Arc<Mailer>, webhooks: Arc<WebhookManager>, history: Arc<RenderingHistory>) -> RenderingManager{
+        RenderingManager{
+            section_cache: PreparedSectionCache::new(settings.section_cache_size),
             settings,
+            project_storage,
             data_storage,
             csl_data,
+            mailer,
+            webhooks,
+            history,
             requests_archive: RwLock::new(HashMap::new()),
             rendering_requests: RwLock::new(VecDeque::new()),
-        };
+            cancellations: RwLock::new(HashMap::new()),
+            queue_changed: Notify::new(),
+        }
+    }
 
-        let rendering_manager = Arc::new(rendering_manager);
+    /// Creates the [RenderingManager] and starts the worker which runs queued renderings
+    pub fn start(settings: Settings, project_storage: Arc<ProjectStorage>, data_storage: Arc<DataStorage>, csl_data: Arc<CslData>, mailer: Arc<Mailer>, webhooks: Arc<WebhookManager>, history: Arc<RenderingHistory>) -> Arc<RenderingManager>{
+        let rendering_manager = Arc::new(RenderingManager::new(settings, project_storage, data_storage, csl_data, mailer, webhooks, history));
         let rendering_manager_cpy = rendering_manager.clone();
 
 
@@ -92,46 +245,80 @@ impl RenderingManager{
         tokio::spawn(async move {
             let running_threads: Arc<AtomicU64> = Arc::new(AtomicU64::new(0));
 
-            //TODO: kill hanging threads
             loop{
-                // Check if there are any new rendering requests
-
-                let rendering_requests_len = rendering_manager_cpy.rendering_requests.read().unwrap().len();
-                if rendering_requests_len > 0 && rendering_manager_cpy.settings.max_rendering_threads > running_threads.load(std::sync::atomic::Ordering::Relaxed){
+                // Start queued renderings until all threads are busy or the remaining ones are over their limits
+                while rendering_manager_cpy.settings.max_rendering_threads > running_threads.load(std::sync::atomic::Ordering::Relaxed){
+                    let rendering_request = match rendering_manager_cpy.next_request(){
+                        Some(rendering_request) => rendering_request,
+                        None => break,
+                    };
                     println!("Starting new rendering thread for request...");
-                    {
-                        let mut rendering_requests = rendering_manager_cpy.rendering_requests.write().unwrap();
-                        running_threads.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-
-                        // Move the rendering request out of the vector, put it into the archive and start rendering
-                        let request_id = rendering_requests.front().unwrap().read().unwrap().rendering_id;
-                        rendering_manager_cpy.requests_archive.write().unwrap().insert(request_id, rendering_requests.pop_front().unwrap());
-
-                        let rendering_manager_cpy2 = rendering_manager_cpy.clone();
-                        let rendering_manager_cpy3 = rendering_manager_cpy.clone();
-
-                        let running_threads_clone = Arc::clone(&running_threads);
-
-                        // Start rendering in a new thread
-                        tokio::spawn(async move {
-                            match Self::render(rendering_manager_cpy2, request_id){
-                                Ok(_) => {
-                                    let mut storage = rendering_manager_cpy3.requests_archive.write().unwrap();
-                                    let mut rendering_request = storage.get_mut(&request_id).unwrap().write().unwrap();
-                                    rendering_request.status = RenderingStatus::Finished;
-                                }
-                                Err(e) => {
-                                    let mut storage = rendering_manager_cpy3.requests_archive.write().unwrap();
-                                    let mut rendering_request = storage.get_mut(&request_id).unwrap().write().unwrap();
-                                    rendering_request.status = RenderingStatus::Failed(e);
+                    running_threads.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+                    // Move the rendering request out of the vector, put it into the archive and start rendering
+                    let request_id = rendering_request.read().unwrap().rendering_id;
+                    rendering_manager_cpy.requests_archive.write().unwrap().insert(request_id, rendering_request);
+
+                    let rendering_manager_cpy2 = rendering_manager_cpy.clone();
+                    let rendering_manager_cpy3 = rendering_manager_cpy.clone();
+
+                    let running_threads_clone = Arc::clone(&running_threads);
+
+                    // Renderings block (handlebars, vivliostyle), so they run on a blocking thread
+                    tokio::spawn(async move {
+                        let result = match tokio::task::spawn_blocking(move || Self::render(rendering_manager_cpy2, request_id)).await{
+                            Ok(result) => result,
+                            Err(e) => {
+                                eprintln!("Rendering thread of {} crashed: {}", request_id, e);
+                                Err(RenderingError::IoError(format!("Rendering crashed: {}", e)))
+                            }
+                        };
+                        let status = match result{
+                            Ok(_) => RenderingStatus::Finished,
+                            Err(RenderingError::Cancelled) => RenderingStatus::Cancelled,
+                            Err(RenderingError::TimedOut) => RenderingStatus::TimedOut,
+                            Err(e) => RenderingStatus::Failed(e),
+                        };
+                        {
+                            let archive = rendering_manager_cpy3.requests_archive.read().unwrap();
+                            rendering_manager_cpy3.record(&archive.get(&request_id).unwrap().read().unwrap(), &status);
+                        }
+
+                        let rendering_request = rendering_manager_cpy3.requests_archive.write().unwrap().remove(&request_id).unwrap();
+                        let mut rendering_request = rendering_request.into_inner().unwrap();
+                        let project_id = rendering_request.project_id;
+                        let project_name = rendering_request.project_name.clone();
+                        let requested_by = rendering_request.requested_by;
+                        let notification = match &status{
+                            RenderingStatus::Finished => {
+                                if let Some(page_map) = rendering_request.page_map.as_ref().filter(|_| rendering_request.options.update_page_count && rendering_request.options.scope == RenderingScope::Project){
+                                    rendering_manager_cpy3.update_page_count(project_id, page_map.page_count).await;
                                 }
+                                rendering_request.events.log("Rendering finished");
+                                Some((Notification::RenderingFinished { project_id, project_name, rendering_id: request_id }, WebhookEvent::RenderingFinished))
+                            }
+                            RenderingStatus::Cancelled => {
+                                rendering_request.events.log("Rendering cancelled");
+                                None
                             }
-                            running_threads_clone.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
-                        });
-                    }
+                            _ => {
+                                let error = status_error(&status).unwrap_or_default();
+                                rendering_request.events.log(format!("Rendering failed: {}", error));
+                                Some((Notification::RenderingFailed { project_id, project_name, rendering_id: request_id, error }, WebhookEvent::RenderingFailed))
+                            }
+                        };
+                        rendering_request.set_status(status);
+
+                        if let Some((notification, event)) = notification{
+                            notify(&rendering_manager_cpy3.mailer, &rendering_manager_cpy3.data_storage, &requested_by, &notification);
+                            rendering_manager_cpy3.webhooks.trigger(project_id, event, &notification);
+                        }
+                        running_threads_clone.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+                        rendering_manager_cpy3.queue_changed.notify_one();
+                    });
                 }
 
-                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                let _ = tokio::time::timeout(std::time::Duration::from_secs(1), rendering_manager_cpy.queue_changed.notified()).await;
             }
         });
 
@@ -139,16 +326,23 @@ impl RenderingManager{
     }
     fn render(rendering_manager: Arc<RenderingManager>, request_id: uuid::Uuid) -> Result<(), RenderingError>{
         let project_id;
+        let options;
+        let token;
+        let log;
 
         let project_data: ProjectDataV2 = { // Introduction of a new scope to drop the lock on the request
             let mut storage = rendering_manager.requests_archive.write().unwrap();
             let mut rendering_request = storage.get_mut(&request_id).unwrap().write().unwrap();
-            rendering_request.status = RenderingStatus::Preparing;
+            rendering_request.set_status(RenderingStatus::Preparing);
+            rendering_request.events.log("Preparing project");
             project_id = rendering_request.project_id;
+            options = rendering_request.options.clone();
+            token = rendering_request.cancellation.clone();
+            log = rendering_request.log.clone();
+            token.start_timer(rendering_manager.settings.rendering_timeout);
             match mem::take(&mut rendering_request.project_data) {
                 Some(project_data) => project_data,
                 None => {
-                    rendering_request.status = RenderingStatus::Failed(RenderingError::NoProjectData);
                     return Err(RenderingError::NoProjectData);
                 }
             }
@@ -165,57 +359,495 @@ impl RenderingManager{
         std::fs::create_dir_all(temp_dir).unwrap();
 
         // Prepare project
-        let prepared_project = prepare_project(project_data, rendering_manager.data_storage.clone(), rendering_manager.csl_data.clone())?;
+        let prepared_project = log.stage(RenderingStage::Prepare, |_| {
+            prepare_project(project_data, rendering_manager.data_storage.clone(), rendering_manager.csl_data.clone(), &rendering_manager.settings, &options, &rendering_manager.section_cache, &token)
+        })?;
+
+        token.check()?;
 
         // Update project status
         {
             let mut storage = rendering_manager.requests_archive.write().unwrap();
             let mut rendering_request = storage.get_mut(&request_id).unwrap().write().unwrap();
-            rendering_request.status = RenderingStatus::Running;
+            rendering_request.set_status(RenderingStatus::Running);
+            rendering_request.events.log("Rendering HTML and PDF");
         }
 
+        let outline = section_outline(&prepared_project.data);
+
         // Render
-        match render_project(prepared_project, project_id, template_id, temp_dir, &rendering_manager.settings){
-            Ok(_) => {
-                Ok(())
+        render_project(prepared_project, project_id, template_id, temp_dir, &rendering_manager.settings, &token, &log)?;
+
+        // A PDF which can't be parsed doesn't fail the rendering, it's only missing the page map
+        let page_map = log.stage(RenderingStage::PageMap, |stage| {
+            let page_map = read_page_map(&temp_dir.join("output.pdf"), &outline).map_err(RenderingError::IoError)?;
+            for section in outline.iter(){
+                if !page_map.sections.iter().any(|found| found.section_id == section.section_id){
+                    stage.warn(format!("Start page of section {} ({}) not found", section.title, section.section_id));
+                }
             }
-            Err(e) => {
-                Err(e)
+            stage.stdout = format!("{} pages, start pages of {} of {} sections found", page_map.page_count, page_map.sections.len(), outline.len());
+            Ok(page_map)
+        }).ok();
+
+        let storage = rendering_manager.requests_archive.read().unwrap();
+        storage.get(&request_id).unwrap().write().unwrap().page_map = page_map;
+        Ok(())
+    }
+
+    /// Writes the page count of a rendering into the project metadata
+    async fn update_page_count(&self, project_id: uuid::Uuid, page_count: u32){
+        let project = match self.project_storage.get_project(&project_id, &self.settings).await{
+            Ok(project) => project,
+            Err(_) => {
+                eprintln!("Couldn't update page count of project {}: project not found", project_id);
+                return;
             }
+        };
+        let mut project = project.write().unwrap();
+        let metadata = match project.metadata.as_mut(){
+            Some(metadata) => metadata,
+            None => return,
+        };
+        if metadata.number_of_pages == Some(page_count){
+            return;
         }
+        metadata.number_of_pages = Some(page_count);
+        project.increment_version();
+        self.webhooks.trigger(project_id, WebhookEvent::MetadataChanged, &project.metadata);
     }
 
-    pub fn add_rendering_request(&self, project_data: ProjectDataV2, project_id: uuid::Uuid) -> uuid::Uuid{
+    /// Queues a rendering and returns its id
+    ///
+    /// If the same user already queued the same rendering of the project, that one is updated to the latest project
+    /// data and its id is returned instead.
+    pub fn add_rendering_request(&self, project_data: ProjectDataV2, project_id: uuid::Uuid, options: RenderingOptions, requested_by: uuid::Uuid) -> uuid::Uuid{
+        let mut queue = self.rendering_requests.write().unwrap();
+        let duplicate = queue.iter().find(|request| {
+            let request = request.read().unwrap();
+            request.project_id == project_id && request.requested_by == requested_by && request.options == options
+        });
+        if let Some(duplicate) = duplicate{
+            let mut duplicate = duplicate.write().unwrap();
+            duplicate.project_name = project_data.name.clone();
+            duplicate.template_id = project_data.template_id;
+            duplicate.project_data = Some(project_data);
+            duplicate.events.log("Updated to the latest project data");
+            return duplicate.rendering_id;
+        }
+
         let rendering_id = uuid::Uuid::new_v4();
+        let priority = RenderingPriority::of(&options);
+        let cancellation = Arc::new(CancellationToken::default());
+        self.cancellations.write().unwrap().insert(rendering_id, cancellation.clone());
         let rendering_request = RenderingRequest{
             rendering_id,
             status: RenderingStatus::Queued,
             project_id,
+            project_name: project_data.name.clone(),
+            template_id: project_data.template_id,
             project_data: Some(project_data),
+            options,
+            priority,
+            requested_by,
+            requested_at: chrono::Utc::now().naive_utc(),
+            events: JobProgress::default(),
+            cancellation,
+            log: Arc::new(RenderingLog::default()),
+            page_map: None,
         };
 
-        self.rendering_requests.write().unwrap().push_back(RwLock::new(rendering_request));
+        // Behind all requests with the same or a higher priority
+        let position = queue.iter().position(|request| request.read().unwrap().priority > priority).unwrap_or(queue.len());
+        queue.insert(position, RwLock::new(rendering_request));
+        self.queue_changed.notify_one();
         rendering_id
     }
 
-    pub fn get_rendering_request_status(&self, rendering_id: uuid::Uuid) -> Option<RenderingStatus>{
-        let storage = self.requests_archive.read().unwrap();
-        match storage.get(&rendering_id){
-            Some(request) => {
+    /// Takes the next rendering to start out of the queue
+    fn next_request(&self) -> Option<RwLock<RenderingRequest>>{
+        // Only the worker starts renderings, so the snapshot of the running ones can't be outdated by a new one
+        let running: Vec<(uuid::Uuid, uuid::Uuid)> = self.requests_archive.read().unwrap().values()
+            .map(|request| {
                 let request = request.read().unwrap();
-                Some(request.status.clone())
+                (request.requested_by, request.project_id)
+            })
+            .collect();
+        let mut queue = self.rendering_requests.write().unwrap();
+        let position = next_position(&queue, &running, &self.settings.rendering_queue)?;
+        queue.remove(position)
+    }
+
+    /// Running and queued renderings, running ones first
+    pub fn queue_overview(&self) -> Vec<QueueEntry>{
+        let entry = |request: &RenderingRequest, position: Option<usize>| QueueEntry{
+            rendering_id: request.rendering_id,
+            project_id: request.project_id,
+            project_name: request.project_name.clone(),
+            requested_by: request.requested_by,
+            requested_at: request.requested_at,
+            priority: request.priority,
+            status: request.status.clone(),
+            position,
+        };
+
+        let mut entries: Vec<QueueEntry> = self.requests_archive.read().unwrap().values()
+            .map(|request| entry(&request.read().unwrap(), None))
+            .collect();
+        entries.sort_by_key(|entry| entry.requested_at);
+        let queue = self.rendering_requests.read().unwrap();
+        entries.extend(queue.iter().enumerate().map(|(position, request)| entry(&request.read().unwrap(), Some(position))));
+        entries
+    }
+
+    /// Moves a queued rendering to the given position, e.g. 0 to start it next
+    ///
+    /// The rendering takes the priority of the renderings around it, so it keeps its place when more are queued.
+    /// Returns false if the rendering isn't queued (anymore).
+    pub fn move_request(&self, rendering_id: uuid::Uuid, position: usize) -> bool{
+        let mut queue = self.rendering_requests.write().unwrap();
+        let current = match queue.iter().position(|request| request.read().unwrap().rendering_id == rendering_id){
+            Some(current) => current,
+            None => return false,
+        };
+        let request = queue.remove(current).unwrap();
+        let position = position.min(queue.len());
+        let neighbour = queue.get(position).or_else(|| queue.back()).map(|neighbour| neighbour.read().unwrap().priority);
+        if let Some(priority) = neighbour{
+            request.write().unwrap().priority = priority;
+        }
+        queue.insert(position, request);
+        self.queue_changed.notify_one();
+        true
+    }
+
+    /// Returns the user who started the rendering
+    pub fn get_requested_by(&self, rendering_id: uuid::Uuid) -> Option<uuid::Uuid>{
+        if let Some(request) = self.requests_archive.read().unwrap().get(&rendering_id){
+            return Some(request.read().unwrap().requested_by);
+        }
+        let queued = self.rendering_requests.read().unwrap().iter()
+            .map(|request| request.read().unwrap())
+            .find(|request| request.rendering_id == rendering_id)
+            .map(|request| request.requested_by);
+        queued.or_else(|| self.history.get(&rendering_id).map(|record| record.requested_by))
+    }
+
+    /// Adds a rendering which is done to the history, its output files are moved out of the temp directory
+    fn record(&self, request: &RenderingRequest, status: &RenderingStatus){
+        let rendering_id = request.rendering_id;
+        self.cancellations.write().unwrap().remove(&rendering_id);
+        let record = RenderingRecord{
+            id: rendering_id,
+            project_id: request.project_id,
+            requested_by: request.requested_by,
+            requested_at: request.requested_at,
+            finished_at: chrono::Utc::now().naive_utc(),
+            template_id: request.template_id,
+            export_type: ExportType::PDF,
+            options: request.options.clone(),
+            status: status.clone(),
+            error: status_error(status),
+            artifacts: vec![],
+            page_map: request.page_map.clone(),
+        };
+
+        let binding = format!("{}/temp/{}", self.settings.data_path, rendering_id);
+        let temp_dir = Path::new(&binding);
+        if let Err(e) = self.history.add(record, temp_dir, &request.log.stages()){
+            eprintln!("Couldn't add rendering {} to the history: {}", rendering_id, e);
+        }
+        if temp_dir.exists(){
+            if let Err(e) = std::fs::remove_dir_all(temp_dir){
+                eprintln!("Couldn't remove temp directory of rendering {}: {}", rendering_id, e);
             }
-            None => {
-                // Check if the request is still in the rendering_requests vector
-                let storage = self.rendering_requests.read().unwrap();
-                for request in storage.iter(){
-                    let request = request.read().unwrap();
-                    if request.rendering_id == rendering_id{
-                        return Some(request.status.clone());
-                    }
-                }
-                None
+        }
+    }
+
+    /// Returns the stage log of a rendering, while it's running only the stages done so far
+    pub fn get_log(&self, rendering_id: uuid::Uuid) -> Option<Vec<StageLog>>{
+        if let Some(request) = self.requests_archive.read().unwrap().get(&rendering_id){
+            return Some(request.read().unwrap().log.stages());
+        }
+        let queued = self.rendering_requests.read().unwrap().iter()
+            .any(|request| request.read().unwrap().rendering_id == rendering_id);
+        if queued{
+            return Some(vec![]);
+        }
+        self.history.get_log(&self.history.get(&rendering_id)?)
+    }
+
+    /// Cancels a rendering
+    ///
+    /// Queued renderings are removed from the queue, running ones are stopped and their processes killed.
+    /// Returns false if the rendering doesn't exist or is already done.
+    pub fn cancel(&self, rendering_id: uuid::Uuid) -> bool{
+        // The token is removed when the rendering is recorded as done
+        let token = match self.cancellations.read().unwrap().get(&rendering_id){
+            Some(token) => token.clone(),
+            None => return false,
+        };
+
+        let queued = {
+            let mut queue = self.rendering_requests.write().unwrap();
+            queue.iter()
+                .position(|request| request.read().unwrap().rendering_id == rendering_id)
+                .and_then(|position| queue.remove(position))
+        };
+        match queued{
+            Some(request) => {
+                let mut request = request.into_inner().unwrap();
+                self.record(&request, &RenderingStatus::Cancelled);
+                request.events.log("Rendering cancelled");
+                request.set_status(RenderingStatus::Cancelled);
             }
+            // Running or just taken out of the queue by the worker, which stops at its next check of the token
+            None => token.cancel(),
+        }
+        true
+    }
+
+    /// Subscribes to the status and log events of a rendering
+    pub fn subscribe(&self, rendering_id: uuid::Uuid) -> Option<(Vec<JobEvent>, rocket::tokio::sync::broadcast::Receiver<JobEvent>)>{
+        if let Some(request) = self.requests_archive.read().unwrap().get(&rendering_id){
+            return Some(request.read().unwrap().subscribe());
+        }
+        let queued = self.rendering_requests.read().unwrap().iter()
+            .map(|request| request.read().unwrap())
+            .find(|request| request.rendering_id == rendering_id)
+            .map(|request| request.subscribe());
+        queued.or_else(|| {
+            let status = serde_json::to_value(&self.history.get(&rendering_id)?.status).unwrap_or_default();
+            Some(JobProgress::default().subscribe(vec![JobEvent::Status { status, finished: true }]))
+        })
+    }
+
+    pub fn get_rendering_request_status(&self, rendering_id: uuid::Uuid) -> Option<RenderingStatus>{
+        if let Some(request) = self.requests_archive.read().unwrap().get(&rendering_id){
+            return Some(request.read().unwrap().status.clone());
+        }
+
+        // Check if the request is still in the rendering_requests vector
+        let queued = self.rendering_requests.read().unwrap().iter()
+            .map(|request| request.read().unwrap())
+            .find(|request| request.rendering_id == rendering_id)
+            .map(|request| request.status.clone());
+        // Finished renderings are only in the history
+        queued.or_else(|| self.history.get(&rendering_id).map(|record| record.status))
+    }
+}
+
+/// Position of the first queued rendering whose user and project are below their limit of running renderings
+///
+/// `running` holds user and project of each running rendering.
+fn next_position(queue: &VecDeque<RwLock<RenderingRequest>>, running: &[(uuid::Uuid, uuid::Uuid)], limits: &RenderingQueueSettings) -> Option<usize>{
+    queue.iter().position(|request| {
+        let request = request.read().unwrap();
+        let by_user = running.iter().filter(|(user, _)| *user == request.requested_by).count();
+        let by_project = running.iter().filter(|(_, project)| *project == request.project_id).count();
+        (limits.max_per_user == 0 || by_user < limits.max_per_user) && (limits.max_per_project == 0 || by_project < limits.max_per_project)
+    })
+}
+
+/// Error message of a failed or timed out rendering
+fn status_error(status: &RenderingStatus) -> Option<String>{
+    match status{
+        RenderingStatus::Failed(e) => Some(e.to_string()),
+        RenderingStatus::TimedOut => Some(RenderingError::TimedOut.to_string()),
+        _ => None,
+    }
+}
+
+pub mod api{
+    use std::sync::Arc;
+    use rocket::serde::json::Json;
+    use rocket::State;
+    use serde::Deserialize;
+    use crate::export::rendering_manager::{QueueEntry, RenderingManager};
+    use crate::projects::api::{ApiError, ApiResult};
+    use crate::session::session_guard::StaffSession;
+
+    #[derive(Deserialize)]
+    pub struct QueueMove{
+        /// New position in the queue, 0 starts the rendering next
+        pub position: usize,
+    }
+
+    /// GET /api/renderings/queue
+    /// Lists running and queued renderings of all projects
+    #[get("/api/renderings/queue")]
+    pub async fn get_rendering_queue(_session: StaffSession, rendering_manager: &State<Arc<RenderingManager>>) -> Json<ApiResult<Vec<QueueEntry>>>{
+        ApiResult::new_data(rendering_manager.queue_overview())
+    }
+
+    /// POST /api/renderings/queue/<render_id>/move
+    /// Moves a queued rendering to another position, e.g. {"position": 0}. Returns the updated queue
+    #[post("/api/renderings/queue/<render_id>/move", data = "<queue_move>")]
+    pub async fn move_queued_rendering(render_id: String, queue_move: Json<QueueMove>, _session: StaffSession, rendering_manager: &State<Arc<RenderingManager>>) -> Json<ApiResult<Vec<QueueEntry>>>{
+        let render_id = match uuid::Uuid::parse_str(&render_id) {
+            Ok(render_id) => render_id,
+            Err(e) => {
+                eprintln!("Couldn't parse render id: {}", e);
+                return ApiResult::new_error(ApiError::NotFound);
+            },
+        };
+
+        if !rendering_manager.move_request(render_id, queue_move.position){
+            return ApiResult::new_error(ApiError::NotFound);
         }
+        ApiResult::new_data(rendering_manager.queue_overview())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn request(requested_by: uuid::Uuid, project_id: uuid::Uuid) -> RwLock<RenderingRequest>{
+        RwLock::new(RenderingRequest{
+            rendering_id: uuid::Uuid::new_v4(),
+            requested_by,
+            project_id,
+            ..Default::default()
+        })
+    }
+
+    fn manager() -> RenderingManager{
+        let mut settings = Settings::new().unwrap();
+        // Cancelled renderings are recorded in the history
+        settings.data_path = std::env::temp_dir().join(format!("rendering_manager_{}", uuid::Uuid::new_v4())).to_string_lossy().to_string();
+        let data_storage = Arc::new(DataStorage::new());
+        RenderingManager::new(
+            settings.clone(),
+            Arc::new(ProjectStorage::new()),
+            data_storage.clone(),
+            Arc::new(CslData{ locales: vec![], styles: HashMap::new() }),
+            Arc::new(Mailer::new(&settings)),
+            Arc::new(WebhookManager::new(&settings, data_storage)),
+            Arc::new(RenderingHistory::new(&settings)),
+        )
+    }
+
+    fn project(name: &str) -> ProjectDataV2{
+        ProjectDataV2{
+            name: name.to_string(),
+            description: None,
+            template_id: uuid::Uuid::new_v4(),
+            last_interaction: 0,
+            metadata: None,
+            settings: None,
+            sections: vec![],
+            bibliography: HashMap::new(),
+            version: 0,
+            comments: vec![],
+            suggestions: vec![],
+            workflow: Default::default(),
+        }
+    }
+
+    fn preview() -> RenderingOptions{
+        RenderingOptions{
+            scope: RenderingScope::Sections { paths: vec![uuid::Uuid::new_v4().to_string()] },
+            ..Default::default()
+        }
+    }
+
+    fn queued_ids(manager: &RenderingManager) -> Vec<uuid::Uuid>{
+        manager.rendering_requests.read().unwrap().iter().map(|request| request.read().unwrap().rendering_id).collect()
+    }
+
+    #[test]
+    fn test_previews_are_queued_before_full_renderings(){
+        let manager = manager();
+        let user = uuid::Uuid::new_v4();
+        let first = manager.add_rendering_request(project("A"), uuid::Uuid::new_v4(), RenderingOptions::default(), user);
+        let second = manager.add_rendering_request(project("B"), uuid::Uuid::new_v4(), RenderingOptions::default(), user);
+        let first_preview = manager.add_rendering_request(project("C"), uuid::Uuid::new_v4(), preview(), user);
+        let second_preview = manager.add_rendering_request(project("D"), uuid::Uuid::new_v4(), preview(), user);
+
+        assert_eq!(queued_ids(&manager), vec![first_preview, second_preview, first, second]);
+    }
+
+    #[test]
+    fn test_duplicate_request_updates_queued_one(){
+        let manager = manager();
+        let (user, project_id) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let id = manager.add_rendering_request(project("Old name"), project_id, RenderingOptions::default(), user);
+        assert_eq!(manager.add_rendering_request(project("New name"), project_id, RenderingOptions::default(), user), id);
+
+        let queue = manager.rendering_requests.read().unwrap();
+        assert_eq!(queue.len(), 1);
+        let request = queue[0].read().unwrap();
+        assert_eq!(request.project_name, "New name");
+        assert_eq!(request.project_data.as_ref().unwrap().name, "New name");
+        drop(request);
+        drop(queue);
+
+        // Other options or another user are separate renderings
+        assert_ne!(manager.add_rendering_request(project("New name"), project_id, preview(), user), id);
+        assert_ne!(manager.add_rendering_request(project("New name"), project_id, RenderingOptions::default(), uuid::Uuid::new_v4()), id);
+        assert_eq!(queued_ids(&manager).len(), 3);
+    }
+
+    #[test]
+    fn test_moved_request_takes_priority_of_neighbour(){
+        let manager = manager();
+        let user = uuid::Uuid::new_v4();
+        let full_a = manager.add_rendering_request(project("A"), uuid::Uuid::new_v4(), RenderingOptions::default(), user);
+        let full_b = manager.add_rendering_request(project("B"), uuid::Uuid::new_v4(), RenderingOptions::default(), user);
+        let preview_c = manager.add_rendering_request(project("C"), uuid::Uuid::new_v4(), preview(), user);
+        assert_eq!(queued_ids(&manager), vec![preview_c, full_a, full_b]);
+
+        assert!(manager.move_request(full_b, 0));
+        assert_eq!(queued_ids(&manager), vec![full_b, preview_c, full_a]);
+        assert_eq!(manager.rendering_requests.read().unwrap()[0].read().unwrap().priority, RenderingPriority::Preview);
+
+        // New previews are queued behind it, so it keeps its place
+        let preview_d = manager.add_rendering_request(project("D"), uuid::Uuid::new_v4(), preview(), user);
+        assert_eq!(queued_ids(&manager), vec![full_b, preview_c, preview_d, full_a]);
+
+        assert!(manager.move_request(preview_c, 10));
+        assert_eq!(queued_ids(&manager), vec![full_b, preview_d, full_a, preview_c]);
+        assert_eq!(manager.rendering_requests.read().unwrap()[3].read().unwrap().priority, RenderingPriority::Full);
+
+        assert!(!manager.move_request(uuid::Uuid::new_v4(), 0));
+    }
+
+    #[test]
+    fn test_cancel_while_request_is_taken_out_of_queue(){
+        let manager = manager();
+        let queued = manager.add_rendering_request(project("A"), uuid::Uuid::new_v4(), RenderingOptions::default(), uuid::Uuid::new_v4());
+        let starting = manager.add_rendering_request(project("B"), uuid::Uuid::new_v4(), RenderingOptions::default(), uuid::Uuid::new_v4());
+
+        // Taken by the worker, but not yet in the archive
+        let request = manager.next_request().unwrap();
+        assert_eq!(request.read().unwrap().rendering_id, queued);
+        assert!(manager.cancel(queued));
+        assert!(request.read().unwrap().cancellation.is_cancelled());
+
+        assert!(manager.cancel(starting));
+        assert!(queued_ids(&manager).is_empty());
+        assert!(!manager.cancel(starting));
+        assert!(!manager.cancel(uuid::Uuid::new_v4()));
+    }
+
+    #[test]
+    fn test_next_position_respects_limits(){
+        let (busy_user, other_user) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let (busy_project, other_project) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let limits = RenderingQueueSettings{ max_per_user: 1, max_per_project: 2 };
+        let running = vec![(busy_user, busy_project), (other_user, busy_project)];
+
+        let queue: VecDeque<_> = vec![
+            request(busy_user, other_project),
+            request(other_user, busy_project),
+            request(other_user, other_project),
+        ].into();
+        assert_eq!(next_position(&queue, &running, &limits), None);
+        assert_eq!(next_position(&queue, &running[..1], &limits), Some(1));
+        assert_eq!(next_position(&queue, &[], &limits), Some(0));
+
+        let unlimited = RenderingQueueSettings{ max_per_user: 0, max_per_project: 0 };
+        assert_eq!(next_position(&queue, &running, &unlimited), Some(0));
+    }
+}
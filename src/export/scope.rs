@@ -0,0 +1,162 @@
+//! Scope of a rendering: the whole project, some of its sections or the front matter only.
+//!
+//! Scoped renderings are previews with the book's template, e.g. of the chapter an editor is working on.
+//! Notes are numbered per section, so their numbers are the same as in the full book. Links to sections and
+//! blocks outside the scope get a `data-out-of-scope` attribute with the title of the target section, templates
+//! can use it instead of the page reference, e.g. `a[data-out-of-scope]::after{ content: " (" attr(data-out-of-scope) ")" }`.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use crate::export::{PreparedContentBlock, PreparedSection};
+use crate::export::preprocessing::escape_html;
+use crate::projects::api::ApiError;
+use crate::projects::{Section, SectionOrToc};
+use crate::utils::api_helpers::parse_content_path;
+
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RenderingScope{
+    /// The whole book
+    #[default]
+    Project,
+    /// Sections by their content path ("<section_id>:<sub_section_id>"), rendered as top level sections in book order
+    Sections{
+        paths: Vec<String>,
+    },
+    /// Only the parts of the template outside the sections, e.g. title pages and imprint
+    FrontMatter,
+}
+
+impl RenderingScope{
+    /// Checks that all sections of the scope exist
+    pub fn validate(&self, sections: &[SectionOrToc]) -> Result<(), ApiError>{
+        if let RenderingScope::Sections { paths } = self{
+            if paths.is_empty(){
+                return Err(ApiError::BadRequest("No sections in rendering scope".to_string()));
+            }
+            for path in paths{
+                let path = parse_content_path(path)?;
+                if !section_exists(sections, &path){
+                    return Err(ApiError::NotFound);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits the sections of a project into the ones to render and the link targets outside the scope
+    ///
+    /// Returns the sections in book order and the ids of sections and content blocks outside the scope,
+    /// mapped to the title of their section.
+    pub fn select(&self, sections: Vec<SectionOrToc>) -> (Vec<Section>, HashMap<String, String>){
+        let sections: Vec<Section> = sections.into_iter().filter_map(|section| match section{
+            SectionOrToc::Section(section) => Some(section),
+            _ => None,
+        }).collect();
+
+        let selected: Vec<Vec<uuid::Uuid>> = match self{
+            RenderingScope::Project => return (sections, HashMap::new()),
+            RenderingScope::Sections { paths } => paths.iter().filter_map(|path| parse_content_path(path).ok()).collect(),
+            RenderingScope::FrontMatter => vec![],
+        };
+
+        let mut in_scope = vec![];
+        let mut outside = HashMap::new();
+        select_sections(sections, &mut vec![], &selected, &mut in_scope, &mut outside);
+        (in_scope, outside)
+    }
+}
+
+fn section_exists(sections: &[SectionOrToc], path: &[uuid::Uuid]) -> bool{
+    let mut current: Option<&Section> = sections.iter().find_map(|section| match section{
+        SectionOrToc::Section(section) if section.id.unwrap_or_default() == path[0] => Some(section),
+        _ => None,
+    });
+    for part in path.iter().skip(1){
+        current = current.and_then(|section| section.sub_sections.iter().find(|sub_section| sub_section.id.unwrap_or_default() == *part));
+    }
+    current.is_some()
+}
+
+/// Walks the section tree, selected sections are taken with all their sub sections
+fn select_sections(sections: Vec<Section>, path: &mut Vec<uuid::Uuid>, selected: &[Vec<uuid::Uuid>], in_scope: &mut Vec<Section>, outside: &mut HashMap<String, String>){
+    for section in sections{
+        path.push(section.id.unwrap_or_default());
+        if selected.contains(path){
+            in_scope.push(section);
+        }else{
+            let title = section.metadata.title.clone();
+            outside.insert(section.id.unwrap_or_default().to_string(), title.clone());
+            for block in section.children.iter(){
+                outside.insert(block.id.clone(), title.clone());
+            }
+            select_sections(section.sub_sections, path, selected, in_scope, outside);
+        }
+        path.pop();
+    }
+}
+
+/// Marks links to targets outside the scope in all blocks of the section and its sub sections
+pub fn mark_out_of_scope_links(section: &mut PreparedSection, outside: &HashMap<String, String>){
+    for block in section.children.iter_mut(){
+        mark_block(block, outside);
+    }
+    for sub_section in section.sub_sections.iter_mut(){
+        mark_out_of_scope_links(sub_section, outside);
+    }
+}
+
+fn mark_block(block: &mut PreparedContentBlock, outside: &HashMap<String, String>){
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    let link = LINK.get_or_init(|| Regex::new(r##"href="#([^"]+)""##).unwrap());
+
+    block.html = link.replace_all(&block.html, |caps: &Captures| match outside.get(&caps[1]){
+        Some(title) => format!("{} data-out-of-scope=\"{}\"", &caps[0], escape_html(title)),
+        None => caps[0].to_string(),
+    }).to_string();
+    for child in block.children.iter_mut(){
+        mark_block(child, outside);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::projects::SectionMetadata;
+
+    fn section(title: &str, sub_sections: Vec<Section>) -> Section{
+        Section{
+            id: Some(uuid::Uuid::new_v4()),
+            sub_sections,
+            visible_in_toc: true,
+            metadata: SectionMetadata{ title: title.to_string(), ..Default::default() },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_select_sections(){
+        let sub = section("1.1", vec![]);
+        let first = section("1", vec![sub.clone()]);
+        let second = section("2", vec![]);
+        let sections = vec![SectionOrToc::Section(first.clone()), SectionOrToc::Section(second.clone())];
+        let path = |sections: &[&Section]| sections.iter().map(|section| section.id.unwrap().to_string()).collect::<Vec<_>>().join(":");
+
+        // Book order, regardless of the order of the paths
+        let scope = RenderingScope::Sections { paths: vec![path(&[&second]), path(&[&first, &sub])] };
+        assert!(scope.validate(&sections).is_ok());
+        let (in_scope, outside) = scope.select(sections.clone());
+        assert_eq!(in_scope.iter().map(|section| section.metadata.title.as_str()).collect::<Vec<_>>(), vec!["1.1", "2"]);
+        assert_eq!(outside.get(&first.id.unwrap().to_string()), Some(&"1".to_string()));
+        assert_eq!(outside.len(), 1);
+
+        let (in_scope, outside) = RenderingScope::FrontMatter.select(sections.clone());
+        assert!(in_scope.is_empty());
+        assert_eq!(outside.len(), 3);
+
+        let missing = RenderingScope::Sections { paths: vec![path(&[&second, &sub])] };
+        assert!(missing.validate(&sections).is_err());
+    }
+}
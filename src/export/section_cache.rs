@@ -0,0 +1,160 @@
+//! Cache of prepared sections, so renderings only prepare the sections which changed since the last rendering.
+//!
+//! Preparing a section (person lookups, hyphenation, citations, math) is the slow part of [prepare_project] for big
+//! volumes. Entries are keyed by a hash of everything a prepared top-level section depends on: the section including
+//! its sub sections, the persons referenced in it, the rendered citations it might use and the template.
+//! Settings are only read at startup, so they can't change during the lifetime of the cache.
+//!
+//! [prepare_project]: crate::export::preprocessing::prepare_project
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use sha2::{Digest, Sha256};
+use crate::data_storage::DataStorage;
+use crate::export::PreparedSection;
+use crate::projects::Section;
+
+struct CacheEntry{
+    section: PreparedSection,
+    /// Value of [CacheState::clock] when the entry was used last, the least recently used entry is evicted first
+    last_used: u64,
+}
+
+struct CacheState{
+    entries: HashMap<String, CacheEntry>,
+    /// Incremented on every lookup
+    clock: u64,
+}
+
+pub struct PreparedSectionCache{
+    /// Maximum number of cached sections, 0 disables the cache
+    capacity: usize,
+    state: Mutex<CacheState>,
+}
+
+impl PreparedSectionCache{
+    pub fn new(capacity: usize) -> PreparedSectionCache{
+        PreparedSectionCache{
+            capacity,
+            state: Mutex::new(CacheState{
+                entries: HashMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    /// Calculates the key of a top-level section
+    ///
+    /// Citations are included if their key appears anywhere in the section, which might include some unused ones
+    /// but never misses one.
+    pub fn key(section: &Section, template_id: &uuid::Uuid, data_storage: &DataStorage, citation_bib: &HashMap<String, String>) -> String{
+        let section_json = serde_json::to_string(section).unwrap_or_default();
+
+        let mut hasher = Sha256::new();
+        hasher.update(template_id.as_bytes());
+        hasher.update(section_json.as_bytes());
+
+        let mut persons = vec![];
+        collect_persons(section, &mut persons);
+        for person_id in persons{
+            if let Some(person) = data_storage.get_person(&person_id){
+                hasher.update(serde_json::to_string(&*person.read().unwrap()).unwrap_or_default().as_bytes());
+            }
+        }
+
+        let mut citations: Vec<(&String, &String)> = citation_bib.iter()
+            .filter(|(key, _)| section_json.contains(key.as_str()))
+            .collect();
+        citations.sort();
+        for (key, citation) in citations{
+            hasher.update(key.as_bytes());
+            hasher.update(citation.as_bytes());
+        }
+
+        hex::encode(hasher.finalize())
+    }
+
+    /// Returns the cached section for the key or prepares and caches it
+    pub fn get_or_prepare<F: FnOnce() -> PreparedSection>(&self, key: String, prepare: F) -> PreparedSection{
+        if self.capacity == 0{
+            return prepare();
+        }
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.clock += 1;
+            let clock = state.clock;
+            if let Some(entry) = state.entries.get_mut(&key){
+                entry.last_used = clock;
+                return entry.section.clone();
+            }
+        }
+
+        // Prepare without holding the lock, other renderings may use the cache meanwhile
+        let section = prepare();
+
+        let mut state = self.state.lock().unwrap();
+        if state.entries.len() >= self.capacity && !state.entries.contains_key(&key){
+            let oldest = state.entries.iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest{
+                state.entries.remove(&oldest);
+            }
+        }
+        let last_used = state.clock;
+        state.entries.insert(key, CacheEntry{ section: section.clone(), last_used });
+        section
+    }
+}
+
+/// Collects the authors and editors of the section and all its sub sections
+pub(crate) fn collect_persons(section: &Section, persons: &mut Vec<uuid::Uuid>){
+    persons.extend(section.metadata.authors.iter());
+    persons.extend(section.metadata.editors.iter());
+    for sub_section in section.sub_sections.iter(){
+        collect_persons(sub_section, persons);
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::export::{PreparedLanguage, PreparedSectionMetadata};
+
+    fn prepared(title: &str) -> PreparedSection{
+        PreparedSection{
+            id: uuid::Uuid::new_v4(),
+            sub_sections: vec![],
+            children: vec![],
+            metadata: PreparedSectionMetadata{
+                title: title.to_string(),
+                subtitle: None,
+                authors: vec![],
+                editors: vec![],
+                web_url: None,
+                identifiers: vec![],
+                published: None,
+                lang: PreparedLanguage{ de: false, en: true },
+            },
+            visible_in_toc: true,
+            endnotes: vec![],
+        }
+    }
+
+    #[test]
+    fn test_get_or_prepare_evicts_least_recently_used(){
+        let cache = PreparedSectionCache::new(2);
+        assert_eq!(cache.get_or_prepare("a".to_string(), || prepared("A")).metadata.title, "A");
+        cache.get_or_prepare("b".to_string(), || prepared("B"));
+
+        // Cached, so the closure isn't called
+        assert_eq!(cache.get_or_prepare("a".to_string(), || panic!("a should be cached")).metadata.title, "A");
+
+        // b is the least recently used entry
+        cache.get_or_prepare("c".to_string(), || prepared("C"));
+        assert_eq!(cache.state.lock().unwrap().entries.len(), 2);
+        assert_eq!(cache.get_or_prepare("b".to_string(), || prepared("B2")).metadata.title, "B2");
+        assert_eq!(cache.get_or_prepare("c".to_string(), || panic!("c should be cached")).metadata.title, "C");
+    }
+}
@@ -5,20 +5,29 @@ use std::sync::{Arc, RwLock};
 use hayagriva::{io};
 
 use html_parser::{Dom, Node};
-use pandoc::{InputFormat, InputKind, OutputFormat, OutputKind, PandocOutput};
+use pandoc::{InputFormat, InputKind, OutputFormat, OutputKind, PandocOption, PandocOutput};
 
 use rocket::http::ContentType;
 use serde::{Deserialize, Serialize};
-use crate::data_storage::{BibEntryV2, ProjectDataV2, ProjectStorage};
+use crate::export::preprocessing::{escape_html, unescape_html};
+use crate::data_storage::{BibEntryV2, DataStorage, ProjectDataV2, ProjectStorage};
+use crate::mail::Mailer;
+use crate::mail::notifications::{notify, Notification};
 use crate::settings::Settings;
 use tokio::io::AsyncReadExt;
 use crate::import::wordpress::{WordpressAPI, WordpressAPIError};
 use crate::projects::{BlockData, BlockType, Identifier, IdentifierType, NewContentBlock, Section, SectionMetadata, SectionOrToc};
 use crate::utils::block_id_generator::generate_id;
+use crate::utils::html_sanitizer::HtmlSanitizer;
+use crate::utils::job_events::{JobEvent, JobProgress};
+use crate::webhooks::{WebhookEvent, WebhookManager};
 
 pub struct ImportProcessor{
     pub settings: Settings,
     pub project_storage: Arc<ProjectStorage>,
+    pub data_storage: Arc<DataStorage>,
+    pub mailer: Arc<Mailer>,
+    pub webhooks: Arc<WebhookManager>,
     pub job_queue: RwLock<VecDeque<ImportJob>>,
     pub job_archive: RwLock<HashMap<uuid::Uuid, Arc<RwLock<ImportJob>>>>,
 }
@@ -31,6 +40,12 @@ pub enum ImportStatus{
     Failed
 }
 
+impl ImportStatus{
+    pub fn is_final(&self) -> bool{
+        matches!(self, ImportStatus::Complete | ImportStatus::Failed)
+    }
+}
+
 #[derive(Debug)]
 pub enum ImportError{
     UnknownFileType,
@@ -62,13 +77,43 @@ pub struct ImportJob{
     pub wordpress_post_links_to_convert: Option<VecDeque<String>>,
     pub bib_file: Option<String>,
     pub status: ImportStatus,
+    /// User who started the import, gets notified if it fails
+    pub requested_by: uuid::Uuid,
+    /// Status transitions, progress and log lines for the event stream
+    pub events: JobProgress,
+}
+
+impl ImportJob{
+    /// Sets the status and publishes it to the event stream
+    pub fn set_status(&mut self, status: ImportStatus){
+        self.events.status(&status, status.is_final());
+        self.status = status;
+    }
+
+    /// Counts a processed file or post and publishes the progress
+    pub fn increment_processed(&mut self){
+        self.processed += 1;
+        self.events.progress(self.processed, self.length);
+    }
+
+    /// Subscribes to the event stream, starting with the current status and progress
+    pub fn subscribe(&self) -> (Vec<JobEvent>, rocket::tokio::sync::broadcast::Receiver<JobEvent>){
+        let status = serde_json::to_value(&self.status).unwrap_or_default();
+        self.events.subscribe(vec![
+            JobEvent::Progress { processed: self.processed, length: self.length },
+            JobEvent::Status { status, finished: self.status.is_final() },
+        ])
+    }
 }
 
 impl ImportProcessor{
-    pub fn start(settings: Settings, project_storage: Arc<ProjectStorage>) -> Arc<ImportProcessor>{
+    pub fn start(settings: Settings, project_storage: Arc<ProjectStorage>, data_storage: Arc<DataStorage>, mailer: Arc<Mailer>, webhooks: Arc<WebhookManager>) -> Arc<ImportProcessor>{
         let processor = Arc::new(ImportProcessor{
             settings,
             project_storage,
+            data_storage,
+            mailer,
+            webhooks,
             job_queue: RwLock::new(VecDeque::new()),
             job_archive: RwLock::new(HashMap::new()),
         });
@@ -95,11 +140,12 @@ impl ImportProcessor{
                                 return;
                             }
                         };
-                        job.status = ImportStatus::Processing;
+                        job.set_status(ImportStatus::Processing);
                         let job = Arc::new(RwLock::new(job));
                         proc_clone.job_archive.write().unwrap().insert(job.read().unwrap().id, job.clone());
-                        proc_clone.process_job(job, proc_clone.project_storage.clone()).await;
+                        proc_clone.process_job(job.clone(), proc_clone.project_storage.clone()).await;
                         println!("Job finished");
+                        proc_clone.notify_result(&job).await;
                         running_threads_cpy.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
                     });
                 }else{
@@ -111,6 +157,27 @@ impl ImportProcessor{
         processor
     }
 
+    /// Triggers the import webhooks and notifies the user who started the import if it failed
+    async fn notify_result(&self, job: &Arc<RwLock<ImportJob>>){
+        let (import_id, project_id, requested_by, failed) = {
+            let job = job.read().unwrap();
+            (job.id, job.project_id, job.requested_by, matches!(job.status, ImportStatus::Failed))
+        };
+
+        let project_name = match self.project_storage.get_project(&project_id, &self.settings).await{
+            Ok(project) => project.read().unwrap().name.clone(),
+            Err(_) => String::new(),
+        };
+        let data = serde_json::json!({"import_id": import_id, "project_name": project_name});
+
+        if failed{
+            self.webhooks.trigger(project_id, WebhookEvent::ImportFailed, &data);
+            notify(&self.mailer, &self.data_storage, &requested_by, &Notification::ImportFailed { project_id, project_name, import_id });
+        }else{
+            self.webhooks.trigger(project_id, WebhookEvent::ImportCompleted, &data);
+        }
+    }
+
     async fn process_job(&self, job: Arc<RwLock<ImportJob>>, project_storage: Arc<ProjectStorage>){
         let job = job.clone();
 
@@ -122,10 +189,13 @@ impl ImportProcessor{
             match self.import_bib_entries(project_id, bib_file, &self.settings).await{
                 Ok(_) => {
                     println!("Bib entries imported successfully");
+                    job.write().unwrap().events.log("Bibliography imported");
                 }
                 Err(e) => {
                     println!("Error importing bib entries: {:?}", e);
-                    job.write().unwrap().status = ImportStatus::Failed;
+                    let mut job = job.write().unwrap();
+                    job.events.log(format!("Couldn't import bibliography: {:?}", e));
+                    job.set_status(ImportStatus::Failed);
                     return;
                 }
             }
@@ -149,7 +219,7 @@ impl ImportProcessor{
                 let (file, content_type) = match res {
                     Some(f) => f,
                     None => {
-                        job.write().unwrap().status = ImportStatus::Complete;
+                        job.write().unwrap().set_status(ImportStatus::Complete);
                         break;
                     }
                 };
@@ -162,6 +232,7 @@ impl ImportProcessor{
                 match self.convert_file(&file, content_type, project, endnotes).await {
                     Ok(_) => {
                         println!("File processed successfully");
+                        job.write().unwrap().events.log("File processed");
                         // Remove file from temp directory
                         let res = tokio::fs::remove_file(file).await;
                         if let Err(e) = res {
@@ -170,7 +241,11 @@ impl ImportProcessor{
                     }
                     Err(e) => {
                         println!("Error processing file: {:?}", e);
-                        job.write().unwrap().status = ImportStatus::Failed;
+                        {
+                            let mut job = job.write().unwrap();
+                            job.events.log(format!("Couldn't process file: {:?}", e));
+                            job.set_status(ImportStatus::Failed);
+                        }
                         // Remove files from temp directory
                         let res = tokio::fs::remove_file(file).await;
                         if let Err(e) = res {
@@ -198,20 +273,23 @@ impl ImportProcessor{
                     match self.import_by_url(&url, project, endnotes, shift_headings_up, convert_links).await{
                         Ok(_) => {
                             println!("Wordpress Post processed successfully");
+                            job.write().unwrap().events.log(format!("Imported {}", url));
                         }
                         Err(e) => {
                             println!("Error processing wordpress post: {:?}", e);
-                            job.write().unwrap().status = ImportStatus::Failed;
+                            let mut job = job.write().unwrap();
+                            job.events.log(format!("Couldn't import {}: {:?}", url, e));
+                            job.set_status(ImportStatus::Failed);
                             break;
                         }
                     }
                 }
             }else{
-                job.write().unwrap().status = ImportStatus::Complete;
+                job.write().unwrap().set_status(ImportStatus::Complete);
                 break;
             }
 
-            job.write().unwrap().processed += 1;
+            job.write().unwrap().increment_processed();
         }
     }
 
@@ -325,6 +403,9 @@ impl ImportProcessor{
                 last_changed: Some(post.modified),
                 lang: None,
             },
+            version: 0,
+            workflow: Default::default(),
+            assignment: Default::default(),
         };
 
         self.import_html_from_wp(section, post.content.rendered.clone(), project, endnotes, shift_headings_up, convert_links).await
@@ -392,6 +473,8 @@ impl ImportProcessor{
             pandoc.set_input(InputKind::Pipe(input));
             pandoc.set_input_format(input_format, vec![]);
             pandoc.set_output_format(OutputFormat::Html5, vec![]);
+            // Keep formulas as LaTeX instead of pandoc's plain text approximation
+            pandoc.add_option(PandocOption::MathJax(None));
             pandoc.set_output(OutputKind::Pipe);
             match pandoc.execute(){
                 Ok(res) => {
@@ -500,6 +583,7 @@ impl ImportProcessor{
                     section.children.push(cb);
                 }
                 Node::Element(el) => {
+                    let el = unwrap_source_code(el);
                     match el.name.to_lowercase().as_str(){
                         "h1" | "h2" | "h4" | "h5" | "h6" => {
                             let mut level = match el.name.to_lowercase().as_str(){
@@ -569,6 +653,19 @@ impl ImportProcessor{
                                 revision_id: None,
                             });
                         },
+                        "pre" => {
+                            let (code, language) = pre_to_code(&el);
+                            section.children.push(NewContentBlock{
+                                id: generate_id(&section),
+                                block_type: BlockType::Code,
+                                data: BlockData::Code {
+                                    code,
+                                    language,
+                                },
+                                css_classes: vec![],
+                                revision_id: None,
+                            });
+                        },
                         "blockquote" => {
                             section.children.push(NewContentBlock{
                                 id: generate_id(&section),
@@ -619,6 +716,9 @@ impl ImportProcessor{
             }
         }
 
+        // Imported html may contain anything, only keep allowed elements and attributes
+        HtmlSanitizer::new(&self.settings.html_sanitizer).sanitize_section(&mut section);
+
         project_data.write().unwrap().sections.push(SectionOrToc::Section(section));
         Ok(())
 
@@ -653,6 +753,9 @@ impl ImportProcessor{
                 last_changed: None,
                 lang: None,
             },
+            version: 0,
+            workflow: Default::default(),
+            assignment: Default::default(),
         };
 
         // Get footnotes:
@@ -704,8 +807,7 @@ impl ImportProcessor{
                                                                         text.push_str(&format!("<a {}>{}</a>", attributes, &self.dom_to_html(ele.clone(), None, endnotes, false, project_data.clone()).await));
                                                                     },
                                                                     _ => {
-                                                                        // TODO: whitelist elements and attributes
-                                                                        // Currently we allow all elements but strip attributes
+                                                                        // Attributes are stripped here, elements are filtered by the HtmlSanitizer afterwards
                                                                         text.push_str(&format!("<{}>{}</{}>", ele.name, &self.dom_to_html(ele.clone(), None, endnotes, false, project_data.clone()).await, ele.name));
                                                                     },
                                                                 }
@@ -747,6 +849,7 @@ impl ImportProcessor{
                     section.children.push(cb);
                 }
                 Node::Element(el) => {
+                    let el = unwrap_source_code(el);
                     match el.name.to_lowercase().as_str(){
                         "h1" | "h2" | "h4" | "h5" | "h6" => {
                             let level = match el.name.to_lowercase().as_str(){
@@ -770,6 +873,26 @@ impl ImportProcessor{
                             })
                         },
                         "p" => {
+                            // Paragraphs only containing a display formula become a math block
+                            let display_math = match (el.children.len(), el.children.first()){
+                                (1, Some(Node::Element(math))) => match pandoc_math_to_latex(math){
+                                    Some((latex, true)) => Some(latex),
+                                    _ => None
+                                },
+                                _ => None
+                            };
+                            if let Some(latex) = display_math{
+                                section.children.push(NewContentBlock{
+                                    id: generate_id(&section),
+                                    block_type: BlockType::Math,
+                                    data: BlockData::Math {
+                                        latex,
+                                    },
+                                    css_classes: vec![],
+                                    revision_id: None,
+                                });
+                                continue;
+                            }
                             section.children.push(NewContentBlock{
                                 id: generate_id(&section),
                                 block_type: BlockType::Paragraph,
@@ -809,6 +932,19 @@ impl ImportProcessor{
                                 revision_id: None,
                             });
                         },
+                        "pre" => {
+                            let (code, language) = pre_to_code(&el);
+                            section.children.push(NewContentBlock{
+                                id: generate_id(&section),
+                                block_type: BlockType::Code,
+                                data: BlockData::Code {
+                                    code,
+                                    language,
+                                },
+                                css_classes: vec![],
+                                revision_id: None,
+                            });
+                        },
                         "blockquote" => {
                             section.children.push(NewContentBlock{
                                 id: generate_id(&section),
@@ -850,6 +986,9 @@ impl ImportProcessor{
             }
         }
 
+        // Imported html may contain anything, only keep allowed elements and attributes
+        HtmlSanitizer::new(&self.settings.html_sanitizer).sanitize_section(&mut section);
+
         project_data.write().unwrap().sections.push(SectionOrToc::Section(section));
         Ok(())
     }
@@ -945,9 +1084,18 @@ impl ImportProcessor{
                         }
                     }
 
+                    // Formulas from pandoc are converted to inline math marks
+                    if el.name == "span" || el.name == "math"{
+                        if let Some((latex, _)) = pandoc_math_to_latex(&el){
+                            let latex = escape_html(&latex);
+                            html.push_str(&format!("<math-inline data-latex=\"{}\">{}</math-inline>", latex, latex));
+                            continue;
+                        }
+                    }
+
                     let mut attrs : String = String::new();
                     for (attr, attrvalue) in el.attributes.iter(){
-                        match attrvalue{ //TODO: whitelist attributes that are allowed for each tag, e.g. href for a-tags
+                        match attrvalue{ // Attributes are filtered by the HtmlSanitizer before the section is stored
                             Some(value) => attrs.push_str(&format!(" {}=\"{}\"", attr, value)),
                             None => attrs.push_str(&format!(" {}", attr)),
                         }
@@ -995,6 +1143,119 @@ impl ImportProcessor{
     }
 }
 
+/// Classes pandoc adds to highlighted code, which aren't the language
+const SOURCE_CODE_CLASSES: [&str; 3] = ["sourceCode", "numberSource", "numberLines"];
+
+/// Pandoc wraps highlighted code blocks in `<div class="sourceCode">`, returns the `<pre>` element inside of it
+fn unwrap_source_code(el: html_parser::Element) -> html_parser::Element{
+    if el.name != "div" || !el.classes.contains(&"sourceCode".to_string()){
+        return el
+    }
+    match el.children.iter().find_map(|node| match node{
+        Node::Element(pre) if pre.name == "pre" => Some(pre.clone()),
+        _ => None
+    }){
+        Some(pre) => pre,
+        None => el
+    }
+}
+
+/// Converts a `<pre>` element into the plain code and the optional language label
+///
+/// Pandoc writes code as `<pre class="sourceCode rust"><code class="sourceCode rust">...</code></pre>` with one
+/// `<span id="cb1-1">` per line, other sources often use a `language-` prefixed class on the code element.
+fn pre_to_code(el: &html_parser::Element) -> (String, Option<String>){
+    fn collect_text(el: &html_parser::Element, res: &mut String){
+        for node in el.children.iter(){
+            match node{
+                Node::Text(t) => res.push_str(t),
+                Node::Element(el) => {
+                    // Lines of highlighted code, the line breaks between them aren't always kept by the parser
+                    if el.name == "span" && el.id.is_some() && !res.is_empty() && !res.ends_with('\n'){
+                        res.push('\n');
+                    }
+                    collect_text(el, res)
+                },
+                Node::Comment(_) => {}
+            }
+        }
+    }
+
+    let mut classes = el.classes.clone();
+    for node in el.children.iter(){
+        if let Node::Element(code) = node{
+            if code.name == "code"{
+                classes.extend(code.classes.clone());
+            }
+        }
+    }
+    let language = classes.iter()
+        .map(|class| class.strip_prefix("language-").unwrap_or(class))
+        .find(|class| !SOURCE_CODE_CLASSES.contains(class) && !class.is_empty())
+        .map(|class| class.to_string());
+
+    let mut code = String::new();
+    collect_text(el, &mut code);
+    let code = code.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&#39;", "'").replace("&amp;", "&");
+
+    (code, language)
+}
+
+/// Extracts the LaTeX source from pandoc's math output
+///
+/// With MathJax output pandoc writes formulas as `<span class="math inline">\(...\)</span>` or `<span class="math display">\[...\]</span>`.
+/// If the formula was written as MathML, the LaTeX source is taken from its `application/x-tex` annotation.
+///
+/// Returns the LaTeX source and true if the formula is a display formula
+fn pandoc_math_to_latex(el: &html_parser::Element) -> Option<(String, bool)>{
+    if el.name == "math"{
+        let display = el.attributes.get("display").cloned().flatten().as_deref() == Some("block");
+        return tex_annotation(el).map(|latex| (latex, display))
+    }
+    if el.name != "span" || !el.classes.contains(&"math".to_string()){
+        return None
+    }
+    let display = el.classes.contains(&"display".to_string());
+
+    if let Some(latex) = el.children.iter().find_map(|node| match node{
+        Node::Element(math) if math.name == "math" => tex_annotation(math),
+        _ => None
+    }){
+        return Some((latex, display))
+    }
+
+    let mut latex = String::new();
+    for node in el.children.iter(){
+        if let Node::Text(t) = node{
+            latex.push_str(t);
+        }
+    }
+    let latex = latex.trim();
+    let latex = latex.strip_prefix("\\(").or_else(|| latex.strip_prefix("\\[")).unwrap_or(latex);
+    let latex = latex.strip_suffix("\\)").or_else(|| latex.strip_suffix("\\]")).unwrap_or(latex);
+
+    Some((unescape_html(latex.trim()), display))
+}
+
+/// Finds the `<annotation encoding="application/x-tex">` of a MathML formula
+fn tex_annotation(el: &html_parser::Element) -> Option<String>{
+    for node in el.children.iter(){
+        if let Node::Element(child) = node{
+            if child.name == "annotation" && child.attributes.get("encoding").cloned().flatten().as_deref() == Some("application/x-tex"){
+                let latex: String = child.children.iter().filter_map(|n| match n{
+                    Node::Text(t) => Some(t.as_str()),
+                    _ => None
+                }).collect();
+                return Some(unescape_html(latex.trim()))
+            }
+            if let Some(latex) = tex_annotation(child){
+                return Some(latex)
+            }
+        }
+    }
+    None
+}
+
 /// Contains preprocessing methods that get called, BEFORE pandoc is executed.
 mod preprocess{
     use regex::Regex;
@@ -4,6 +4,7 @@ use std::sync::Arc;
 use rocket::form::Form;
 use rocket::fs::TempFile;
 use rocket::http::ContentType;
+use rocket::response::stream::EventStream;
 use rocket::serde::json::Json;
 use rocket::State;
 use serde::{Deserialize, Serialize};
@@ -11,11 +12,12 @@ use uuid::Uuid;
 use crate::data_storage::ProjectStorage;
 use crate::import::processing::{ImportJob, ImportProcessor, ImportStatus, ImportStatusPoll};
 use crate::projects::api::{ApiError, ApiResult};
-use crate::session::session_guard::Session;
+use crate::session::session_guard::StaffSession;
 use crate::settings::Settings;
+use crate::utils::job_events::event_stream;
 
 #[derive(FromForm)]
-struct FileUpload<'r>{
+pub struct FileUpload<'r>{
     files: Vec<TempFile<'r>>,
     bib_file: Option<TempFile<'r>>,
     project_id: String,
@@ -23,7 +25,7 @@ struct FileUpload<'r>{
 }
 
 #[derive(Serialize, Deserialize)]
-struct WordpressImport{
+pub struct WordpressImport{
     project_id: uuid::Uuid,
     endnotes: bool,
     links: Vec<String>,
@@ -32,7 +34,7 @@ struct WordpressImport{
 }
 
 #[post("/api/import/upload", data = "<upload>")]
-pub async fn import_from_upload(mut upload: Form<FileUpload<'_>>, _session: Session, settings: &State<Settings>, _project_storage: &State<Arc<ProjectStorage>>, import_processor: &State<Arc<ImportProcessor>>) -> Json<ApiResult<uuid::Uuid>>{
+pub async fn import_from_upload(mut upload: Form<FileUpload<'_>>, session: StaffSession, settings: &State<Settings>, _project_storage: &State<Arc<ProjectStorage>>, import_processor: &State<Arc<ImportProcessor>>) -> Json<ApiResult<uuid::Uuid>>{
     println!("Uploading files to project {}", upload.project_id);
 
     let mut file_paths: VecDeque<(String, ContentType)> = VecDeque::new();
@@ -78,7 +80,9 @@ pub async fn import_from_upload(mut upload: Form<FileUpload<'_>>, _session: Sess
         wordpress_post_links_to_convert: None,
         status: ImportStatus::Pending,
         shift_headings_up: false,
-        convert_links: false
+        convert_links: false,
+        requested_by: session.user_id,
+        events: Default::default(),
     };
 
     import_processor.job_queue.write().unwrap().push_back(import_job);
@@ -87,7 +91,7 @@ pub async fn import_from_upload(mut upload: Form<FileUpload<'_>>, _session: Sess
 }
 
 #[post("/api/import/wordpress", data = "<job>")]
-pub async fn import_from_wordpress(job: Json<WordpressImport>, _session: Session, _settings: &State<Settings>, import_processor: &State<Arc<ImportProcessor>>) -> Json<ApiResult<uuid::Uuid>>{
+pub async fn import_from_wordpress(job: Json<WordpressImport>, session: StaffSession, _settings: &State<Settings>, import_processor: &State<Arc<ImportProcessor>>) -> Json<ApiResult<uuid::Uuid>>{
     let id = Uuid::new_v4();
 
     let import_job = ImportJob{
@@ -101,7 +105,9 @@ pub async fn import_from_wordpress(job: Json<WordpressImport>, _session: Session
         status: ImportStatus::Pending,
         bib_file: None,
         shift_headings_up: job.shift_headings,
-        convert_links: job.convert_links
+        convert_links: job.convert_links,
+        requested_by: session.user_id,
+        events: Default::default(),
     };
 
     import_processor.job_queue.write().unwrap().push_back(import_job);
@@ -109,7 +115,7 @@ pub async fn import_from_wordpress(job: Json<WordpressImport>, _session: Session
 }
 
 #[get("/api/import/status/<id>")]
-pub async fn poll_import_status(id: String, _session: Session, import_processor: &State<Arc<ImportProcessor>>) -> Json<ApiResult<ImportStatusPoll>>{
+pub async fn poll_import_status(id: String, _session: StaffSession, import_processor: &State<Arc<ImportProcessor>>) -> Json<ApiResult<ImportStatusPoll>>{
     let job_archive = import_processor.job_archive.read().unwrap();
 
     let id = match uuid::Uuid::parse_str(&id){
@@ -140,4 +146,18 @@ pub async fn poll_import_status(id: String, _session: Session, import_processor:
         }),
         None => ApiResult::new_error(ApiError::NotFound)
     }
-}
\ No newline at end of file
+}
+
+/// GET /api/import/events/<id>
+/// Server-sent events with the status transitions, progress and log lines of an import, ends when the import is done
+#[get("/api/import/events/<id>")]
+pub async fn stream_import_events(id: String, _session: StaffSession, import_processor: &State<Arc<ImportProcessor>>) -> Option<EventStream![]>{
+    let id = uuid::Uuid::parse_str(&id).ok()?;
+
+    let subscription = match import_processor.job_archive.read().unwrap().get(&id){
+        Some(job) => Some(job.read().unwrap().subscribe()),
+        None => import_processor.job_queue.read().unwrap().iter().find(|job| job.id == id).map(|job| job.subscribe()),
+    };
+    let (initial, receiver) = subscription?;
+    Some(event_stream(initial, receiver))
+}
@@ -0,0 +1,295 @@
+//! Outgoing mail through the SMTP relay configured in [MailSettings].
+//!
+//! Mails are rendered from the handlebars templates in templates/mail (first line is the subject, the rest the
+//! plain text body) and put into a queue. A worker sends queued mails and retries failed ones with an
+//! increasing delay until [MailSettings::max_attempts] is reached.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use handlebars::{DirectorySourceOptions, Handlebars};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use serde::Serialize;
+use crate::settings::{MailSettings, MailTls, Settings};
+use crate::utils::retry_queue::{Queued, RetryQueue};
+
+pub mod notifications;
+
+#[derive(Debug, PartialEq)]
+pub enum MailError{
+    /// No SMTP relay configured
+    Disabled,
+    Template(String),
+    InvalidAddress(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueuedMail{
+    pub id: uuid::Uuid,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub struct Mailer{
+    settings: Option<MailSettings>,
+    transport: Option<AsyncSmtpTransport<Tokio1Executor>>,
+    templates: Handlebars<'static>,
+    queue: RetryQueue<QueuedMail>,
+}
+
+impl Mailer{
+    pub fn new(settings: &Settings) -> Mailer{
+        let transport = match &settings.mail{
+            Some(mail_settings) => match Self::build_transport(mail_settings){
+                Ok(transport) => Some(transport),
+                Err(e) => {
+                    eprintln!("Couldn't set up SMTP transport, mails are disabled: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let (max_attempts, retry_interval) = settings.mail.as_ref().map(|mail| (mail.max_attempts, mail.retry_interval)).unwrap_or_default();
+        Mailer{
+            settings: settings.mail.clone(),
+            transport,
+            templates: Self::load_templates(),
+            queue: RetryQueue::new(max_attempts, retry_interval),
+        }
+    }
+
+    /// Creates the [Mailer] and starts the worker which sends queued mails
+    pub fn start(settings: &Settings) -> Arc<Mailer>{
+        let mailer = Arc::new(Mailer::new(settings));
+        if mailer.transport.is_none(){
+            println!("No SMTP relay configured, email notifications are disabled.");
+            return mailer;
+        }
+
+        let mailer_cpy = mailer.clone();
+        tokio::spawn(async move {
+            loop{
+                mailer_cpy.process_queue().await;
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+
+        mailer
+    }
+
+    fn load_templates() -> Handlebars<'static>{
+        let mut templates = Handlebars::new();
+        // Mails are plain text
+        templates.register_escape_fn(handlebars::no_escape);
+        if let Err(e) = templates.register_templates_directory(Path::new("templates/mail"), DirectorySourceOptions::default()){
+            eprintln!("Couldn't load mail templates: {}", e);
+        }
+        templates
+    }
+
+    fn build_transport(settings: &MailSettings) -> Result<AsyncSmtpTransport<Tokio1Executor>, lettre::transport::smtp::Error>{
+        let mut builder = match settings.tls{
+            MailTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host),
+            MailTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)?,
+            MailTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?,
+        };
+        if let Some(port) = settings.port{
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&settings.username, &settings.password){
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(builder.build())
+    }
+
+    pub fn is_enabled(&self) -> bool{
+        self.transport.is_some()
+    }
+
+    /// Public url of the app, used for links in mails
+    pub fn base_url(&self) -> String{
+        self.settings.as_ref().map(|settings| settings.base_url.trim_end_matches('/').to_string()).unwrap_or_default()
+    }
+
+    /// Renders a mail template, returns subject and body
+    pub fn render<T: Serialize>(&self, template: &str, data: &T) -> Result<(String, String), MailError>{
+        let rendered = match self.templates.render(template, data){
+            Ok(rendered) => rendered,
+            Err(e) => return Err(MailError::Template(e.to_string())),
+        };
+        let (subject, body) = rendered.split_once('\n').unwrap_or((&rendered, ""));
+        Ok((subject.trim().to_string(), body.trim_start_matches(['\r', '\n']).to_string()))
+    }
+
+    /// Renders a mail template and queues the mail
+    pub fn send_template<T: Serialize>(&self, to: &str, template: &str, data: &T) -> Result<uuid::Uuid, MailError>{
+        if !self.is_enabled(){
+            return Err(MailError::Disabled);
+        }
+        let (subject, body) = self.render(template, data)?;
+        self.enqueue(to, subject, body)
+    }
+
+    /// Queues a mail, it's sent by the worker within a second
+    pub fn enqueue(&self, to: &str, subject: String, body: String) -> Result<uuid::Uuid, MailError>{
+        if to.parse::<Mailbox>().is_err(){
+            return Err(MailError::InvalidAddress(to.to_string()));
+        }
+        let id = uuid::Uuid::new_v4();
+        self.queue.push(QueuedMail{
+            id,
+            to: to.to_string(),
+            subject,
+            body,
+        });
+        Ok(id)
+    }
+
+    /// Returns a copy of all queued mails (not yet sent or waiting for a retry)
+    pub fn queued_mails(&self) -> Vec<Queued<QueuedMail>>{
+        self.queue.entries()
+    }
+
+    /// Sends all mails which are due, failed mails are queued again until the maximum number of attempts is reached
+    pub async fn process_queue(&self){
+        let (transport, settings) = match (&self.transport, &self.settings){
+            (Some(transport), Some(settings)) => (transport, settings),
+            _ => return,
+        };
+
+        for mail in self.queue.take_due(){
+            let result = match Self::build_message(settings, &mail.item){
+                Ok(message) => transport.send(message).await.map(|_| ()).map_err(|e| e.to_string()),
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = result{
+                let (to, attempt) = (mail.item.to.clone(), mail.attempts + 1);
+                if self.queue.retry(mail, e.clone()){
+                    eprintln!("Couldn't send mail to {} (attempt {}), retrying: {}", to, attempt, e);
+                }else{
+                    eprintln!("Couldn't send mail to {} after {} attempts, dropping it: {}", to, attempt, e);
+                }
+            }
+        }
+    }
+
+    fn build_message(settings: &MailSettings, mail: &QueuedMail) -> Result<Message, String>{
+        let from = settings.sender.parse::<Mailbox>().map_err(|e| format!("invalid sender address: {}", e))?;
+        let to = mail.to.parse::<Mailbox>().map_err(|e| format!("invalid recipient address: {}", e))?;
+        Message::builder()
+            .from(from)
+            .to(to)
+            .subject(mail.subject.clone())
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body.clone())
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Minimal SMTP sink, returns the data of the received mails
+    async fn smtp_sink(listener: TcpListener, expected: usize) -> Vec<String>{
+        let mut received = vec![];
+        while received.len() < expected{
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+
+            let mut line = String::new();
+            while reader.read_line(&mut line).await.unwrap() > 0{
+                let command = line.trim_end().to_uppercase();
+                if command.starts_with("EHLO") || command.starts_with("HELO"){
+                    writer.write_all(b"250 localhost\r\n").await.unwrap();
+                }else if command == "DATA"{
+                    writer.write_all(b"354 go ahead\r\n").await.unwrap();
+                    let mut data = String::new();
+                    loop{
+                        let mut data_line = String::new();
+                        reader.read_line(&mut data_line).await.unwrap();
+                        if data_line == ".\r\n"{
+                            break;
+                        }
+                        data.push_str(&data_line);
+                    }
+                    received.push(data);
+                    writer.write_all(b"250 accepted\r\n").await.unwrap();
+                    // The connection may be kept open by the connection pool
+                    if received.len() == expected{
+                        return received;
+                    }
+                }else if command == "QUIT"{
+                    writer.write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                }else{
+                    writer.write_all(b"250 ok\r\n").await.unwrap();
+                }
+                line.clear();
+            }
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn test_queued_mails_are_sent(){
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sink = tokio::spawn(smtp_sink(listener, 1));
+
+        let mail_settings = MailSettings{
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            tls: MailTls::None,
+            username: None,
+            password: None,
+            sender: "Books <books@example.com>".to_string(),
+            base_url: "http://localhost".to_string(),
+            max_attempts: 3,
+            retry_interval: 0,
+        };
+        let mailer = Mailer{
+            transport: Some(Mailer::build_transport(&mail_settings).unwrap()),
+            settings: Some(mail_settings),
+            templates: Handlebars::new(),
+            queue: RetryQueue::new(3, 0),
+        };
+
+        assert_eq!(mailer.enqueue("not an address", "Subject".to_string(), "Body".to_string()), Err(MailError::InvalidAddress("not an address".to_string())));
+        mailer.enqueue("author@example.com", "Rendering finished".to_string(), "Your PDF is ready.".to_string()).unwrap();
+
+        assert_eq!(mailer.queued_mails().len(), 1);
+
+        mailer.process_queue().await;
+        assert!(mailer.queued_mails().is_empty());
+
+        let received = sink.await.unwrap();
+        assert!(received[0].contains("Subject: Rendering finished"));
+        assert!(received[0].contains("Your PDF is ready."));
+    }
+
+    #[test]
+    fn test_render_template(){
+        let mut templates = Mailer::load_templates();
+        templates.register_template_string("test", "Hello {{name}}\n\n<{{name}}> & more").unwrap();
+        let mailer = Mailer{
+            settings: None,
+            transport: None,
+            templates,
+            queue: RetryQueue::new(0, 0),
+        };
+        let (subject, body) = mailer.render("test", &serde_json::json!({"name": "Ada"})).unwrap();
+        assert_eq!(subject, "Hello Ada");
+        assert_eq!(body, "<Ada> & more");
+    }
+}
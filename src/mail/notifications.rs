@@ -0,0 +1,168 @@
+//! Email notifications about renderings, imports, comments and workflow transitions.
+//!
+//! Each user can switch off single notification types, see [NotificationPreferences].
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use crate::data_storage::DataStorage;
+use crate::mail::Mailer;
+
+/// Notification types a user wants to receive by mail
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+pub struct NotificationPreferences{
+    pub rendering_finished: bool,
+    pub rendering_failed: bool,
+    pub import_failed: bool,
+    /// New comment threads on assigned sections and replies to threads the user participates in
+    pub new_comment: bool,
+    /// Workflow transitions of assigned sections
+    pub workflow_transition: bool,
+}
+
+impl Default for NotificationPreferences{
+    fn default() -> Self {
+        NotificationPreferences{
+            rendering_finished: true,
+            rendering_failed: true,
+            import_failed: true,
+            new_comment: true,
+            workflow_transition: true,
+        }
+    }
+}
+
+/// Notification with the data passed to its mail template
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Notification{
+    RenderingFinished{
+        project_id: uuid::Uuid,
+        project_name: String,
+        rendering_id: uuid::Uuid,
+    },
+    RenderingFailed{
+        project_id: uuid::Uuid,
+        project_name: String,
+        rendering_id: uuid::Uuid,
+        error: String,
+    },
+    ImportFailed{
+        project_id: uuid::Uuid,
+        project_name: String,
+        import_id: uuid::Uuid,
+    },
+    NewComment{
+        project_id: uuid::Uuid,
+        project_name: String,
+        section_title: String,
+        author_name: String,
+        content: String,
+        reply: bool,
+    },
+    WorkflowTransition{
+        project_id: uuid::Uuid,
+        project_name: String,
+        section_title: String,
+        user_name: String,
+        from: String,
+        to: String,
+        comment: Option<String>,
+    },
+}
+
+impl Notification{
+    /// Name of the mail template in templates/mail
+    pub fn template(&self) -> &'static str{
+        match self{
+            Notification::RenderingFinished { .. } => "rendering_finished",
+            Notification::RenderingFailed { .. } => "rendering_failed",
+            Notification::ImportFailed { .. } => "import_failed",
+            Notification::NewComment { .. } => "new_comment",
+            Notification::WorkflowTransition { .. } => "workflow_transition",
+        }
+    }
+
+    pub fn is_enabled(&self, preferences: &NotificationPreferences) -> bool{
+        match self{
+            Notification::RenderingFinished { .. } => preferences.rendering_finished,
+            Notification::RenderingFailed { .. } => preferences.rendering_failed,
+            Notification::ImportFailed { .. } => preferences.import_failed,
+            Notification::NewComment { .. } => preferences.new_comment,
+            Notification::WorkflowTransition { .. } => preferences.workflow_transition,
+        }
+    }
+}
+
+/// Sends a notification to a user, unless mails are disabled or the user switched this notification type off
+pub fn notify(mailer: &Mailer, data_storage: &DataStorage, user_id: &uuid::Uuid, notification: &Notification){
+    if !mailer.is_enabled() || !notification.is_enabled(&data_storage.get_notification_preferences(user_id)){
+        return;
+    }
+
+    let user = match data_storage.get_user_by_id(user_id){
+        Some(user) => user.read().unwrap().clone(),
+        None => {
+            eprintln!("Couldn't send notification: user {} not found", user_id);
+            return;
+        }
+    };
+
+    let mut data = match serde_json::to_value(notification){
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Couldn't serialize notification: {}", e);
+            return;
+        }
+    };
+    data["recipient_name"] = serde_json::Value::String(user.name);
+    data["base_url"] = serde_json::Value::String(mailer.base_url());
+
+    if let Err(e) = mailer.send_template(&user.email, notification.template(), &data){
+        eprintln!("Couldn't send notification to {}: {:?}", user.email, e);
+    }
+}
+
+pub mod api{
+    use std::sync::Arc;
+    use rocket::serde::json::Json;
+    use rocket::State;
+    use crate::data_storage::DataStorage;
+    use crate::mail::{MailError, Mailer};
+    use crate::mail::notifications::NotificationPreferences;
+    use crate::projects::api::{ApiError, ApiResult};
+    use crate::session::session_guard::Session;
+    use crate::settings::Settings;
+
+    /// GET /api/notifications/preferences
+    /// Returns the notification preferences of the current user
+    #[get("/api/notifications/preferences")]
+    pub async fn get_notification_preferences(session: Session, data_storage: &State<Arc<DataStorage>>) -> Json<ApiResult<NotificationPreferences>>{
+        ApiResult::new_data(data_storage.get_notification_preferences(&session.user_id))
+    }
+
+    /// PUT /api/notifications/preferences
+    /// Updates the notification preferences of the current user
+    #[put("/api/notifications/preferences", data = "<preferences>")]
+    pub async fn set_notification_preferences(preferences: Json<NotificationPreferences>, session: Session, settings: &State<Settings>, data_storage: &State<Arc<DataStorage>>) -> Json<ApiResult<NotificationPreferences>>{
+        let preferences = preferences.into_inner();
+        data_storage.set_notification_preferences(session.user_id, preferences.clone());
+        if data_storage.save_to_disk(settings).await.is_err(){
+            return ApiResult::new_error(ApiError::InternalServerError);
+        }
+        ApiResult::new_data(preferences)
+    }
+
+    /// POST /api/notifications/test
+    /// Sends a test mail to the current user, e.g. to check the SMTP settings against a local sink
+    #[post("/api/notifications/test")]
+    pub async fn send_test_mail(session: Session, mailer: &State<Arc<Mailer>>, data_storage: &State<Arc<DataStorage>>) -> Json<ApiResult<uuid::Uuid>>{
+        let name = data_storage.get_user_by_id(&session.user_id).map(|user| user.read().unwrap().name.clone()).unwrap_or_default();
+        let data = serde_json::json!({"recipient_name": name, "base_url": mailer.base_url()});
+
+        match mailer.send_template(&session.user_email, "test", &data){
+            Ok(id) => ApiResult::new_data(id),
+            Err(MailError::Disabled) => ApiResult::new_error(ApiError::Other("Mail is not configured".to_string())),
+            Err(e) => ApiResult::new_error(ApiError::Other(format!("Couldn't send test mail: {:?}", e))),
+        }
+    }
+}
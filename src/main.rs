@@ -11,7 +11,9 @@ use argon2::password_hash::rand_core::OsRng;
 //noinspection RsMainFunctionNotFound
 use rocket_dyn_templates::Template;
 use rocket::response::Redirect;
+use rocket::serde::json::Json;
 use crate::data_storage::User;
+use crate::projects::api::{ApiError, ApiResult};
 use crate::session::session_storage::SessionStorage;
 use crate::settings::Settings;
 use rand::Rng;
@@ -26,16 +28,30 @@ pub mod utils;
 pub mod settings_page;
 pub mod import;
 pub mod export;
+pub mod mail;
+pub mod webhooks;
 
 
 #[macro_use] extern crate rocket;
 
 /// This is the catch-all route that redirects all 401 errors to the login page.
 #[catch(401)]
-fn forward_to_login<'r>() -> Redirect {
+fn forward_to_login() -> Redirect {
     Redirect::to("/login")
 }
 
+/// Staff pages answer contributors with 403, they are redirected to the contributor portal.
+#[catch(403)]
+fn forward_to_contributor_portal() -> Redirect {
+    Redirect::to("/contributor")
+}
+
+/// API endpoints answer contributors with 403 too, they get the JSON error instead of the portal.
+#[catch(403)]
+fn api_forbidden() -> Json<ApiResult<()>> {
+    ApiResult::new_error(ApiError::Unauthorized)
+}
+
 
 /// Starts the web server, mounts all routes and attaches the [SessionStorage][session::session_storage::SessionStorage] and [Settings][settings::Settings] structs.
 #[launch]
@@ -106,15 +122,27 @@ async fn rocket() -> _ {
     // Start seperate thread for auto-saving
     data_storage::save_data_worker(data_storage.clone(), project_storage.clone(), settings.clone()).await;
 
+    println!("Starting mail worker...");
+    let mailer = mail::Mailer::start(&settings);
+
+    println!("Starting webhook worker...");
+    let webhook_manager = webhooks::WebhookManager::start(&settings, data_storage.clone());
+
+    println!("Loading rendering history...");
+    let rendering_history = export::history::RenderingHistory::start(&settings);
+
     println!("Starting rendering worker...");
-    let rendering_manager = export::rendering_manager::RenderingManager::start(settings.clone(), data_storage.clone(), csl_data.clone());
+    let rendering_manager = export::rendering_manager::RenderingManager::start(settings.clone(), project_storage.clone(), data_storage.clone(), csl_data.clone(), mailer.clone(), webhook_manager.clone(), rendering_history.clone());
 
     println!("Starting import processing worker...");
-    let import_manager = import::processing::ImportProcessor::start(settings.clone(), project_storage.clone());
+    let import_manager = import::processing::ImportProcessor::start(settings.clone(), project_storage.clone(), data_storage.clone(), mailer.clone(), webhook_manager.clone());
+
+    let collaboration_manager = Arc::new(projects::collaboration::CollaborationManager::new());
 
     println!("Starting web server...");
     rocket::build()
-        .register("/", catchers![forward_to_login])
+        .register("/", catchers![forward_to_login, forward_to_contributor_portal])
+        .register("/api", catchers![api_forbidden])
         .attach(Template::fairing())
         .mount("/css", rocket::fs::FileServer::from("static/css"))
         .mount("/js", rocket::fs::FileServer::from("static/js"))
@@ -122,14 +150,19 @@ async fn rocket() -> _ {
             projects::templates_editor::list_templates,
             projects::templates_editor::create_template,
             projects::templates_editor::form_create_template,
-            utils::lobid_proxy::search_gnd, session::logout::logout_page, session::login::login_page, session::login::process_login_form, projects::create::show_create_project, projects::api::get_csl_styles, projects::create::process_create_project, projects::list::list_projects, projects::editor::show_editor, projects::bibliography_editor::show_bib_editor, projects::bibliography_editor::api::get_library, projects::bibliography_editor::api::update_bib_entry, projects::api::get_project_template, projects::api::set_project_template, projects::api::list_templates, projects::bibliography_editor::api::get_bib_entry, projects::bibliography_editor::api::search_bib_entry, projects::bibliography_editor::api::add_bib_entry, projects::api::get_project_metadata, projects::api::get_project_settings, projects::api::set_project_metadata, projects::api::set_project_settings, projects::api::add_author_to_project, projects::api::add_editor_to_project, projects::api::remove_editor_from_project, projects::api::remove_author_from_project, projects::api::add_keyword_to_project, projects::api::remove_keyword_from_project, projects::api::add_identifier_to_project, projects::api::remove_identifier_from_project, projects::api::update_identifier_in_project, projects::api::delete_project, persons::api::delete_person, persons::list::list_persons, persons::create::show_create_person, persons::api::create_person, persons::api::get_person, persons::api::update_person, persons::api::search_persons, projects::api::patch_project_metadata, projects::api::get_project_contents, projects::api::add_content, projects::api::move_content_after, projects::api::move_content_child_of, projects::api::get_section, projects::api::update_section, projects::api::delete_section, projects::api::get_content_blocks_in_section, projects::api::set_content_blocks_in_section, projects::api::render_project, projects::api::get_rendering_status, projects::api::upload_to_project, import::upload::poll_import_status, projects::api::get_project_upload, import::upload::import_from_wordpress, export::download::download_rendering, settings_page::settings_page, settings_page::api::add_user, settings_page::api::update_user, settings_page::api::delete_user, import::upload::import_from_upload])
+            utils::lobid_proxy::search_gnd, session::logout::logout_page, session::login::login_page, session::login::process_login_form, projects::create::show_create_project, projects::api::get_csl_styles, projects::create::process_create_project, projects::list::list_projects, projects::editor::show_editor, projects::bibliography_editor::show_bib_editor, projects::bibliography_editor::api::get_library, projects::bibliography_editor::api::update_bib_entry, projects::api::get_project_template, projects::api::set_project_template, projects::api::list_templates, projects::bibliography_editor::api::get_bib_entry, projects::bibliography_editor::api::search_bib_entry, projects::bibliography_editor::api::add_bib_entry, projects::api::get_project_metadata, projects::api::get_project_settings, projects::api::set_project_metadata, projects::api::set_project_settings, projects::api::add_author_to_project, projects::api::add_editor_to_project, projects::api::remove_editor_from_project, projects::api::remove_author_from_project, projects::api::add_keyword_to_project, projects::api::remove_keyword_from_project, projects::api::add_identifier_to_project, projects::api::remove_identifier_from_project, projects::api::update_identifier_in_project, projects::api::delete_project, persons::api::delete_person, persons::list::list_persons, persons::create::show_create_person, persons::api::create_person, persons::api::get_person, persons::api::update_person, persons::api::search_persons, projects::api::patch_project_metadata, projects::api::get_project_contents, projects::api::add_content, projects::api::move_content_after, projects::api::move_content_child_of, projects::api::get_section, projects::api::update_section, projects::api::delete_section, projects::api::get_content_blocks_in_section, projects::api::set_content_blocks_in_section, projects::api::render_project, projects::collaboration::project_channel, projects::comments::api::list_comments, projects::comments::api::add_comment, projects::comments::api::reply_to_comment, projects::comments::api::update_comment, projects::comments::api::delete_comment, projects::comments::api::resolve_comment, projects::comments::api::reopen_comment, projects::suggestions::api::list_suggestions, projects::suggestions::api::add_suggestion, projects::suggestions::api::accept_suggestion, projects::suggestions::api::reject_suggestion, projects::suggestions::api::accept_section_suggestions, projects::suggestions::api::reject_section_suggestions, projects::workflow::api::get_workflow, projects::workflow::api::get_project_workflow, projects::workflow::api::transition_project, projects::workflow::api::list_section_states, projects::workflow::api::get_section_workflow, projects::workflow::api::transition_section, projects::tasks::api::set_section_assignment, projects::tasks::api::list_tasks, projects::tasks::api::overdue_dashboard, projects::contributors::api::contributor_portal, projects::contributors::api::list_contributor_sections, projects::contributors::api::render_section_preview, projects::contributors::api::approve_section_proof, mail::notifications::api::get_notification_preferences, mail::notifications::api::set_notification_preferences, mail::notifications::api::send_test_mail, webhooks::api::list_webhooks, webhooks::api::add_webhook, webhooks::api::update_webhook, webhooks::api::delete_webhook, webhooks::api::list_deliveries, projects::api::get_rendering_status, projects::api::stream_rendering_events, projects::api::cancel_rendering, export::rendering_manager::api::get_rendering_queue, export::rendering_manager::api::move_queued_rendering, projects::api::get_rendering_log, export::history::api::list_renderings, export::history::api::get_rendering_pages, export::history::api::get_project_pages, export::html_preview::api::html_preview, export::html_preview::api::preview_asset, projects::api::upload_to_project, import::upload::poll_import_status, import::upload::stream_import_events, projects::api::get_project_upload, import::upload::import_from_wordpress, export::download::download_rendering, settings_page::settings_page, settings_page::api::add_user, settings_page::api::update_user, settings_page::api::delete_user, settings_page::api::set_user_person, import::upload::import_from_upload])
         .manage(SessionStorage::new())
         .manage(settings)
         .manage(data_storage)
         .manage(project_storage)
         .manage(rendering_manager)
+        .manage(rendering_history)
+        .manage(Arc::new(export::html_preview::HtmlPreviewCache::new(settings_cpy.preview_cache_size)))
         .manage(import_manager)
         .manage(csl_data)
+        .manage(collaboration_manager)
+        .manage(mailer)
+        .manage(webhook_manager)
 }
 
 //TODO: clean shutdown
\ No newline at end of file
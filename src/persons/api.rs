@@ -4,12 +4,12 @@ use rocket::State;
 use crate::data_storage::DataStorage;
 use crate::projects::api::{ApiError, ApiResult};
 use crate::projects::Person;
-use crate::session::session_guard::Session;
+use crate::session::session_guard::StaffSession;
 
 /// POST /api/persons/
 /// Create a new person
 #[post("/api/persons", data = "<person>")]
-pub fn create_person(_session: Session, data_storage: &State<Arc<DataStorage>>, person: Json<Person>) -> Json<ApiResult<Person>> {
+pub fn create_person(_session: StaffSession, data_storage: &State<Arc<DataStorage>>, person: Json<Person>) -> Json<ApiResult<Person>> {
     let mut person = person.into_inner();
     let data_storage = Arc::clone(data_storage);
 
@@ -60,7 +60,7 @@ pub fn create_person(_session: Session, data_storage: &State<Arc<DataStorage>>,
 /// PUT /api/persons/<id>
 /// Update specified person
 #[put("/api/persons/<id>", data = "<person>")]
-pub fn update_person(_session: Session, data_storage: &State<Arc<DataStorage>>, person: Json<Person>, id: &str) -> Json<ApiResult<Person>> {
+pub fn update_person(_session: StaffSession, data_storage: &State<Arc<DataStorage>>, person: Json<Person>, id: &str) -> Json<ApiResult<Person>> {
     let person = person.into_inner();
     let data_storage = Arc::clone(data_storage);
 
@@ -95,7 +95,7 @@ pub fn update_person(_session: Session, data_storage: &State<Arc<DataStorage>>,
 // GET /api/persons/<id>
 /// Get a person by id
 #[get("/api/persons/<id>")]
-pub fn get_person(_session: Session, data_storage: &State<Arc<DataStorage>>, id: String) -> Json<ApiResult<Person>> {
+pub fn get_person(_session: StaffSession, data_storage: &State<Arc<DataStorage>>, id: String) -> Json<ApiResult<Person>> {
     let data_storage = Arc::clone(data_storage);
     let id = match uuid::Uuid::parse_str(&id) {
         Ok(id) => id,
@@ -126,7 +126,7 @@ pub fn get_person(_session: Session, data_storage: &State<Arc<DataStorage>>, id:
 /// Returns
 /// * `Json<ApiResult<Vec<Person>>>` - Api Result with Json List of persons matching the query
 #[get("/api/persons?<query>&<limit>")]
-pub fn search_persons(_session: Session, data_storage: &State<Arc<DataStorage>>, query: String, limit: Option<usize>) -> Json<ApiResult<Vec<Person>>> {
+pub fn search_persons(_session: StaffSession, data_storage: &State<Arc<DataStorage>>, query: String, limit: Option<usize>) -> Json<ApiResult<Vec<Person>>> {
     let data_storage = Arc::clone(data_storage);
 
     let query = query.to_lowercase();
@@ -185,7 +185,7 @@ pub fn search_persons(_session: Session, data_storage: &State<Arc<DataStorage>>,
 /// DELETE /api/persons/<id>
 /// Delete a person by id
 #[delete("/api/persons/<id>")]
-pub fn delete_person(_session: Session, data_storage: &State<Arc<DataStorage>>, id: String) -> Json<ApiResult<()>> {
+pub fn delete_person(_session: StaffSession, data_storage: &State<Arc<DataStorage>>, id: String) -> Json<ApiResult<()>> {
     let data_storage = Arc::clone(data_storage);
     let id = match uuid::Uuid::parse_str(&id) {
         Ok(id) => id,
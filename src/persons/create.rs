@@ -3,10 +3,10 @@ use rocket::http::Status;
 use rocket::State;
 use rocket_dyn_templates::Template;
 use crate::data_storage::DataStorage;
-use crate::session::session_guard::Session;
+use crate::session::session_guard::StaffSession;
 
 #[get("/persons/create")]
-pub async fn show_create_person(_session: Session, _data_storage: &State<Arc<DataStorage>>) -> Result<Template, Status> {
+pub async fn show_create_person(_session: StaffSession, _data_storage: &State<Arc<DataStorage>>) -> Result<Template, Status> {
 
     Ok(Template::render("create_person", ()))
 }
\ No newline at end of file
@@ -5,7 +5,7 @@ use rocket_dyn_templates::Template;
 use serde::Serialize;
 use crate::data_storage::DataStorage;
 use crate::projects::Person;
-use crate::session::session_guard::Session;
+use crate::session::session_guard::StaffSession;
 
 #[derive(Debug, PartialEq, FromFormField)]
 pub enum OrderBy{
@@ -25,7 +25,7 @@ struct ListData<'a>{
 }
 
 #[get("/persons?<offset>&<limit>&<order>")]
-pub fn list_persons(_session: Session, data_storage: &State<Arc<DataStorage>>, offset: Option<u32>, limit: Option<u32>, order: Option<OrderBy>) -> Template {
+pub fn list_persons(_session: StaffSession, data_storage: &State<Arc<DataStorage>>, offset: Option<u32>, limit: Option<u32>, order: Option<OrderBy>) -> Template {
     let mut persons : Vec<Person> = data_storage.data.read().unwrap().persons.iter().map(|person|person.1.read().unwrap().clone()).collect();
 
     let offset = offset.unwrap_or_else(|| 0);
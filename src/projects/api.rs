@@ -1,6 +1,7 @@
 use crate::data_storage::{DataStorage, ProjectTemplateV2};
 use crate::projects::{SectionMetadata, NewContentBlock, NewContentBlockEditorJSFormat};
 use crate::projects::SectionOrToc;
+use crate::projects::collaboration::{CollaborationManager, ServerMessage};
 use rocket::serde::json::Json;
 use std::sync::Arc;
 use bincode::{Decode, Encode};
@@ -9,12 +10,19 @@ use rocket::form::Form;
 use rocket::fs::{NamedFile, TempFile};
 use rocket::http::Status;
 use rocket::State;
+use rocket::response::stream::EventStream;
 use serde::{Deserialize, Serialize};
 use crate::data_storage::ProjectStorage;
-use crate::export::rendering_manager::{RenderingManager, RenderingStatus};
+use crate::export::rendering_log::StageLog;
+use crate::export::rendering_manager::{RenderingManager, RenderingOptions, RenderingStatus};
 use crate::projects::{Identifier, Keyword, Language, License, ProjectMetadata, ProjectSettings, Section};
-use crate::session::session_guard::Session;
+use crate::projects::contributors::Access;
+use crate::session::session_guard::{Session, StaffSession};
 use crate::settings::Settings;
+use crate::utils::api_helpers::parse_content_path;
+use crate::utils::etag::{ETagged, IfMatch};
+use crate::utils::job_events::event_stream;
+use crate::webhooks::{WebhookEvent, WebhookManager};
 
 /// Api Endpoints for the project editor
 
@@ -33,6 +41,8 @@ pub enum ApiError{
     Unauthorized,
     InternalServerError,
     Other(String),
+    /// The resource was changed by someone else since it was loaded (If-Match didn't match)
+    PreconditionFailed,
 }
 
 impl<T> ApiResult<T>{
@@ -53,7 +63,7 @@ impl<T> ApiResult<T>{
 /// Delete project
 /// DELETE /api/projects/<project_id>
 #[delete("/api/projects/<project_id>")]
-pub async fn delete_project(project_id: String, _session: Session, _settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
+pub async fn delete_project(project_id: String, _session: StaffSession, _settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -69,12 +79,12 @@ pub async fn delete_project(project_id: String, _session: Session, _settings: &S
 }
 
 #[get("/api/projects/<project_id>/metadata")]
-pub async fn get_project_metadata(project_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Option<ProjectMetadata>>> {
+pub async fn get_project_metadata(project_id: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> ETagged<Option<ProjectMetadata>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
             eprintln!("Couldn't parse project id: {}", e);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
@@ -84,15 +94,16 @@ pub async fn get_project_metadata(project_id: String, _session: Session, setting
         Ok(project_entry) => project_entry.clone(),
         Err(_) => {
             eprintln!("Couldn't get project with id {}", project_id);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
-    let metadata = project_entry.read().unwrap().metadata.clone();
+    let project = project_entry.read().unwrap();
+    let metadata = project.metadata.clone();
 
     // TODO: Check if all authors and editors still exist, if not, remove them from the metadata and save the project
 
-    ApiResult::new_data(metadata)
+    ETagged::new(metadata, project.version)
 
 }
 pub trait Patch<P, T>{
@@ -343,12 +354,12 @@ pub struct PatchProjectMetadata{
 }
 
 #[post("/api/projects/<project_id>/metadata", data = "<metadata>")]
-pub async fn set_project_metadata(project_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, metadata: Json<ProjectMetadata>) -> Json<ApiResult<()>> {
+pub async fn set_project_metadata(project_id: String, _session: StaffSession, if_match: IfMatch, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, webhooks: &State<Arc<WebhookManager>>, metadata: Json<ProjectMetadata>) -> ETagged<Option<ProjectMetadata>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
             eprintln!("Couldn't parse project id: {}", e);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
@@ -358,24 +369,31 @@ pub async fn set_project_metadata(project_id: String, _session: Session, setting
         Ok(project_entry) => project_entry.clone(),
         Err(_) => {
             eprintln!("Couldn't get project with id {}", project_id);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
     let mut project = project_entry.write().unwrap();
 
+    if !if_match.matches(project.version){
+        return ETagged::precondition_failed(project.metadata.clone(), project.version);
+    }
+
     project.metadata = Some(metadata.into_inner());
+    project.increment_version();
+    webhooks.trigger(project_id, WebhookEvent::MetadataChanged, &project.metadata);
 
-    ApiResult::new_data(())
+    ETagged::new(project.metadata.clone(), project.version)
 }
 
 #[patch("/api/projects/<project_id>/metadata", data = "<metadata>")]
-pub async fn patch_project_metadata(project_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, data_storage: &State<Arc<DataStorage>>, metadata: Json<PatchProjectMetadata>) -> Json<ApiResult<()>> {
+#[allow(clippy::too_many_arguments, reason = "the webhook and person lookups for new authors and editors need their own managed states")]
+pub async fn patch_project_metadata(project_id: String, _session: StaffSession, if_match: IfMatch, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, webhooks: &State<Arc<WebhookManager>>, data_storage: &State<Arc<DataStorage>>, metadata: Json<PatchProjectMetadata>) -> ETagged<Option<ProjectMetadata>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
             eprintln!("Couldn't parse project id: {}", e);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
@@ -385,7 +403,7 @@ pub async fn patch_project_metadata(project_id: String, _session: Session, setti
         Ok(project_entry) => project_entry.clone(),
         Err(_) => {
             eprintln!("Couldn't get project with id {}", project_id);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
@@ -405,7 +423,7 @@ pub async fn patch_project_metadata(project_id: String, _session: Session, setti
     if let Some(ref authors) = new_metadata.authors {
         for author in authors.iter() {
             if !data_storage.person_exists(author){
-                return ApiResult::new_error(ApiError::BadRequest(format!("Author {} does not exist", author)));
+                return ApiResult::new_error(ApiError::BadRequest(format!("Author {} does not exist", author))).into();
             }
         }
     }
@@ -413,20 +431,27 @@ pub async fn patch_project_metadata(project_id: String, _session: Session, setti
     if let Some(ref editors) = new_metadata.editors {
         for editor in editors.iter() {
             if !data_storage.person_exists(editor){
-                return ApiResult::new_error(ApiError::BadRequest(format!("Editor {} does not exist", editor)));
+                return ApiResult::new_error(ApiError::BadRequest(format!("Editor {} does not exist", editor))).into();
             }
         }
     }
 
     let mut project = project_entry.write().unwrap();
 
+    // The patch was computed from the metadata at the time of the read lock, so the version has to be checked here
+    if !if_match.matches(project.version){
+        return ETagged::precondition_failed(project.metadata.clone(), project.version);
+    }
+
     project.metadata = Some(new_metadata);
+    project.increment_version();
+    webhooks.trigger(project_id, WebhookEvent::MetadataChanged, &project.metadata);
 
-    ApiResult::new_data(())
+    ETagged::new(project.metadata.clone(), project.version)
 }
 
 #[get("/api/csl/styles")]
-pub async fn get_csl_styles(_session: Session, settings: &State<Settings>) -> Json<ApiResult<Vec<String>>> {
+pub async fn get_csl_styles(_session: StaffSession, settings: &State<Settings>) -> Json<ApiResult<Vec<String>>> {
     let path = format!("{}/csl_styles", settings.data_path);
     let mut styles = vec![];
 
@@ -464,7 +489,7 @@ pub async fn get_csl_styles(_session: Session, settings: &State<Settings>) -> Js
 }
 
 #[get("/api/projects/<project_id>/settings")]
-pub async fn get_project_settings(project_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Option<ProjectSettings>>> {
+pub async fn get_project_settings(project_id: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Option<ProjectSettings>>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -489,7 +514,7 @@ pub async fn get_project_settings(project_id: String, _session: Session, setting
 }
 
 #[post("/api/projects/<project_id>/settings", data = "<project_settings>")]
-pub async fn set_project_settings(project_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, project_settings: Json<ProjectSettings>) -> Json<ApiResult<()>> {
+pub async fn set_project_settings(project_id: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, project_settings: Json<ProjectSettings>) -> Json<ApiResult<()>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -518,7 +543,7 @@ pub async fn set_project_settings(project_id: String, _session: Session, setting
 /// PUT /api/projects/<project_id>/metadata/authors/<author_id>
 /// Add person as author to project
 #[put("/api/projects/<project_id>/metadata/authors/<author_id>")]
-pub async fn add_author_to_project(project_id: String, author_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
+pub async fn add_author_to_project(project_id: String, author_id: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, webhooks: &State<Arc<WebhookManager>>) -> Json<ApiResult<()>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -560,13 +585,15 @@ pub async fn add_author_to_project(project_id: String, author_id: String, _sessi
         project.metadata.as_mut().unwrap().authors.as_mut().unwrap().push(author_id);
     }
 
+    project.increment_version();
+    webhooks.trigger(project_id, WebhookEvent::MetadataChanged, &project.metadata);
     ApiResult::new_data(())
 }
 
 /// PUT /api/projects/<project_id>/metadata/editors/<editor_id>
 /// Add person as editor to project
 #[put("/api/projects/<project_id>/metadata/editors/<editor_id>")]
-pub async fn add_editor_to_project(project_id: String, editor_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
+pub async fn add_editor_to_project(project_id: String, editor_id: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, webhooks: &State<Arc<WebhookManager>>) -> Json<ApiResult<()>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -608,13 +635,15 @@ pub async fn add_editor_to_project(project_id: String, editor_id: String, _sessi
         project.metadata.as_mut().unwrap().editors.as_mut().unwrap().push(editor_id);
     }
 
+    project.increment_version();
+    webhooks.trigger(project_id, WebhookEvent::MetadataChanged, &project.metadata);
     ApiResult::new_data(())
 }
 
 /// DELETE /api/projects/<project_id>/metadata/authors/<author_id>
 /// Remove person from project as author
 #[delete("/api/projects/<project_id>/metadata/authors/<author_id>")]
-pub async fn remove_author_from_project(project_id: String, author_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
+pub async fn remove_author_from_project(project_id: String, author_id: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, webhooks: &State<Arc<WebhookManager>>) -> Json<ApiResult<()>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -655,13 +684,15 @@ pub async fn remove_author_from_project(project_id: String, author_id: String, _
         project.metadata.as_mut().unwrap().authors.as_mut().unwrap().remove(index);
     }
 
+    project.increment_version();
+    webhooks.trigger(project_id, WebhookEvent::MetadataChanged, &project.metadata);
     ApiResult::new_data(())
 }
 
 /// DELETE /api/projects/<project_id>/metadata/editors/<editor_id>
 /// Remove person from project as editor
 #[delete("/api/projects/<project_id>/metadata/editors/<editor_id>")]
-pub async fn remove_editor_from_project(project_id: String, editor_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
+pub async fn remove_editor_from_project(project_id: String, editor_id: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, webhooks: &State<Arc<WebhookManager>>) -> Json<ApiResult<()>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -704,13 +735,15 @@ pub async fn remove_editor_from_project(project_id: String, editor_id: String, _
         return ApiResult::new_error(ApiError::NotFound);
     }
 
+    project.increment_version();
+    webhooks.trigger(project_id, WebhookEvent::MetadataChanged, &project.metadata);
     ApiResult::new_data(())
 }
 
 /// PUT /api/projects/<project_id>/metadata/keywords
 /// Add keyword to project
 #[put("/api/projects/<project_id>/metadata/keywords", data = "<keyword>")]
-pub async fn add_keyword_to_project(project_id: String, keyword: Json<Keyword>, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
+pub async fn add_keyword_to_project(project_id: String, keyword: Json<Keyword>, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, webhooks: &State<Arc<WebhookManager>>) -> Json<ApiResult<()>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -744,13 +777,15 @@ pub async fn add_keyword_to_project(project_id: String, keyword: Json<Keyword>,
         project.metadata.as_mut().unwrap().keywords.as_mut().unwrap().push(keyword.into_inner());
     }
 
+    project.increment_version();
+    webhooks.trigger(project_id, WebhookEvent::MetadataChanged, &project.metadata);
     ApiResult::new_data(())
 }
 
 /// DELETE /api/projects/<project_id>/metadata/keywords/<keyword>
 /// Remove keyword from project
 #[delete("/api/projects/<project_id>/metadata/keywords/<keyword>")]
-pub async fn remove_keyword_from_project(project_id: String, keyword: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
+pub async fn remove_keyword_from_project(project_id: String, keyword: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, webhooks: &State<Arc<WebhookManager>>) -> Json<ApiResult<()>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -785,13 +820,15 @@ pub async fn remove_keyword_from_project(project_id: String, keyword: String, _s
         return ApiResult::new_error(ApiError::NotFound);
     }
 
+    project.increment_version();
+    webhooks.trigger(project_id, WebhookEvent::MetadataChanged, &project.metadata);
     ApiResult::new_data(())
 }
 
 /// POST /api/projects/<project_id>/metadata/identifiers/
 /// Add identifier to project
 #[post("/api/projects/<project_id>/metadata/identifiers", data = "<identifier>")]
-pub async fn add_identifier_to_project(project_id: String, mut identifier: Json<Identifier>, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Identifier>> {
+pub async fn add_identifier_to_project(project_id: String, mut identifier: Json<Identifier>, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, webhooks: &State<Arc<WebhookManager>>) -> Json<ApiResult<Identifier>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -831,13 +868,15 @@ pub async fn add_identifier_to_project(project_id: String, mut identifier: Json<
         project.metadata.as_mut().unwrap().identifiers.as_mut().unwrap().push(identifier.clone().into_inner());
     }
 
+    project.increment_version();
+    webhooks.trigger(project_id, WebhookEvent::MetadataChanged, &project.metadata);
     ApiResult::new_data(identifier.into_inner())
 }
 
 /// DELETE /api/projects/<project_id>/metadata/identifiers/<identifier_ic>
 /// Remove identifier
 #[delete("/api/projects/<project_id>/metadata/identifiers/<identifier_id>")]
-pub async fn remove_identifier_from_project(project_id: String, identifier_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
+pub async fn remove_identifier_from_project(project_id: String, identifier_id: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, webhooks: &State<Arc<WebhookManager>>) -> Json<ApiResult<()>> {
     let identifier_id = match uuid::Uuid::parse_str(&identifier_id) {
         Ok(identifier_id) => identifier_id,
         Err(e) => {
@@ -875,6 +914,8 @@ pub async fn remove_identifier_from_project(project_id: String, identifier_id: S
 
     if let Some(index) = project.metadata.as_ref().unwrap().identifiers.as_ref().unwrap().iter().position(|x| x.id.unwrap_or_default() == identifier_id){
         project.metadata.as_mut().unwrap().identifiers.as_mut().unwrap().remove(index);
+        project.increment_version();
+        webhooks.trigger(project_id, WebhookEvent::MetadataChanged, &project.metadata);
         ApiResult::new_data(())
     }else{
         ApiResult::new_error(ApiError::NotFound)
@@ -884,7 +925,7 @@ pub async fn remove_identifier_from_project(project_id: String, identifier_id: S
 /// PUT /api/projects/<project_id>/metadata/identifiers/<identifier_id>
 /// Update identifier
 #[put("/api/projects/<project_id>/metadata/identifiers/<identifier_id>", data = "<identifier>")]
-pub async fn update_identifier_in_project(project_id: String, identifier_id: String, identifier: Json<Identifier>, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
+pub async fn update_identifier_in_project(project_id: String, identifier_id: String, identifier: Json<Identifier>, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, webhooks: &State<Arc<WebhookManager>>) -> Json<ApiResult<()>> {
 
     let identifier_id = match uuid::Uuid::parse_str(&identifier_id) {
         Ok(identifier_id) => identifier_id,
@@ -933,6 +974,8 @@ pub async fn update_identifier_in_project(project_id: String, identifier_id: Str
 
     if let Some(index) = project.metadata.as_ref().unwrap().identifiers.as_ref().unwrap().iter().position(|x| x.id.unwrap_or_default() == identifier_id){
         project.metadata.as_mut().unwrap().identifiers.as_mut().unwrap()[index] = identifier;
+        project.increment_version();
+        webhooks.trigger(project_id, WebhookEvent::MetadataChanged, &project.metadata);
         ApiResult::new_data(())
     }else{
         ApiResult::new_error(ApiError::NotFound)
@@ -943,7 +986,7 @@ pub async fn update_identifier_in_project(project_id: String, identifier_id: Str
 /// Returns a list of all contents (sections or toc placeholder) in the project
 /// Strips out the inner content of ContentBlocks
 #[get("/api/projects/<project_id>/contents")]
-pub async fn get_project_contents(project_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Vec<SectionOrToc>>> {
+pub async fn get_project_contents(project_id: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Vec<SectionOrToc>>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -982,7 +1025,7 @@ pub async fn get_project_contents(project_id: String, _session: Session, setting
 /// POST /api/projects/<project_id>/contents
 /// Add a new section or toc placeholder to the project
 #[post("/api/projects/<project_id>/contents", data = "<content>")]
-pub async fn add_content(project_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, content: Json<SectionOrToc>) -> Json<ApiResult<SectionOrToc>>{
+pub async fn add_content(project_id: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, content: Json<SectionOrToc>) -> Json<ApiResult<SectionOrToc>>{
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -998,6 +1041,8 @@ pub async fn add_content(project_id: String, _session: Session, settings: &State
             if let None = section.id{
                 section.id = Some(uuid::Uuid::new_v4());
             }
+            // New sections always start in the initial workflow state
+            section.workflow = Default::default();
         },
         SectionOrToc::Toc => {},
     }
@@ -1013,7 +1058,8 @@ pub async fn add_content(project_id: String, _session: Session, settings: &State
     };
 
     // Insert new content block at the end
-    project_entry.write().unwrap().sections.push(content.clone());
+    let mut project = project_entry.write().unwrap();
+    project.sections.push(content.clone());
 
     //Return inserted content block
     ApiResult::new_data(content)
@@ -1023,7 +1069,7 @@ pub async fn add_content(project_id: String, _session: Session, settings: &State
 /// Move a section or toc after another section or toc
 // TODO: implement for toc
 #[put("/api/projects/<project_id>/contents/<content_id>/move/after/<after_id>")]
-pub async fn move_content_after(project_id: String, content_id: String, after_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
+pub async fn move_content_after(project_id: String, content_id: String, after_id: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
     let content_id = match uuid::Uuid::parse_str(&content_id) {
         Ok(content_id) => content_id,
         Err(e) => {
@@ -1086,7 +1132,7 @@ pub async fn move_content_after(project_id: String, content_id: String, after_id
 /// Move a section or toc to be a child of another section or toc. It will be the first child.
 //TODO: Implement for toc
 #[put("/api/projects/<project_id>/contents/<content_id>/move/child_of/<parent_id>")]
-pub async fn move_content_child_of(project_id: String, content_id: String, parent_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
+pub async fn move_content_child_of(project_id: String, content_id: String, parent_id: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
     let content_id = match uuid::Uuid::parse_str(&content_id) {
         Ok(content_id) => content_id,
         Err(e) => {
@@ -1147,12 +1193,12 @@ pub async fn move_content_child_of(project_id: String, content_id: String, paren
 /// GET /api/projects/<project_id>/sections/<content_id>
 /// Get a section, but strip out subsections
 #[get("/api/projects/<project_id>/sections/<content_path>")]
-pub async fn get_section(project_id: &str, content_path: &str, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Section>> {
+pub async fn get_section(project_id: &str, content_path: &str, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> ETagged<Section> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
             println!("Couldn't parse project id: {}", e);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
@@ -1163,7 +1209,7 @@ pub async fn get_section(project_id: &str, content_path: &str, _session: Session
             Ok(part) => path.push(part),
             Err(e) => {
                 println!("Couldn't parse content path: {}", e);
-                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string()));
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string())).into();
             }
         }
     }
@@ -1172,7 +1218,7 @@ pub async fn get_section(project_id: &str, content_path: &str, _session: Session
 
     if path.len() == 0{
         println!("Couldn't parse content path: path is empty");
-        return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string()));
+        return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string())).into();
     }
 
     let project_storage = Arc::clone(project_storage);
@@ -1181,18 +1227,22 @@ pub async fn get_section(project_id: &str, content_path: &str, _session: Session
         Ok(project) => project,
         Err(_) => {
             println!("Couldn't get project with id {}", project_id);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
     let project = project.read().unwrap();
 
+    if !access.can_access_section(&project.sections, &path){
+        return ApiResult::new_error(ApiError::Unauthorized).into();
+    }
+
     let section = crate::data_storage::get_section_by_path(&project, &path);
 
     // TODO: check if authors and editors still exist, if not, remove them and save section
     match section{
-        Ok(section) => ApiResult::new_data(section.clone_without_subsections()),
-        Err(e) => ApiResult::new_error(e)
+        Ok(section) => ETagged::new(section.clone_without_subsections(), section.version),
+        Err(e) => ApiResult::new_error(e).into()
     }
 }
 
@@ -1200,12 +1250,13 @@ pub async fn get_section(project_id: &str, content_path: &str, _session: Session
 /// Patch a section, but without content (subsections / content blocks)
 /// Check [PatchSection] for more information
 #[patch("/api/projects/<project_id>/sections/<content_path>", data = "<section_patch>")]
-pub async fn update_section(project_id: String, content_path: String, section_patch: Json<PatchSection>, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, data_storage: &State<Arc<DataStorage>>) -> Json<ApiResult<Section>> {
+#[allow(clippy::too_many_arguments, reason = "Rocket passes the If-Match guard and each managed state as own argument")]
+pub async fn update_section(project_id: String, content_path: String, section_patch: Json<PatchSection>, access: Access, if_match: IfMatch, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, data_storage: &State<Arc<DataStorage>>) -> ETagged<Section> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
             println!("Couldn't parse project id: {}", e);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
@@ -1216,14 +1267,14 @@ pub async fn update_section(project_id: String, content_path: String, section_pa
             Ok(part) => path.push(part),
             Err(e) => {
                 println!("Couldn't parse content path: {}", e);
-                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string()));
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string())).into();
             }
         }
     }
 
     if path.len() == 0{
         println!("Couldn't parse content path: path is empty");
-        return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string()));
+        return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string())).into();
     }
 
     let project_storage = Arc::clone(project_storage);
@@ -1232,29 +1283,42 @@ pub async fn update_section(project_id: String, content_path: String, section_pa
         Ok(project) => project,
         Err(_) => {
             println!("Couldn't get project with id {}", project_id);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
     let mut project = project.write().unwrap();
 
+    if !access.can_access_section(&project.sections, &path){
+        return ApiResult::new_error(ApiError::Unauthorized).into();
+    }
+
     let section = crate::data_storage::get_section_by_path_mut(&mut project, &path);
 
     match section{
         Ok(section) => {
+            if !if_match.matches(section.version){
+                return ETagged::precondition_failed(section.clone_without_subsections(), section.version);
+            }
+
             let mut new_section_data = section.patch(section_patch.into_inner());
+            // Contributors can't change who is credited on the section
+            if !access.is_staff() && (new_section_data.metadata.authors != section.metadata.authors || new_section_data.metadata.editors != section.metadata.editors){
+                return ApiResult::new_error(ApiError::Unauthorized).into();
+            }
+
             // Check if new section data is valid
             // Check authors
             for author in new_section_data.metadata.authors.iter(){
                 if !data_storage.person_exists(author){
-                    return ApiResult::new_error(ApiError::BadRequest(format!("Author {} does not exist", author)));
+                    return ApiResult::new_error(ApiError::BadRequest(format!("Author {} does not exist", author))).into();
                 }
             }
 
             // Check editors
             for editor in new_section_data.metadata.editors.iter(){
                 if !data_storage.person_exists(editor){
-                    return ApiResult::new_error(ApiError::BadRequest(format!("Editor {} does not exist", editor)));
+                    return ApiResult::new_error(ApiError::BadRequest(format!("Editor {} does not exist", editor))).into();
                 }
             }
 
@@ -1274,19 +1338,20 @@ pub async fn update_section(project_id: String, content_path: String, section_pa
 
             // Set last changed to now
             new_section_data.metadata.last_changed = Some(chrono::Utc::now().naive_utc());
+            new_section_data.increment_version();
 
             *section = new_section_data.clone();
 
-            ApiResult::new_data(new_section_data)
+            ETagged::new(new_section_data.clone_without_subsections(), new_section_data.version)
         },
-        Err(e) => ApiResult::new_error(e)
+        Err(e) => ApiResult::new_error(e).into()
     }
 }
 
 /// DELETE /api/projects/<project_id>/sections/<content_path>
 /// Delete a section including all subsections and content blocks
 #[delete("/api/projects/<project_id>/sections/<content_path>")]
-pub async fn delete_section(project_id: String, content_path: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
+pub async fn delete_section(project_id: String, content_path: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -1325,7 +1390,9 @@ pub async fn delete_section(project_id: String, content_path: String, _session:
     let mut project = project.write().unwrap();
 
     match project.remove_section(path.last().unwrap()){
-        Some(_) => ApiResult::new_data(()),
+        Some(_) => {
+            ApiResult::new_data(())
+        },
         None => ApiResult::new_error(ApiError::NotFound)
     }
 }
@@ -1333,12 +1400,12 @@ pub async fn delete_section(project_id: String, content_path: String, _session:
 /// GET /api/projects/<project_id>/sections/<content_path>/content_blocks
 /// Get all content blocks in a section
 #[get("/api/projects/<project_id>/sections/<content_path>/content_blocks")]
-pub async fn get_content_blocks_in_section(project_id: String, content_path: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Vec<NewContentBlockEditorJSFormat>>> {
+pub async fn get_content_blocks_in_section(project_id: String, content_path: String, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> ETagged<Vec<NewContentBlockEditorJSFormat>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
             println!("Couldn't parse project id: {}", e);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
@@ -1349,14 +1416,14 @@ pub async fn get_content_blocks_in_section(project_id: String, content_path: Str
             Ok(part) => path.push(part),
             Err(e) => {
                 println!("Couldn't parse content path: {}", e);
-                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string()));
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string())).into();
             }
         }
     }
 
     if path.len() == 0{
         println!("Couldn't parse content path: path is empty");
-        return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string()));
+        return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string())).into();
     }
 
     let project_storage = Arc::clone(project_storage);
@@ -1365,12 +1432,16 @@ pub async fn get_content_blocks_in_section(project_id: String, content_path: Str
         Ok(project) => project,
         Err(_) => {
             println!("Couldn't get project with id {}", project_id);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
     let project = project.read().unwrap();
 
+    if !access.can_access_section(&project.sections, &path){
+        return ApiResult::new_error(ApiError::Unauthorized).into();
+    }
+
     let section = crate::data_storage::get_section_by_path(&project, &path);
 
     match section{
@@ -1380,21 +1451,22 @@ pub async fn get_content_blocks_in_section(project_id: String, content_path: Str
             for block in section.children.iter(){
                 blocks.push(NewContentBlockEditorJSFormat::from(block.clone()))
             }
-            ApiResult::new_data(blocks)
+            ETagged::new(blocks, section.version)
         },
-        Err(e) => ApiResult::new_error(e)
+        Err(e) => ApiResult::new_error(e).into()
     }
 }
 
 /// PUT /api/projects/<project_id>/sections/<content_path>/content_blocks
 /// Replace all content blocks in a section
 #[put("/api/projects/<project_id>/sections/<content_path>/content_blocks", data = "<blocks>")]
-pub async fn set_content_blocks_in_section(project_id: String, content_path: String, blocks: Json<Vec<NewContentBlockEditorJSFormat>>, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>>{
+#[allow(clippy::too_many_arguments, reason = "the If-Match guard and the collaboration channels come on top of the usual project arguments")]
+pub async fn set_content_blocks_in_section(project_id: String, content_path: String, blocks: Json<Vec<NewContentBlockEditorJSFormat>>, access: Access, if_match: IfMatch, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, collaboration: &State<Arc<CollaborationManager>>) -> ETagged<Vec<NewContentBlockEditorJSFormat>>{
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
             println!("Couldn't parse project id: {}", e);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
@@ -1405,14 +1477,14 @@ pub async fn set_content_blocks_in_section(project_id: String, content_path: Str
             Ok(part) => path.push(part),
             Err(e) => {
                 println!("Couldn't parse content path: {}", e);
-                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string()));
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string())).into();
             }
         }
     }
 
     if path.len() == 0{
         println!("Couldn't parse content path: path is empty");
-        return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string()));
+        return ApiResult::new_error(ApiError::BadRequest("Couldn't parse content path".to_string())).into();
     }
 
     let project_storage = Arc::clone(project_storage);
@@ -1421,16 +1493,25 @@ pub async fn set_content_blocks_in_section(project_id: String, content_path: Str
         Ok(project) => project,
         Err(_) => {
             println!("Couldn't get project with id {}", project_id);
-            return ApiResult::new_error(ApiError::NotFound);
+            return ApiResult::new_error(ApiError::NotFound).into();
         },
     };
 
     let mut project = project.write().unwrap();
 
+    if !access.can_access_section(&project.sections, &path){
+        return ApiResult::new_error(ApiError::Unauthorized).into();
+    }
+
     let section = crate::data_storage::get_section_by_path_mut(&mut project, &path);
 
     match section{
         Ok(section) => {
+            if !if_match.matches(section.version){
+                let current_blocks = section.children.iter().map(|block| NewContentBlockEditorJSFormat::from(block.clone())).collect();
+                return ETagged::precondition_failed(current_blocks, section.version);
+            }
+
             let mut new_blocks : Vec<NewContentBlock> = vec![];
 
             for block in blocks.iter(){
@@ -1439,22 +1520,39 @@ pub async fn set_content_blocks_in_section(project_id: String, content_path: Str
                 match new_block{
                     Ok(new_block) => new_blocks.push(new_block),
                     Err(e) => {
-                        return ApiResult::new_error(ApiError::BadRequest(e))
+                        return ApiResult::new_error(ApiError::BadRequest(e)).into()
                     }
                 }
             }
 
+            // Reject inconsistent blocks before they get stored
+            if let Err(e) = crate::projects::validate_content_blocks(&new_blocks){
+                return ApiResult::new_error(ApiError::BadRequest(e)).into()
+            }
+
+            crate::utils::html_sanitizer::HtmlSanitizer::new(&settings.html_sanitizer).sanitize_content_blocks(&mut new_blocks);
+
             section.children = new_blocks;
-            ApiResult::new_data(())
+            section.increment_version();
+
+            let stored_blocks = section.children.iter().map(|block| NewContentBlockEditorJSFormat::from(block.clone())).collect();
+            let version = section.version;
+
+            // Tell connected editors to reload the section
+            collaboration.broadcast(&project_id, ServerMessage::SectionReplaced { section_path: content_path });
+            ETagged::new(stored_blocks, version)
         },
-        Err(e) => ApiResult::new_error(e)
+        Err(e) => ApiResult::new_error(e).into()
     }
 }
 
 /// POST /api/projects/<project_id>/render
 /// Renders project
-#[post("/api/projects/<project_id>/render")]
-pub async fn render_project(project_id: String, project_storage: &State<Arc<ProjectStorage>>, _session: Session, rendering_manager: &State<Arc<RenderingManager>>, settings: &State<Settings>) -> Json<ApiResult<uuid::Uuid>>{
+/// Accepts [RenderingOptions] as optional body, e.g. {"review": true} to include open comments
+/// or {"scope": {"type": "sections", "paths": ["<content_path>"]}} to render only some sections
+/// Final exports ({"final_export": true}) are refused until all sections are in the final workflow state
+#[post("/api/projects/<project_id>/render", data = "<options>")]
+pub async fn render_project(project_id: String, project_storage: &State<Arc<ProjectStorage>>, session: StaffSession, rendering_manager: &State<Arc<RenderingManager>>, settings: &State<Settings>, options: Option<Json<RenderingOptions>>) -> Json<ApiResult<uuid::Uuid>>{
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -1477,8 +1575,25 @@ pub async fn render_project(project_id: String, project_storage: &State<Arc<Proj
 
     // TODO: Check if all authors and editors still exist, if not, remove them from the metadata and save the project
 
+    let options = options.map(|options| options.into_inner()).unwrap_or_default();
+
+    if let Err(e) = options.scope.validate(&project.sections){
+        return ApiResult::new_error(e);
+    }
+
+    // Final exports require all sections to be in the final workflow state
+    if options.final_export{
+        let not_final: Vec<String> = crate::projects::workflow::section_summaries(&project.sections, &settings.workflow).into_iter()
+            .filter(|section| section.state != settings.workflow.final_state)
+            .map(|section| section.title)
+            .collect();
+        if !not_final.is_empty(){
+            return ApiResult::new_error(ApiError::BadRequest(format!("Final export not possible, sections not in state {}: {}", settings.workflow.final_state, not_final.join(", "))));
+        }
+    }
+
     // Add to render queue
-    let render_id = rendering_manager.add_rendering_request(project, project_id);
+    let render_id = rendering_manager.add_rendering_request(project, project_id, options, session.user_id);
 
     ApiResult::new_data(render_id)
 }
@@ -1486,7 +1601,7 @@ pub async fn render_project(project_id: String, project_storage: &State<Arc<Proj
 /// GET /api/renderings/<render_id>/status
 /// Get status of rendering
 #[get("/api/renderings/<render_id>/status")]
-pub async fn get_rendering_status(render_id: String, rendering_manager: &State<Arc<RenderingManager>>, _session: Session) -> Json<ApiResult<RenderingStatus>>{
+pub async fn get_rendering_status(render_id: String, rendering_manager: &State<Arc<RenderingManager>>, session: Session, access: Access) -> Json<ApiResult<RenderingStatus>>{
     let render_id = match uuid::Uuid::parse_str(&render_id) {
         Ok(render_id) => render_id,
         Err(e) => {
@@ -1495,6 +1610,11 @@ pub async fn get_rendering_status(render_id: String, rendering_manager: &State<A
         },
     };
 
+    // Contributors only see their own renderings
+    if !access.is_staff() && rendering_manager.get_requested_by(render_id) != Some(session.user_id){
+        return ApiResult::new_error(ApiError::NotFound);
+    }
+
     let status = rendering_manager.get_rendering_request_status(render_id);
 
     match status{
@@ -1503,13 +1623,75 @@ pub async fn get_rendering_status(render_id: String, rendering_manager: &State<A
     }
 }
 
+/// GET /api/renderings/<render_id>/log
+/// Timings, template warnings and Vivliostyle output of each stage of a rendering
+#[get("/api/renderings/<render_id>/log")]
+pub async fn get_rendering_log(render_id: String, rendering_manager: &State<Arc<RenderingManager>>, session: Session, access: Access) -> Json<ApiResult<Vec<StageLog>>>{
+    let render_id = match uuid::Uuid::parse_str(&render_id) {
+        Ok(render_id) => render_id,
+        Err(e) => {
+            eprintln!("Couldn't parse render id: {}", e);
+            return ApiResult::new_error(ApiError::NotFound);
+        },
+    };
+
+    // Contributors only see their own renderings
+    if !access.is_staff() && rendering_manager.get_requested_by(render_id) != Some(session.user_id){
+        return ApiResult::new_error(ApiError::NotFound);
+    }
+
+    match rendering_manager.get_log(render_id){
+        Some(log) => ApiResult::new_data(log),
+        None => ApiResult::new_error(ApiError::NotFound)
+    }
+}
+
+/// POST /api/renderings/<render_id>/cancel
+/// Cancels a queued or running rendering
+#[post("/api/renderings/<render_id>/cancel")]
+pub async fn cancel_rendering(render_id: String, rendering_manager: &State<Arc<RenderingManager>>, session: Session, access: Access) -> Json<ApiResult<()>>{
+    let render_id = match uuid::Uuid::parse_str(&render_id) {
+        Ok(render_id) => render_id,
+        Err(e) => {
+            eprintln!("Couldn't parse render id: {}", e);
+            return ApiResult::new_error(ApiError::NotFound);
+        },
+    };
+
+    // Contributors only cancel their own renderings
+    if !access.is_staff() && rendering_manager.get_requested_by(render_id) != Some(session.user_id){
+        return ApiResult::new_error(ApiError::NotFound);
+    }
+
+    if rendering_manager.cancel(render_id){
+        ApiResult::new_data(())
+    }else{
+        ApiResult::new_error(ApiError::BadRequest("Rendering is already done".to_string()))
+    }
+}
+
+/// GET /api/renderings/<render_id>/events
+/// Server-sent events with the status transitions and log lines of a rendering, ends when the rendering is done
+#[get("/api/renderings/<render_id>/events")]
+pub async fn stream_rendering_events(render_id: String, rendering_manager: &State<Arc<RenderingManager>>, session: Session, access: Access) -> Option<EventStream![]>{
+    let render_id = uuid::Uuid::parse_str(&render_id).ok()?;
+
+    // Contributors only see their own renderings
+    if !access.is_staff() && rendering_manager.get_requested_by(render_id) != Some(session.user_id){
+        return None;
+    }
+
+    let (initial, receiver) = rendering_manager.subscribe(render_id)?;
+    Some(event_stream(initial, receiver))
+}
+
 #[derive(FromForm)]
-struct ImageUpload<'a>{
+pub struct ImageUpload<'a>{
     image: TempFile<'a>,
 }
 
 #[derive(Serialize, Deserialize, Default)]
-struct ImageUploadResponse{
+pub struct ImageUploadResponse{
     success: u8,
     file: Option<UploadedImage>
 }
@@ -1524,9 +1706,10 @@ pub struct UploadedImage {
 
 /// Upload image via multipart form
 /// Endpoint for EditorJS image upload
-/// POST /api/projects/<project_id>/uploads
-#[post("/api/projects/<project_id>/uploads", data = "<form>")]
-pub async fn upload_to_project(project_id: String, form: Form<ImageUpload<'_>>, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ImageUploadResponse> {
+/// POST /api/projects/<project_id>/uploads?<section>
+/// Contributors have to pass the content path of the section they are editing
+#[post("/api/projects/<project_id>/uploads?<section>", data = "<form>")]
+pub async fn upload_to_project(project_id: String, section: Option<String>, form: Form<ImageUpload<'_>>, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ImageUploadResponse> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -1536,7 +1719,7 @@ pub async fn upload_to_project(project_id: String, form: Form<ImageUpload<'_>>,
     };
 
     let project_storage = Arc::clone(project_storage);
-    let _project = match project_storage.get_project(&project_id, settings).await{
+    let project = match project_storage.get_project(&project_id, settings).await{
         Ok(project) => project,
         Err(_) => {
             eprintln!("Couldn't get project with id {}", project_id);
@@ -1544,7 +1727,15 @@ pub async fn upload_to_project(project_id: String, form: Form<ImageUpload<'_>>,
         },
     };
 
-    //TODO: check if user has access to this project once we have user management
+    if !access.is_staff(){
+        let path = match section.as_deref().map(parse_content_path){
+            Some(Ok(path)) => path,
+            _ => return Json(ImageUploadResponse::default()),
+        };
+        if !access.can_access_section(&project.read().unwrap().sections, &path){
+            return Json(ImageUploadResponse::default());
+        }
+    }
 
     // Create projects upload directory if it doesn't exist
     match tokio::fs::create_dir(format!("{}/projects/{}/uploads", settings.data_path, project_id)).await{
@@ -1586,8 +1777,10 @@ pub async fn upload_to_project(project_id: String, form: Form<ImageUpload<'_>>,
     }
 }
 
+/// GET /api/projects/<project_id>/uploads/<filename>
+/// Contributors only get uploads used in their sections
 #[get("/api/projects/<project_id>/uploads/<filename>")]
-pub async fn get_project_upload(project_id: String, filename: String, settings: &State<Settings>, _session: Session) -> Result<NamedFile, Status> {
+pub async fn get_project_upload(project_id: String, filename: String, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Result<NamedFile, Status> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -1596,6 +1789,13 @@ pub async fn get_project_upload(project_id: String, filename: String, settings:
         },
     };
 
+    if !access.is_staff(){
+        let project = project_storage.get_project(&project_id, settings).await.map_err(|_| Status::NotFound)?;
+        if !access.can_access_upload(&project.read().unwrap().sections, &filename){
+            return Err(Status::NotFound);
+        }
+    }
+
     let path = format!("{}/projects/{}/uploads/{}", settings.data_path, project_id, filename);
 
     let file = NamedFile::open(path).await.map_err(|_| Status::NotFound)?;
@@ -1605,7 +1805,7 @@ pub async fn get_project_upload(project_id: String, filename: String, settings:
 /// Get current project template
 /// GET /api/projects/<project_id>/template
 #[get("/api/projects/<project_id>/template")]
-pub async fn get_project_template(project_id: String, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, _session: Session) -> Json<ApiResult<uuid::Uuid>> {
+pub async fn get_project_template(project_id: String, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, _session: StaffSession) -> Json<ApiResult<uuid::Uuid>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -1632,7 +1832,7 @@ pub async fn get_project_template(project_id: String, settings: &State<Settings>
 /// Set project template
 /// PUT /api/projects/<project_id>/template
 #[put("/api/projects/<project_id>/template", data = "<template_id>")]
-pub async fn set_project_template(project_id: String, template_id: Json<uuid::Uuid>, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, _session: Session) -> Json<ApiResult<()>> {
+pub async fn set_project_template(project_id: String, template_id: Json<uuid::Uuid>, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, _session: StaffSession) -> Json<ApiResult<()>> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -1661,7 +1861,7 @@ pub async fn set_project_template(project_id: String, template_id: Json<uuid::Uu
 /// List all templates
 /// GET /api/templates
 #[get("/api/templates")]
-pub async fn list_templates(_session: Session, data_storage: &State<Arc<DataStorage>>) -> Json<ApiResult<Vec<ProjectTemplateV2>>> {
+pub async fn list_templates(_session: StaffSession, data_storage: &State<Arc<DataStorage>>) -> Json<ApiResult<Vec<ProjectTemplateV2>>> {
     let data_storage = Arc::clone(data_storage);
 
     let templates = data_storage.data.read().unwrap().templates.clone().iter().map(|x|x.1.read().unwrap().clone()).collect();
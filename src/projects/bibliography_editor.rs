@@ -3,11 +3,11 @@ use rocket::http::Status;
 use rocket::State;
 use rocket_dyn_templates::Template;
 use crate::data_storage::ProjectStorage;
-use crate::session::session_guard::Session;
+use crate::session::session_guard::StaffSession;
 use crate::settings::Settings;
 
 #[get("/projects/<project_id>/bibliography")]
-pub async fn show_bib_editor(project_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Result<Template, Status> {
+pub async fn show_bib_editor(project_id: String, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Result<Template, Status> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -40,18 +40,18 @@ pub mod api{
     use serde::{Deserialize, Serialize};
     use crate::data_storage::{BibEntryV2, ProjectStorage};
     use crate::projects::api::{ApiError, ApiResult};
-    use crate::session::session_guard::Session;
+    use crate::session::session_guard::StaffSession;
     use crate::settings::Settings;
 
     #[derive(Deserialize, Serialize)]
-    struct NewBibEntry{
+    pub struct NewBibEntry{
         pub key: String,
         pub entry_type: EntryType,
     }
 
     /// Get a list of all bibliography entry keys in the project
     #[get("/api/projects/<project_id>/bibliography")]
-    pub async fn get_library(_session: Session, project_id: String, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Vec<String>>>{
+    pub async fn get_library(_session: StaffSession, project_id: String, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Vec<String>>>{
         let project_id = match uuid::Uuid::parse_str(&project_id) {
             Ok(project_id) => project_id,
             Err(e) => {
@@ -75,7 +75,7 @@ pub mod api{
 
     /// Get a bibliography entry by its key
     #[get("/api/projects/<project_id>/bibliography/<entry_key>")]
-    pub async fn get_bib_entry(_session: Session, project_id: String, entry_key: String, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<BibEntryV2>>{
+    pub async fn get_bib_entry(_session: StaffSession, project_id: String, entry_key: String, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<BibEntryV2>>{
         let project_id = match uuid::Uuid::parse_str(&project_id) {
             Ok(project_id) => project_id,
             Err(e) => {
@@ -104,7 +104,7 @@ pub mod api{
 
     /// Search for bibliography entries by their key or title
     #[get("/api/projects/<project_id>/bibliography/search?<query>")]
-    pub async fn search_bib_entry(_session: Session, project_id: String, query: String, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Vec<BibEntryV2>>>{
+    pub async fn search_bib_entry(_session: StaffSession, project_id: String, query: String, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Vec<BibEntryV2>>>{
         let project_id = match uuid::Uuid::parse_str(&project_id) {
             Ok(project_id) => project_id,
             Err(e) => {
@@ -138,7 +138,7 @@ pub mod api{
 
 
     #[post("/api/projects/<project_id>/bibliography", data="<new_bib_entry>")]
-    pub async fn add_bib_entry(new_bib_entry: Json<NewBibEntry>, _session: Session, project_id: String, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<BibEntryV2>>{
+    pub async fn add_bib_entry(new_bib_entry: Json<NewBibEntry>, _session: StaffSession, project_id: String, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<BibEntryV2>>{
         let project_id = match uuid::Uuid::parse_str(&project_id) {
             Ok(project_id) => project_id,
             Err(e) => {
@@ -167,7 +167,7 @@ pub mod api{
     }
 
     #[put("/api/projects/<project_id>/bibliography/<key>", data="<bib_entry>")]
-    pub async fn update_bib_entry(bib_entry: Json<BibEntryV2>, key: &str, _session: Session, project_id: &str, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>>{
+    pub async fn update_bib_entry(bib_entry: Json<BibEntryV2>, key: &str, _session: StaffSession, project_id: &str, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>>{
         let project_id = match uuid::Uuid::parse_str(&project_id) {
             Ok(project_id) => project_id,
             Err(e) => {
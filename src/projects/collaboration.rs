@@ -0,0 +1,580 @@
+//! Real-time collaboration on sections over WebSockets.
+//!
+//! Every project has its own channel. Clients connected to the channel send block-level operations,
+//! which get merged into the in-memory [`ProjectDataV2`] and broadcast to all other clients of the project.
+//! Additionally the channel distributes the presence of users (who is in which section) and soft block locks.
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::State;
+use rocket::tokio::sync::broadcast;
+use rocket_ws::{Channel, Message, WebSocket};
+use serde::{Deserialize, Serialize};
+use crate::data_storage::{ProjectDataV2, ProjectStorage};
+use crate::projects::{NewContentBlock, NewContentBlockEditorJSFormat};
+use crate::session::session_guard::StaffSession;
+use crate::settings::Settings;
+use crate::utils::api_helpers::parse_content_path;
+use crate::utils::html_sanitizer::HtmlSanitizer;
+
+/// Soft block locks expire after this many seconds if they aren't renewed
+const BLOCK_LOCK_TIMEOUT: u64 = 120;
+
+/// Holds the collaboration channels of all projects with connected clients
+pub struct CollaborationManager{
+    channels: RwLock<HashMap<uuid::Uuid, Arc<ProjectChannel>>>,
+}
+
+/// Collaboration channel of a single project
+pub struct ProjectChannel{
+    sender: broadcast::Sender<ServerMessage>,
+    presence: RwLock<HashMap<uuid::Uuid, Presence>>,
+    /// Soft block locks, identified by section path and block id
+    locks: RwLock<HashMap<(String, String), BlockLock>>,
+}
+
+/// A connected client and the section it currently edits
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Presence{
+    pub connection_id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub user_email: String,
+    pub section_path: Option<String>,
+}
+
+/// Soft lock on a content block
+///
+/// Operations of other clients on a locked block are rejected.
+/// Locks get released when the client disconnects or after [`BLOCK_LOCK_TIMEOUT`] seconds.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockLock{
+    pub section_path: String,
+    pub block_id: String,
+    pub connection_id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub user_email: String,
+    /// Unix timestamp in seconds when the lock was acquired or renewed
+    pub locked_at: u64,
+}
+
+/// Block-level operation on the content blocks of a section
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "op")]
+pub enum BlockOperation{
+    /// Inserts a new block after the given block or as first block if no block is given
+    Insert{section_path: String, after_block_id: Option<String>, block: NewContentBlockEditorJSFormat},
+    /// Replaces the block with the same id
+    Update{section_path: String, block: NewContentBlockEditorJSFormat},
+    Delete{section_path: String, block_id: String},
+    /// Moves the block after the given block or to the first position if no block is given
+    Move{section_path: String, block_id: String, after_block_id: Option<String>},
+}
+
+impl BlockOperation{
+    fn section_path(&self) -> &str{
+        match self{
+            BlockOperation::Insert { section_path, .. } => section_path,
+            BlockOperation::Update { section_path, .. } => section_path,
+            BlockOperation::Delete { section_path, .. } => section_path,
+            BlockOperation::Move { section_path, .. } => section_path,
+        }
+    }
+
+    /// Id of the existing block the operation modifies, if any
+    fn target_block_id(&self) -> Option<&str>{
+        match self{
+            BlockOperation::Insert { .. } => None,
+            BlockOperation::Update { block, .. } => Some(&block.id),
+            BlockOperation::Delete { block_id, .. } => Some(block_id),
+            BlockOperation::Move { block_id, .. } => Some(block_id),
+        }
+    }
+}
+
+/// Messages sent by clients
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum ClientMessage{
+    EnterSection{section_path: String},
+    LeaveSection,
+    LockBlock{section_path: String, block_id: String},
+    UnlockBlock{section_path: String, block_id: String},
+    Operation{operation: Box<BlockOperation>},
+}
+
+/// Messages sent to clients
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub enum ServerMessage{
+    /// First message after connecting, contains the id of the connection
+    Welcome{connection_id: uuid::Uuid},
+    /// Operation applied by another client
+    Operation{connection_id: uuid::Uuid, user_id: uuid::Uuid, operation: Box<BlockOperation>},
+    /// All content blocks of the section were replaced (e.g. via the REST api), clients should reload the section
+    SectionReplaced{section_path: String},
+    /// Suggestions of the section were discarded, clients showing them should reload them
+    SuggestionsChanged{section_path: String},
+    Presence{users: Vec<Presence>},
+    Locks{locks: Vec<BlockLock>},
+    /// Sent only to the client whose message couldn't be processed
+    Error{message: String},
+}
+
+impl CollaborationManager{
+    pub fn new() -> CollaborationManager{
+        CollaborationManager{
+            channels: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Registers the connection in the channel of the project, creates the channel if it doesn't exist yet
+    ///
+    /// The presence is added while the channels are locked, so the channel can't be removed in between by [`Self::leave`]
+    fn join(&self, project_id: &uuid::Uuid, connection: &Presence) -> Arc<ProjectChannel>{
+        let mut channels = self.channels.write().unwrap();
+        let channel = channels.entry(*project_id).or_insert_with(|| {
+            let (sender, _) = broadcast::channel(256);
+            Arc::new(ProjectChannel{
+                sender,
+                presence: RwLock::new(HashMap::new()),
+                locks: RwLock::new(HashMap::new()),
+            })
+        }).clone();
+        channel.presence.write().unwrap().insert(connection.connection_id, connection.clone());
+        channel
+    }
+
+    /// Sends a message to all clients connected to the project, if there are any
+    pub fn broadcast(&self, project_id: &uuid::Uuid, message: ServerMessage){
+        if let Some(channel) = self.channels.read().unwrap().get(project_id){
+            // Sending only fails if there are no receivers, that's fine
+            let _ = channel.sender.send(message);
+        }
+    }
+
+    /// Removes the presence and locks of the connection, the channel is removed if no client is connected anymore
+    fn leave(&self, project_id: &uuid::Uuid, connection_id: &uuid::Uuid){
+        let mut channels = self.channels.write().unwrap();
+        let channel = match channels.get(project_id){
+            Some(channel) => channel.clone(),
+            None => return,
+        };
+        channel.presence.write().unwrap().remove(connection_id);
+        channel.locks.write().unwrap().retain(|_, lock| lock.connection_id != *connection_id);
+        if channel.presence.read().unwrap().is_empty(){
+            channels.remove(project_id);
+        }else{
+            channel.broadcast_presence();
+            channel.broadcast_locks();
+        }
+    }
+}
+
+impl Default for CollaborationManager{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProjectChannel{
+    fn broadcast_presence(&self){
+        let users = self.presence.read().unwrap().values().cloned().collect();
+        let _ = self.sender.send(ServerMessage::Presence { users });
+    }
+
+    fn broadcast_locks(&self){
+        let locks = self.locks.read().unwrap().values().cloned().collect();
+        let _ = self.sender.send(ServerMessage::Locks { locks });
+    }
+
+    /// Removes all expired locks, returns true if a lock was removed
+    fn remove_expired_locks(&self) -> bool{
+        let now = now();
+        let mut locks = self.locks.write().unwrap();
+        let len = locks.len();
+        locks.retain(|_, lock| lock.locked_at + BLOCK_LOCK_TIMEOUT > now);
+        len != locks.len()
+    }
+
+    /// Returns the lock on the block, if it is held by another connection
+    fn foreign_lock(&self, section_path: &str, block_id: &str, connection_id: &uuid::Uuid) -> Option<BlockLock>{
+        match self.locks.read().unwrap().get(&(section_path.to_string(), block_id.to_string())){
+            Some(lock) if lock.connection_id != *connection_id => Some(lock.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn now() -> u64{
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Applies a block-level operation to a section of the project
+///
+/// Returns the operation as it was stored (e.g. with sanitized content) to broadcast it to the other clients
+pub fn apply_operation(project: &Arc<RwLock<ProjectDataV2>>, operation: BlockOperation, sanitizer: &HtmlSanitizer) -> Result<BlockOperation, String>{
+    let path = parse_content_path(operation.section_path()).map_err(|_| "Couldn't parse content path".to_string())?;
+
+    let mut project = project.write().unwrap();
+    let section = crate::data_storage::get_section_by_path_mut(&mut project, &path).map_err(|_| "Section not found".to_string())?;
+
+    let find_block = |blocks: &Vec<NewContentBlock>, block_id: &str| blocks.iter().position(|block| block.id == block_id);
+
+    let convert_block = |block: NewContentBlockEditorJSFormat| -> Result<NewContentBlock, String>{
+        let mut block: NewContentBlock = block.try_into()?;
+        block.validate()?;
+        sanitizer.sanitize_content_block(&mut block);
+        Ok(block)
+    };
+
+    let result = match operation{
+        BlockOperation::Insert { section_path, after_block_id, block } => {
+            let block = convert_block(block)?;
+            if find_block(&section.children, &block.id).is_some(){
+                return Err(format!("Block with id {} already exists", block.id));
+            }
+            let index = match &after_block_id{
+                Some(after_block_id) => find_block(&section.children, after_block_id).ok_or("Block to insert after not found".to_string())? + 1,
+                None => 0,
+            };
+            section.children.insert(index, block.clone());
+            Ok(BlockOperation::Insert { section_path, after_block_id, block: block.into() })
+        },
+        BlockOperation::Update { section_path, block } => {
+            let block = convert_block(block)?;
+            let index = find_block(&section.children, &block.id).ok_or("Block not found".to_string())?;
+            section.children[index] = block.clone();
+            Ok(BlockOperation::Update { section_path, block: block.into() })
+        },
+        BlockOperation::Delete { section_path, block_id } => {
+            let index = find_block(&section.children, &block_id).ok_or("Block not found".to_string())?;
+            section.children.remove(index);
+            Ok(BlockOperation::Delete { section_path, block_id })
+        },
+        BlockOperation::Move { section_path, block_id, after_block_id } => {
+            let index = find_block(&section.children, &block_id).ok_or("Block not found".to_string())?;
+            let block = section.children.remove(index);
+            let new_index = match &after_block_id{
+                Some(after_block_id) => match find_block(&section.children, after_block_id){
+                    Some(i) => i + 1,
+                    None => {
+                        // Restore the old position before failing
+                        section.children.insert(index, block);
+                        return Err("Block to move after not found".to_string());
+                    }
+                },
+                None => 0,
+            };
+            section.children.insert(new_index, block);
+            Ok(BlockOperation::Move { section_path, block_id, after_block_id })
+        },
+    };
+
+    if result.is_ok(){
+        section.increment_version();
+    }
+    result
+}
+
+/// Handles a single message of a client, returns a message to send back only to this client
+async fn handle_client_message(message: ClientMessage, connection: &Presence, project_id: &uuid::Uuid, channel: &ProjectChannel, project_storage: &ProjectStorage, settings: &Settings) -> Option<ServerMessage>{
+    match message{
+        ClientMessage::EnterSection { section_path } => {
+            if let Some(presence) = channel.presence.write().unwrap().get_mut(&connection.connection_id){
+                presence.section_path = Some(section_path);
+            }
+            channel.broadcast_presence();
+            None
+        },
+        ClientMessage::LeaveSection => {
+            if let Some(presence) = channel.presence.write().unwrap().get_mut(&connection.connection_id){
+                presence.section_path = None;
+            }
+            channel.broadcast_presence();
+            None
+        },
+        ClientMessage::LockBlock { section_path, block_id } => {
+            channel.remove_expired_locks();
+            if let Some(lock) = channel.foreign_lock(&section_path, &block_id, &connection.connection_id){
+                return Some(ServerMessage::Error { message: format!("Block is locked by {}", lock.user_email) });
+            }
+            // Acquire or renew the lock
+            channel.locks.write().unwrap().insert((section_path.clone(), block_id.clone()), BlockLock{
+                section_path,
+                block_id,
+                connection_id: connection.connection_id,
+                user_id: connection.user_id,
+                user_email: connection.user_email.clone(),
+                locked_at: now(),
+            });
+            channel.broadcast_locks();
+            None
+        },
+        ClientMessage::UnlockBlock { section_path, block_id } => {
+            let key = (section_path, block_id);
+            let removed = {
+                let mut locks = channel.locks.write().unwrap();
+                match locks.get(&key){
+                    Some(lock) if lock.connection_id == connection.connection_id => locks.remove(&key).is_some(),
+                    _ => false,
+                }
+            };
+            if removed{
+                channel.broadcast_locks();
+            }
+            None
+        },
+        ClientMessage::Operation { operation } => {
+            if channel.remove_expired_locks(){
+                channel.broadcast_locks();
+            }
+            if let Some(block_id) = operation.target_block_id(){
+                if let Some(lock) = channel.foreign_lock(operation.section_path(), block_id, &connection.connection_id){
+                    return Some(ServerMessage::Error { message: format!("Block is locked by {}", lock.user_email) });
+                }
+            }
+
+            let project = match project_storage.get_project(project_id, settings).await{
+                Ok(project) => project,
+                Err(_) => return Some(ServerMessage::Error { message: "Project not found".to_string() }),
+            };
+
+            let sanitizer = HtmlSanitizer::new(&settings.html_sanitizer);
+            match apply_operation(&project, *operation, &sanitizer){
+                Ok(operation) => {
+                    let _ = channel.sender.send(ServerMessage::Operation {
+                        connection_id: connection.connection_id,
+                        user_id: connection.user_id,
+                        operation: Box::new(operation),
+                    });
+                    None
+                },
+                Err(e) => Some(ServerMessage::Error { message: e }),
+            }
+        },
+    }
+}
+
+/// GET /api/projects/<project_id>/ws
+/// Opens the collaboration WebSocket channel of the project
+#[get("/api/projects/<project_id>/ws")]
+pub async fn project_channel(project_id: String, ws: WebSocket, session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, collaboration: &State<Arc<CollaborationManager>>) -> Option<Channel<'static>>{
+    let project_id = match uuid::Uuid::parse_str(&project_id){
+        Ok(project_id) => project_id,
+        Err(e) => {
+            eprintln!("Couldn't parse project id: {}", e);
+            return None
+        }
+    };
+
+    // Only open channels for existing projects
+    if project_storage.get_project(&project_id, settings).await.is_err(){
+        return None
+    }
+
+    let settings = settings.inner().clone();
+    let project_storage = project_storage.inner().clone();
+    let collaboration = collaboration.inner().clone();
+
+    Some(ws.channel(move |mut stream| Box::pin(async move {
+        let connection = Presence{
+            connection_id: uuid::Uuid::new_v4(),
+            user_id: session.user_id,
+            user_email: session.user_email.clone(),
+            section_path: None,
+        };
+        let channel = collaboration.join(&project_id, &connection);
+        let mut receiver = channel.sender.subscribe();
+
+        let welcome = serde_json::to_string(&ServerMessage::Welcome { connection_id: connection.connection_id }).unwrap();
+        if stream.send(Message::Text(welcome)).await.is_ok(){
+            channel.broadcast_presence();
+            channel.broadcast_locks();
+
+            loop{
+                let outgoing = rocket::tokio::select! {
+                    incoming = stream.next() => match incoming{
+                        Some(Ok(Message::Text(text))) => match serde_json::from_str::<ClientMessage>(&text){
+                            Ok(message) => handle_client_message(message, &connection, &project_id, &channel, &project_storage, &settings).await,
+                            Err(e) => Some(ServerMessage::Error { message: format!("Couldn't parse message: {}", e) }),
+                        },
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => None,
+                    },
+                    broadcast = receiver.recv() => match broadcast{
+                        // Don't echo operations back to the client which sent them
+                        Ok(ServerMessage::Operation { connection_id, .. }) if connection_id == connection.connection_id => None,
+                        Ok(message) => Some(message),
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            eprintln!("Collaboration client {} lagged behind, skipped {} messages", connection.connection_id, skipped);
+                            None
+                        },
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                };
+
+                if let Some(message) = outgoing{
+                    let message = serde_json::to_string(&message).unwrap();
+                    if stream.send(Message::Text(message)).await.is_err(){
+                        break
+                    }
+                }
+            }
+        }
+
+        // Clean up presence and locks of this connection
+        collaboration.leave(&project_id, &connection.connection_id);
+
+        Ok(())
+    })))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::projects::{Section, SectionMetadata, SectionOrToc};
+    use crate::settings::HtmlSanitizerSettings;
+
+    fn generate_project() -> (Arc<RwLock<ProjectDataV2>>, String){
+        let section_id = uuid::Uuid::new_v4();
+        let section = Section{
+            id: Some(section_id),
+            visible_in_toc: true,
+            metadata: SectionMetadata{ title: "Chapter".to_string(), ..Default::default() },
+            ..Default::default()
+        };
+        let project = ProjectDataV2{
+            name: "Project".to_string(),
+            description: None,
+            template_id: uuid::Uuid::new_v4(),
+            last_interaction: 0,
+            metadata: None,
+            settings: None,
+            sections: vec![SectionOrToc::Section(section)],
+            bibliography: HashMap::new(),
+            version: 0,
+            comments: vec![],
+            suggestions: vec![],
+            workflow: Default::default(),
+        };
+        (Arc::new(RwLock::new(project)), section_id.to_string())
+    }
+
+    fn generate_sanitizer() -> HtmlSanitizer{
+        HtmlSanitizer::new(&HtmlSanitizerSettings{
+            allowed_elements: HashMap::from([("b".to_string(), vec![])]),
+            generic_attributes: vec![],
+            url_schemes: vec![],
+            html_attributes: vec![],
+        })
+    }
+
+    fn operation(json: serde_json::Value) -> BlockOperation{
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn insert(section_path: &str, id: &str, after: Option<&str>, text: &str) -> BlockOperation{
+        operation(serde_json::json!({"op": "Insert", "section_path": section_path, "after_block_id": after, "block": {"id": id, "type": "paragraph", "data": {"text": text}, "tunes": {}}}))
+    }
+
+    fn block_ids(project: &Arc<RwLock<ProjectDataV2>>) -> Vec<String>{
+        let project = project.read().unwrap();
+        match &project.sections[0]{
+            SectionOrToc::Section(section) => section.children.iter().map(|block| block.id.clone()).collect(),
+            SectionOrToc::Toc => vec![],
+        }
+    }
+
+    #[test]
+    fn test_apply_operation(){
+        let (project, path) = generate_project();
+        let sanitizer = generate_sanitizer();
+
+        apply_operation(&project, insert(&path, "a", None, "A"), &sanitizer).unwrap();
+        apply_operation(&project, insert(&path, "b", Some("a"), "B"), &sanitizer).unwrap();
+        apply_operation(&project, insert(&path, "c", None, "C"), &sanitizer).unwrap();
+        assert_eq!(block_ids(&project), vec!["c", "a", "b"]);
+        assert!(apply_operation(&project, insert(&path, "a", None, "A"), &sanitizer).is_err());
+
+        // The stored (sanitized) block is returned for the broadcast
+        let updated = apply_operation(&project, operation(serde_json::json!({"op": "Update", "section_path": path, "block": {"id": "a", "type": "paragraph", "data": {"text": "<b>A</b><script>x()</script>"}, "tunes": {}}})), &sanitizer).unwrap();
+        match updated{
+            BlockOperation::Update { block, .. } => assert_eq!(block.data.text, Some("<b>A</b>".to_string())),
+            _ => panic!("Expected update"),
+        }
+
+        apply_operation(&project, operation(serde_json::json!({"op": "Move", "section_path": path, "block_id": "c", "after_block_id": "b"})), &sanitizer).unwrap();
+        assert_eq!(block_ids(&project), vec!["a", "b", "c"]);
+        // Moving after a missing block keeps the old position
+        assert!(apply_operation(&project, operation(serde_json::json!({"op": "Move", "section_path": path, "block_id": "a", "after_block_id": "x"})), &sanitizer).is_err());
+        assert_eq!(block_ids(&project), vec!["a", "b", "c"]);
+
+        apply_operation(&project, operation(serde_json::json!({"op": "Delete", "section_path": path, "block_id": "b"})), &sanitizer).unwrap();
+        assert_eq!(block_ids(&project), vec!["a", "c"]);
+        assert!(apply_operation(&project, operation(serde_json::json!({"op": "Delete", "section_path": path, "block_id": "b"})), &sanitizer).is_err());
+
+        // Failed operations don't change the version
+        let project = project.read().unwrap();
+        match &project.sections[0]{
+            SectionOrToc::Section(section) => assert_eq!(section.version, 6),
+            SectionOrToc::Toc => panic!("Expected section"),
+        }
+    }
+
+    fn presence(user_email: &str) -> Presence{
+        Presence{
+            connection_id: uuid::Uuid::new_v4(),
+            user_id: uuid::Uuid::new_v4(),
+            user_email: user_email.to_string(),
+            section_path: None,
+        }
+    }
+
+    #[test]
+    fn test_locks_and_presence(){
+        let manager = CollaborationManager::default();
+        let project_id = uuid::Uuid::new_v4();
+        let first = presence("first@example.org");
+        let second = presence("second@example.org");
+
+        let channel = manager.join(&project_id, &first);
+        assert!(Arc::ptr_eq(&channel, &manager.join(&project_id, &second)));
+
+        channel.locks.write().unwrap().insert(("path".to_string(), "a".to_string()), BlockLock{
+            section_path: "path".to_string(),
+            block_id: "a".to_string(),
+            connection_id: first.connection_id,
+            user_id: first.user_id,
+            user_email: first.user_email.clone(),
+            locked_at: now(),
+        });
+        assert!(channel.foreign_lock("path", "a", &first.connection_id).is_none());
+        assert_eq!(channel.foreign_lock("path", "a", &second.connection_id).unwrap().user_email, "first@example.org");
+
+        // Expired locks are removed
+        channel.locks.write().unwrap().get_mut(&("path".to_string(), "a".to_string())).unwrap().locked_at = now() - BLOCK_LOCK_TIMEOUT;
+        assert!(channel.remove_expired_locks());
+        assert!(channel.foreign_lock("path", "a", &second.connection_id).is_none());
+
+        // Locks of a connection are released when it leaves, the channel is kept while others are connected
+        channel.locks.write().unwrap().insert(("path".to_string(), "b".to_string()), BlockLock{
+            section_path: "path".to_string(),
+            block_id: "b".to_string(),
+            connection_id: first.connection_id,
+            user_id: first.user_id,
+            user_email: first.user_email.clone(),
+            locked_at: now(),
+        });
+        manager.leave(&project_id, &first.connection_id);
+        assert!(channel.locks.read().unwrap().is_empty());
+        assert!(manager.channels.read().unwrap().contains_key(&project_id));
+
+        manager.leave(&project_id, &second.connection_id);
+        assert!(!manager.channels.read().unwrap().contains_key(&project_id));
+
+        // Joining again creates a new channel
+        let third = presence("third@example.org");
+        assert!(!Arc::ptr_eq(&channel, &manager.join(&project_id, &third)));
+    }
+}
@@ -0,0 +1,470 @@
+//! Review comments anchored to content blocks.
+//!
+//! Comments are stored per project as a flat list. A thread consists of a root comment, which holds the anchor
+//! (section, block and optional text range) and the resolved state, and all comments replying to it.
+
+use bincode::{Decode, Encode};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// Range of characters in the text of a content block a comment refers to
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+pub struct TextRange{
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+pub struct Comment{
+    #[bincode(with_serde)]
+    pub id: uuid::Uuid,
+    /// Id of the section the commented block is in
+    #[bincode(with_serde)]
+    pub section_id: uuid::Uuid,
+    /// Id of the commented content block
+    pub block_id: String,
+    /// Commented part of the block text, None if the comment refers to the whole block
+    pub text_range: Option<TextRange>,
+    /// Id of the root comment of the thread, None if this comment starts a new thread
+    #[bincode(with_serde)]
+    pub reply_to: Option<uuid::Uuid>,
+    /// Id of the user who wrote the comment
+    #[bincode(with_serde)]
+    pub author: uuid::Uuid,
+    pub content: String,
+    #[bincode(with_serde)]
+    pub created: NaiveDateTime,
+    #[bincode(with_serde)]
+    pub last_changed: Option<NaiveDateTime>,
+    /// Only used on root comments, replies share the state of their thread
+    pub resolved: bool,
+    #[bincode(with_serde)]
+    pub resolved_by: Option<uuid::Uuid>,
+    #[bincode(with_serde)]
+    pub resolved_at: Option<NaiveDateTime>,
+}
+
+/// Root comment with all its replies (oldest first)
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CommentThread{
+    pub comment: Comment,
+    pub replies: Vec<Comment>,
+}
+
+impl CommentThread{
+    /// Checks if the user wrote the root comment or one of the replies
+    pub fn has_participant(&self, user_id: &uuid::Uuid) -> bool{
+        self.comment.author == *user_id || self.replies.iter().any(|reply| reply.author == *user_id)
+    }
+}
+
+/// Groups a flat list of comments into threads, ordered by creation date
+pub fn build_threads(comments: &[Comment]) -> Vec<CommentThread>{
+    let mut threads: Vec<CommentThread> = comments.iter()
+        .filter(|comment| comment.reply_to.is_none())
+        .map(|comment| CommentThread{
+            comment: comment.clone(),
+            replies: vec![],
+        })
+        .collect();
+
+    for reply in comments.iter().filter(|comment| comment.reply_to.is_some()){
+        if let Some(thread) = threads.iter_mut().find(|thread| Some(thread.comment.id) == reply.reply_to){
+            thread.replies.push(reply.clone());
+        }
+    }
+
+    threads.sort_by_key(|thread| thread.comment.created);
+    for thread in threads.iter_mut(){
+        thread.replies.sort_by_key(|reply| reply.created);
+    }
+    threads
+}
+
+pub mod api{
+    use std::sync::Arc;
+    use rocket::serde::json::Json;
+    use rocket::State;
+    use serde::{Deserialize, Serialize};
+    use crate::data_storage::{DataStorage, ProjectDataV2, ProjectStorage};
+    use crate::mail::Mailer;
+    use crate::mail::notifications::{notify, Notification};
+    use crate::projects::api::{ApiError, ApiResult};
+    use crate::projects::comments::{build_threads, Comment, CommentThread, TextRange};
+    use crate::projects::find_content_block;
+    use crate::projects::contributors::Access;
+    use crate::projects::tasks::Assignee;
+    use crate::session::session_guard::Session;
+    use crate::settings::Settings;
+
+    #[derive(Deserialize, Serialize)]
+    pub struct NewComment{
+        pub section_id: uuid::Uuid,
+        pub block_id: String,
+        pub text_range: Option<TextRange>,
+        pub content: String,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct CommentContent{
+        pub content: String,
+    }
+
+    /// Notifies all recipients except the author of the comment
+    fn send_comment_notifications(project: &ProjectDataV2, project_id: uuid::Uuid, comment: &Comment, mut recipients: Vec<uuid::Uuid>, mailer: &Mailer, data_storage: &DataStorage){
+        recipients.sort();
+        recipients.dedup();
+        recipients.retain(|recipient| *recipient != comment.author);
+        if recipients.is_empty(){
+            return;
+        }
+
+        let notification = Notification::NewComment {
+            project_id,
+            project_name: project.name.clone(),
+            section_title: project.find_section(&comment.section_id).map(|section| section.metadata.title.clone()).unwrap_or_default(),
+            author_name: data_storage.get_user_by_id(&comment.author).map(|user| user.read().unwrap().name.clone()).unwrap_or_default(),
+            content: comment.content.clone(),
+            reply: comment.reply_to.is_some(),
+        };
+        for recipient in recipients.iter(){
+            notify(mailer, data_storage, recipient, &notification);
+        }
+    }
+
+    /// GET /api/projects/<project_id>/comments?<section_id>&<user_id>&<include_resolved>
+    /// List comment threads of a project, only open threads by default
+    /// Filter by section or by user (threads the user started or replied to)
+    #[get("/api/projects/<project_id>/comments?<section_id>&<user_id>&<include_resolved>")]
+    pub async fn list_comments(project_id: String, section_id: Option<String>, user_id: Option<String>, include_resolved: Option<bool>, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Vec<CommentThread>>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let section_id = match section_id.map(|id| uuid::Uuid::parse_str(&id)).transpose(){
+            Ok(section_id) => section_id,
+            Err(_) => return ApiResult::new_error(ApiError::BadRequest("Couldn't parse section id".to_string())),
+        };
+
+        let user_id = match user_id.map(|id| uuid::Uuid::parse_str(&id)).transpose(){
+            Ok(user_id) => user_id,
+            Err(_) => return ApiResult::new_error(ApiError::BadRequest("Couldn't parse user id".to_string())),
+        };
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let project = project.read().unwrap();
+        let threads = build_threads(&project.comments).into_iter()
+            .filter(|thread| access.can_access_section_id(&project, &thread.comment.section_id))
+            .filter(|thread| include_resolved.unwrap_or(false) || !thread.comment.resolved)
+            .filter(|thread| section_id.is_none_or(|section_id| thread.comment.section_id == section_id))
+            .filter(|thread| user_id.is_none_or(|user_id| thread.has_participant(&user_id)))
+            .collect();
+
+        ApiResult::new_data(threads)
+    }
+
+    /// POST /api/projects/<project_id>/comments
+    /// Start a new comment thread on a content block
+    #[post("/api/projects/<project_id>/comments", data = "<new_comment>")]
+    #[allow(clippy::too_many_arguments, reason = "assignees of the section are notified by mail")]
+    pub async fn add_comment(project_id: String, new_comment: Json<NewComment>, session: Session, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, data_storage: &State<Arc<DataStorage>>, mailer: &State<Arc<Mailer>>) -> Json<ApiResult<Comment>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let new_comment = new_comment.into_inner();
+
+        if new_comment.content.trim().is_empty(){
+            return ApiResult::new_error(ApiError::BadRequest("Comment is empty".to_string()));
+        }
+        if let Some(range) = &new_comment.text_range{
+            if range.start > range.end{
+                return ApiResult::new_error(ApiError::BadRequest("Invalid text range".to_string()));
+            }
+        }
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let mut project = project.write().unwrap();
+
+        if !access.can_access_section_id(&project, &new_comment.section_id){
+            return ApiResult::new_error(ApiError::Unauthorized);
+        }
+
+        // Check that the commented block exists
+        let assigned_users: Vec<uuid::Uuid> = match project.find_section(&new_comment.section_id){
+            Some(section) => {
+                if find_content_block(&section.children, &new_comment.block_id).is_none(){
+                    return ApiResult::new_error(ApiError::BadRequest(format!("Block {} does not exist", new_comment.block_id)));
+                }
+                section.assignment.assignees.iter().filter_map(|assignee| match assignee{
+                    Assignee::User { id } => Some(*id),
+                    Assignee::Person { .. } => None,
+                }).collect()
+            },
+            None => return ApiResult::new_error(ApiError::BadRequest(format!("Section {} does not exist", new_comment.section_id))),
+        };
+
+        let comment = Comment{
+            id: uuid::Uuid::new_v4(),
+            section_id: new_comment.section_id,
+            block_id: new_comment.block_id,
+            text_range: new_comment.text_range,
+            reply_to: None,
+            author: session.user_id,
+            content: new_comment.content,
+            created: chrono::Utc::now().naive_utc(),
+            last_changed: None,
+            resolved: false,
+            resolved_by: None,
+            resolved_at: None,
+        };
+
+        project.comments.push(comment.clone());
+        send_comment_notifications(&project, project_id, &comment, assigned_users, mailer, data_storage);
+        ApiResult::new_data(comment)
+    }
+
+    /// POST /api/projects/<project_id>/comments/<comment_id>/replies
+    /// Reply to a comment thread, replies to replies are added to the same thread
+    #[post("/api/projects/<project_id>/comments/<comment_id>/replies", data = "<reply>")]
+    #[allow(clippy::too_many_arguments, reason = "participants of the thread are notified by mail")]
+    pub async fn reply_to_comment(project_id: String, comment_id: String, reply: Json<CommentContent>, session: Session, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, data_storage: &State<Arc<DataStorage>>, mailer: &State<Arc<Mailer>>) -> Json<ApiResult<Comment>>{
+        let (project_id, comment_id) = match (uuid::Uuid::parse_str(&project_id), uuid::Uuid::parse_str(&comment_id)) {
+            (Ok(project_id), Ok(comment_id)) => (project_id, comment_id),
+            _ => return ApiResult::new_error(ApiError::BadRequest("Couldn't parse id".to_string())),
+        };
+
+        if reply.content.trim().is_empty(){
+            return ApiResult::new_error(ApiError::BadRequest("Comment is empty".to_string()));
+        }
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let mut project = project.write().unwrap();
+
+        let parent = match project.comments.iter().find(|comment| comment.id == comment_id){
+            Some(parent) => parent.clone(),
+            None => return ApiResult::new_error(ApiError::NotFound),
+        };
+        if !access.can_access_section_id(&project, &parent.section_id){
+            return ApiResult::new_error(ApiError::Unauthorized);
+        }
+
+        let comment = Comment{
+            id: uuid::Uuid::new_v4(),
+            section_id: parent.section_id,
+            block_id: parent.block_id,
+            text_range: parent.text_range,
+            reply_to: Some(parent.reply_to.unwrap_or(parent.id)),
+            author: session.user_id,
+            content: reply.into_inner().content,
+            created: chrono::Utc::now().naive_utc(),
+            last_changed: None,
+            resolved: false,
+            resolved_by: None,
+            resolved_at: None,
+        };
+
+        // Notify everyone who participated in the thread
+        let thread_id = parent.reply_to.unwrap_or(parent.id);
+        let participants = project.comments.iter()
+            .filter(|comment| comment.id == thread_id || comment.reply_to == Some(thread_id))
+            .map(|comment| comment.author)
+            .collect();
+
+        project.comments.push(comment.clone());
+        send_comment_notifications(&project, project_id, &comment, participants, mailer, data_storage);
+        ApiResult::new_data(comment)
+    }
+
+    /// PUT /api/projects/<project_id>/comments/<comment_id>
+    /// Edit the content of a comment, only allowed for the author as long as they can access the section
+    #[put("/api/projects/<project_id>/comments/<comment_id>", data = "<content>")]
+    pub async fn update_comment(project_id: String, comment_id: String, content: Json<CommentContent>, session: Session, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Comment>>{
+        let (project_id, comment_id) = match (uuid::Uuid::parse_str(&project_id), uuid::Uuid::parse_str(&comment_id)) {
+            (Ok(project_id), Ok(comment_id)) => (project_id, comment_id),
+            _ => return ApiResult::new_error(ApiError::BadRequest("Couldn't parse id".to_string())),
+        };
+
+        if content.content.trim().is_empty(){
+            return ApiResult::new_error(ApiError::BadRequest("Comment is empty".to_string()));
+        }
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let mut project = project.write().unwrap();
+
+        // Authors who lost access to the section can't change their comments there anymore
+        let section_id = match project.comments.iter().find(|comment| comment.id == comment_id){
+            Some(comment) => comment.section_id,
+            None => return ApiResult::new_error(ApiError::NotFound),
+        };
+        if !access.can_access_section_id(&project, &section_id){
+            return ApiResult::new_error(ApiError::Unauthorized);
+        }
+
+        match project.comments.iter_mut().find(|comment| comment.id == comment_id){
+            Some(comment) => {
+                if comment.author != session.user_id{
+                    return ApiResult::new_error(ApiError::Unauthorized);
+                }
+                comment.content = content.into_inner().content;
+                comment.last_changed = Some(chrono::Utc::now().naive_utc());
+                ApiResult::new_data(comment.clone())
+            },
+            None => ApiResult::new_error(ApiError::NotFound),
+        }
+    }
+
+    /// DELETE /api/projects/<project_id>/comments/<comment_id>
+    /// Delete a comment, only allowed for the author as long as they can access the section. Deleting the root comment deletes the whole thread
+    #[delete("/api/projects/<project_id>/comments/<comment_id>")]
+    pub async fn delete_comment(project_id: String, comment_id: String, session: Session, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<()>>{
+        let (project_id, comment_id) = match (uuid::Uuid::parse_str(&project_id), uuid::Uuid::parse_str(&comment_id)) {
+            (Ok(project_id), Ok(comment_id)) => (project_id, comment_id),
+            _ => return ApiResult::new_error(ApiError::BadRequest("Couldn't parse id".to_string())),
+        };
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let mut project = project.write().unwrap();
+
+        match project.comments.iter().find(|comment| comment.id == comment_id){
+            Some(comment) => {
+                if comment.author != session.user_id || !access.can_access_section_id(&project, &comment.section_id){
+                    return ApiResult::new_error(ApiError::Unauthorized);
+                }
+            },
+            None => return ApiResult::new_error(ApiError::NotFound),
+        }
+
+        project.comments.retain(|comment| comment.id != comment_id && comment.reply_to != Some(comment_id));
+        ApiResult::new_data(())
+    }
+
+    /// PUT /api/projects/<project_id>/comments/<comment_id>/resolve
+    /// Mark a thread as resolved, works on the root comment or any reply of the thread
+    #[put("/api/projects/<project_id>/comments/<comment_id>/resolve")]
+    pub async fn resolve_comment(project_id: String, comment_id: String, session: Session, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Comment>>{
+        set_resolved(project_id, comment_id, true, session, access, settings, project_storage).await
+    }
+
+    /// PUT /api/projects/<project_id>/comments/<comment_id>/reopen
+    /// Reopen a resolved thread
+    #[put("/api/projects/<project_id>/comments/<comment_id>/reopen")]
+    pub async fn reopen_comment(project_id: String, comment_id: String, session: Session, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Comment>>{
+        set_resolved(project_id, comment_id, false, session, access, settings, project_storage).await
+    }
+
+    async fn set_resolved(project_id: String, comment_id: String, resolved: bool, session: Session, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Comment>>{
+        let (project_id, comment_id) = match (uuid::Uuid::parse_str(&project_id), uuid::Uuid::parse_str(&comment_id)) {
+            (Ok(project_id), Ok(comment_id)) => (project_id, comment_id),
+            _ => return ApiResult::new_error(ApiError::BadRequest("Couldn't parse id".to_string())),
+        };
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let mut project = project.write().unwrap();
+
+        // Resolve state is stored on the root comment of the thread
+        let (root_id, section_id) = match project.comments.iter().find(|comment| comment.id == comment_id){
+            Some(comment) => (comment.reply_to.unwrap_or(comment.id), comment.section_id),
+            None => return ApiResult::new_error(ApiError::NotFound),
+        };
+        if !access.can_access_section_id(&project, &section_id){
+            return ApiResult::new_error(ApiError::Unauthorized);
+        }
+
+        match project.comments.iter_mut().find(|comment| comment.id == root_id){
+            Some(root) => {
+                root.resolved = resolved;
+                if resolved{
+                    root.resolved_by = Some(session.user_id);
+                    root.resolved_at = Some(chrono::Utc::now().naive_utc());
+                }else{
+                    root.resolved_by = None;
+                    root.resolved_at = None;
+                }
+                ApiResult::new_data(root.clone())
+            },
+            None => ApiResult::new_error(ApiError::NotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn generate_comment(reply_to: Option<uuid::Uuid>, minute: u32) -> Comment{
+        Comment{
+            id: uuid::Uuid::new_v4(),
+            section_id: uuid::Uuid::nil(),
+            block_id: "block".to_string(),
+            text_range: None,
+            reply_to,
+            author: uuid::Uuid::new_v4(),
+            content: "Comment".to_string(),
+            created: chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(12, minute, 0).unwrap(),
+            last_changed: None,
+            resolved: false,
+            resolved_by: None,
+            resolved_at: None,
+        }
+    }
+
+    #[test]
+    fn test_build_threads(){
+        let root = generate_comment(None, 0);
+        let late_reply = generate_comment(Some(root.id), 10);
+        let early_reply = generate_comment(Some(root.id), 5);
+        let other_root = generate_comment(None, 1);
+
+        let threads = build_threads(&[late_reply.clone(), other_root.clone(), root.clone(), early_reply.clone()]);
+        assert_eq!(threads.len(), 2);
+        assert_eq!(threads[0].comment, root);
+        assert_eq!(threads[0].replies, vec![early_reply.clone(), late_reply]);
+        assert!(threads[0].has_participant(&early_reply.author));
+        assert!(threads[1].replies.is_empty());
+    }
+}
@@ -0,0 +1,388 @@
+//! Contributor portal.
+//!
+//! Users can be linked to a [Person][crate::data_storage::Person], which makes them contributors. Contributors only
+//! see and edit the sections the person is credited on as author (including their subsections), can render
+//! chapter-only PDF previews and approve the proof of their chapters. All other endpoints require a
+//! [StaffSession][crate::session::session_guard::StaffSession].
+
+use std::sync::Arc;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Request, State};
+use serde::{Deserialize, Serialize};
+use crate::data_storage::{DataStorage, ProjectDataV2};
+use crate::projects::{Section, SectionOrToc};
+use crate::session::errors::LoginError;
+use crate::session::session_guard::Session;
+use crate::settings::WorkflowSettings;
+
+/// What the current user is allowed to access
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access{
+    /// Users without person link, full access
+    Staff,
+    /// Users linked to a person, access to the sections crediting the person as author
+    Contributor{
+        person_id: uuid::Uuid,
+    },
+}
+
+impl Access{
+    pub fn is_staff(&self) -> bool{
+        *self == Access::Staff
+    }
+
+    /// Checks if the section at `path` or one of its parent sections credits the contributor as author
+    pub fn can_access_section(&self, sections: &[SectionOrToc], path: &[uuid::Uuid]) -> bool{
+        let person_id = match self{
+            Access::Staff => return true,
+            Access::Contributor { person_id } => person_id,
+        };
+
+        let mut candidates: Vec<&Section> = sections.iter().filter_map(|section| match section{
+            SectionOrToc::Section(section) => Some(section),
+            _ => None,
+        }).collect();
+
+        for id in path{
+            let section = match candidates.iter().find(|section| section.id == Some(*id)){
+                Some(section) => *section,
+                None => return false,
+            };
+            if section.metadata.authors.contains(person_id){
+                return true;
+            }
+            candidates = section.sub_sections.iter().collect();
+        }
+        false
+    }
+
+    /// Checks if a project upload is referenced in one of the sections crediting the contributor
+    pub fn can_access_upload(&self, sections: &[SectionOrToc], filename: &str) -> bool{
+        fn credited_with_upload(section: &Section, person_id: &uuid::Uuid, filename: &str) -> bool{
+            if section.metadata.authors.contains(person_id){
+                // Uploads are named by uuid, so they can't be confused with other content
+                return serde_json::to_string(section).map(|json| json.contains(filename)).unwrap_or(false);
+            }
+            section.sub_sections.iter().any(|sub_section| credited_with_upload(sub_section, person_id, filename))
+        }
+
+        let person_id = match self{
+            Access::Staff => return true,
+            Access::Contributor { person_id } => person_id,
+        };
+        sections.iter().any(|section| match section{
+            SectionOrToc::Section(section) => credited_with_upload(section, person_id, filename),
+            _ => false,
+        })
+    }
+
+    /// Like [Access::can_access_section], for a section id instead of a path
+    pub fn can_access_section_id(&self, project: &ProjectDataV2, section_id: &uuid::Uuid) -> bool{
+        if self.is_staff(){
+            return true;
+        }
+        match project.find_section_path(section_id){
+            Some(path) => self.can_access_section(&project.sections, &path),
+            None => false,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Access {
+    type Error = LoginError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let session = match request.guard::<Session>().await {
+            Outcome::Success(session) => session,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+        let data_storage : &State<Arc<DataStorage>> = match request.guard::<&State<Arc<DataStorage>>>().await {
+            Outcome::Success(data_storage) => data_storage,
+            _ => return Outcome::Error((Status::Unauthorized, LoginError::Unavailable)),
+        };
+        match data_storage.get_contributor_person(&session.user_id) {
+            Some(person_id) => Outcome::Success(Access::Contributor { person_id }),
+            None => Outcome::Success(Access::Staff),
+        }
+    }
+}
+
+/// Section a person is credited on as author, as listed in the contributor portal
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ContributorSection{
+    pub project_id: uuid::Uuid,
+    pub project_name: String,
+    /// Colon separated content path of the section
+    pub section_path: String,
+    pub section_title: String,
+    /// Workflow state of the section
+    pub state: String,
+    /// True if the proof can be approved, i.e. the section can be moved to the approval state
+    pub can_approve: bool,
+}
+
+/// Collects the sections crediting the person as author in document order, subsections of credited sections are not listed separately
+pub fn credited_sections(project_id: uuid::Uuid, project_name: &str, sections: &[SectionOrToc], person_id: &uuid::Uuid, settings: &WorkflowSettings) -> Vec<ContributorSection>{
+    fn collect(section: &Section, parent_path: &str, project: (uuid::Uuid, &str), person_id: &uuid::Uuid, settings: &WorkflowSettings, result: &mut Vec<ContributorSection>){
+        let id = section.id.unwrap_or_default();
+        let path = if parent_path.is_empty(){
+            id.to_string()
+        }else{
+            format!("{}:{}", parent_path, id)
+        };
+
+        if section.metadata.authors.contains(person_id){
+            let state = section.workflow.current_state(settings).to_string();
+            let can_approve = settings.approval_state.as_ref().is_some_and(|approval_state| settings.find_transition(&state, approval_state).is_some());
+            result.push(ContributorSection{
+                project_id: project.0,
+                project_name: project.1.to_string(),
+                section_path: path,
+                section_title: section.metadata.title.clone(),
+                state,
+                can_approve,
+            });
+            return;
+        }
+
+        for sub_section in section.sub_sections.iter(){
+            collect(sub_section, &path, project, person_id, settings, result);
+        }
+    }
+
+    let mut result = vec![];
+    for section in sections.iter(){
+        if let SectionOrToc::Section(section) = section{
+            collect(section, "", (project_id, project_name), person_id, settings, &mut result);
+        }
+    }
+    result
+}
+
+pub mod api{
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use rocket::serde::json::Json;
+    use rocket::State;
+    use rocket_dyn_templates::Template;
+    use serde::{Deserialize, Serialize};
+    use crate::data_storage::{DataStorage, ProjectStorage};
+    use crate::export::rendering_manager::{RenderingManager, RenderingOptions};
+    use crate::export::scope::RenderingScope;
+    use crate::mail::Mailer;
+    use crate::projects::api::{ApiError, ApiResult};
+    use crate::projects::contributors::{credited_sections, Access, ContributorSection};
+    use crate::projects::workflow::api::{notify_section_transition, WorkflowOverview};
+    use crate::session::session_guard::Session;
+    use crate::settings::Settings;
+    use crate::utils::api_helpers::parse_content_path;
+    use crate::webhooks::WebhookManager;
+
+    #[derive(Deserialize, Serialize)]
+    pub struct ProofApproval{
+        pub comment: Option<String>,
+    }
+
+    /// Loads the credited sections of the contributor from all projects
+    async fn contributor_sections(person_id: uuid::Uuid, settings: &Settings, project_storage: &ProjectStorage) -> Vec<ContributorSection>{
+        let project_ids: Vec<uuid::Uuid> = project_storage.projects.read().unwrap().keys().cloned().collect();
+
+        let mut sections = vec![];
+        for project_id in project_ids{
+            let project = match project_storage.get_project(&project_id, settings).await{
+                Ok(project) => project,
+                Err(_) => {
+                    eprintln!("Couldn't load project {} while collecting contributor sections", project_id);
+                    continue
+                }
+            };
+            let project = project.read().unwrap();
+            sections.append(&mut credited_sections(project_id, &project.name, &project.sections, &person_id, &settings.workflow));
+        }
+        sections
+    }
+
+    /// GET /contributor
+    /// Contributor portal, lists the chapters of the current contributor
+    #[get("/contributor")]
+    pub async fn contributor_portal(access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Template {
+        let sections = match access{
+            Access::Contributor { person_id } => contributor_sections(person_id, settings, project_storage).await,
+            Access::Staff => vec![],
+        };
+
+        let mut data = BTreeMap::new();
+        data.insert("sections", sections);
+        Template::render("contributor", data)
+    }
+
+    /// GET /api/contributor/sections
+    /// Lists all sections the current contributor is credited on as author
+    #[get("/api/contributor/sections")]
+    pub async fn list_contributor_sections(access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Vec<ContributorSection>>>{
+        match access{
+            Access::Contributor { person_id } => ApiResult::new_data(contributor_sections(person_id, settings, project_storage).await),
+            Access::Staff => ApiResult::new_error(ApiError::BadRequest("User isn't linked to a person".to_string())),
+        }
+    }
+
+    /// POST /api/projects/<project_id>/sections/<content_path>/preview
+    /// Renders a PDF preview which only contains the section, returns the rendering id
+    #[post("/api/projects/<project_id>/sections/<content_path>/preview")]
+    pub async fn render_section_preview(project_id: String, content_path: String, session: Session, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, rendering_manager: &State<Arc<RenderingManager>>) -> Json<ApiResult<uuid::Uuid>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let path = match parse_content_path(&content_path){
+            Ok(path) => path,
+            Err(e) => return ApiResult::new_error(e),
+        };
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let project = {
+            let project = project.read().unwrap();
+            if !access.can_access_section(&project.sections, &path){
+                return ApiResult::new_error(ApiError::Unauthorized);
+            }
+            if let Err(e) = crate::data_storage::get_section_by_path(&project, &path){
+                return ApiResult::new_error(e);
+            }
+            project.clone()
+        };
+
+        let options = RenderingOptions{
+            scope: RenderingScope::Sections { paths: vec![content_path] },
+            ..Default::default()
+        };
+        ApiResult::new_data(rendering_manager.add_rendering_request(project, project_id, options, session.user_id))
+    }
+
+    /// POST /api/projects/<project_id>/sections/<content_path>/approve
+    /// Approves the proof of a section, moves it to the configured approval state
+    #[post("/api/projects/<project_id>/sections/<content_path>/approve", data = "<approval>")]
+    #[allow(clippy::too_many_arguments, reason = "approving notifies by mail and webhook like a workflow transition")]
+    pub async fn approve_section_proof(project_id: String, content_path: String, approval: Option<Json<ProofApproval>>, session: Session, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, data_storage: &State<Arc<DataStorage>>, mailer: &State<Arc<Mailer>>, webhooks: &State<Arc<WebhookManager>>) -> Json<ApiResult<WorkflowOverview>>{
+        let approval_state = match &settings.workflow.approval_state{
+            Some(approval_state) => approval_state.clone(),
+            None => return ApiResult::new_error(ApiError::BadRequest("Proof approval is not configured".to_string())),
+        };
+
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let path = match parse_content_path(&content_path){
+            Ok(path) => path,
+            Err(e) => return ApiResult::new_error(e),
+        };
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let comment = approval.and_then(|approval| approval.into_inner().comment);
+        let mut project = project.write().unwrap();
+        if !access.can_access_section(&project.sections, &path){
+            return ApiResult::new_error(ApiError::Unauthorized);
+        }
+        let project_name = project.name.clone();
+        let section = match crate::data_storage::get_section_by_path_mut(&mut project, &path){
+            Ok(section) => section,
+            Err(e) => return ApiResult::new_error(e),
+        };
+
+        if let Err(e) = section.workflow.transition(&approval_state, &settings.workflow, session.user_id, &session.user_email, comment){
+            return ApiResult::new_error(e.into());
+        }
+        notify_section_transition(project_id, project_name, section, session.user_id, data_storage, mailer, webhooks);
+
+        ApiResult::new_data(WorkflowOverview::new(&section.workflow, &settings.workflow, &session.user_email))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::projects::{BlockData, BlockType, NewContentBlock, SectionMetadata};
+    use crate::projects::api::UploadedImage;
+
+    fn section(title: &str, authors: Vec<uuid::Uuid>, sub_sections: Vec<Section>) -> Section{
+        Section{
+            id: Some(uuid::Uuid::new_v4()),
+            sub_sections,
+            visible_in_toc: true,
+            metadata: SectionMetadata{ title: title.to_string(), authors, ..Default::default() },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_contributor_access(){
+        let person = uuid::Uuid::new_v4();
+        let sub_section = section("Subsection", vec![], vec![]);
+        let sub_section_id = sub_section.id.unwrap();
+        let mut own = section("Own chapter", vec![person], vec![sub_section]);
+        let upload = uuid::Uuid::new_v4().to_string();
+        own.children.push(NewContentBlock{
+            id: "image".to_string(),
+            block_type: BlockType::Image,
+            data: BlockData::Image{
+                file: UploadedImage{ url: format!("/api/projects/{}/uploads/{}", uuid::Uuid::nil(), upload), filename: upload.clone() },
+                caption: None,
+                with_border: false,
+                with_background: false,
+                stretched: false,
+            },
+            css_classes: vec![],
+            revision_id: None,
+        });
+        let own_id = own.id.unwrap();
+        let other = section("Other chapter", vec![uuid::Uuid::new_v4()], vec![]);
+        let other_id = other.id.unwrap();
+        let sections = vec![SectionOrToc::Toc, SectionOrToc::Section(own), SectionOrToc::Section(other)];
+
+        let access = Access::Contributor { person_id: person };
+        assert!(access.can_access_section(&sections, &[own_id]));
+        assert!(access.can_access_section(&sections, &[own_id, sub_section_id]));
+        assert!(!access.can_access_section(&sections, &[other_id]));
+        assert!(!access.can_access_section(&sections, &[sub_section_id]));
+        assert!(Access::Staff.can_access_section(&sections, &[other_id]));
+
+        assert!(access.can_access_upload(&sections, &upload));
+        assert!(!access.can_access_upload(&sections, &uuid::Uuid::new_v4().to_string()));
+        assert!(!Access::Contributor { person_id: uuid::Uuid::new_v4() }.can_access_upload(&sections, &upload));
+
+        let mut settings = WorkflowSettings::default();
+        let credited = credited_sections(uuid::Uuid::nil(), "Project", &sections, &person, &settings);
+        assert_eq!(credited.len(), 1);
+        assert_eq!(credited[0].section_title, "Own chapter");
+        assert_eq!(credited[0].section_path, own_id.to_string());
+        assert!(!credited[0].can_approve);
+
+        settings.states.rotate_left(3);
+        let credited = credited_sections(uuid::Uuid::nil(), "Project", &sections, &person, &settings);
+        assert_eq!(credited[0].state, "copy_edited");
+        assert!(credited[0].can_approve);
+    }
+}
@@ -7,12 +7,12 @@ use rocket::response::Redirect;
 use rocket::State;
 use rocket_dyn_templates::Template;
 use crate::data_storage::{DataStorage, ProjectStorage};
-use crate::session::session_guard::Session;
+use crate::session::session_guard::StaffSession;
 use crate::settings::Settings;
 
 /// Show create project form
 #[get("/projects/create")]
-pub async fn show_create_project(_session: Session, data_storage: &State<Arc<DataStorage>>) -> Result<Template, Status> {
+pub async fn show_create_project(_session: StaffSession, data_storage: &State<Arc<DataStorage>>) -> Result<Template, Status> {
     // Get list of all templates
     let templates : Vec<ProjectTemplateV2> = data_storage.data.read().unwrap().templates.iter().map(|(_id, entry) | entry.clone().read().unwrap().clone()).collect();
 
@@ -29,7 +29,7 @@ pub struct CreateProjectForm{
 
 /// Process create project form
 #[post("/projects/create", data = "<data>")]
-pub async fn process_create_project(_session: Session, data: rocket::form::Form<CreateProjectForm>, data_storage: &State<Arc<DataStorage>>, project_storage: &State<Arc<ProjectStorage>>, settings: &State<Settings>) -> Result<Redirect, Status> {
+pub async fn process_create_project(_session: StaffSession, data: rocket::form::Form<CreateProjectForm>, data_storage: &State<Arc<DataStorage>>, project_storage: &State<Arc<ProjectStorage>>, settings: &State<Settings>) -> Result<Redirect, Status> {
     let template_id = match uuid::Uuid::try_parse(&data.template_id){
         Ok(template_id) => template_id,
         Err(e) => {
@@ -52,6 +52,10 @@ pub async fn process_create_project(_session: Session, data: rocket::form::Form<
         settings: None,
         sections: vec![],
         bibliography: HashMap::new(),
+        version: 0,
+        comments: vec![],
+        suggestions: vec![],
+        workflow: Default::default(),
     };
 
     match project_storage.insert_project(project_data, settings).await{
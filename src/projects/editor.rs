@@ -2,13 +2,17 @@ use std::sync::Arc;
 use rocket::http::Status;
 use rocket::State;
 use rocket_dyn_templates::Template;
+use serde_json::json;
 use crate::data_storage::ProjectStorage;
-use crate::session::session_guard::Session;
+use crate::projects::contributors::Access;
 use crate::settings::Settings;
+use crate::utils::api_helpers::parse_content_path;
 
 
-#[get("/projects/<project_id>")]
-pub async fn show_editor(project_id: String, _session: Session, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Result<Template, Status> {
+/// GET /projects/<project_id>?<section>
+/// Editor of a project. Contributors only get the editor of a section they are credited on, given by its content path.
+#[get("/projects/<project_id>?<section>")]
+pub async fn show_editor(project_id: String, section: Option<String>, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Result<Template, Status> {
     let project_id = match uuid::Uuid::parse_str(&project_id) {
         Ok(project_id) => project_id,
         Err(e) => {
@@ -19,7 +23,7 @@ pub async fn show_editor(project_id: String, _session: Session, settings: &State
 
     let project_storage = Arc::clone(project_storage);
 
-    let _project_entry = match project_storage.get_project(&project_id, settings).await{
+    let project_entry = match project_storage.get_project(&project_id, settings).await{
         Ok(project_entry) => project_entry.clone(),
         Err(_) => {
             eprintln!("Couldn't get project with id {}", project_id);
@@ -27,5 +31,19 @@ pub async fn show_editor(project_id: String, _session: Session, settings: &State
         },
     };
 
-    Ok(Template::render("editor", project_id))
-}
\ No newline at end of file
+    if !access.is_staff(){
+        let path = match section.as_deref().map(parse_content_path){
+            Some(Ok(path)) => path,
+            _ => return Err(Status::Forbidden),
+        };
+        if !access.can_access_section(&project_entry.read().unwrap().sections, &path){
+            return Err(Status::Forbidden);
+        }
+    }
+
+    Ok(Template::render("editor", json!({
+        "project_id": project_id,
+        "contributor": !access.is_staff(),
+        "section_path": section,
+    })))
+}
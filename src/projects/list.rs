@@ -3,10 +3,10 @@ use std::sync::Arc;
 use rocket_dyn_templates::Template;
 use rocket::State;
 use crate::data_storage::ProjectStorage;
-use crate::session::session_guard::Session;
+use crate::session::session_guard::StaffSession;
 
 #[get("/")]
-pub fn list_projects(_session: Session, project_storage: &State<Arc<ProjectStorage>>) -> Template {
+pub fn list_projects(_session: StaffSession, project_storage: &State<Arc<ProjectStorage>>) -> Template {
     // Get all projects
     let mut projects = vec![];
 
@@ -1,5 +1,7 @@
 use crate::projects::api::{UploadedImage, Patch};
 use chrono::NaiveDateTime;
+use crate::projects::workflow::WorkflowStatus;
+use crate::projects::tasks::SectionAssignment;
 use bincode::{Encode, Decode};
 use serde::{Serialize, Deserialize};
 
@@ -50,7 +52,7 @@ pub struct ProjectMetadata{
     pub published: Option<NaiveDateTime>,
     /// Languages of the book
     pub languages: Option<Vec<Language>>,
-    /// Number of pages of the book (should be automatically calculated)
+    /// Number of pages of the book, updated by renderings with the option `update_page_count`
     pub number_of_pages: Option<u32>,
     /// Short abstract of the book
     pub short_abstract: Option<String>,
@@ -94,7 +96,7 @@ pub enum License{
 
 
 /// Struct holds all data for a section (e.g. chapter, part, ...)
-#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Default)]
 pub struct Section{
     /// Unique id of the section
     /// Only None if the section is not yet saved in the database
@@ -110,9 +112,39 @@ pub struct Section{
     pub visible_in_toc: bool,
     /// Metadata of the section
     pub metadata: SectionMetadata,
+    /// Incremented on every change of the section itself (metadata or content blocks, not subsections)
+    ///
+    /// Sent to clients as ETag, so concurrent edits can be detected
+    #[serde(default)]
+    pub version: u64,
+    /// Editorial workflow state, see [workflow]
+    #[serde(default)]
+    pub workflow: WorkflowStatus,
+    /// Assignees and due date, see [tasks]
+    #[serde(default)]
+    pub assignment: SectionAssignment,
 }
 
 impl Section{
+    /// Marks the section as changed by incrementing its version
+    pub fn increment_version(&mut self){
+        self.version += 1;
+    }
+
+    /// Repairs the block types of all content blocks in this section and its subsections
+    ///
+    /// Returns the number of blocks that had to be repaired
+    pub fn repair_block_types(&mut self) -> usize {
+        let mut repaired = 0;
+        for block in self.children.iter_mut(){
+            repaired += block.repair_block_type();
+        }
+        for section in self.sub_sections.iter_mut(){
+            repaired += section.repair_block_types();
+        }
+        repaired
+    }
+
     pub fn clone_without_contentblocks(&self) -> Section {
         let mut new_section = self.clone();
         new_section.children = vec![];
@@ -347,7 +379,7 @@ pub enum SectionLevel{
 }
 
 /// Struct holds all metadata of a section
-#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Default)]
 pub struct SectionMetadata{
     pub title: String,
     pub subtitle: Option<String>,
@@ -470,7 +502,7 @@ pub struct BlockStyleTuneEditorJS{
     pub css_classes: String,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
 pub struct BlockDataEditorJSFormat{
     #[serde(skip_serializing_if = "Option::is_none")]
     pub text: Option<String>,
@@ -494,6 +526,19 @@ pub struct BlockDataEditorJSFormat{
     pub withBackground: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stretched: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attribution: Option<String>,
+    /// Nested blocks of container blocks (box, epigraph, abstract)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocks: Option<Vec<NewContentBlockEditorJSFormat>>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
@@ -521,62 +566,30 @@ impl TryFrom<NewContentBlockEditorJSFormat> for NewContentBlock{
             Some(tune) => tune.css_classes.split(" ").map(|s| s.to_string()).collect(),
             None => vec![],
         };
-        match value.block_type.as_str(){
+        let data = match value.block_type.as_str(){
             "paragraph" => {
-               let text = value.data.text.ok_or("Missing field 'text' in paragraph block".to_string())?;
-                Ok(NewContentBlock {
-                     id: value.id,
-                     block_type: BlockType::Paragraph,
-                     data: BlockData::Paragraph { text },
-                    css_classes,
-                    revision_id: None,
-                })
+                let text = value.data.text.ok_or("Missing field 'text' in paragraph block".to_string())?;
+                BlockData::Paragraph { text }
             },
             "header" => {
                 let level = value.data.level.ok_or("Missing field 'level' in header block".to_string())?;
                 let text = value.data.text.ok_or("Missing field 'text' in header block".to_string())?;
-
-                Ok(NewContentBlock {
-                    id: value.id,
-                    block_type: BlockType::Heading,
-                    data: BlockData::Heading { text, level },
-                    css_classes,
-                    revision_id: None,
-                })
+                BlockData::Heading { text, level }
             },
             "raw" => {
                 let html = value.data.html.ok_or("Missing field 'html' in raw block".to_string())?;
-
-                Ok(NewContentBlock {
-                    id: value.id,
-                    block_type: BlockType::Heading,
-                    data: BlockData::Raw {html},
-                    css_classes,
-                    revision_id: None,
-                })
+                BlockData::Raw {html}
             },
             "list" => {
-                let items = value.data.items.ok_or("Missing field 'items' in raw block".to_string())?;
-                let style = value.data.style.ok_or("Missing field 'style' in raw block".to_string())?;
-                Ok(NewContentBlock {
-                    id: value.id,
-                    block_type: BlockType::Heading,
-                    data: BlockData::List {style, items},
-                    css_classes,
-                    revision_id: None,
-                })
+                let items = value.data.items.ok_or("Missing field 'items' in list block".to_string())?;
+                let style = value.data.style.ok_or("Missing field 'style' in list block".to_string())?;
+                BlockData::List {style, items}
             },
             "quote" => {
                 let text = value.data.text.ok_or("Missing field 'text' in quote block".to_string())?;
                 let caption = value.data.caption.ok_or("Missing field 'caption' in quote block".to_string())?;
                 let alignment = value.data.alignment.ok_or("Missing field 'alignment' in quote block".to_string())?;
-                Ok(NewContentBlock {
-                    id: value.id,
-                    block_type: BlockType::Heading,
-                    data: BlockData::Quote {text, caption, alignment},
-                    css_classes,
-                    revision_id: None,
-                })
+                BlockData::Quote {text, caption, alignment}
             },
             "image" => {
                 let file = value.data.file.ok_or("Missing field 'file' in image block".to_string())?;
@@ -584,19 +597,143 @@ impl TryFrom<NewContentBlockEditorJSFormat> for NewContentBlock{
                 let with_border = value.data.withBorder.unwrap_or(false);
                 let with_background = value.data.withBackground.unwrap_or(false);
                 let stretched = value.data.stretched.unwrap_or(false);
-                Ok(NewContentBlock {
-                    id: value.id,
-                    block_type: BlockType::Heading,
-                    data: BlockData::Image {file, caption, with_border, with_background, stretched},
-                    css_classes,
-                    revision_id: None,
-                })
+                BlockData::Image {file, caption, with_border, with_background, stretched}
+            },
+            "math" => {
+                let latex = value.data.latex.ok_or("Missing field 'latex' in math block".to_string())?;
+                BlockData::Math {latex}
+            },
+            "code" => {
+                let code = value.data.code.ok_or("Missing field 'code' in code block".to_string())?;
+                // Treat an empty language label as no language
+                let language = value.data.language.filter(|language| !language.trim().is_empty());
+                BlockData::Code {code, language}
+            },
+            "box" => {
+                let title = value.data.title.unwrap_or_default();
+                let children = convert_nested_blocks(value.data.blocks.ok_or("Missing field 'blocks' in box block".to_string())?)?;
+                BlockData::Box {title, children}
+            },
+            "epigraph" => {
+                let attribution = value.data.attribution.unwrap_or_default();
+                let children = convert_nested_blocks(value.data.blocks.ok_or("Missing field 'blocks' in epigraph block".to_string())?)?;
+                BlockData::Epigraph {attribution, children}
+            },
+            "abstract" => {
+                let children = convert_nested_blocks(value.data.blocks.ok_or("Missing field 'blocks' in abstract block".to_string())?)?;
+                BlockData::Abstract {children}
             }
-            _ => Err("Unknown block type".to_string()),
+            _ => return Err("Unknown block type".to_string()),
+        };
+
+        // The block type is always derived from the data, so they can't diverge
+        Ok(NewContentBlock {
+            id: value.id,
+            block_type: data.block_type(),
+            data,
+            css_classes,
+            revision_id: None,
+        })
+    }
+}
+
+impl NewContentBlock{
+    /// Checks that the block is consistent and can be stored
+    ///
+    /// Returns a description of the first problem found
+    pub fn validate(&self) -> Result<(), String>{
+        if self.id.trim().is_empty(){
+            return Err("Block id must not be empty".to_string());
+        }
+        if self.block_type != self.data.block_type(){
+            return Err(format!("Block {} has type {:?} but contains data of type {:?}", self.id, self.block_type, self.data.block_type()));
         }
+        match &self.data{
+            BlockData::Heading { level, .. } if *level < 1 || *level > 6 => {
+                return Err(format!("Heading block {} has invalid level {}", self.id, level));
+            },
+            BlockData::List { style, .. } if style != "ordered" && style != "unordered" => {
+                return Err(format!("List block {} has invalid style {}", self.id, style));
+            },
+            BlockData::Box { children, .. } | BlockData::Epigraph { children, .. } | BlockData::Abstract { children } => {
+                for child in children{
+                    match child.data{
+                        BlockData::Paragraph {..} | BlockData::List {..} => child.validate()?,
+                        _ => return Err(format!("Container block {} may only contain paragraphs and lists", self.id)),
+                    }
+                }
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Sets the block type based on the data of the block and all nested blocks
+    ///
+    /// Returns the number of blocks that had to be repaired
+    pub fn repair_block_type(&mut self) -> usize{
+        let mut repaired = 0;
+        let block_type = self.data.block_type();
+        if self.block_type != block_type{
+            self.block_type = block_type;
+            repaired += 1;
+        }
+        match &mut self.data{
+            BlockData::Box { children, .. } | BlockData::Epigraph { children, .. } | BlockData::Abstract { children } => {
+                for child in children.iter_mut(){
+                    repaired += child.repair_block_type();
+                }
+            },
+            _ => {}
+        }
+        repaired
     }
 }
 
+/// Validates a list of content blocks, e.g. all blocks of a section
+///
+/// Additionally to [`NewContentBlock::validate`], checks that block ids are unique
+pub fn validate_content_blocks(blocks: &Vec<NewContentBlock>) -> Result<(), String>{
+    let mut ids = std::collections::HashSet::new();
+    for block in blocks{
+        block.validate()?;
+        if !ids.insert(block.id.as_str()){
+            return Err(format!("Duplicate block id {}", block.id));
+        }
+    }
+    Ok(())
+}
+
+/// Finds a content block by its id, including blocks nested in container blocks
+pub fn find_content_block<'a>(blocks: &'a [NewContentBlock], block_id: &str) -> Option<&'a NewContentBlock>{
+    for block in blocks{
+        if block.id == block_id{
+            return Some(block);
+        }
+        if let BlockData::Box { children, .. } | BlockData::Epigraph { children, .. } | BlockData::Abstract { children } = &block.data{
+            if let Some(block) = find_content_block(children, block_id){
+                return Some(block);
+            }
+        }
+    }
+    None
+}
+
+/// Converts the nested blocks of a container block
+///
+/// Container blocks may only hold paragraphs and lists
+fn convert_nested_blocks(blocks: Vec<NewContentBlockEditorJSFormat>) -> Result<Vec<NewContentBlock>, String>{
+    let mut res = Vec::with_capacity(blocks.len());
+    for block in blocks{
+        let block: NewContentBlock = block.try_into()?;
+        match block.data{
+            BlockData::Paragraph {..} | BlockData::List {..} => res.push(block),
+            _ => return Err("Container blocks may only contain paragraphs and lists".to_string()),
+        }
+    }
+    Ok(res)
+}
+
 impl From<NewContentBlock> for NewContentBlockEditorJSFormat{
 
     fn from(value: NewContentBlock) -> Self {
@@ -615,16 +752,7 @@ impl From<NewContentBlock> for NewContentBlockEditorJSFormat{
                     block_type: "paragraph".to_string(),
                     data: BlockDataEditorJSFormat {
                         text: Some(text),
-                        level: None,
-                        items: None,
-                        html: None,
-                        caption: None,
-                        alignment: None,
-                        style: None,
-                        file: None,
-                        withBorder: None,
-                        withBackground: None,
-                        stretched: None,
+                        ..Default::default()
                     },
                     tunes,
                 }
@@ -636,15 +764,7 @@ impl From<NewContentBlock> for NewContentBlockEditorJSFormat{
                     data: BlockDataEditorJSFormat {
                         text: Some(text),
                         level: Some(level),
-                        items: None,
-                        html: None,
-                        caption: None,
-                        alignment: None,
-                        style: None,
-                        file: None,
-                        withBorder: None,
-                        withBackground: None,
-                        stretched: None,
+                        ..Default::default()
                     },
                     tunes,
                 }
@@ -654,17 +774,8 @@ impl From<NewContentBlock> for NewContentBlockEditorJSFormat{
                     id: value.id,
                     block_type: "raw".to_string(),
                     data: BlockDataEditorJSFormat {
-                        text: None,
-                        level: None,
-                        items: None,
                         html: Some(html),
-                        caption: None,
-                        alignment: None,
-                        style: None,
-                        file: None,
-                        withBorder: None,
-                        withBackground: None,
-                        stretched: None,
+                        ..Default::default()
                     },
                     tunes,
                 }
@@ -674,17 +785,9 @@ impl From<NewContentBlock> for NewContentBlockEditorJSFormat{
                     id: value.id,
                     block_type: "list".to_string(),
                     data: BlockDataEditorJSFormat {
-                        text: None,
-                        level: None,
                         items: Some(items),
-                        html: None,
-                        caption: None,
-                        alignment: None,
                         style: Some(style),
-                        file: None,
-                        withBorder: None,
-                        withBackground: None,
-                        stretched: None,
+                        ..Default::default()
                     },
                     tunes,
                 }
@@ -695,16 +798,9 @@ impl From<NewContentBlock> for NewContentBlockEditorJSFormat{
                     block_type: "quote".to_string(),
                     data: BlockDataEditorJSFormat {
                         text: Some(text),
-                        level: None,
-                        items: None,
-                        html: None,
                         caption: Some(caption),
                         alignment: Some(alignment),
-                        style: None,
-                        file: None,
-                        withBorder: None,
-                        withBackground: None,
-                        stretched: None,
+                        ..Default::default()
                     },
                     tunes,
                 }
@@ -714,17 +810,70 @@ impl From<NewContentBlock> for NewContentBlockEditorJSFormat{
                     id: value.id,
                     block_type: "image".to_string(),
                     data: BlockDataEditorJSFormat {
-                        text: None,
-                        level: None,
-                        items: None,
-                        html: None,
                         caption,
-                        alignment: None,
-                        style: None,
                         file: Some(file),
                         withBorder: Some(with_border),
                         withBackground: Some(with_background),
                         stretched: Some(stretched),
+                        ..Default::default()
+                    },
+                    tunes,
+                }
+            },
+            BlockData::Math {latex} => {
+                NewContentBlockEditorJSFormat {
+                    id: value.id,
+                    block_type: "math".to_string(),
+                    data: BlockDataEditorJSFormat {
+                        latex: Some(latex),
+                        ..Default::default()
+                    },
+                    tunes,
+                }
+            },
+            BlockData::Code {code, language} => {
+                NewContentBlockEditorJSFormat {
+                    id: value.id,
+                    block_type: "code".to_string(),
+                    data: BlockDataEditorJSFormat {
+                        code: Some(code),
+                        language,
+                        ..Default::default()
+                    },
+                    tunes,
+                }
+            },
+            BlockData::Box {title, children} => {
+                NewContentBlockEditorJSFormat {
+                    id: value.id,
+                    block_type: "box".to_string(),
+                    data: BlockDataEditorJSFormat {
+                        title: Some(title),
+                        blocks: Some(children.into_iter().map(|child| child.into()).collect()),
+                        ..Default::default()
+                    },
+                    tunes,
+                }
+            },
+            BlockData::Epigraph {attribution, children} => {
+                NewContentBlockEditorJSFormat {
+                    id: value.id,
+                    block_type: "epigraph".to_string(),
+                    data: BlockDataEditorJSFormat {
+                        attribution: Some(attribution),
+                        blocks: Some(children.into_iter().map(|child| child.into()).collect()),
+                        ..Default::default()
+                    },
+                    tunes,
+                }
+            },
+            BlockData::Abstract {children} => {
+                NewContentBlockEditorJSFormat {
+                    id: value.id,
+                    block_type: "abstract".to_string(),
+                    data: BlockDataEditorJSFormat {
+                        blocks: Some(children.into_iter().map(|child| child.into()).collect()),
+                        ..Default::default()
                     },
                     tunes,
                 }
@@ -740,7 +889,31 @@ pub enum BlockType{
     Raw,
     List,
     Quote,
-    Image
+    Image,
+    Math,
+    Code,
+    Box,
+    Epigraph,
+    Abstract,
+}
+
+impl BlockData{
+    /// Returns the [`BlockType`] matching this data
+    pub fn block_type(&self) -> BlockType{
+        match self{
+            BlockData::Paragraph {..} => BlockType::Paragraph,
+            BlockData::Heading {..} => BlockType::Heading,
+            BlockData::Raw {..} => BlockType::Raw,
+            BlockData::List {..} => BlockType::List,
+            BlockData::Quote {..} => BlockType::Quote,
+            BlockData::Image {..} => BlockType::Image,
+            BlockData::Math {..} => BlockType::Math,
+            BlockData::Code {..} => BlockType::Code,
+            BlockData::Box {..} => BlockType::Box,
+            BlockData::Epigraph {..} => BlockType::Epigraph,
+            BlockData::Abstract {..} => BlockType::Abstract,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone, PartialEq)]
@@ -751,6 +924,16 @@ pub enum BlockData{
     List{style: String, items: Vec<String>},
     Quote{text: String, caption: String, alignment: String},
     Image{file: UploadedImage, caption: Option<String>, with_border: bool, with_background: bool, stretched: bool},
+    /// Display formula in LaTeX syntax
+    Math{latex: String},
+    /// Preformatted text or source code with an optional language label, excluded from hyphenation
+    Code{code: String, language: Option<String>},
+    /// Text box with a title, holding paragraphs and lists
+    Box{title: String, children: Vec<NewContentBlock>},
+    /// Epigraph with attribution, holding paragraphs and lists
+    Epigraph{attribution: String, children: Vec<NewContentBlock>},
+    /// Abstract or summary, holding paragraphs and lists
+    Abstract{children: Vec<NewContentBlock>},
 }
 
 /// Test function to test the deserialization of a content block
@@ -770,9 +953,61 @@ pub fn test_deserialize_and_serialize_content_block(){
     assert_eq!(json.parse::<serde_json::Value>().unwrap(), serde_json::to_string(&new).unwrap().parse::<serde_json::Value>().unwrap());
 }
 
+/// Test function to test that the block type is derived from the block data
+#[test]
+pub fn test_block_type_derived_from_data(){
+    let json = r#"{
+        "id": "123",
+        "type": "list",
+        "data": {
+            "style": "unordered",
+            "items": ["A"]
+        },
+        "tunes": {}
+    }"#;
+    let content_block: NewContentBlockEditorJSFormat = serde_json::from_str(json).unwrap();
+    let mut content_block: NewContentBlock = content_block.try_into().unwrap();
+    assert_eq!(content_block.block_type, BlockType::List);
+    assert!(content_block.validate().is_ok());
+
+    // Blocks stored with the wrong type get rejected and can be repaired
+    content_block.block_type = BlockType::Heading;
+    assert!(content_block.validate().is_err());
+    assert_eq!(content_block.repair_block_type(), 1);
+    assert_eq!(content_block.block_type, BlockType::List);
+}
+
+/// Test function to test the round-trip of a container block with nested blocks
+#[test]
+pub fn test_deserialize_and_serialize_container_block(){
+    let json = r#"{
+        "id": "box1",
+        "type": "box",
+        "data": {
+            "title": "Summary",
+            "blocks": [
+                {"id": "p1", "type": "paragraph", "data": {"text": "Test"}, "tunes": {}},
+                {"id": "l1", "type": "list", "data": {"style": "ordered", "items": ["A", "B"]}, "tunes": {}}
+            ]
+        },
+        "tunes": {}
+    }"#;
+    let content_block: NewContentBlockEditorJSFormat = serde_json::from_str(json).unwrap();
+    let content_block: NewContentBlock = content_block.try_into().unwrap();
+    assert_eq!(content_block.block_type, BlockType::Box);
+    let new: NewContentBlockEditorJSFormat = content_block.into();
+    assert_eq!(json.parse::<serde_json::Value>().unwrap(), serde_json::to_string(&new).unwrap().parse::<serde_json::Value>().unwrap());
+}
+
 pub mod create;
 pub mod editor;
 pub mod list;
 pub mod api;
 pub mod bibliography_editor;
-pub mod templates_editor;
\ No newline at end of file
+pub mod templates_editor;
+pub mod collaboration;
+pub mod comments;
+pub mod suggestions;
+pub mod workflow;
+pub mod tasks;
+pub mod contributors;
\ No newline at end of file
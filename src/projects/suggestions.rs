@@ -0,0 +1,693 @@
+//! Suggestion mode (track changes) for copy-editing.
+//!
+//! Suggestions are proposed insertions, deletions or formatting changes on the text of a content block.
+//! They are stored per project and only change the content when they are accepted.
+//!
+//! Positions are character offsets into the html of the block text (or of a list item). Every suggestion stores the
+//! text it was made on, suggestions on a text that was changed in the meantime are outdated and can't be applied.
+
+use bincode::{Decode, Encode};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::projects::{BlockData, NewContentBlock};
+
+/// Inline elements which can be suggested as formatting change
+pub const FORMAT_ELEMENTS: [&str; 8] = ["b", "i", "u", "s", "sub", "sup", "mark", "code"];
+
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+pub enum SuggestedChange{
+    /// Insert text (may contain inline html) at a position
+    Insert{position: u32, text: String},
+    /// Delete the text between start and end
+    Delete{start: u32, end: u32},
+    /// Wrap the text between start and end in an inline element (e.g. "i")
+    Format{start: u32, end: u32, element: String},
+}
+
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+pub struct Suggestion{
+    #[bincode(with_serde)]
+    pub id: uuid::Uuid,
+    #[bincode(with_serde)]
+    pub section_id: uuid::Uuid,
+    pub block_id: String,
+    /// Index of the list item, only used for list blocks
+    pub item: Option<u32>,
+    pub change: SuggestedChange,
+    /// Text of the block (or list item) the positions refer to
+    pub base_text: String,
+    /// Id of the user who made the suggestion
+    #[bincode(with_serde)]
+    pub author: uuid::Uuid,
+    #[bincode(with_serde)]
+    pub created: NaiveDateTime,
+}
+
+/// Suggestion with the name of its author, used to show changes in review exports
+pub struct AttributedSuggestion{
+    pub suggestion: Suggestion,
+    pub author_name: String,
+}
+
+pub enum ApplyMode{
+    /// Apply the change to the text
+    Accept,
+    /// Mark the change with ins/del/span elements, without applying it
+    ShowChanges,
+}
+
+/// Replaces the characters between start and end with the replacement
+struct TextEdit{
+    start: u32,
+    end: u32,
+    replacement: String,
+}
+
+impl TextEdit{
+    /// Moves a position of the text before the edit to the matching position after the edit
+    fn shift(&self, position: u32) -> u32{
+        if position <= self.start{
+            position
+        }else if position >= self.end{
+            position - (self.end - self.start) + self.replacement.chars().count() as u32
+        }else{
+            // Position was inside of the replaced text
+            self.start
+        }
+    }
+
+    fn apply(&self, text: &str) -> String{
+        let start = byte_index(text, self.start).unwrap_or(text.len());
+        let end = byte_index(text, self.end).unwrap_or(text.len());
+        format!("{}{}{}", &text[..start], self.replacement, &text[end..])
+    }
+}
+
+impl Suggestion{
+    /// Checks if the suggestion refers to the same block text as another one
+    pub fn same_text_as(&self, other: &Suggestion) -> bool{
+        self.section_id == other.section_id && self.block_id == other.block_id && self.item == other.item
+    }
+
+    /// Checks that the suggestion can be applied to the text without breaking its html
+    pub fn validate(&self, text: &str) -> Result<(), String>{
+        let check_position = |position: u32| -> Result<usize, String>{
+            let index = byte_index(text, position).ok_or("Position is out of range".to_string())?;
+            if inside_tag(text, index){
+                return Err("Position is inside of an html tag".to_string());
+            }
+            Ok(index)
+        };
+
+        match &self.change{
+            SuggestedChange::Insert { position, text: inserted } => {
+                check_position(*position)?;
+                if inserted.is_empty(){
+                    return Err("Inserted text is empty".to_string());
+                }
+                if !is_balanced(inserted){
+                    return Err("Inserted text contains unbalanced html".to_string());
+                }
+            },
+            SuggestedChange::Delete { start, end } | SuggestedChange::Format { start, end, .. } => {
+                if start >= end{
+                    return Err("Range is empty".to_string());
+                }
+                let start = check_position(*start)?;
+                let end = check_position(*end)?;
+                if !is_balanced(&text[start..end]){
+                    return Err("Range contains unbalanced html".to_string());
+                }
+            },
+        }
+
+        if let SuggestedChange::Format { element, .. } = &self.change{
+            if !FORMAT_ELEMENTS.contains(&element.as_str()){
+                return Err(format!("Unsupported format element {}", element));
+            }
+        }
+        Ok(())
+    }
+
+    /// Text edits to apply the suggestion, ordered from the end of the text to the start
+    fn edits(&self, mode: &ApplyMode, author_name: &str) -> Vec<TextEdit>{
+        let attributes = format!("data-author=\"{}\" data-date=\"{}\"", escape_attribute(author_name), self.created.format("%d.%m.%Y"));
+        match (&self.change, mode){
+            (SuggestedChange::Insert { position, text }, ApplyMode::Accept) => vec![
+                TextEdit{ start: *position, end: *position, replacement: text.clone() },
+            ],
+            (SuggestedChange::Insert { position, text }, ApplyMode::ShowChanges) => vec![
+                TextEdit{ start: *position, end: *position, replacement: format!("<ins class=\"suggestion suggestion-insert\" {}>{}</ins>", attributes, text) },
+            ],
+            (SuggestedChange::Delete { start, end }, ApplyMode::Accept) => vec![
+                TextEdit{ start: *start, end: *end, replacement: String::new() },
+            ],
+            (SuggestedChange::Delete { start, end }, ApplyMode::ShowChanges) => vec![
+                TextEdit{ start: *end, end: *end, replacement: "</del>".to_string() },
+                TextEdit{ start: *start, end: *start, replacement: format!("<del class=\"suggestion suggestion-delete\" {}>", attributes) },
+            ],
+            (SuggestedChange::Format { start, end, element }, ApplyMode::Accept) => vec![
+                TextEdit{ start: *end, end: *end, replacement: format!("</{}>", element) },
+                TextEdit{ start: *start, end: *start, replacement: format!("<{}>", element) },
+            ],
+            (SuggestedChange::Format { start, end, element }, ApplyMode::ShowChanges) => vec![
+                TextEdit{ start: *end, end: *end, replacement: format!("</{}></span>", element) },
+                TextEdit{ start: *start, end: *start, replacement: format!("<span class=\"suggestion suggestion-format\" data-format=\"{}\" {}><{}>", element, attributes, element) },
+            ],
+        }
+    }
+
+    fn shift(&mut self, edit: &TextEdit){
+        match &mut self.change{
+            SuggestedChange::Insert { position, .. } => *position = edit.shift(*position),
+            SuggestedChange::Delete { start, end } | SuggestedChange::Format { start, end, .. } => {
+                *start = edit.shift(*start);
+                *end = edit.shift(*end);
+            },
+        }
+    }
+}
+
+/// Applies a suggestion to the text
+///
+/// Pending suggestions on the same text are moved to the positions in the new text.
+/// Fails if the suggestion was made on another version of the text.
+pub fn apply_suggestion(text: &str, suggestion: &Suggestion, mode: &ApplyMode, author_name: &str, pending: &mut [&mut Suggestion]) -> Result<String, String>{
+    if suggestion.base_text != text{
+        return Err("Suggestion is outdated, the text was changed in the meantime".to_string());
+    }
+    suggestion.validate(text)?;
+
+    let mut new_text = text.to_string();
+    for edit in suggestion.edits(mode, author_name){
+        new_text = edit.apply(&new_text);
+        for other in pending.iter_mut(){
+            if other.base_text == text{
+                other.shift(&edit);
+            }
+        }
+    }
+
+    for other in pending.iter_mut(){
+        if other.base_text == text{
+            other.base_text = new_text.clone();
+        }
+    }
+    Ok(new_text)
+}
+
+/// Returns the text a suggestion refers to
+pub fn block_text(block: &NewContentBlock, item: Option<u32>) -> Option<&String>{
+    match (&block.data, item){
+        (BlockData::Paragraph { text }, None) => Some(text),
+        (BlockData::Heading { text, .. }, None) => Some(text),
+        (BlockData::Quote { text, .. }, None) => Some(text),
+        (BlockData::List { items, .. }, Some(item)) => items.get(item as usize),
+        _ => None,
+    }
+}
+
+pub fn block_text_mut(block: &mut NewContentBlock, item: Option<u32>) -> Option<&mut String>{
+    match (&mut block.data, item){
+        (BlockData::Paragraph { text }, None) => Some(text),
+        (BlockData::Heading { text, .. }, None) => Some(text),
+        (BlockData::Quote { text, .. }, None) => Some(text),
+        (BlockData::List { items, .. }, Some(item)) => items.get_mut(item as usize),
+        _ => None,
+    }
+}
+
+/// Marks all suggestions for a block in its text (show changes mode), outdated suggestions are skipped
+pub fn mark_suggestions(block: &mut NewContentBlock, suggestions: &[AttributedSuggestion]){
+    let mut items: Vec<Option<u32>> = suggestions.iter().filter(|s| s.suggestion.block_id == block.id).map(|s| s.suggestion.item).collect();
+    items.sort();
+    items.dedup();
+
+    for item in items{
+        let mut pending: Vec<(Suggestion, &str)> = suggestions.iter()
+            .filter(|s| s.suggestion.block_id == block.id && s.suggestion.item == item)
+            .map(|s| (s.suggestion.clone(), s.author_name.as_str()))
+            .collect();
+        pending.sort_by_key(|(suggestion, _)| suggestion.created);
+
+        let text = match block_text_mut(block, item){
+            Some(text) => text,
+            None => continue,
+        };
+
+        while !pending.is_empty(){
+            let (suggestion, author_name) = pending.remove(0);
+            let mut others: Vec<&mut Suggestion> = pending.iter_mut().map(|(s, _)| s).collect();
+            match apply_suggestion(text, &suggestion, &ApplyMode::ShowChanges, author_name, &mut others){
+                Ok(new_text) => *text = new_text,
+                Err(e) => eprintln!("Couldn't show suggestion {}: {}", suggestion.id, e),
+            }
+        }
+    }
+}
+
+/// Converts a character position to a byte index, None if the position is out of range
+fn byte_index(text: &str, position: u32) -> Option<usize>{
+    let position = position as usize;
+    if position == text.chars().count(){
+        return Some(text.len());
+    }
+    text.char_indices().nth(position).map(|(index, _)| index)
+}
+
+/// Checks if a byte index is inside of an html tag (e.g. between < and >)
+fn inside_tag(text: &str, index: usize) -> bool{
+    let before = &text[..index];
+    match before.rfind('<'){
+        Some(open) => before[open..].find('>').is_none(),
+        None => false,
+    }
+}
+
+/// Checks if every opened element in the html fragment is closed in the fragment and vice versa
+fn is_balanced(html: &str) -> bool{
+    const VOID_ELEMENTS: [&str; 4] = ["br", "hr", "img", "wbr"];
+    let mut stack: Vec<String> = vec![];
+    let mut rest = html;
+    while let Some(open) = rest.find('<'){
+        let close = match rest[open..].find('>'){
+            Some(close) => open + close,
+            None => return false,
+        };
+        let tag = &rest[open + 1..close];
+        rest = &rest[close + 1..];
+
+        if tag.ends_with('/'){
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/'){
+            if stack.pop().as_deref() != Some(name.trim()){
+                return false;
+            }
+        }else{
+            let name = tag.split_whitespace().next().unwrap_or_default().to_string();
+            if !VOID_ELEMENTS.contains(&name.as_str()){
+                stack.push(name);
+            }
+        }
+    }
+    !rest.contains('>') && stack.is_empty()
+}
+
+/// Escapes a value for an attribute, spaces are encoded too so the value isn't hyphenated on export
+fn escape_attribute(value: &str) -> String{
+    value.replace("&", "&amp;").replace("\"", "&quot;").replace("<", "&lt;").replace(">", "&gt;").replace(" ", "&#32;")
+}
+
+pub mod api{
+    use std::sync::{Arc, RwLockWriteGuard};
+    use rocket::serde::json::Json;
+    use rocket::State;
+    use serde::{Deserialize, Serialize};
+    use crate::data_storage::{ProjectDataV2, ProjectStorage};
+    use crate::projects::Section;
+    use crate::projects::api::{ApiError, ApiResult};
+    use crate::projects::collaboration::{CollaborationManager, ServerMessage};
+    use crate::projects::contributors::Access;
+    use crate::projects::suggestions::{apply_suggestion, block_text, block_text_mut, ApplyMode, SuggestedChange, Suggestion};
+    use crate::session::session_guard::Session;
+    use crate::settings::Settings;
+    use crate::utils::api_helpers::parse_content_path;
+    use crate::utils::html_sanitizer::HtmlSanitizer;
+
+    #[derive(Deserialize, Serialize)]
+    pub struct NewSuggestion{
+        pub section_id: uuid::Uuid,
+        pub block_id: String,
+        pub item: Option<u32>,
+        pub change: SuggestedChange,
+    }
+
+    /// GET /api/projects/<project_id>/suggestions?<section_id>
+    /// List all pending suggestions of a project or a section
+    #[get("/api/projects/<project_id>/suggestions?<section_id>")]
+    pub async fn list_suggestions(project_id: String, section_id: Option<String>, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Vec<Suggestion>>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let section_id = match section_id.map(|id| uuid::Uuid::parse_str(&id)).transpose(){
+            Ok(section_id) => section_id,
+            Err(_) => return ApiResult::new_error(ApiError::BadRequest("Couldn't parse section id".to_string())),
+        };
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let project = project.read().unwrap();
+        let mut suggestions: Vec<Suggestion> = project.suggestions.iter()
+            .filter(|suggestion| access.can_access_section_id(&project, &suggestion.section_id))
+            .filter(|suggestion| section_id.is_none_or(|section_id| suggestion.section_id == section_id))
+            .cloned()
+            .collect();
+        suggestions.sort_by_key(|suggestion| suggestion.created);
+
+        ApiResult::new_data(suggestions)
+    }
+
+    /// POST /api/projects/<project_id>/suggestions
+    /// Suggest a change on the current text of a content block
+    #[post("/api/projects/<project_id>/suggestions", data = "<new_suggestion>")]
+    pub async fn add_suggestion(project_id: String, new_suggestion: Json<NewSuggestion>, session: Session, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Suggestion>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let mut new_suggestion = new_suggestion.into_inner();
+
+        // Inserted text is stored in the block when accepted, so it has to follow the same rules as all other content
+        if let SuggestedChange::Insert { text, .. } = &mut new_suggestion.change{
+            *text = HtmlSanitizer::new(&settings.html_sanitizer).clean(text);
+        }
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let mut project = project.write().unwrap();
+
+        if !access.can_access_section_id(&project, &new_suggestion.section_id){
+            return ApiResult::new_error(ApiError::Unauthorized);
+        }
+
+        let base_text = match project.find_section(&new_suggestion.section_id){
+            Some(section) => {
+                match section.children.iter().find(|block| block.id == new_suggestion.block_id){
+                    Some(block) => match block_text(block, new_suggestion.item){
+                        Some(text) => text.clone(),
+                        None => return ApiResult::new_error(ApiError::BadRequest("Suggestions are not supported for this block".to_string())),
+                    },
+                    None => return ApiResult::new_error(ApiError::BadRequest(format!("Block {} does not exist", new_suggestion.block_id))),
+                }
+            },
+            None => return ApiResult::new_error(ApiError::BadRequest(format!("Section {} does not exist", new_suggestion.section_id))),
+        };
+
+        let suggestion = Suggestion{
+            id: uuid::Uuid::new_v4(),
+            section_id: new_suggestion.section_id,
+            block_id: new_suggestion.block_id,
+            item: new_suggestion.item,
+            change: new_suggestion.change,
+            base_text,
+            author: session.user_id,
+            created: chrono::Utc::now().naive_utc(),
+        };
+
+        if let Err(e) = suggestion.validate(&suggestion.base_text){
+            return ApiResult::new_error(ApiError::BadRequest(e));
+        }
+
+        project.suggestions.push(suggestion.clone());
+        ApiResult::new_data(suggestion)
+    }
+
+    /// PUT /api/projects/<project_id>/suggestions/<suggestion_id>/accept
+    /// Apply a suggestion to the content block
+    #[put("/api/projects/<project_id>/suggestions/<suggestion_id>/accept")]
+    pub async fn accept_suggestion(project_id: String, suggestion_id: String, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, collaboration: &State<Arc<CollaborationManager>>) -> Json<ApiResult<()>>{
+        let (project_id, suggestion_id) = match (uuid::Uuid::parse_str(&project_id), uuid::Uuid::parse_str(&suggestion_id)) {
+            (Ok(project_id), Ok(suggestion_id)) => (project_id, suggestion_id),
+            _ => return ApiResult::new_error(ApiError::BadRequest("Couldn't parse id".to_string())),
+        };
+
+        let project_entry = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let mut project = project_entry.write().unwrap();
+
+        let section_id = match project.suggestions.iter().find(|suggestion| suggestion.id == suggestion_id){
+            Some(suggestion) => suggestion.section_id,
+            None => return ApiResult::new_error(ApiError::NotFound),
+        };
+        if !access.can_access_section_id(&project, &section_id){
+            return ApiResult::new_error(ApiError::Unauthorized);
+        }
+
+        match accept_suggestions(&mut project, &section_id, &[suggestion_id], settings){
+            Ok(section_path) => {
+                collaboration.broadcast(&project_id, ServerMessage::SectionReplaced { section_path });
+                ApiResult::new_data(())
+            },
+            Err(e) => ApiResult::new_error(e),
+        }
+    }
+
+    /// PUT /api/projects/<project_id>/suggestions/<suggestion_id>/reject
+    /// Discard a suggestion without changing the content
+    #[put("/api/projects/<project_id>/suggestions/<suggestion_id>/reject")]
+    pub async fn reject_suggestion(project_id: String, suggestion_id: String, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, collaboration: &State<Arc<CollaborationManager>>) -> Json<ApiResult<()>>{
+        let (project_id, suggestion_id) = match (uuid::Uuid::parse_str(&project_id), uuid::Uuid::parse_str(&suggestion_id)) {
+            (Ok(project_id), Ok(suggestion_id)) => (project_id, suggestion_id),
+            _ => return ApiResult::new_error(ApiError::BadRequest("Couldn't parse id".to_string())),
+        };
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let mut project = project.write().unwrap();
+        let section_id = match project.suggestions.iter().find(|suggestion| suggestion.id == suggestion_id){
+            Some(suggestion) => suggestion.section_id,
+            None => return ApiResult::new_error(ApiError::NotFound),
+        };
+        if !access.can_access_section_id(&project, &section_id){
+            return ApiResult::new_error(ApiError::Unauthorized);
+        }
+        project.suggestions.retain(|suggestion| suggestion.id != suggestion_id);
+
+        if let Some(path) = project.find_section_path(&section_id){
+            let section_path = path.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(":");
+            collaboration.broadcast(&project_id, ServerMessage::SuggestionsChanged { section_path });
+        }
+        ApiResult::new_data(())
+    }
+
+    /// PUT /api/projects/<project_id>/sections/<content_path>/suggestions/accept
+    /// Apply all suggestions of a section (without subsections) in the order they were made
+    ///
+    /// Returns the ids of suggestions which couldn't be applied because they are outdated, they are kept
+    #[put("/api/projects/<project_id>/sections/<content_path>/suggestions/accept")]
+    pub async fn accept_section_suggestions(project_id: String, content_path: String, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, collaboration: &State<Arc<CollaborationManager>>) -> Json<ApiResult<Vec<uuid::Uuid>>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let path = match parse_content_path(&content_path){
+            Ok(path) => path,
+            Err(e) => return ApiResult::new_error(e),
+        };
+        let section_id = *path.last().unwrap();
+
+        let project_entry = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let mut project = project_entry.write().unwrap();
+        if !access.can_access_section(&project.sections, &path){
+            return ApiResult::new_error(ApiError::Unauthorized);
+        }
+
+        let mut suggestions: Vec<&Suggestion> = project.suggestions.iter().filter(|suggestion| suggestion.section_id == section_id).collect();
+        suggestions.sort_by_key(|suggestion| suggestion.created);
+        let ids: Vec<uuid::Uuid> = suggestions.iter().map(|suggestion| suggestion.id).collect();
+
+        if let Err(e) = accept_suggestions(&mut project, &section_id, &ids, settings){
+            return ApiResult::new_error(e);
+        }
+
+        let outdated = project.suggestions.iter().filter(|suggestion| suggestion.section_id == section_id).map(|suggestion| suggestion.id).collect();
+        collaboration.broadcast(&project_id, ServerMessage::SectionReplaced { section_path: content_path });
+        ApiResult::new_data(outdated)
+    }
+
+    /// PUT /api/projects/<project_id>/sections/<content_path>/suggestions/reject
+    /// Discard all suggestions of a section (without subsections)
+    #[put("/api/projects/<project_id>/sections/<content_path>/suggestions/reject")]
+    pub async fn reject_section_suggestions(project_id: String, content_path: String, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, collaboration: &State<Arc<CollaborationManager>>) -> Json<ApiResult<()>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let path = match parse_content_path(&content_path){
+            Ok(path) => path,
+            Err(e) => return ApiResult::new_error(e),
+        };
+        let section_id = *path.last().unwrap();
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let mut project = project.write().unwrap();
+        if !access.can_access_section(&project.sections, &path){
+            return ApiResult::new_error(ApiError::Unauthorized);
+        }
+        project.suggestions.retain(|suggestion| suggestion.section_id != section_id);
+        collaboration.broadcast(&project_id, ServerMessage::SuggestionsChanged { section_path: content_path });
+        ApiResult::new_data(())
+    }
+
+    /// Accepts the suggestions (all in the same section) in the given order
+    ///
+    /// A single outdated suggestion is an error, when accepting multiple suggestions outdated ones are skipped and kept.
+    /// Returns the content path of the section
+    fn accept_suggestions(project: &mut RwLockWriteGuard<ProjectDataV2>, section_id: &uuid::Uuid, ids: &[uuid::Uuid], settings: &Settings) -> Result<String, ApiError>{
+        let path = project.find_section_path(section_id).ok_or(ApiError::NotFound)?;
+
+        // Take the suggestions out of the project, so they can be changed together with the section
+        let mut suggestions = std::mem::take(&mut project.suggestions);
+        let result = match crate::data_storage::get_section_by_path_mut(project, &path){
+            Ok(section) => accept_in_section(section, &mut suggestions, ids, &HtmlSanitizer::new(&settings.html_sanitizer)),
+            Err(e) => Err(e),
+        };
+        project.suggestions = suggestions;
+        result?;
+
+        Ok(path.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(":"))
+    }
+
+    fn accept_in_section(section: &mut Section, suggestions: &mut Vec<Suggestion>, ids: &[uuid::Uuid], sanitizer: &HtmlSanitizer) -> Result<(), ApiError>{
+        // Check all suggestions first, so the section isn't changed if one of them can't be applied at all
+        let mut accepted = Vec::new();
+        let mut deleted_blocks = Vec::new();
+        for id in ids{
+            let suggestion = match suggestions.iter().find(|suggestion| suggestion.id == *id){
+                Some(suggestion) => suggestion,
+                None => continue,
+            };
+            match section.children.iter().find(|block| block.id == suggestion.block_id){
+                Some(block) => {
+                    let text = block_text(block, suggestion.item).ok_or(ApiError::BadRequest("Suggestions are not supported for this block".to_string()))?;
+                    if ids.len() == 1{
+                        apply_suggestion(text, suggestion, &ApplyMode::Accept, "", &mut []).map_err(ApiError::BadRequest)?;
+                    }
+                    accepted.push(*id);
+                },
+                // The block was deleted, the suggestion can't be applied anymore
+                None => deleted_blocks.push(*id),
+            }
+        }
+        suggestions.retain(|suggestion| !deleted_blocks.contains(&suggestion.id));
+
+        let mut changed = false;
+        for id in accepted{
+            let index = match suggestions.iter().position(|suggestion| suggestion.id == id){
+                Some(index) => index,
+                None => continue,
+            };
+            let suggestion = suggestions[index].clone();
+            let block = section.children.iter_mut().find(|block| block.id == suggestion.block_id).unwrap();
+            let text = block_text_mut(block, suggestion.item).unwrap();
+
+            let mut pending: Vec<&mut Suggestion> = suggestions.iter_mut().filter(|other| other.id != suggestion.id && other.same_text_as(&suggestion)).collect();
+            match apply_suggestion(text, &suggestion, &ApplyMode::Accept, "", &mut pending){
+                Ok(new_text) => {
+                    *text = new_text;
+                    suggestions.remove(index);
+                    changed = true;
+                },
+                Err(e) => eprintln!("Couldn't accept suggestion {}: {}", suggestion.id, e),
+            }
+        }
+
+        if changed{
+            // The inserted text was sanitized when the suggestion was made, the blocks are checked again nonetheless
+            sanitizer.sanitize_content_blocks(&mut section.children);
+            section.increment_version();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn generate_suggestion(base_text: &str, change: SuggestedChange) -> Suggestion{
+        Suggestion{
+            id: uuid::Uuid::new_v4(),
+            section_id: uuid::Uuid::nil(),
+            block_id: "block".to_string(),
+            item: None,
+            change,
+            base_text: base_text.to_string(),
+            author: uuid::Uuid::nil(),
+            created: chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_accept_moves_pending_suggestions(){
+        let text = "Das ist <b>ein</b> Text";
+        let insert = generate_suggestion(text, SuggestedChange::Insert { position: 4, text: "hier ".to_string() });
+        let mut format = generate_suggestion(text, SuggestedChange::Format { start: 19, end: 23, element: "i".to_string() });
+
+        let text = apply_suggestion(text, &insert, &ApplyMode::Accept, "", &mut [&mut format]).unwrap();
+        assert_eq!(text, "Das hier ist <b>ein</b> Text");
+
+        let text = apply_suggestion(&text, &format.clone(), &ApplyMode::Accept, "", &mut []).unwrap();
+        assert_eq!(text, "Das hier ist <b>ein</b> <i>Text</i>");
+    }
+
+    #[test]
+    fn test_invalid_and_outdated_suggestions_are_rejected(){
+        let text = "Das ist <b>ein</b> Text";
+        // Inside of a tag
+        assert!(generate_suggestion(text, SuggestedChange::Insert { position: 10, text: "x".to_string() }).validate(text).is_err());
+        // Deletes only the opening tag
+        assert!(generate_suggestion(text, SuggestedChange::Delete { start: 8, end: 14 }).validate(text).is_err());
+
+        let outdated = generate_suggestion("Das ist kein Text", SuggestedChange::Delete { start: 0, end: 4 });
+        assert!(apply_suggestion(text, &outdated, &ApplyMode::Accept, "", &mut []).is_err());
+    }
+
+    #[test]
+    fn test_show_changes(){
+        let text = "Das ist ein Text";
+        let delete = generate_suggestion(text, SuggestedChange::Delete { start: 8, end: 12 });
+        let res = apply_suggestion(text, &delete, &ApplyMode::ShowChanges, "Jane Doe", &mut []).unwrap();
+        assert_eq!(res, "Das ist <del class=\"suggestion suggestion-delete\" data-author=\"Jane&#32;Doe\" data-date=\"01.01.2024\">ein </del>Text");
+    }
+}
@@ -0,0 +1,316 @@
+//! Task assignments and deadlines per section.
+//!
+//! Every section can be assigned to users and/or persons (e.g. contributors without an account) with an optional
+//! due date. A task stays open until its section reaches the final workflow state.
+
+use bincode::{Decode, Encode};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use crate::projects::{Section, SectionOrToc};
+use crate::settings::WorkflowSettings;
+
+/// Assignees and due date of a section
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Default)]
+pub struct SectionAssignment{
+    pub assignees: Vec<Assignee>,
+    #[bincode(with_serde)]
+    pub due_date: Option<NaiveDate>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
+pub enum Assignee{
+    User{
+        #[bincode(with_serde)]
+        id: uuid::Uuid,
+    },
+    Person{
+        #[bincode(with_serde)]
+        id: uuid::Uuid,
+    },
+}
+
+/// Assigned section, as listed in task lists
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Task{
+    pub project_id: uuid::Uuid,
+    pub project_name: String,
+    /// Colon separated content path of the section
+    pub section_path: String,
+    pub section_title: String,
+    pub assignees: Vec<Assignee>,
+    pub due_date: Option<NaiveDate>,
+    /// Workflow state of the section
+    pub state: String,
+    /// True if the section is in the final workflow state
+    pub completed: bool,
+    pub overdue: bool,
+}
+
+impl SectionAssignment{
+    pub fn is_empty(&self) -> bool{
+        self.assignees.is_empty() && self.due_date.is_none()
+    }
+}
+
+/// Collects the tasks of all assigned sections (including subsections) of a project in document order
+pub fn collect_tasks(project_id: uuid::Uuid, project_name: &str, sections: &[SectionOrToc], settings: &WorkflowSettings, today: NaiveDate) -> Vec<Task>{
+    fn collect(section: &Section, parent_path: &str, project: (uuid::Uuid, &str), settings: &WorkflowSettings, today: NaiveDate, tasks: &mut Vec<Task>){
+        let id = section.id.unwrap_or_default();
+        let path = if parent_path.is_empty(){
+            id.to_string()
+        }else{
+            format!("{}:{}", parent_path, id)
+        };
+
+        if !section.assignment.is_empty(){
+            let state = section.workflow.current_state(settings).to_string();
+            let completed = state == settings.final_state;
+            tasks.push(Task{
+                project_id: project.0,
+                project_name: project.1.to_string(),
+                section_path: path.clone(),
+                section_title: section.metadata.title.clone(),
+                assignees: section.assignment.assignees.clone(),
+                due_date: section.assignment.due_date,
+                state,
+                completed,
+                overdue: !completed && section.assignment.due_date.is_some_and(|due_date| due_date < today),
+            });
+        }
+
+        for sub_section in section.sub_sections.iter(){
+            collect(sub_section, &path, project, settings, today, tasks);
+        }
+    }
+
+    let mut tasks = vec![];
+    for section in sections.iter(){
+        if let SectionOrToc::Section(section) = section{
+            collect(section, "", (project_id, project_name), settings, today, &mut tasks);
+        }
+    }
+    tasks
+}
+
+pub mod api{
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use rocket::serde::json::Json;
+    use rocket::State;
+    use serde::{Deserialize, Serialize};
+    use crate::data_storage::{DataStorage, ProjectStorage};
+    use crate::projects::api::{ApiError, ApiResult};
+    use crate::projects::tasks::{collect_tasks, Assignee, SectionAssignment, Task};
+    use crate::session::session_guard::StaffSession;
+    use crate::settings::Settings;
+    use crate::utils::api_helpers::parse_content_path;
+
+    /// Summary of all overdue tasks
+    #[derive(Deserialize, Serialize)]
+    pub struct OverdueDashboard{
+        pub total: usize,
+        pub by_project: Vec<OverdueCount>,
+        pub by_assignee: Vec<OverdueCount>,
+        /// Overdue tasks, oldest due date first
+        pub tasks: Vec<Task>,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct OverdueCount{
+        pub id: uuid::Uuid,
+        pub name: String,
+        pub count: usize,
+    }
+
+    /// Loads the tasks of all projects in the [ProjectStorage]
+    async fn all_tasks(settings: &Settings, project_storage: &ProjectStorage) -> Vec<Task>{
+        let today = chrono::Utc::now().date_naive();
+        let project_ids: Vec<uuid::Uuid> = project_storage.projects.read().unwrap().keys().cloned().collect();
+
+        let mut tasks = vec![];
+        for project_id in project_ids{
+            let project = match project_storage.get_project(&project_id, settings).await{
+                Ok(project) => project,
+                Err(_) => {
+                    eprintln!("Couldn't load project {} while collecting tasks", project_id);
+                    continue
+                }
+            };
+            let project = project.read().unwrap();
+            tasks.append(&mut collect_tasks(project_id, &project.name, &project.sections, &settings.workflow, today));
+        }
+        tasks
+    }
+
+    fn assignee_name(assignee: &Assignee, data_storage: &DataStorage) -> String{
+        match assignee{
+            Assignee::User { id } => data_storage.get_user_by_id(id).map(|user| user.read().unwrap().name.clone()),
+            Assignee::Person { id } => data_storage.get_person(id).map(|person| {
+                let person = person.read().unwrap();
+                match &person.first_names{
+                    Some(first_names) => format!("{} {}", first_names, person.last_names),
+                    None => person.last_names.clone(),
+                }
+            }),
+        }.unwrap_or_default()
+    }
+
+    /// PUT /api/projects/<project_id>/sections/<content_path>/assignment
+    /// Sets assignees and due date of a section
+    #[put("/api/projects/<project_id>/sections/<content_path>/assignment", data = "<assignment>")]
+    pub async fn set_section_assignment(project_id: String, content_path: String, assignment: Json<SectionAssignment>, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, data_storage: &State<Arc<DataStorage>>) -> Json<ApiResult<SectionAssignment>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let path = match parse_content_path(&content_path){
+            Ok(path) => path,
+            Err(e) => return ApiResult::new_error(e),
+        };
+
+        let mut assignment = assignment.into_inner();
+        for assignee in assignment.assignees.iter(){
+            let exists = match assignee{
+                Assignee::User { id } => data_storage.get_user_by_id(id).is_some(),
+                Assignee::Person { id } => data_storage.person_exists(id),
+            };
+            if !exists{
+                return ApiResult::new_error(ApiError::BadRequest(format!("Assignee {:?} does not exist", assignee)));
+            }
+        }
+        // Remove duplicates, keeping the order
+        let mut seen = vec![];
+        assignment.assignees.retain(|assignee| {
+            if seen.contains(assignee){
+                return false;
+            }
+            seen.push(assignee.clone());
+            true
+        });
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let mut project = project.write().unwrap();
+        match crate::data_storage::get_section_by_path_mut(&mut project, &path){
+            Ok(section) => {
+                section.assignment = assignment.clone();
+                section.increment_version();
+                ApiResult::new_data(assignment)
+            },
+            Err(e) => ApiResult::new_error(e),
+        }
+    }
+
+    /// GET /api/tasks?<user_id>&<person_id>&<include_completed>
+    /// Lists the tasks across all projects assigned to a user (the current user by default) or a person
+    /// Completed tasks are only included if include_completed is true
+    #[get("/api/tasks?<user_id>&<person_id>&<include_completed>")]
+    pub async fn list_tasks(user_id: Option<String>, person_id: Option<String>, include_completed: Option<bool>, session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Vec<Task>>>{
+        let assignee = match (user_id, person_id){
+            (_, Some(person_id)) => match uuid::Uuid::parse_str(&person_id){
+                Ok(id) => Assignee::Person { id },
+                Err(_) => return ApiResult::new_error(ApiError::BadRequest("Couldn't parse person id".to_string())),
+            },
+            (Some(user_id), None) => match uuid::Uuid::parse_str(&user_id){
+                Ok(id) => Assignee::User { id },
+                Err(_) => return ApiResult::new_error(ApiError::BadRequest("Couldn't parse user id".to_string())),
+            },
+            (None, None) => Assignee::User { id: session.user_id },
+        };
+
+        let mut tasks: Vec<Task> = all_tasks(settings, project_storage).await.into_iter()
+            .filter(|task| task.assignees.contains(&assignee))
+            .filter(|task| include_completed.unwrap_or(false) || !task.completed)
+            .collect();
+        // Tasks with due date first, earliest due date first
+        tasks.sort_by_key(|task| (task.due_date.is_none(), task.due_date));
+
+        ApiResult::new_data(tasks)
+    }
+
+    /// GET /api/tasks/overdue
+    /// Summarises all overdue tasks across all projects, by project and by assignee
+    #[get("/api/tasks/overdue")]
+    pub async fn overdue_dashboard(_session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, data_storage: &State<Arc<DataStorage>>) -> Json<ApiResult<OverdueDashboard>>{
+        let mut tasks: Vec<Task> = all_tasks(settings, project_storage).await.into_iter()
+            .filter(|task| task.overdue)
+            .collect();
+        tasks.sort_by_key(|task| task.due_date);
+
+        let mut by_project: Vec<OverdueCount> = vec![];
+        let mut by_assignee: HashMap<Assignee, usize> = HashMap::new();
+        for task in tasks.iter(){
+            match by_project.iter_mut().find(|count| count.id == task.project_id){
+                Some(count) => count.count += 1,
+                None => by_project.push(OverdueCount{ id: task.project_id, name: task.project_name.clone(), count: 1 }),
+            }
+            for assignee in task.assignees.iter(){
+                *by_assignee.entry(assignee.clone()).or_default() += 1;
+            }
+        }
+
+        let mut by_assignee: Vec<OverdueCount> = by_assignee.into_iter().map(|(assignee, count)| OverdueCount{
+            id: match &assignee{
+                Assignee::User { id } => *id,
+                Assignee::Person { id } => *id,
+            },
+            name: assignee_name(&assignee, data_storage),
+            count,
+        }).collect();
+        by_project.sort_by_key(|project| std::cmp::Reverse(project.count));
+        by_assignee.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+
+        ApiResult::new_data(OverdueDashboard{
+            total: tasks.len(),
+            by_project,
+            by_assignee,
+            tasks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::projects::SectionMetadata;
+
+    fn section(title: &str, assignment: SectionAssignment, sub_sections: Vec<Section>) -> Section{
+        Section{
+            id: Some(uuid::Uuid::new_v4()),
+            sub_sections,
+            visible_in_toc: true,
+            metadata: SectionMetadata{ title: title.to_string(), ..Default::default() },
+            assignment,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_collect_tasks(){
+        let settings = WorkflowSettings::default();
+        let today = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let user = Assignee::User { id: uuid::Uuid::new_v4() };
+
+        let overdue = section("Overdue", SectionAssignment{ assignees: vec![user.clone()], due_date: NaiveDate::from_ymd_opt(2024, 5, 1) }, vec![]);
+        let mut done = section("Done", SectionAssignment{ assignees: vec![user.clone()], due_date: NaiveDate::from_ymd_opt(2024, 5, 1) }, vec![]);
+        done.workflow.state = Some(settings.final_state.clone());
+        let parent = section("Parent", SectionAssignment::default(), vec![overdue, done]);
+        let parent_id = parent.id.unwrap();
+
+        let tasks = collect_tasks(uuid::Uuid::nil(), "Project", &[SectionOrToc::Toc, SectionOrToc::Section(parent)], &settings, today);
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].section_title, "Overdue");
+        assert!(tasks[0].section_path.starts_with(&format!("{}:", parent_id)));
+        assert!(tasks[0].overdue && !tasks[0].completed);
+        assert!(!tasks[1].overdue && tasks[1].completed);
+    }
+}
@@ -3,18 +3,18 @@ use std::sync::Arc;
 use rocket::http::Status;
 use rocket::State;
 use crate::data_storage::{DataStorage, ProjectTemplateV2};
-use crate::session::session_guard::Session;
+use crate::session::session_guard::StaffSession;
 use crate::settings::Settings;
 
 /// Get a list of all templates
 #[get("/templates")]
-pub async fn list_templates(_session: Session, _data_storage: &State<Arc<DataStorage>>) -> Result<rocket_dyn_templates::Template, Status>{
+pub async fn list_templates(_session: StaffSession, _data_storage: &State<Arc<DataStorage>>) -> Result<rocket_dyn_templates::Template, Status>{
     Ok(rocket_dyn_templates::Template::render("templates", ()))
 }
 
 /// Create new template
 #[get("/templates/create")]
-pub async fn create_template(_session: Session) -> Result<rocket_dyn_templates::Template, Status>{
+pub async fn create_template(_session: StaffSession) -> Result<rocket_dyn_templates::Template, Status>{
     Ok(rocket_dyn_templates::Template::render("create_template", ()))
 }
 
@@ -25,7 +25,7 @@ pub struct CreateTemplate {
 }
 
 #[post("/templates/create", data = "<template>")]
-pub async fn form_create_template(_session: Session, settings: &State<Settings>, template: rocket::form::Form<CreateTemplate>, data_storage: &State<Arc<DataStorage>>) -> Result<rocket::response::Redirect, Status>{
+pub async fn form_create_template(_session: StaffSession, settings: &State<Settings>, template: rocket::form::Form<CreateTemplate>, data_storage: &State<Arc<DataStorage>>) -> Result<rocket::response::Redirect, Status>{
     let template = ProjectTemplateV2 {
         id: uuid::Uuid::new_v4(),
         name: template.name.clone(),
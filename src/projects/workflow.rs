@@ -0,0 +1,431 @@
+//! Editorial workflow of sections and projects.
+//!
+//! The states and the allowed transitions between them are configured in [WorkflowSettings]. Sections and
+//! projects store their current state and a history of all transitions.
+
+use bincode::{Decode, Encode};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::projects::{Section, SectionOrToc};
+use crate::settings::WorkflowSettings;
+
+/// Workflow state of a section or project
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Default)]
+pub struct WorkflowStatus{
+    /// Current state, None if no transition happened yet (initial state)
+    pub state: Option<String>,
+    /// All transitions, oldest first
+    pub history: Vec<WorkflowHistoryEntry>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+pub struct WorkflowHistoryEntry{
+    pub from: String,
+    pub to: String,
+    /// Id of the user who performed the transition
+    #[bincode(with_serde)]
+    pub user: uuid::Uuid,
+    #[bincode(with_serde)]
+    pub timestamp: NaiveDateTime,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WorkflowError{
+    UnknownState(String),
+    /// There is no transition from the current to the requested state
+    TransitionNotAllowed{from: String, to: String},
+    /// The user isn't allowed to perform the transition
+    PermissionDenied,
+}
+
+impl WorkflowStatus{
+    pub fn current_state<'a>(&'a self, settings: &'a WorkflowSettings) -> &'a str{
+        match &self.state{
+            Some(state) => state.as_str(),
+            None => settings.initial_state(),
+        }
+    }
+
+    /// States the user can move this section or project to
+    pub fn available_transitions(&self, settings: &WorkflowSettings, user_email: &str) -> Vec<String>{
+        let current = self.current_state(settings);
+        settings.transitions.iter()
+            .filter(|transition| transition.from == current && transition.is_allowed(user_email))
+            .map(|transition| transition.to.clone())
+            .collect()
+    }
+
+    /// Moves to the state `to`, if the transition is configured and the user is allowed to perform it
+    pub fn transition(&mut self, to: &str, settings: &WorkflowSettings, user_id: uuid::Uuid, user_email: &str, comment: Option<String>) -> Result<(), WorkflowError>{
+        if !settings.states.iter().any(|state| state == to){
+            return Err(WorkflowError::UnknownState(to.to_string()));
+        }
+
+        let from = self.current_state(settings).to_string();
+        let transition = match settings.find_transition(&from, to){
+            Some(transition) => transition,
+            None => return Err(WorkflowError::TransitionNotAllowed { from, to: to.to_string() }),
+        };
+        if !transition.is_allowed(user_email){
+            return Err(WorkflowError::PermissionDenied);
+        }
+
+        self.history.push(WorkflowHistoryEntry{
+            from,
+            to: to.to_string(),
+            user: user_id,
+            timestamp: chrono::Utc::now().naive_utc(),
+            comment: comment.filter(|comment| !comment.trim().is_empty()),
+        });
+        self.state = Some(to.to_string());
+        Ok(())
+    }
+}
+
+/// Workflow state of a single section, used for filtered section lists
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SectionWorkflowSummary{
+    /// Colon separated content path of the section
+    pub path: String,
+    pub id: uuid::Uuid,
+    pub title: String,
+    pub state: String,
+    pub last_transition: Option<NaiveDateTime>,
+}
+
+/// Lists the workflow states of all sections (including subsections) in document order
+pub fn section_summaries(sections: &[SectionOrToc], settings: &WorkflowSettings) -> Vec<SectionWorkflowSummary>{
+    fn collect(section: &Section, parent_path: &str, settings: &WorkflowSettings, summaries: &mut Vec<SectionWorkflowSummary>){
+        let id = section.id.unwrap_or_default();
+        let path = if parent_path.is_empty(){
+            id.to_string()
+        }else{
+            format!("{}:{}", parent_path, id)
+        };
+
+        summaries.push(SectionWorkflowSummary{
+            path: path.clone(),
+            id,
+            title: section.metadata.title.clone(),
+            state: section.workflow.current_state(settings).to_string(),
+            last_transition: section.workflow.history.last().map(|entry| entry.timestamp),
+        });
+
+        for sub_section in section.sub_sections.iter(){
+            collect(sub_section, &path, settings, summaries);
+        }
+    }
+
+    let mut summaries = vec![];
+    for section in sections.iter(){
+        if let SectionOrToc::Section(section) = section{
+            collect(section, "", settings, &mut summaries);
+        }
+    }
+    summaries
+}
+
+pub mod api{
+    use std::sync::Arc;
+    use rocket::serde::json::Json;
+    use rocket::State;
+    use serde::{Deserialize, Serialize};
+    use crate::data_storage::{DataStorage, ProjectStorage};
+    use crate::mail::Mailer;
+    use crate::mail::notifications::{notify, Notification};
+    use crate::projects::api::{ApiError, ApiResult};
+    use crate::projects::contributors::Access;
+    use crate::projects::Section;
+    use crate::projects::tasks::Assignee;
+    use crate::projects::workflow::{section_summaries, SectionWorkflowSummary, WorkflowError, WorkflowHistoryEntry, WorkflowStatus};
+    use crate::session::session_guard::{Session, StaffSession};
+    use crate::settings::{Settings, WorkflowSettings};
+    use crate::utils::api_helpers::parse_content_path;
+    use crate::webhooks::{WebhookEvent, WebhookManager};
+
+    /// Configured workflow, transitions are marked if the current user is allowed to perform them
+    #[derive(Deserialize, Serialize)]
+    pub struct WorkflowInfo{
+        pub states: Vec<String>,
+        pub final_state: String,
+        pub transitions: Vec<WorkflowTransitionInfo>,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct WorkflowTransitionInfo{
+        pub from: String,
+        pub to: String,
+        pub allowed: bool,
+    }
+
+    /// Workflow state of a section or project
+    #[derive(Deserialize, Serialize)]
+    pub struct WorkflowOverview{
+        pub state: String,
+        pub history: Vec<WorkflowHistoryEntry>,
+        /// States the current user can move to
+        pub available_transitions: Vec<String>,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct TransitionRequest{
+        pub to: String,
+        pub comment: Option<String>,
+    }
+
+    impl WorkflowOverview{
+        pub(crate) fn new(status: &WorkflowStatus, settings: &WorkflowSettings, user_email: &str) -> WorkflowOverview{
+            WorkflowOverview{
+                state: status.current_state(settings).to_string(),
+                history: status.history.clone(),
+                available_transitions: status.available_transitions(settings, user_email),
+            }
+        }
+    }
+
+    impl From<WorkflowError> for ApiError{
+        fn from(value: WorkflowError) -> Self {
+            match value{
+                WorkflowError::UnknownState(state) => ApiError::BadRequest(format!("Unknown workflow state {}", state)),
+                WorkflowError::TransitionNotAllowed { from, to } => ApiError::BadRequest(format!("Transition from {} to {} is not allowed", from, to)),
+                WorkflowError::PermissionDenied => ApiError::Unauthorized,
+            }
+        }
+    }
+
+    /// Notifies the users assigned to a section and the project's webhooks about its last workflow transition
+    pub(crate) fn notify_section_transition(project_id: uuid::Uuid, project_name: String, section: &Section, user_id: uuid::Uuid, data_storage: &DataStorage, mailer: &Mailer, webhooks: &WebhookManager){
+        let entry = match section.workflow.history.last(){
+            Some(entry) => entry,
+            None => return,
+        };
+        let notification = Notification::WorkflowTransition {
+            project_id,
+            project_name,
+            section_title: section.metadata.title.clone(),
+            user_name: data_storage.get_user_by_id(&user_id).map(|user| user.read().unwrap().name.clone()).unwrap_or_default(),
+            from: entry.from.clone(),
+            to: entry.to.clone(),
+            comment: entry.comment.clone(),
+        };
+        webhooks.trigger(project_id, WebhookEvent::SectionStatusChanged, &serde_json::json!({
+            "section_id": section.id,
+            "transition": &notification,
+        }));
+        for assignee in section.assignment.assignees.iter(){
+            if let Assignee::User { id } = assignee{
+                if *id != user_id{
+                    notify(mailer, data_storage, id, &notification);
+                }
+            }
+        }
+    }
+
+    /// GET /api/workflow
+    /// Returns the configured workflow states and transitions
+    #[get("/api/workflow")]
+    pub async fn get_workflow(session: Session, settings: &State<Settings>) -> Json<ApiResult<WorkflowInfo>>{
+        let workflow = &settings.workflow;
+        ApiResult::new_data(WorkflowInfo{
+            states: workflow.states.clone(),
+            final_state: workflow.final_state.clone(),
+            transitions: workflow.transitions.iter().map(|transition| WorkflowTransitionInfo{
+                from: transition.from.clone(),
+                to: transition.to.clone(),
+                allowed: transition.is_allowed(&session.user_email),
+            }).collect(),
+        })
+    }
+
+    /// GET /api/projects/<project_id>/workflow
+    /// Returns the workflow state and history of the project
+    #[get("/api/projects/<project_id>/workflow")]
+    pub async fn get_project_workflow(project_id: String, session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<WorkflowOverview>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let project = project.read().unwrap();
+        ApiResult::new_data(WorkflowOverview::new(&project.workflow, &settings.workflow, &session.user_email))
+    }
+
+    /// POST /api/projects/<project_id>/workflow
+    /// Moves the project to another workflow state
+    #[post("/api/projects/<project_id>/workflow", data = "<transition>")]
+    pub async fn transition_project(project_id: String, transition: Json<TransitionRequest>, session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<WorkflowOverview>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let transition = transition.into_inner();
+        let mut project = project.write().unwrap();
+        if let Err(e) = project.workflow.transition(&transition.to, &settings.workflow, session.user_id, &session.user_email, transition.comment){
+            return ApiResult::new_error(e.into());
+        }
+
+        ApiResult::new_data(WorkflowOverview::new(&project.workflow, &settings.workflow, &session.user_email))
+    }
+
+    /// GET /api/projects/<project_id>/workflow/sections?<state>
+    /// Lists the workflow states of all sections, optionally only sections in the given state (e.g. all sections awaiting copy-edit)
+    #[get("/api/projects/<project_id>/workflow/sections?<state>")]
+    pub async fn list_section_states(project_id: String, state: Option<String>, _session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Vec<SectionWorkflowSummary>>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        if let Some(state) = &state{
+            if !settings.workflow.states.contains(state){
+                return ApiResult::new_error(WorkflowError::UnknownState(state.clone()).into());
+            }
+        }
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let summaries = section_summaries(&project.read().unwrap().sections, &settings.workflow).into_iter()
+            .filter(|summary| state.as_ref().is_none_or(|state| &summary.state == state))
+            .collect();
+
+        ApiResult::new_data(summaries)
+    }
+
+    /// GET /api/projects/<project_id>/sections/<content_path>/workflow
+    /// Returns the workflow state and history of a section
+    #[get("/api/projects/<project_id>/sections/<content_path>/workflow")]
+    pub async fn get_section_workflow(project_id: String, content_path: String, session: Session, access: Access, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<WorkflowOverview>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let path = match parse_content_path(&content_path){
+            Ok(path) => path,
+            Err(e) => return ApiResult::new_error(e),
+        };
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let project = project.read().unwrap();
+        if !access.can_access_section(&project.sections, &path){
+            return ApiResult::new_error(ApiError::Unauthorized);
+        }
+        match crate::data_storage::get_section_by_path(&project, &path){
+            Ok(section) => ApiResult::new_data(WorkflowOverview::new(&section.workflow, &settings.workflow, &session.user_email)),
+            Err(e) => ApiResult::new_error(e),
+        }
+    }
+
+    /// POST /api/projects/<project_id>/sections/<content_path>/workflow
+    /// Moves a section to another workflow state
+    #[post("/api/projects/<project_id>/sections/<content_path>/workflow", data = "<transition>")]
+    #[allow(clippy::too_many_arguments, reason = "mails and webhooks about the transition need their own managed states")]
+    pub async fn transition_section(project_id: String, content_path: String, transition: Json<TransitionRequest>, session: StaffSession, settings: &State<Settings>, project_storage: &State<Arc<ProjectStorage>>, data_storage: &State<Arc<DataStorage>>, mailer: &State<Arc<Mailer>>, webhooks: &State<Arc<WebhookManager>>) -> Json<ApiResult<WorkflowOverview>>{
+        let project_id = match uuid::Uuid::parse_str(&project_id) {
+            Ok(project_id) => project_id,
+            Err(e) => {
+                eprintln!("Couldn't parse project id: {}", e);
+                return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string()));
+            },
+        };
+
+        let path = match parse_content_path(&content_path){
+            Ok(path) => path,
+            Err(e) => return ApiResult::new_error(e),
+        };
+
+        let project = match project_storage.get_project(&project_id, settings).await{
+            Ok(project) => project.clone(),
+            Err(_) => {
+                return ApiResult::new_error(ApiError::NotFound)
+            }
+        };
+
+        let transition = transition.into_inner();
+        let mut project = project.write().unwrap();
+        let project_name = project.name.clone();
+        let section = match crate::data_storage::get_section_by_path_mut(&mut project, &path){
+            Ok(section) => section,
+            Err(e) => return ApiResult::new_error(e),
+        };
+
+        if let Err(e) = section.workflow.transition(&transition.to, &settings.workflow, session.user_id, &session.user_email, transition.comment){
+            return ApiResult::new_error(e.into());
+        }
+        section.increment_version();
+
+        notify_section_transition(project_id, project_name, section, session.user_id, data_storage, mailer, webhooks);
+
+        ApiResult::new_data(WorkflowOverview::new(&section.workflow, &settings.workflow, &session.user_email))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::settings::WorkflowTransition;
+
+    #[test]
+    fn test_transitions_and_permissions(){
+        let mut settings = WorkflowSettings::default();
+        settings.transitions.push(WorkflowTransition{
+            from: "submitted".to_string(),
+            to: "final".to_string(),
+            allowed_users: vec!["editor@example.com".to_string()],
+        });
+        let user = uuid::Uuid::new_v4();
+        let mut status = WorkflowStatus::default();
+
+        assert_eq!(status.current_state(&settings), "submitted");
+        assert_eq!(status.available_transitions(&settings, "author@example.com"), vec!["in_review".to_string()]);
+        assert_eq!(status.transition("copy_edited", &settings, user, "author@example.com", None), Err(WorkflowError::TransitionNotAllowed { from: "submitted".to_string(), to: "copy_edited".to_string() }));
+        assert_eq!(status.transition("final", &settings, user, "author@example.com", None), Err(WorkflowError::PermissionDenied));
+        assert_eq!(status.transition("published", &settings, user, "editor@example.com", None), Err(WorkflowError::UnknownState("published".to_string())));
+        assert!(status.history.is_empty());
+
+        status.transition("in_review", &settings, user, "author@example.com", Some("Ready".to_string())).unwrap();
+        assert_eq!(status.current_state(&settings), "in_review");
+        assert_eq!(status.history.len(), 1);
+        assert_eq!(status.history[0].from, "submitted");
+        assert_eq!(status.history[0].comment, Some("Ready".to_string()));
+    }
+}
@@ -1,5 +1,5 @@
 pub mod session_guard;
-mod errors;
+pub mod errors;
 pub mod session_storage;
 pub mod login;
 pub mod logout;
\ No newline at end of file
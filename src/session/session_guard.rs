@@ -1,7 +1,10 @@
+use std::ops::Deref;
+use std::sync::Arc;
 use rocket::{Request, State};
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome};
 use rocket::serde::{Deserialize, Serialize};
+use crate::data_storage::DataStorage;
 use crate::session::errors::LoginError;
 use crate::session::session_storage::SessionStorage;
 
@@ -33,4 +36,40 @@ impl<'r> FromRequest<'r> for Session {
             None => Outcome::Error((Status::Unauthorized, LoginError::Missing)),
         }
     }
-}
\ No newline at end of file
+}
+
+/// [Session] of a staff user
+///
+/// Fails with 403 for contributors (users linked to a person), they only get access to the contributor portal
+/// and the section endpoints which check the [Access][crate::projects::contributors::Access] themselves.
+#[derive(Clone, Debug)]
+pub struct StaffSession(pub Session);
+
+impl Deref for StaffSession {
+    type Target = Session;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for StaffSession {
+    type Error = LoginError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let session = match request.guard::<Session>().await {
+            Outcome::Success(session) => session,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+        let data_storage : &State<Arc<DataStorage>> = match request.guard::<&State<Arc<DataStorage>>>().await {
+            Outcome::Success(data_storage) => data_storage,
+            _ => return Outcome::Error((Status::Unauthorized, LoginError::Unavailable)),
+        };
+        match data_storage.get_contributor_person(&session.user_id) {
+            Some(_) => Outcome::Error((Status::Forbidden, LoginError::Invalid)),
+            None => Outcome::Success(StaffSession(session)),
+        }
+    }
+}
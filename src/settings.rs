@@ -1,4 +1,5 @@
 use config::{Config, ConfigError, Environment, File};
+use std::collections::HashMap;
 use std::env;
 use serde::Deserialize;
 
@@ -16,9 +17,235 @@ pub struct Settings{
     pub file_lock_timeout: u64,
     pub backup_to_file_interval: u64,
     pub max_rendering_threads : u64,
+    /// Wall-clock limit of a single rendering in seconds, the rendering is killed when exceeded (0 = no limit)
+    #[serde(default = "default_rendering_timeout")]
+    pub rendering_timeout: u64,
+    /// Limits of running renderings per user and project
+    #[serde(default)]
+    pub rendering_queue: RenderingQueueSettings,
+    /// Number of prepared sections kept in memory to speed up repeated renderings (0 = no caching)
+    #[serde(default = "default_section_cache_size")]
+    pub section_cache_size: usize,
+    /// Number of html previews kept in memory, one per project and section (0 = no caching)
+    #[serde(default = "default_preview_cache_size")]
+    pub preview_cache_size: usize,
     pub max_import_threads: u64,
     pub chromium_path: Option<String>,
+    /// Engine converting the rendered html to pdf
+    #[serde(default)]
+    pub pdf_renderer: PdfRendererKind,
     pub zotero_translation_server: String,
+    /// Allow-list for the html sanitizer, applied on save, import and export
+    pub html_sanitizer: HtmlSanitizerSettings,
+    /// Editorial workflow of sections and projects
+    #[serde(default)]
+    pub workflow: WorkflowSettings,
+    /// Outgoing mail, notifications are disabled if not set
+    #[serde(default)]
+    pub mail: Option<MailSettings>,
+    /// Delivery of outgoing webhooks
+    #[serde(default)]
+    pub webhooks: WebhookSettings,
+    /// Retention of rendered files
+    #[serde(default)]
+    pub rendering_history: RenderingHistorySettings,
+}
+
+/// Engines available to convert the rendered html to pdf, see [crate::export::renderers]
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PdfRendererKind{
+    /// Vivliostyle CLI
+    #[default]
+    Vivliostyle,
+    /// pagedjs-cli
+    PagedJs,
+    /// Print to pdf of headless Chromium, without paged media polyfill
+    Chromium,
+    /// Writes an empty pdf without running a browser, for tests and development
+    Mock,
+}
+
+/// Limits of concurrently running renderings, so a single user or project can't occupy all rendering threads
+#[derive(Debug, Deserialize, Clone)]
+pub struct RenderingQueueSettings{
+    /// Maximum number of running renderings started by the same user (0 = no limit)
+    pub max_per_user: usize,
+    /// Maximum number of running renderings of the same project (0 = no limit)
+    pub max_per_project: usize,
+}
+
+impl Default for RenderingQueueSettings{
+    fn default() -> Self {
+        RenderingQueueSettings{
+            max_per_user: 2,
+            max_per_project: 2,
+        }
+    }
+}
+
+/// Retention policy for the output files of past renderings, the rendering records are kept
+#[derive(Debug, Deserialize, Clone)]
+pub struct RenderingHistorySettings{
+    /// Output files of renderings older than this many days are deleted (0 = no limit)
+    pub max_age: u64,
+    /// Number of most recent renderings per project whose output files are kept (0 = no limit)
+    pub keep_per_project: usize,
+}
+
+impl Default for RenderingHistorySettings{
+    fn default() -> Self {
+        RenderingHistorySettings{
+            max_age: 30,
+            keep_per_project: 20,
+        }
+    }
+}
+
+fn default_rendering_timeout() -> u64{
+    600
+}
+
+fn default_section_cache_size() -> usize{
+    500
+}
+
+fn default_preview_cache_size() -> usize{
+    50
+}
+
+/// Delivery settings of outgoing webhooks, the webhooks themselves are managed through the API
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebhookSettings{
+    /// How often a delivery is attempted before it's dropped
+    pub max_attempts: u32,
+    /// Seconds until the first retry, doubled for every further attempt
+    pub retry_interval: u64,
+    /// Request timeout in seconds
+    pub timeout: u64,
+    /// Number of delivery attempts kept in the log of each webhook
+    pub log_size: usize,
+}
+
+impl Default for WebhookSettings{
+    fn default() -> Self {
+        WebhookSettings{
+            max_attempts: 5,
+            retry_interval: 30,
+            timeout: 10,
+            log_size: 100,
+        }
+    }
+}
+
+/// SMTP relay used for email notifications
+#[derive(Debug, Deserialize, Clone)]
+pub struct MailSettings{
+    pub host: String,
+    /// Defaults to the standard port of the tls mode (25, 587 or 465)
+    pub port: Option<u16>,
+    pub tls: MailTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender address, e.g. "Verfassungsbooks <books@example.com>"
+    pub sender: String,
+    /// Public url of the app, used for links in mails
+    pub base_url: String,
+    /// How often sending a mail is attempted before it's dropped
+    pub max_attempts: u32,
+    /// Seconds until the first retry, doubled for every further attempt
+    pub retry_interval: u64,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTls{
+    /// Unencrypted, only use for local relays or test sinks
+    None,
+    StartTls,
+    Tls,
+}
+
+/// Allow-list of the html sanitizer
+#[derive(Debug, Deserialize, Clone)]
+pub struct HtmlSanitizerSettings{
+    /// Allowed elements and the attributes allowed on each of them
+    pub allowed_elements: HashMap<String, Vec<String>>,
+    /// Attributes allowed on all allowed elements
+    pub generic_attributes: Vec<String>,
+    /// Allowed url schemes in links and images, relative urls are always allowed
+    pub url_schemes: Vec<String>,
+    /// Attributes which contain html themselves (e.g. the content of notes) and are sanitized as well
+    pub html_attributes: Vec<String>,
+}
+
+/// Editorial workflow states and the allowed transitions between them
+#[derive(Debug, Deserialize, Clone)]
+pub struct WorkflowSettings{
+    /// All workflow states, the first one is the initial state of new sections and projects
+    pub states: Vec<String>,
+    /// State every section has to be in before a final export is allowed
+    pub final_state: String,
+    /// State contributors move their sections to when they approve the proof, proof approval is disabled if missing
+    #[serde(default)]
+    pub approval_state: Option<String>,
+    pub transitions: Vec<WorkflowTransition>,
+}
+
+/// Allowed transition between two workflow states
+#[derive(Debug, Deserialize, Clone)]
+pub struct WorkflowTransition{
+    pub from: String,
+    pub to: String,
+    /// Emails of the users allowed to perform this transition, all users are allowed if empty
+    #[serde(default)]
+    pub allowed_users: Vec<String>,
+}
+
+impl WorkflowTransition{
+    fn new(from: &str, to: &str) -> WorkflowTransition{
+        WorkflowTransition{
+            from: from.to_string(),
+            to: to.to_string(),
+            allowed_users: vec![],
+        }
+    }
+
+    /// Checks if the user with the given email is allowed to perform this transition
+    pub fn is_allowed(&self, user_email: &str) -> bool{
+        self.allowed_users.is_empty() || self.allowed_users.iter().any(|email| email == user_email)
+    }
+}
+
+impl WorkflowSettings{
+    /// State of sections and projects without any transitions
+    pub fn initial_state(&self) -> &str{
+        self.states.first().map(|state| state.as_str()).unwrap_or_default()
+    }
+
+    pub fn find_transition(&self, from: &str, to: &str) -> Option<&WorkflowTransition>{
+        self.transitions.iter().find(|transition| transition.from == from && transition.to == to)
+    }
+}
+
+impl Default for WorkflowSettings{
+    fn default() -> Self {
+        WorkflowSettings{
+            states: ["submitted", "in_review", "revisions_requested", "copy_edited", "author_approved", "final"].iter().map(|state| state.to_string()).collect(),
+            final_state: "final".to_string(),
+            approval_state: Some("author_approved".to_string()),
+            transitions: vec![
+                WorkflowTransition::new("submitted", "in_review"),
+                WorkflowTransition::new("in_review", "revisions_requested"),
+                WorkflowTransition::new("revisions_requested", "in_review"),
+                WorkflowTransition::new("in_review", "copy_edited"),
+                WorkflowTransition::new("copy_edited", "author_approved"),
+                WorkflowTransition::new("copy_edited", "revisions_requested"),
+                WorkflowTransition::new("author_approved", "final"),
+                WorkflowTransition::new("final", "in_review"),
+            ],
+        }
+    }
 }
 
 impl Settings{
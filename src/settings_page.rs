@@ -2,10 +2,10 @@ use std::sync::Arc;
 use rocket::State;
 use rocket_dyn_templates::Template;
 use crate::data_storage::{DataStorage, User};
-use crate::session::session_guard::Session;
+use crate::session::session_guard::StaffSession;
 
 #[get("/settings")]
-pub async fn settings_page(_session: Session, data_storage: &State<Arc<DataStorage>>) -> Template {
+pub async fn settings_page(_session: StaffSession, data_storage: &State<Arc<DataStorage>>) -> Template {
     let data_storage = data_storage;
     let users : Vec<User> = data_storage.data.read().unwrap().login_data.iter().map(|x|x.1.read().unwrap().clone()).collect();
     Template::render("settings", users)
@@ -19,11 +19,11 @@ pub mod api{
     use rocket::State;
     use crate::data_storage::{DataStorage, User};
     use crate::projects::api::{ApiError, ApiResult, Patch};
-    use crate::session::session_guard::Session;
+    use crate::session::session_guard::StaffSession;
     use crate::settings::Settings;
 
     #[derive(serde::Deserialize)]
-    struct NewUser{
+    pub struct NewUser{
         username: String,
         password: String,
         email: String,
@@ -31,7 +31,7 @@ pub mod api{
 
     /// Insert a new user
     #[post("/api/users", data = "<new_user>")]
-    pub async fn add_user(new_user: Json<NewUser>, _session: Session, data_storage: &State<Arc<DataStorage>>, settings: &State<Settings>) -> Json<ApiResult<User>>{
+    pub async fn add_user(new_user: Json<NewUser>, _session: StaffSession, data_storage: &State<Arc<DataStorage>>, settings: &State<Settings>) -> Json<ApiResult<User>>{
         let new_user = new_user.into_inner();
         let data_storage = data_storage;
 
@@ -46,7 +46,7 @@ pub mod api{
     }
 
     #[derive(serde::Deserialize)]
-    struct PatchUser{
+    pub struct PatchUser{
         pub id: uuid::Uuid,
         pub email: Option<String>,
         pub name: Option<String>,
@@ -82,7 +82,7 @@ pub mod api{
 
     /// Update a user
     #[patch("/api/users/<id>", data = "<new_user>")]
-    pub async fn update_user(id: String, new_user: Json<PatchUser>, _session: Session, data_storage: &State<Arc<DataStorage>>) -> Json<ApiResult<User>>{
+    pub async fn update_user(id: String, new_user: Json<PatchUser>, _session: StaffSession, data_storage: &State<Arc<DataStorage>>) -> Json<ApiResult<User>>{
         // Parse id or return error
         let id = match uuid::Uuid::parse_str(&id){
             Ok(id) => id,
@@ -114,9 +114,44 @@ pub mod api{
         }
     }
 
+    #[derive(serde::Deserialize)]
+    pub struct PersonLink{
+        pub person_id: Option<uuid::Uuid>,
+    }
+
+    /// PUT /api/users/<id>/person
+    /// Links a user to a person, which makes the user a contributor who only gets access to the sections
+    /// crediting the person as author. {"person_id": null} removes the link
+    #[put("/api/users/<id>/person", data = "<link>")]
+    pub async fn set_user_person(id: String, link: Json<PersonLink>, session: StaffSession, data_storage: &State<Arc<DataStorage>>, settings: &State<Settings>) -> Json<ApiResult<()>>{
+        // Parse id or return error
+        let id = match uuid::Uuid::parse_str(&id){
+            Ok(id) => id,
+            Err(_) => return ApiResult::new_error(ApiError::BadRequest("Invalid id".to_string()))
+        };
+
+        if id == session.user_id{
+            return ApiResult::new_error(ApiError::BadRequest("Cannot link own user".to_string()));
+        }
+        if data_storage.get_user_by_id(&id).is_none(){
+            return ApiResult::new_error(ApiError::NotFound);
+        }
+        if let Some(person_id) = &link.person_id{
+            if !data_storage.person_exists(person_id){
+                return ApiResult::new_error(ApiError::BadRequest(format!("Person {} does not exist", person_id)));
+            }
+        }
+
+        data_storage.set_contributor_person(id, link.person_id);
+        if data_storage.save_to_disk(settings).await.is_err(){
+            return ApiResult::new_error(ApiError::InternalServerError);
+        }
+        ApiResult::new_data(())
+    }
+
     /// Delete a user
     #[delete("/api/users/<id>")]
-    pub async fn delete_user(id: String, session: Session, data_storage: &State<Arc<DataStorage>>) -> Json<ApiResult<()>>{
+    pub async fn delete_user(id: String, session: StaffSession, data_storage: &State<Arc<DataStorage>>) -> Json<ApiResult<()>>{
         // Parse id or return error
         let id = match uuid::Uuid::parse_str(&id){
             Ok(id) => id,
@@ -130,6 +165,7 @@ pub mod api{
         let data_storage = data_storage;
         let mut data = data_storage.data.write().unwrap();
 
+        data.contributors.remove(&id);
         match data.login_data.remove(&id){
             Some(_) => ApiResult::new_data(()),
             None => ApiResult::new_error(ApiError::NotFound)
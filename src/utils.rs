@@ -2,4 +2,8 @@ pub mod lobid_proxy;
 pub mod fs_copy_recursive;
 pub mod api_helpers;
 pub mod csl;
-pub mod block_id_generator;
\ No newline at end of file
+pub mod block_id_generator;
+pub mod html_sanitizer;
+pub mod etag;
+pub mod job_events;
+pub mod retry_queue;
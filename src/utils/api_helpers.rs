@@ -22,4 +22,24 @@ pub async fn get_project(project_id: &uuid::Uuid, settings: &State<Settings>, pr
             Err(ApiResult::new_error(ApiError::NotFound))
         },
     }
-}
\ No newline at end of file
+}
+
+/// Parses a colon separated content path (e.g. "section-id:subsection-id") into a list of section ids
+pub fn parse_content_path(content_path: &str) -> Result<Vec<uuid::Uuid>, ApiError>{
+    let mut path = vec![];
+
+    for part in content_path.split(":"){
+        match uuid::Uuid::parse_str(part){
+            Ok(part) => path.push(part),
+            Err(e) => {
+                eprintln!("Couldn't parse content path: {}", e);
+                return Err(ApiError::BadRequest("Couldn't parse content path".to_string()));
+            }
+        }
+    }
+
+    if path.is_empty(){
+        return Err(ApiError::BadRequest("Couldn't parse content path".to_string()));
+    }
+    Ok(path)
+}
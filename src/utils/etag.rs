@@ -0,0 +1,137 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::Responder;
+use rocket::serde::json::Json;
+use rocket::{Request, Response};
+use serde::Serialize;
+use crate::projects::api::{ApiError, ApiResult};
+
+/// Formats a version counter as (strong) ETag value
+pub fn format_etag(version: u64) -> String{
+    format!("\"{}\"", version)
+}
+
+/// Parses an ETag value created by [format_etag]
+///
+/// If-Match uses the strong comparison, so weak ETags (W/"1") are rejected
+pub fn parse_etag(etag: &str) -> Option<u64>{
+    let etag = etag.trim();
+    if etag.starts_with("W/"){
+        return None
+    }
+    etag.trim_matches('"').parse::<u64>().ok()
+}
+
+/// Request guard for the If-Match header
+///
+/// Requests without If-Match header are rejected with 428 Precondition Required, so clients can't
+/// overwrite changes of other users by accident. Use "*" to explicitly overwrite any version.
+pub struct IfMatch{
+    /// Accepted versions, None if any version is accepted ("*")
+    versions: Option<Vec<u64>>,
+}
+
+impl IfMatch{
+    /// Checks if the current version of the resource matches the If-Match header
+    pub fn matches(&self, version: u64) -> bool{
+        match &self.versions{
+            Some(versions) => versions.contains(&version),
+            None => true,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let header = match request.headers().get_one("If-Match"){
+            Some(header) => header,
+            None => return Outcome::Error((Status::PreconditionRequired, ())),
+        };
+
+        if header.trim() == "*"{
+            return Outcome::Success(IfMatch{ versions: None });
+        }
+
+        let mut versions = vec![];
+        for etag in header.split(","){
+            // Weak ETags never match with the strong comparison
+            if etag.trim().starts_with("W/"){
+                continue;
+            }
+            match parse_etag(etag){
+                Some(version) => versions.push(version),
+                None => return Outcome::Error((Status::BadRequest, ())),
+            }
+        }
+        Outcome::Success(IfMatch{ versions: Some(versions) })
+    }
+}
+
+/// Api response with ETag header
+///
+/// Used for resources which can be changed concurrently by multiple users (sections, content blocks, project metadata).
+/// If the If-Match header of an update doesn't match, the current state is returned with status 412 Precondition Failed.
+pub struct ETagged<T>{
+    body: Json<ApiResult<T>>,
+    version: Option<u64>,
+    status: Status,
+}
+
+impl<T> ETagged<T>{
+    pub fn new(data: T, version: u64) -> ETagged<T>{
+        ETagged{
+            body: ApiResult::new_data(data),
+            version: Some(version),
+            status: Status::Ok,
+        }
+    }
+
+    /// Response for a failed If-Match check, containing the current state and version of the resource
+    pub fn precondition_failed(current: T, version: u64) -> ETagged<T>{
+        ETagged{
+            body: Json(ApiResult{
+                error: Some(ApiError::PreconditionFailed),
+                data: Some(current),
+            }),
+            version: Some(version),
+            status: Status::PreconditionFailed,
+        }
+    }
+}
+
+impl<T> From<Json<ApiResult<T>>> for ETagged<T>{
+    fn from(body: Json<ApiResult<T>>) -> Self {
+        ETagged{
+            body,
+            version: None,
+            status: Status::Ok,
+        }
+    }
+}
+
+impl<'r, T: Serialize> Responder<'r, 'static> for ETagged<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let mut response = Response::build_from(self.body.respond_to(request)?);
+        response.status(self.status);
+        if let Some(version) = self.version{
+            response.raw_header("ETag", format_etag(version));
+        }
+        response.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_parse_etag(){
+        assert_eq!(parse_etag(&format_etag(42)), Some(42));
+        assert_eq!(parse_etag(" \"7\" "), Some(7));
+        assert_eq!(parse_etag("W/\"7\""), None);
+        assert_eq!(parse_etag("\"abc\""), None);
+    }
+}
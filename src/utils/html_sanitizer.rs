@@ -0,0 +1,147 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use crate::projects::{BlockData, NewContentBlock, Section};
+use crate::settings::HtmlSanitizerSettings;
+
+/// Central html sanitizer for all user supplied or imported html
+///
+/// Removes all elements and attributes which aren't in the allow-list configured in [`HtmlSanitizerSettings`].
+/// Scripts and styles are removed including their content, other disallowed elements are replaced by their content.
+pub struct HtmlSanitizer{
+    settings: Arc<HtmlSanitizerSettings>,
+}
+
+impl HtmlSanitizer{
+    pub fn new(settings: &HtmlSanitizerSettings) -> Self{
+        HtmlSanitizer{
+            settings: Arc::new(settings.clone()),
+        }
+    }
+
+    /// Builds the ammonia builder for the allow-list, without sanitizing html attributes
+    fn builder(settings: &HtmlSanitizerSettings) -> ammonia::Builder<'_>{
+        let tags: HashSet<&str> = settings.allowed_elements.keys().map(|tag| tag.as_str()).collect();
+        let tag_attributes: HashMap<&str, HashSet<&str>> = settings.allowed_elements.iter()
+            .map(|(tag, attributes)| (tag.as_str(), attributes.iter().map(|attr| attr.as_str()).collect()))
+            .collect();
+        let generic_attributes: HashSet<&str> = settings.generic_attributes.iter().map(|attr| attr.as_str()).collect();
+        let url_schemes: HashSet<&str> = settings.url_schemes.iter().map(|scheme| scheme.as_str()).collect();
+
+        let mut builder = ammonia::Builder::empty();
+        builder.tags(tags)
+            .tag_attributes(tag_attributes)
+            .generic_attributes(generic_attributes)
+            .url_schemes(url_schemes)
+            .clean_content_tags(HashSet::from(["script", "style"]))
+            .link_rel(None)
+            .strip_comments(true);
+        builder
+    }
+
+    /// Sanitizes a html fragment
+    pub fn clean(&self, html: &str) -> String{
+        // ammonia borrows the allow-list, so the builder only lives as long as this call
+        let mut builder = Self::builder(&self.settings);
+
+        // Some attributes contain html themselves (e.g. the content of notes), sanitize them too
+        let settings = self.settings.clone();
+        builder.attribute_filter(move |_element, attribute, value| {
+            if settings.html_attributes.iter().any(|attr| attr == attribute){
+                Some(Cow::Owned(Self::builder(&settings).clean(value).to_string()))
+            }else{
+                Some(Cow::Borrowed(value))
+            }
+        });
+
+        builder.clean(html).to_string()
+    }
+
+    /// Sanitizes all html fields of a content block, including nested blocks
+    ///
+    /// Math and code blocks contain plain text which is escaped on export, so they are left untouched
+    pub fn sanitize_content_block(&self, block: &mut NewContentBlock){
+        match &mut block.data{
+            BlockData::Paragraph { text } => *text = self.clean(text),
+            BlockData::Heading { text, .. } => *text = self.clean(text),
+            BlockData::Raw { html } => *html = self.clean(html),
+            BlockData::List { items, .. } => {
+                for item in items.iter_mut(){
+                    *item = self.clean(item);
+                }
+            },
+            BlockData::Quote { text, caption, .. } => {
+                *text = self.clean(text);
+                *caption = self.clean(caption);
+            },
+            BlockData::Image { caption, .. } => {
+                if let Some(caption) = caption{
+                    *caption = self.clean(caption);
+                }
+            },
+            BlockData::Math { .. } | BlockData::Code { .. } => {},
+            BlockData::Box { title, children } => {
+                *title = self.clean(title);
+                self.sanitize_content_blocks(children);
+            },
+            BlockData::Epigraph { attribution, children } => {
+                *attribution = self.clean(attribution);
+                self.sanitize_content_blocks(children);
+            },
+            BlockData::Abstract { children } => self.sanitize_content_blocks(children),
+        }
+    }
+
+    pub fn sanitize_content_blocks(&self, blocks: &mut [NewContentBlock]){
+        for block in blocks.iter_mut(){
+            self.sanitize_content_block(block);
+        }
+    }
+
+    /// Sanitizes all content blocks of a section and its subsections
+    pub fn sanitize_section(&self, section: &mut Section){
+        self.sanitize_content_blocks(&mut section.children);
+        for sub_section in section.sub_sections.iter_mut(){
+            self.sanitize_section(sub_section);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn generate_sanitizer() -> HtmlSanitizer{
+        HtmlSanitizer::new(&HtmlSanitizerSettings{
+            allowed_elements: HashMap::from([
+                ("p".to_string(), vec![]),
+                ("a".to_string(), vec!["href".to_string()]),
+                ("span".to_string(), vec!["note-type".to_string(), "note-content".to_string()]),
+                ("citation".to_string(), vec!["data-key".to_string()]),
+            ]),
+            generic_attributes: vec!["class".to_string()],
+            url_schemes: vec!["https".to_string()],
+            html_attributes: vec!["note-content".to_string()],
+        })
+    }
+
+    #[test]
+    fn test_sanitize_removes_scripts_handlers_and_styles(){
+        let sanitizer = generate_sanitizer();
+        let res = sanitizer.clean(r#"<p style="color: red" onclick="alert(1)">Text<script>alert(1)</script> <a href="javascript:alert(1)">Link</a></p>"#);
+        assert_eq!(res, "<p>Text <a>Link</a></p>");
+    }
+
+    #[test]
+    fn test_sanitize_keeps_editor_marks(){
+        let sanitizer = generate_sanitizer();
+        let html = r#"Text<citation data-key="doe2020">C</citation>"#;
+        assert_eq!(sanitizer.clean(html), html);
+
+        let res = sanitizer.clean(r#"<span class="note" note-type="footnote" note-content="<a href='https://example.org' onclick='x()'>Note</a><script>x()</script>">F</span>"#);
+        assert!(res.contains("note-type=\"footnote\""));
+        assert!(res.contains("https://example.org"));
+        assert!(!res.contains("onclick"));
+        assert!(!res.contains("script"));
+    }
+}
@@ -0,0 +1,139 @@
+//! Live progress of rendering and import jobs, streamed to the browser as server-sent events.
+//!
+//! Every job owns a [JobProgress]. The workers publish status transitions, item progress and log lines to it,
+//! SSE clients subscribe to it and get the current state first, followed by all further events until the job
+//! reached a final status.
+
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::sync::broadcast;
+use serde::Serialize;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobEvent{
+    /// New status of the job, serialized like the status returned by the polling endpoints
+    Status{
+        status: serde_json::Value,
+        /// True if the job won't change anymore, the stream ends after this event
+        finished: bool,
+    },
+    Progress{
+        processed: usize,
+        length: usize,
+    },
+    Log{
+        line: String,
+    },
+}
+
+impl JobEvent{
+    /// Name of the SSE event, used with `EventSource.addEventListener`
+    pub fn name(&self) -> &'static str{
+        match self{
+            JobEvent::Status { .. } => "status",
+            JobEvent::Progress { .. } => "progress",
+            JobEvent::Log { .. } => "log",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool{
+        matches!(self, JobEvent::Status { finished: true, .. })
+    }
+}
+
+pub struct JobProgress{
+    sender: broadcast::Sender<JobEvent>,
+    /// Log lines so far, replayed to clients which subscribe later
+    log: Vec<String>,
+}
+
+impl Default for JobProgress{
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(64);
+        JobProgress{
+            sender,
+            log: vec![],
+        }
+    }
+}
+
+impl JobProgress{
+    fn send(&self, event: JobEvent){
+        // Sending only fails if nobody is subscribed, that's fine
+        let _ = self.sender.send(event);
+    }
+
+    pub fn status<S: Serialize>(&self, status: &S, finished: bool){
+        let status = serde_json::to_value(status).unwrap_or_default();
+        self.send(JobEvent::Status { status, finished });
+    }
+
+    pub fn progress(&self, processed: usize, length: usize){
+        self.send(JobEvent::Progress { processed, length });
+    }
+
+    pub fn log<T: Into<String>>(&mut self, line: T){
+        let line = line.into();
+        self.log.push(line.clone());
+        self.send(JobEvent::Log { line });
+    }
+
+    /// Subscribes to the job
+    ///
+    /// Returns the events describing the current state (log so far and the given snapshot) and a receiver for
+    /// all following events. Must be called while holding the lock of the job, so no event gets lost in between.
+    pub fn subscribe(&self, snapshot: Vec<JobEvent>) -> (Vec<JobEvent>, broadcast::Receiver<JobEvent>){
+        let mut initial: Vec<JobEvent> = self.log.iter().map(|line| JobEvent::Log { line: line.clone() }).collect();
+        initial.extend(snapshot);
+        (initial, self.sender.subscribe())
+    }
+}
+
+/// Turns a subscription into an SSE stream, which ends after the job finished
+pub fn event_stream(initial: Vec<JobEvent>, mut receiver: broadcast::Receiver<JobEvent>) -> EventStream![]{
+    EventStream!{
+        let mut finished = false;
+        for event in initial{
+            finished = event.is_finished();
+            yield Event::json(&event).event(event.name());
+            if finished{
+                break;
+            }
+        }
+
+        while !finished{
+            match receiver.recv().await{
+                Ok(event) => {
+                    finished = event.is_finished();
+                    yield Event::json(&event).event(event.name());
+                },
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    eprintln!("Job event stream lagged behind, skipped {} events", skipped);
+                },
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_subscribe_replays_log(){
+        let mut progress = JobProgress::default();
+        progress.log("Preparing project");
+
+        let (initial, mut receiver) = progress.subscribe(vec![JobEvent::Status { status: "Running".into(), finished: false }]);
+        assert_eq!(initial, vec![
+            JobEvent::Log { line: "Preparing project".to_string() },
+            JobEvent::Status { status: "Running".into(), finished: false },
+        ]);
+
+        progress.progress(1, 2);
+        progress.status(&"Finished", true);
+        assert_eq!(receiver.try_recv().unwrap(), JobEvent::Progress { processed: 1, length: 2 });
+        assert!(receiver.try_recv().unwrap().is_finished());
+    }
+}
@@ -0,0 +1,117 @@
+//! Queue of outgoing messages (mails, webhook deliveries) whose failed attempts are retried with an increasing delay.
+//!
+//! The owner takes the due entries with [RetryQueue::take_due], tries to send them and hands failed ones back
+//! with [RetryQueue::retry]. Entries are dropped once the maximum number of attempts is reached.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Queued<T>{
+    #[serde(flatten)]
+    pub item: T,
+    /// Number of failed attempts
+    pub attempts: u32,
+    #[serde(skip)]
+    pub next_attempt: SystemTime,
+    pub last_error: Option<String>,
+}
+
+pub struct RetryQueue<T>{
+    max_attempts: u32,
+    /// Seconds until the first retry, doubled for every further attempt
+    retry_interval: u64,
+    entries: Mutex<VecDeque<Queued<T>>>,
+}
+
+impl<T> RetryQueue<T>{
+    pub fn new(max_attempts: u32, retry_interval: u64) -> RetryQueue<T>{
+        RetryQueue{
+            max_attempts,
+            retry_interval,
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Queues a new entry, it's due immediately
+    pub fn push(&self, item: T){
+        self.entries.lock().unwrap().push_back(Queued{
+            item,
+            attempts: 0,
+            next_attempt: SystemTime::now(),
+            last_error: None,
+        });
+    }
+
+    /// Removes and returns all entries which are due
+    pub fn take_due(&self) -> Vec<Queued<T>>{
+        let now = SystemTime::now();
+        let mut entries = self.entries.lock().unwrap();
+        let (due, waiting): (VecDeque<Queued<T>>, VecDeque<Queued<T>>) = entries.drain(..).partition(|entry| entry.next_attempt <= now);
+        *entries = waiting;
+        due.into()
+    }
+
+    /// Queues an entry again after a failed attempt
+    ///
+    /// Returns false if the maximum number of attempts is reached, the entry is dropped then
+    pub fn retry(&self, mut entry: Queued<T>, error: String) -> bool{
+        entry.attempts += 1;
+        if entry.attempts >= self.max_attempts{
+            return false;
+        }
+        let delay = self.retry_interval.saturating_mul(2u64.saturating_pow(entry.attempts - 1));
+        entry.next_attempt = SystemTime::now() + Duration::from_secs(delay);
+        entry.last_error = Some(error);
+        self.entries.lock().unwrap().push_back(entry);
+        true
+    }
+
+    /// Removes all queued entries for which the predicate returns false
+    pub fn retain(&self, f: impl Fn(&T) -> bool){
+        self.entries.lock().unwrap().retain(|entry| f(&entry.item));
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.entries.lock().unwrap().is_empty()
+    }
+}
+
+impl<T: Clone> RetryQueue<T>{
+    /// Returns a copy of all queued entries (not yet sent or waiting for a retry)
+    pub fn entries(&self) -> Vec<Queued<T>>{
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_failed_entries_are_retried_with_increasing_delay(){
+        let queue = RetryQueue::new(3, 10);
+        queue.push("message");
+
+        let entry = queue.take_due().pop().unwrap();
+        assert!(queue.retry(entry, "timeout".to_string()));
+        // Waits for the retry interval
+        assert!(queue.take_due().is_empty());
+
+        let mut entries = queue.entries.lock().unwrap().drain(..).collect::<Vec<_>>();
+        assert_eq!((entries[0].attempts, entries[0].last_error.as_deref()), (1, Some("timeout")));
+        let first_delay = entries[0].next_attempt.duration_since(SystemTime::now()).unwrap();
+        assert!(first_delay <= Duration::from_secs(10) && first_delay > Duration::from_secs(8));
+
+        let entry = entries.pop().unwrap();
+        assert!(queue.retry(entry, "timeout".to_string()));
+        let entry = queue.entries.lock().unwrap().pop_front().unwrap();
+        assert!(entry.next_attempt.duration_since(SystemTime::now()).unwrap() > Duration::from_secs(18));
+
+        // The third failed attempt drops the entry
+        assert!(!queue.retry(entry, "timeout".to_string()));
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,521 @@
+//! Outgoing webhooks for project events, e.g. to trigger a repository upload or a website rebuild.
+//!
+//! Webhooks are registered globally or for a single project and subscribe to [WebhookEvent]s. Every delivery is a
+//! JSON POST request, signed with a HMAC-SHA256 of the body keyed with the secret of the webhook
+//! (`X-Webhook-Signature: sha256=<hex>`). Failed deliveries are retried with an increasing delay until
+//! [WebhookSettings::max_attempts] is reached, every attempt is kept in the delivery log of the webhook, which is
+//! stored next to the webhooks in the [DataStorage].
+
+use std::sync::Arc;
+use std::time::Duration;
+use bincode::{Decode, Encode};
+use chrono::NaiveDateTime;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use crate::data_storage::DataStorage;
+use crate::settings::{Settings, WebhookSettings};
+use crate::utils::retry_queue::RetryQueue;
+
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent{
+    RenderingFinished,
+    RenderingFailed,
+    ImportCompleted,
+    ImportFailed,
+    MetadataChanged,
+    SectionStatusChanged,
+}
+
+impl WebhookEvent{
+    /// Name of the event as sent in the payload and the X-Webhook-Event header
+    pub fn name(&self) -> &'static str{
+        match self{
+            WebhookEvent::RenderingFinished => "rendering_finished",
+            WebhookEvent::RenderingFailed => "rendering_failed",
+            WebhookEvent::ImportCompleted => "import_completed",
+            WebhookEvent::ImportFailed => "import_failed",
+            WebhookEvent::MetadataChanged => "metadata_changed",
+            WebhookEvent::SectionStatusChanged => "section_status_changed",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone)]
+pub struct Webhook{
+    #[bincode(with_serde)]
+    pub id: uuid::Uuid,
+    /// Project the webhook belongs to, global webhooks (None) fire for all projects
+    #[bincode(with_serde)]
+    pub project_id: Option<uuid::Uuid>,
+    pub url: String,
+    /// Key of the HMAC signature
+    pub secret: String,
+    /// Subscribed events, all events if empty
+    pub events: Vec<WebhookEvent>,
+    pub active: bool,
+}
+
+impl Webhook{
+    pub fn matches(&self, project_id: &uuid::Uuid, event: WebhookEvent) -> bool{
+        self.active
+            && self.project_id.is_none_or(|id| id == *project_id)
+            && (self.events.is_empty() || self.events.contains(&event))
+    }
+
+    /// Copy of the webhook with an empty secret, the secret is only returned when the webhook is created
+    pub fn without_secret(&self) -> Webhook{
+        Webhook{
+            secret: String::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Random secret for new webhooks without a given secret
+    pub fn generate_secret() -> String{
+        hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+    }
+}
+
+/// Body of a webhook delivery
+#[derive(Serialize, Debug)]
+pub struct WebhookPayload<'a, T: Serialize>{
+    /// Id of the delivery, stays the same for retries
+    pub id: uuid::Uuid,
+    pub event: WebhookEvent,
+    pub project_id: uuid::Uuid,
+    pub timestamp: NaiveDateTime,
+    pub data: &'a T,
+}
+
+/// Single delivery attempt
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DeliveryLogEntry{
+    pub delivery_id: uuid::Uuid,
+    pub event: WebhookEvent,
+    pub attempt: u32,
+    pub timestamp: NaiveDateTime,
+    /// HTTP status of the response, None if the request failed
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub success: bool,
+    /// True if the delivery failed and is retried later
+    pub retry: bool,
+}
+
+#[derive(Debug, Clone)]
+struct PendingDelivery{
+    id: uuid::Uuid,
+    webhook_id: uuid::Uuid,
+    url: String,
+    secret: String,
+    event: WebhookEvent,
+    body: String,
+}
+
+/// Signature of a delivery body, sent in the X-Webhook-Signature header
+pub fn sign(secret: &str, body: &[u8]) -> String{
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+pub struct WebhookManager{
+    settings: WebhookSettings,
+    data_storage: Arc<DataStorage>,
+    client: reqwest::Client,
+    queue: RetryQueue<PendingDelivery>,
+}
+
+impl WebhookManager{
+    pub fn new(settings: &Settings, data_storage: Arc<DataStorage>) -> WebhookManager{
+        WebhookManager{
+            settings: settings.webhooks.clone(),
+            data_storage,
+            client: reqwest::Client::new(),
+            queue: RetryQueue::new(settings.webhooks.max_attempts, settings.webhooks.retry_interval),
+        }
+    }
+
+    /// Creates the [WebhookManager] and starts the worker which sends queued deliveries
+    pub fn start(settings: &Settings, data_storage: Arc<DataStorage>) -> Arc<WebhookManager>{
+        let manager = Arc::new(WebhookManager::new(settings, data_storage));
+
+        let manager_cpy = manager.clone();
+        tokio::spawn(async move {
+            loop{
+                manager_cpy.process_queue().await;
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+
+        manager
+    }
+
+    /// Queues a delivery of the event to all matching webhooks
+    pub fn trigger<T: Serialize>(&self, project_id: uuid::Uuid, event: WebhookEvent, data: &T){
+        let webhooks: Vec<Webhook> = self.data_storage.data.read().unwrap().webhooks.values()
+            .filter(|webhook| webhook.matches(&project_id, event))
+            .cloned()
+            .collect();
+        if webhooks.is_empty(){
+            return;
+        }
+
+        let id = uuid::Uuid::new_v4();
+        let body = match serde_json::to_string(&WebhookPayload{ id, event, project_id, timestamp: chrono::Utc::now().naive_utc(), data }){
+            Ok(body) => body,
+            Err(e) => {
+                eprintln!("Couldn't serialize webhook payload: {}", e);
+                return;
+            }
+        };
+
+        for webhook in webhooks{
+            self.queue.push(PendingDelivery{
+                id,
+                webhook_id: webhook.id,
+                url: webhook.url,
+                secret: webhook.secret,
+                event,
+                body: body.clone(),
+            });
+        }
+    }
+
+    /// Returns the logged delivery attempts of a webhook, newest first
+    pub fn delivery_log(&self, webhook_id: &uuid::Uuid) -> Vec<DeliveryLogEntry>{
+        self.data_storage.data.read().unwrap().webhook_deliveries.get(webhook_id).map(|log| log.iter().rev().cloned().collect()).unwrap_or_default()
+    }
+
+    /// Removes the log and all queued deliveries of a deleted webhook
+    pub fn forget(&self, webhook_id: &uuid::Uuid){
+        self.data_storage.data.write().unwrap().webhook_deliveries.remove(webhook_id);
+        self.queue.retain(|delivery| delivery.webhook_id != *webhook_id);
+    }
+
+    /// Sends all deliveries which are due, failed deliveries are queued again until the maximum number of attempts is reached
+    pub async fn process_queue(&self){
+        for delivery in self.queue.take_due(){
+            let (status_code, error) = match self.send(&delivery.item).await{
+                Ok(status) if status.is_success() => (Some(status.as_u16()), None),
+                Ok(status) => (Some(status.as_u16()), Some(format!("Unexpected response status {}", status))),
+                Err(e) => (None, Some(e.to_string())),
+            };
+
+            let attempt = delivery.attempts + 1;
+            let entry = DeliveryLogEntry{
+                delivery_id: delivery.item.id,
+                event: delivery.item.event,
+                attempt,
+                timestamp: chrono::Utc::now().naive_utc(),
+                status_code,
+                error: error.clone(),
+                success: error.is_none(),
+                retry: false,
+            };
+            let (webhook_id, url) = (delivery.item.webhook_id, delivery.item.url.clone());
+
+            match error{
+                Some(e) => {
+                    let retry = self.queue.retry(delivery, e.clone());
+                    if retry{
+                        eprintln!("Couldn't deliver webhook to {} (attempt {}), retrying: {}", url, attempt, e);
+                    }else{
+                        eprintln!("Couldn't deliver webhook to {} after {} attempts, dropping it: {}", url, attempt, e);
+                    }
+                    self.record(webhook_id, DeliveryLogEntry{ retry, ..entry });
+                },
+                None => self.record(webhook_id, entry),
+            }
+        }
+    }
+
+    async fn send(&self, delivery: &PendingDelivery) -> Result<reqwest::StatusCode, reqwest::Error>{
+        let response = self.client.post(&delivery.url)
+            .timeout(Duration::from_secs(self.settings.timeout))
+            .header("Content-Type", "application/json")
+            .header("X-Webhook-Event", delivery.event.name())
+            .header("X-Webhook-Delivery", delivery.id.to_string())
+            .header("X-Webhook-Signature", sign(&delivery.secret, delivery.body.as_bytes()))
+            .body(delivery.body.clone())
+            .send()
+            .await?;
+        Ok(response.status())
+    }
+
+    /// Appends an attempt to the delivery log, the log is saved with the next auto-save of the [DataStorage]
+    fn record(&self, webhook_id: uuid::Uuid, entry: DeliveryLogEntry){
+        let mut data = self.data_storage.data.write().unwrap();
+        // The webhook may have been deleted in the meantime
+        if !data.webhooks.contains_key(&webhook_id){
+            return;
+        }
+        let entries = data.webhook_deliveries.entry(webhook_id).or_default();
+        entries.push_back(entry);
+        while entries.len() > self.settings.log_size{
+            entries.pop_front();
+        }
+    }
+}
+
+pub mod api{
+    use std::sync::Arc;
+    use rocket::serde::json::Json;
+    use rocket::State;
+    use serde::{Deserialize, Serialize};
+    use crate::data_storage::{DataStorage, ProjectStorage};
+    use crate::projects::api::{ApiError, ApiResult};
+    use crate::session::session_guard::StaffSession;
+    use crate::settings::Settings;
+    use crate::webhooks::{DeliveryLogEntry, Webhook, WebhookEvent, WebhookManager};
+
+    #[derive(Deserialize, Serialize)]
+    pub struct NewWebhook{
+        pub project_id: Option<uuid::Uuid>,
+        pub url: String,
+        /// Generated if missing, kept on updates if missing
+        pub secret: Option<String>,
+        #[serde(default)]
+        pub events: Vec<WebhookEvent>,
+        #[serde(default = "default_active")]
+        pub active: bool,
+    }
+
+    fn default_active() -> bool{
+        true
+    }
+
+    /// Checks url and project of a new or updated webhook
+    fn validate(webhook: &NewWebhook, project_storage: &ProjectStorage) -> Result<(), ApiError>{
+        match url::Url::parse(&webhook.url){
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {},
+            _ => return Err(ApiError::BadRequest("Invalid webhook url".to_string())),
+        }
+        if let Some(project_id) = &webhook.project_id{
+            if !project_storage.projects.read().unwrap().contains_key(project_id){
+                return Err(ApiError::BadRequest(format!("Project {} does not exist", project_id)));
+            }
+        }
+        Ok(())
+    }
+
+    /// GET /api/webhooks?<project_id>
+    /// Lists all webhooks, or the webhooks of a project including the global ones
+    ///
+    /// Secrets are not included
+    #[get("/api/webhooks?<project_id>")]
+    pub async fn list_webhooks(project_id: Option<String>, _session: StaffSession, data_storage: &State<Arc<DataStorage>>) -> Json<ApiResult<Vec<Webhook>>>{
+        let project_id = match project_id.map(|id| uuid::Uuid::parse_str(&id)).transpose(){
+            Ok(project_id) => project_id,
+            Err(_) => return ApiResult::new_error(ApiError::BadRequest("Couldn't parse project id".to_string())),
+        };
+
+        let mut webhooks: Vec<Webhook> = data_storage.data.read().unwrap().webhooks.values()
+            .filter(|webhook| project_id.is_none_or(|project_id| webhook.project_id.is_none_or(|id| id == project_id)))
+            .map(Webhook::without_secret)
+            .collect();
+        webhooks.sort_by(|a, b| a.url.cmp(&b.url));
+        ApiResult::new_data(webhooks)
+    }
+
+    /// POST /api/webhooks
+    /// Registers a new webhook, global if no project_id is given
+    #[post("/api/webhooks", data = "<new_webhook>")]
+    pub async fn add_webhook(new_webhook: Json<NewWebhook>, _session: StaffSession, settings: &State<Settings>, data_storage: &State<Arc<DataStorage>>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Webhook>>{
+        let new_webhook = new_webhook.into_inner();
+        if let Err(e) = validate(&new_webhook, project_storage){
+            return ApiResult::new_error(e);
+        }
+
+        let webhook = Webhook{
+            id: uuid::Uuid::new_v4(),
+            project_id: new_webhook.project_id,
+            url: new_webhook.url,
+            secret: new_webhook.secret.filter(|secret| !secret.is_empty()).unwrap_or_else(Webhook::generate_secret),
+            events: new_webhook.events,
+            active: new_webhook.active,
+        };
+        data_storage.data.write().unwrap().webhooks.insert(webhook.id, webhook.clone());
+        if data_storage.save_to_disk(settings).await.is_err(){
+            return ApiResult::new_error(ApiError::InternalServerError);
+        }
+        ApiResult::new_data(webhook)
+    }
+
+    /// PUT /api/webhooks/<id>
+    /// Replaces a webhook, the secret is kept if none is given
+    ///
+    /// The secret is not included in the response
+    #[put("/api/webhooks/<id>", data = "<new_webhook>")]
+    pub async fn update_webhook(id: String, new_webhook: Json<NewWebhook>, _session: StaffSession, settings: &State<Settings>, data_storage: &State<Arc<DataStorage>>, project_storage: &State<Arc<ProjectStorage>>) -> Json<ApiResult<Webhook>>{
+        let id = match uuid::Uuid::parse_str(&id){
+            Ok(id) => id,
+            Err(_) => return ApiResult::new_error(ApiError::BadRequest("Invalid id".to_string()))
+        };
+
+        let new_webhook = new_webhook.into_inner();
+        if let Err(e) = validate(&new_webhook, project_storage){
+            return ApiResult::new_error(e);
+        }
+
+        let webhook = {
+            let mut data = data_storage.data.write().unwrap();
+            let webhook = match data.webhooks.get_mut(&id){
+                Some(webhook) => webhook,
+                None => return ApiResult::new_error(ApiError::NotFound),
+            };
+            webhook.project_id = new_webhook.project_id;
+            webhook.url = new_webhook.url;
+            if let Some(secret) = new_webhook.secret.filter(|secret| !secret.is_empty()){
+                webhook.secret = secret;
+            }
+            webhook.events = new_webhook.events;
+            webhook.active = new_webhook.active;
+            webhook.without_secret()
+        };
+        if data_storage.save_to_disk(settings).await.is_err(){
+            return ApiResult::new_error(ApiError::InternalServerError);
+        }
+        ApiResult::new_data(webhook)
+    }
+
+    /// DELETE /api/webhooks/<id>
+    #[delete("/api/webhooks/<id>")]
+    pub async fn delete_webhook(id: String, _session: StaffSession, settings: &State<Settings>, data_storage: &State<Arc<DataStorage>>, webhooks: &State<Arc<WebhookManager>>) -> Json<ApiResult<()>>{
+        let id = match uuid::Uuid::parse_str(&id){
+            Ok(id) => id,
+            Err(_) => return ApiResult::new_error(ApiError::BadRequest("Invalid id".to_string()))
+        };
+
+        if data_storage.data.write().unwrap().webhooks.remove(&id).is_none(){
+            return ApiResult::new_error(ApiError::NotFound);
+        }
+        webhooks.forget(&id);
+        if data_storage.save_to_disk(settings).await.is_err(){
+            return ApiResult::new_error(ApiError::InternalServerError);
+        }
+        ApiResult::new_data(())
+    }
+
+    /// GET /api/webhooks/<id>/deliveries
+    /// Returns the delivery log of a webhook, newest attempt first
+    #[get("/api/webhooks/<id>/deliveries")]
+    pub async fn list_deliveries(id: String, _session: StaffSession, data_storage: &State<Arc<DataStorage>>, webhooks: &State<Arc<WebhookManager>>) -> Json<ApiResult<Vec<DeliveryLogEntry>>>{
+        let id = match uuid::Uuid::parse_str(&id){
+            Ok(id) => id,
+            Err(_) => return ApiResult::new_error(ApiError::BadRequest("Invalid id".to_string()))
+        };
+
+        if !data_storage.data.read().unwrap().webhooks.contains_key(&id){
+            return ApiResult::new_error(ApiError::NotFound);
+        }
+        ApiResult::new_data(webhooks.delivery_log(&id))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Minimal HTTP sink, returns the received requests
+    async fn http_sink(listener: TcpListener, expected: usize) -> Vec<String>{
+        let mut received = vec![];
+        while received.len() < expected{
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buffer = [0u8; 4096];
+            // Read until the whole body (Content-Length) arrived
+            loop{
+                let n = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n"){
+                    let length = head.lines()
+                        .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|value| value.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if body.len() >= length{
+                        break;
+                    }
+                }
+                if n == 0{
+                    break;
+                }
+            }
+
+            received.push(String::from_utf8_lossy(&request).to_string());
+            stream.write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n").await.unwrap();
+        }
+        received
+    }
+
+    #[test]
+    fn test_sign(){
+        assert_eq!(sign("key", b"The quick brown fox jumps over the lazy dog"), "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+    }
+
+    fn generate_webhook(project_id: uuid::Uuid, url: String) -> Webhook{
+        Webhook{
+            id: uuid::Uuid::new_v4(),
+            project_id: Some(project_id),
+            url,
+            secret: "secret".to_string(),
+            events: vec![WebhookEvent::RenderingFinished],
+            active: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deliveries_are_signed_and_logged(){
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sink = tokio::spawn(http_sink(listener, 1));
+        // Nothing listens on this port anymore, so deliveries to it fail
+        let closed_port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+
+        let project_id = uuid::Uuid::new_v4();
+        let data_storage = Arc::new(DataStorage::new());
+        let webhook = generate_webhook(project_id, format!("http://127.0.0.1:{}/hook", port));
+        let failing = generate_webhook(project_id, format!("http://127.0.0.1:{}/hook", closed_port));
+        data_storage.data.write().unwrap().webhooks.insert(webhook.id, webhook.clone());
+        data_storage.data.write().unwrap().webhooks.insert(failing.id, failing.clone());
+
+        let manager = WebhookManager{
+            settings: WebhookSettings{ max_attempts: 3, retry_interval: 60, timeout: 5, log_size: 10 },
+            data_storage: data_storage.clone(),
+            client: reqwest::Client::new(),
+            queue: RetryQueue::new(3, 60),
+        };
+
+        // Other projects and events are ignored
+        manager.trigger(uuid::Uuid::new_v4(), WebhookEvent::RenderingFinished, &serde_json::json!({}));
+        manager.trigger(project_id, WebhookEvent::RenderingFailed, &serde_json::json!({}));
+        assert!(manager.queue.is_empty());
+
+        manager.trigger(project_id, WebhookEvent::RenderingFinished, &serde_json::json!({"rendering_id": "abc"}));
+        manager.process_queue().await;
+
+        let log = manager.delivery_log(&webhook.id);
+        assert!(log.len() == 1 && log[0].success && log[0].status_code == Some(204));
+        let log = manager.delivery_log(&failing.id);
+        assert!(log.len() == 1 && !log[0].success && log[0].retry && log[0].status_code.is_none());
+        // The failed delivery waits for its retry
+        assert_eq!(manager.queue.entries().len(), 1);
+
+        // The log is stored with the webhooks and removed together with the webhook
+        assert_eq!(data_storage.data.read().unwrap().webhook_deliveries.len(), 2);
+        data_storage.data.write().unwrap().webhooks.remove(&failing.id);
+        manager.forget(&failing.id);
+        assert!(manager.queue.is_empty() && manager.delivery_log(&failing.id).is_empty());
+
+        let received = sink.await.unwrap();
+        let (head, body) = received[0].split_once("\r\n\r\n").unwrap();
+        assert!(head.to_lowercase().contains("x-webhook-event: rendering_finished"));
+        assert!(head.contains(&sign("secret", body.as_bytes())));
+        assert!(body.contains("\"rendering_id\":\"abc\""));
+    }
+}
@@ -13,6 +13,8 @@ var Editor;
 (function (Editor) {
     let ProjectOverview;
     (function (ProjectOverview) {
+        /// ETag of the loaded project metadata, sent as If-Match on updates
+        let project_metadata_etag = null;
         function show_overview() {
             console.log("Loading overview for project " + globalThis.project_id);
             let project_data = load_project_metadata(globalThis.project_id);
@@ -909,16 +911,29 @@ var Editor;
                     }
                 }
                 console.log("new data: " + JSON.stringify(data));
+                // Without the version of the loaded metadata changes of other users could be overwritten
+                if (project_metadata_etag === null) {
+                    Tools.show_alert("The metadata wasn't loaded completely. Please reload the page.", "warning");
+                    return;
+                }
                 try {
                     Tools.start_loading_spinner();
                     const response = yield fetch(`/api/projects/${globalThis.project_id}/metadata`, {
                         method: 'PATCH',
                         body: JSON.stringify(data),
                         headers: {
-                            'Content-Type': 'application/json'
+                            'Content-Type': 'application/json',
+                            'If-Match': project_metadata_etag
                         }
                     });
                     Tools.stop_loading_spinner();
+                    if (response.status === 412) {
+                        Tools.show_alert("The metadata was changed by someone else. Please reload the page to see the current version.", "warning");
+                        return;
+                    }
+                    if (response.headers.has("ETag")) {
+                        project_metadata_etag = response.headers.get("ETag");
+                    }
                     if (!response.ok) {
                         throw new Error(`Failed to update project metadata ${globalThis.project_id}`);
                     }
@@ -946,6 +961,7 @@ var Editor;
                     throw new Error(`Failed to load project metadata ${project_id}`);
                 }
                 else {
+                    project_metadata_etag = response.headers.get("ETag");
                     return response.json();
                 }
             });
@@ -1045,6 +1061,8 @@ var Editor;
 (function (Editor) {
     let SectionView;
     (function (SectionView) {
+        /// ETags of the loaded sections, sent as If-Match on updates
+        let section_etags = {};
         // @ts-ignore
         function show_section_view() {
             return __awaiter(this, void 0, void 0, function* () {
@@ -1927,6 +1945,7 @@ var Editor;
                         throw new Error(`Failed to get section data: ${response_data["error"]}`);
                     }
                     else {
+                        section_etags[section_path] = response.headers.get("ETag");
                         return response_data.data;
                     }
                 }
@@ -1934,13 +1953,26 @@ var Editor;
         }
         function send_patch_section(section_path, section_data) {
             return __awaiter(this, void 0, void 0, function* () {
+                // Without the version of the loaded section changes of other users could be overwritten
+                if (!section_etags[section_path]) {
+                    Tools.show_alert("The section wasn't loaded completely. Please reload the page.", "warning");
+                    throw new Error(`Failed to patch section data: section version unknown`);
+                }
                 const response = yield fetch(`/api/projects/${globalThis.project_id}/sections/` + section_path, {
                     method: 'PATCH',
                     headers: {
-                        'Content-Type': 'application/json'
+                        'Content-Type': 'application/json',
+                        'If-Match': section_etags[section_path]
                     },
                     body: JSON.stringify(section_data)
                 });
+                if (response.status === 412) {
+                    Tools.show_alert("The section was changed by someone else. Please reload the page to see the current version.", "warning");
+                    throw new Error(`Failed to patch section data: section was changed concurrently`);
+                }
+                if (response.headers.has("ETag")) {
+                    section_etags[section_path] = response.headers.get("ETag");
+                }
                 if (!response.ok) {
                     throw new Error(`Failed to patch section data: ${response.status}`);
                 }
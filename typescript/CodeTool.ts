@@ -0,0 +1,58 @@
+/// Block tool for preformatted text and source code with an optional language label.
+/// The content is stored as plain text and never hyphenated on export.
+export class CodeTool{
+    private data: {code: string, language: string};
+
+    static get toolbox() {
+        return {
+            title: 'Code',
+            icon: '<svg width="17" height="15" viewBox="0 0 17 15"><text x="0" y="12" font-size="11" font-family="monospace">&lt;/&gt;</text></svg>'
+        };
+    }
+
+    static get enableLineBreaks() {
+        return true;
+    }
+
+    // @ts-ignore
+    constructor({data}){
+        this.data = {
+            code: data && data.code ? data.code : '',
+            language: data && data.language ? data.language : ''
+        };
+    }
+
+    render(): HTMLDivElement{
+        let wrapper = document.createElement('div');
+        wrapper.classList.add('code-block-tool');
+
+        let language = document.createElement('input');
+        language.type = 'text';
+        language.classList.add('cdx-input');
+        language.placeholder = 'Language (optional), e.g. rust';
+        language.value = this.data.language;
+        language.addEventListener('input', (event) => {
+            this.data.language = (<HTMLInputElement>event.target).value;
+        });
+
+        let code = document.createElement('textarea');
+        code.classList.add('cdx-input');
+        code.style.fontFamily = 'monospace';
+        code.spellcheck = false;
+        code.value = this.data.code;
+        code.addEventListener('input', (event) => {
+            this.data.code = (<HTMLTextAreaElement>event.target).value;
+        });
+
+        wrapper.appendChild(language);
+        wrapper.appendChild(code);
+        return wrapper;
+    }
+
+    save(): any{
+        return {
+            code: this.data.code,
+            language: this.data.language
+        };
+    }
+}
@@ -0,0 +1,210 @@
+import EditorJS from "@editorjs/editorjs";
+import * as API from "./api_requests";
+import * as Tools from "./tools";
+
+/// Client for the collaboration WebSocket channel of a project.
+/// Sends block-level operations instead of replacing the whole section and applies operations of other users.
+export class Collaboration{
+    private socket: WebSocket;
+    private editor: EditorJS;
+    private project_id: string;
+    private section_path: string;
+    private connection_id: string = null;
+    private locked_block_id: string = null;
+    private locks: any[] = [];
+    /// Set while operations of other users are applied, so they aren't sent back
+    private applying_remote: boolean = false;
+
+    constructor(project_id: string, section_path: string, editor: EditorJS){
+        this.project_id = project_id;
+        this.section_path = section_path;
+        this.editor = editor;
+
+        let protocol = location.protocol === 'https:' ? 'wss' : 'ws';
+        this.socket = new WebSocket(protocol + '://' + location.host + '/api/projects/' + project_id + '/ws');
+        this.socket.addEventListener('open', () => {
+            this.send({type: 'EnterSection', section_path: this.section_path});
+        });
+        this.socket.addEventListener('message', (event) => this.handle_message(JSON.parse(event.data)));
+        this.socket.addEventListener('close', () => {
+            console.log("Collaboration channel closed, falling back to saving whole sections.");
+        });
+    }
+
+    is_connected(): boolean{
+        return this.socket.readyState === WebSocket.OPEN && this.connection_id !== null;
+    }
+
+    close(){
+        this.socket.close();
+    }
+
+    private send(message: any){
+        if(this.socket.readyState === WebSocket.OPEN){
+            this.socket.send(JSON.stringify(message));
+        }
+    }
+
+    /// Converts a saved EditorJS block into the format expected by the backend
+    private static to_backend_format(saved: any): any{
+        return {
+            id: saved.id,
+            type: saved.tool,
+            data: saved.data,
+            tunes: saved.tunes || {}
+        };
+    }
+
+    private previous_block_id(index: number): string|null{
+        if(index <= 0){
+            return null;
+        }
+        let previous = this.editor.blocks.getBlockByIndex(index - 1);
+        return previous ? previous.id : null;
+    }
+
+    /// Handles the onChange events of the editor and sends the matching operations
+    async handle_editor_change(events: any){
+        if(this.applying_remote){
+            return;
+        }
+        if(!Array.isArray(events)){
+            events = [events];
+        }
+        for(let event of events){
+            let block = event.detail.target;
+            switch(event.type){
+                case 'block-added': {
+                    let saved = await block.save();
+                    this.send({type: 'Operation', operation: {op: 'Insert', section_path: this.section_path, after_block_id: this.previous_block_id(event.detail.index), block: Collaboration.to_backend_format(saved)}});
+                    break;
+                }
+                case 'block-changed': {
+                    if(this.locked_block_id !== block.id){
+                        if(this.locked_block_id !== null){
+                            this.send({type: 'UnlockBlock', section_path: this.section_path, block_id: this.locked_block_id});
+                        }
+                        this.locked_block_id = block.id;
+                    }
+                    // Acquire or renew the soft lock on the block
+                    this.send({type: 'LockBlock', section_path: this.section_path, block_id: block.id});
+                    let saved = await block.save();
+                    this.send({type: 'Operation', operation: {op: 'Update', section_path: this.section_path, block: Collaboration.to_backend_format(saved)}});
+                    break;
+                }
+                case 'block-removed':
+                    this.send({type: 'Operation', operation: {op: 'Delete', section_path: this.section_path, block_id: block.id}});
+                    break;
+                case 'block-moved':
+                    this.send({type: 'Operation', operation: {op: 'Move', section_path: this.section_path, block_id: block.id, after_block_id: this.previous_block_id(event.detail.toIndex)}});
+                    break;
+            }
+        }
+    }
+
+    private async handle_message(message: any){
+        switch(message.type){
+            case 'Welcome':
+                this.connection_id = message.connection_id;
+                break;
+            case 'Operation':
+                if(message.operation.section_path === this.section_path){
+                    await this.apply_operation(message.operation);
+                }
+                break;
+            case 'SectionReplaced':
+                if(message.section_path === this.section_path){
+                    await this.reload_section();
+                }
+                break;
+            case 'Presence':
+                this.show_presence(message.users);
+                break;
+            case 'Locks':
+                this.locks = message.locks;
+                this.show_locks();
+                break;
+            case 'Error':
+                console.error(message.message);
+                Tools.show_alert(message.message, "warning");
+                break;
+        }
+    }
+
+    private index_after(after_block_id: string|null): number{
+        if(after_block_id === null){
+            return 0;
+        }
+        return this.editor.blocks.getBlockIndex(after_block_id) + 1;
+    }
+
+    private async apply_operation(operation: any){
+        this.applying_remote = true;
+        try{
+            switch(operation.op){
+                case 'Insert':
+                    this.editor.blocks.insert(operation.block.type, operation.block.data, undefined, this.index_after(operation.after_block_id), false, false, operation.block.id);
+                    break;
+                case 'Update':
+                    await this.editor.blocks.update(operation.block.id, operation.block.data);
+                    break;
+                case 'Delete':
+                    this.editor.blocks.delete(this.editor.blocks.getBlockIndex(operation.block_id));
+                    break;
+                case 'Move': {
+                    let from = this.editor.blocks.getBlockIndex(operation.block_id);
+                    let to = this.index_after(operation.after_block_id);
+                    this.editor.blocks.move(to > from ? to - 1 : to, from);
+                    break;
+                }
+            }
+        }catch(e){
+            console.error(e);
+            await this.reload_section();
+        }finally{
+            this.applying_remote = false;
+            this.show_locks();
+        }
+    }
+
+    private async reload_section(){
+        this.applying_remote = true;
+        try{
+            let data = (await API.send_get_content_blocks(this.project_id, this.section_path)).data;
+            await this.editor.render({blocks: data});
+        }catch(e){
+            console.error(e);
+            Tools.show_alert("Couldn't reload content.", "danger");
+        }finally{
+            this.applying_remote = false;
+            this.show_locks();
+        }
+    }
+
+    private show_presence(users: any[]){
+        let container = document.getElementById("collaboration_presence");
+        if(container === null){
+            return;
+        }
+        let others = users.filter((user) => user.connection_id !== this.connection_id && user.section_path === this.section_path);
+        container.textContent = others.length > 0 ? "Also editing: " + others.map((user) => user.user_email).join(", ") : "";
+    }
+
+    private show_locks(){
+        // @ts-ignore
+        for(let block of document.querySelectorAll(".ce-block.block-locked")){
+            block.classList.remove("block-locked");
+            block.removeAttribute("title");
+        }
+        for(let lock of this.locks){
+            if(lock.section_path !== this.section_path || lock.connection_id === this.connection_id){
+                continue;
+            }
+            let block = this.editor.blocks.getById(lock.block_id);
+            if(block){
+                block.holder.classList.add("block-locked");
+                block.holder.setAttribute("title", "Currently edited by " + lock.user_email);
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+import EditorJS from "@editorjs/editorjs";
+const List: any = require("@editorjs/list");
+
+/// Block tool for container blocks (box, epigraph, abstract).
+/// Holds a nested editor which only allows paragraphs and lists.
+/// Configure the kind of container with the tool config, e.g. config: {kind: 'box'}
+export class ContainerTool{
+    private data: {title: string, attribution: string, blocks: any[]};
+    private kind: string;
+    private nested_editor: EditorJS;
+
+    // @ts-ignore
+    constructor({data, config}){
+        this.kind = config && config.kind ? config.kind : 'box';
+        this.data = {
+            title: data && data.title ? data.title : '',
+            attribution: data && data.attribution ? data.attribution : '',
+            blocks: data && data.blocks ? data.blocks : []
+        };
+        this.nested_editor = null;
+    }
+
+    static get toolbox() {
+        return {
+            title: 'Container',
+            icon: '<svg width="17" height="15" viewBox="0 0 17 15"><rect x="1" y="1" width="15" height="13" fill="none" stroke="currentColor" stroke-width="2"/></svg>'
+        };
+    }
+
+    static get enableLineBreaks() {
+        return true;
+    }
+
+    render(): HTMLDivElement{
+        let wrapper = document.createElement('div');
+        wrapper.classList.add('container-block-tool', 'container-block-' + this.kind);
+
+        if(this.kind === 'box'){
+            wrapper.appendChild(this.create_input('Title', 'title'));
+        }
+
+        let holder = document.createElement('div');
+        holder.classList.add('container-block-content');
+        // Stop events from bubbling up to the outer editor, otherwise both editors react on key presses
+        holder.addEventListener('keydown', (e) => e.stopPropagation());
+        holder.addEventListener('paste', (e) => e.stopPropagation());
+        wrapper.appendChild(holder);
+
+        if(this.kind === 'epigraph'){
+            wrapper.appendChild(this.create_input('Attribution', 'attribution'));
+        }
+
+        this.nested_editor = new EditorJS({
+            holder: holder,
+            minHeight: 0,
+            tools: {
+                list: {
+                    class: List,
+                    inlineToolbar: true,
+                    config: {
+                        defaultStyle: 'unordered'
+                    }
+                },
+            },
+            data: {blocks: this.data.blocks},
+        });
+
+        return wrapper;
+    }
+
+    private create_input(placeholder: string, field: 'title' | 'attribution'): HTMLInputElement{
+        let input = document.createElement('input');
+        input.type = 'text';
+        input.classList.add('cdx-input');
+        input.placeholder = placeholder;
+        input.value = this.data[field];
+        input.addEventListener('input', (event) => {
+            this.data[field] = (<HTMLInputElement>event.target).value;
+        });
+        return input;
+    }
+
+    async save(): Promise<any>{
+        let nested = await this.nested_editor.save();
+        let res: any = {
+            blocks: nested.blocks.map((block: any) => {
+                // Nested blocks have no tunes, but the backend expects the field
+                block.tunes = block.tunes || {};
+                return block;
+            })
+        };
+        if(this.kind === 'box'){
+            res.title = this.data.title;
+        }
+        if(this.kind === 'epigraph'){
+            res.attribution = this.data.attribution;
+        }
+        return res;
+    }
+}
@@ -0,0 +1,85 @@
+import * as Tools from "./tools";
+import * as API from "./api_requests";
+
+function add_contributor_listeners(){
+    // @ts-ignore
+    for(let el of document.getElementsByClassName("contributor-preview")){
+        el.addEventListener("click", preview_listener)
+    }
+
+    // @ts-ignore
+    for(let el of document.getElementsByClassName("contributor-approve")){
+        el.addEventListener("click", approve_listener)
+    }
+}
+
+async function preview_listener(event: Event){
+    let button = event.target as HTMLButtonElement;
+    let row = button.closest(".contributor_section_row");
+    let project_id = row.getAttribute("data-project-id");
+    let section_path = row.getAttribute("data-section-path");
+
+    button.disabled = true;
+    try{
+        let render_id : string = (await API.send_render_section_preview(project_id, section_path)).data;
+        await wait_for_rendering(render_id);
+
+        let download = <HTMLLinkElement> row.querySelector(".contributor-download");
+        download.href = `/download/renderings/`+render_id;
+        download.classList.remove("hide");
+        window.open(download.href, "_blank");
+    }catch(e){
+        Tools.show_alert("Failed to render preview.", "danger");
+        console.error(e);
+    }
+    button.disabled = false;
+}
+
+function wait_for_rendering(render_id: string): Promise<void>{
+    return new Promise((resolve, reject) => {
+        let events = API.open_rendering_events(render_id);
+        events.addEventListener("status", function(event: MessageEvent){
+            let data = JSON.parse(event.data);
+            if(!data.finished){
+                return;
+            }
+            events.close();
+            if(data.status === "Finished"){
+                resolve();
+            }else{
+                reject(new Error(`Rendering failed: ${JSON.stringify(data.status)}`));
+            }
+        });
+        events.onerror = function(){
+            if(events.readyState === EventSource.CLOSED){
+                reject(new Error(`Couldn't follow rendering ${render_id}`));
+            }
+        };
+    });
+}
+
+async function approve_listener(event: Event){
+    let button = event.target as HTMLButtonElement;
+    let row = button.closest(".contributor_section_row");
+    let project_id = row.getAttribute("data-project-id");
+    let section_path = row.getAttribute("data-section-path");
+
+    let comment = window.prompt("Approve the proof of this chapter? You can add a comment for the editors.", "");
+    if(comment === null){
+        return;
+    }
+
+    try{
+        let workflow = (await API.send_approve_section_proof(project_id, section_path, comment || null)).data;
+        row.querySelector(".contributor-section-state").textContent = workflow.state;
+        button.remove();
+        Tools.show_alert("Proof approved.", "success");
+    }catch(e){
+        Tools.show_alert("Failed to approve proof.", "danger");
+        console.error(e);
+    }
+}
+
+window.addEventListener("load", async function(){
+    add_contributor_listeners();
+});
@@ -52,28 +52,7 @@ async function upload_files_handler(){
 
     try {
         let import_id = (await API.send_import_from_upload(formData))["data"];
-        let poller = setInterval(async function(){
-            let res = (await API.send_poll_import_status(import_id))["data"];
-            let status = res["status"];
-            progress_bar.setAttribute("max", res["length"]);
-            progress_bar.setAttribute("value", res["processed"]);
-            if(status == "Pending"){
-                status_text.innerHTML = "Waiting for files to be processed...";
-            }
-            if(status == "Processing"){
-                status_text.innerHTML = "Processing file "+res["processed"]+" of "+res["length"]+"...";
-            }
-            if(status == "Complete"){
-                status_text.innerHTML = "Files processed successfully!";
-                clearInterval(poller);
-                // Reload page:
-                location.reload();
-            }
-            if(status == "Failed"){
-                status_text.innerHTML = "Failed to process files!";
-                clearInterval(poller);
-            }
-        }, 250);
+        follow_import(import_id, status_text, progress_bar);
 
     }catch (e) {
         console.error(e);
@@ -106,29 +85,7 @@ async function wordpress_import_handler(){
 
     try {
         let import_id = (await API.send_import_from_wordpress(data))["data"];
-        let poller = setInterval(async function(){
-            let res = (await API.send_poll_import_status(import_id))["data"];
-            let status = res["status"];
-            console.log(res);
-            progress_bar.setAttribute("max", res["length"]);
-            progress_bar.setAttribute("value", res["processed"]);
-            if(status == "Pending"){
-                status_text.innerHTML = "Waiting for files to be processed...";
-            }
-            if(status == "Processing"){
-                status_text.innerHTML = "Processing file "+res["processed"]+" of "+res["length"]+"...";
-            }
-            if(status == "Complete"){
-                status_text.innerHTML = "Files processed successfully!";
-                clearInterval(poller);
-                // Reload page:
-                location.reload();
-            }
-            if(status == "Failed"){
-                status_text.innerHTML = "Failed to process files!";
-                clearInterval(poller);
-            }
-        }, 250);
+        follow_import(import_id, status_text, progress_bar);
 
     }catch (e) {
         console.error(e);
@@ -137,6 +94,46 @@ async function wordpress_import_handler(){
     }
 }
 
+/// Shows the progress of an import until it's done, reloads the page on success
+function follow_import(import_id: string, status_text: HTMLElement, progress_bar: HTMLElement){
+    let events = API.open_import_events(import_id);
+    events.addEventListener("progress", function(event: MessageEvent){
+        let progress = JSON.parse(event.data);
+        progress_bar.setAttribute("max", progress["length"]);
+        progress_bar.setAttribute("value", progress["processed"]);
+        if(progress["processed"] < progress["length"]){
+            status_text.innerHTML = "Processing file "+(progress["processed"]+1)+" of "+progress["length"]+"...";
+        }
+    });
+    events.addEventListener("log", function(event: MessageEvent){
+        console.log(JSON.parse(event.data)["line"]);
+    });
+    events.addEventListener("status", function(event: MessageEvent){
+        let status = JSON.parse(event.data)["status"];
+        if(status == "Pending"){
+            status_text.innerHTML = "Waiting for files to be processed...";
+        }
+        if(status == "Processing" && progress_bar.getAttribute("value") == "0"){
+            status_text.innerHTML = "Processing files...";
+        }
+        if(status == "Complete"){
+            status_text.innerHTML = "Files processed successfully!";
+            events.close();
+            // Reload page:
+            location.reload();
+        }
+        if(status == "Failed"){
+            status_text.innerHTML = "Failed to process files!";
+            events.close();
+        }
+    });
+    events.onerror = function(){
+        if(events.readyState === EventSource.CLOSED){
+            status_text.innerHTML = "Couldn't get the import status!";
+        }
+    };
+}
+
 window.addEventListener("load", async function(){
     // @ts-ignore
     window.add_import_listeners = () => {document.getElementById("editor_sidebar_import").addEventListener("click", import_btn_handler)}
@@ -0,0 +1,112 @@
+/// Block tool for display formulas written in LaTeX syntax.
+/// The formula is stored as plain LaTeX and rendered server-side to MathML on export.
+export class MathTool{
+    private data: {latex: string};
+    private wrapper: HTMLDivElement;
+
+    static get toolbox() {
+        return {
+            title: 'Formula',
+            icon: '<svg width="17" height="15" viewBox="0 0 17 15"><text x="1" y="13" font-size="14" font-family="serif">&#8721;</text></svg>'
+        };
+    }
+
+    // @ts-ignore
+    constructor({data}){
+        this.data = {
+            latex: data && data.latex ? data.latex : ''
+        };
+        this.wrapper = null;
+    }
+
+    render(): HTMLDivElement{
+        this.wrapper = document.createElement('div');
+        this.wrapper.classList.add('math-block-tool');
+
+        let input = document.createElement('textarea');
+        input.classList.add('cdx-input');
+        input.placeholder = 'LaTeX, e.g. \\sum_{i=1}^{n} x_i';
+        input.spellcheck = false;
+        input.value = this.data.latex;
+        input.addEventListener('input', (event) => {
+            this.data.latex = (<HTMLTextAreaElement>event.target).value;
+        });
+
+        this.wrapper.appendChild(input);
+        return this.wrapper;
+    }
+
+    save(): any{
+        return {
+            latex: this.data.latex
+        };
+    }
+
+    validate(saved: any): boolean{
+        return saved.latex.trim() !== '';
+    }
+}
+
+/// Inline tool to insert formulas into running text.
+/// Creates <math-inline data-latex="...">...</math-inline> marks which get rendered on export.
+export class MathInlineTool{
+    private button: HTMLButtonElement;
+    private state: boolean;
+
+    static get isInline() {
+        return true;
+    }
+
+    constructor() {
+        this.button = null;
+        this.state = false;
+    }
+
+    render(){
+        this.button = document.createElement('button');
+        this.button.type = 'button';
+        this.button.textContent = 'Math';
+        this.button.classList.add("ce-inline-tool");
+
+        return this.button;
+    }
+
+    surround(range: Range){
+        if (this.state) {
+            return;
+        }
+        let latex = range.toString();
+        if(latex.trim() === ''){
+            latex = prompt("LaTeX formula:") || '';
+        }
+        if(latex.trim() === ''){
+            return;
+        }
+
+        let math = document.createElement("math-inline");
+        math.setAttribute("data-latex", latex);
+        math.innerText = latex;
+        range.deleteContents();
+        range.insertNode(math);
+    }
+
+    checkState(selection: any) {
+        const text = selection.anchorNode;
+
+        if (!text) {
+            return;
+        }
+
+        const anchorElement = text instanceof Element ? text : text.parentElement;
+
+        this.state = !!anchorElement.closest('math-inline');
+    }
+
+    static get sanitize() {
+        return {
+            "math-inline": function(el : any){
+                return {"data-latex": true};
+            }
+        };
+    }
+}
@@ -1,12 +1,68 @@
 import * as pdfjs from 'pdfjs-dist';
 
-import {send_get_rendering_status, send_render_project} from "./api_requests";
+import {open_rendering_events, send_cancel_rendering, send_render_project} from "./api_requests";
 
-let status_timer : NodeJS.Timeout|null = null;
+let status_events : EventSource|null = null;
+let current_rendering : string|null = null;
 pdfjs.GlobalWorkerOptions.workerSrc =
     '/js/pdf.worker.mjs';
+/// Renders the whole project, its page count is written into the project metadata
 export async function render_project_listener(){
-    if(status_timer !== null){
+    await start_rendering({update_page_count: true});
+}
+
+/// Renders the project including all open review comments
+export async function render_review_listener(){
+    await start_rendering({review: true});
+}
+
+/// Renders the project with all pending suggestions marked as tracked changes
+export async function render_changes_listener(){
+    await start_rendering({review: true, show_changes: true});
+}
+
+/// Renders a proof for authors and copy editors: draft watermark, proof stamp and line numbers
+export async function render_proof_listener(){
+    await start_rendering({watermark: "DRAFT", proof_stamp: true, line_numbers: true});
+}
+
+/// Cancels the running rendering
+export async function cancel_rendering_listener(){
+    if(current_rendering === null){
+        return;
+    }
+    try{
+        await send_cancel_rendering(current_rendering);
+    }catch(e){
+        console.error(e);
+    }
+}
+
+/// Shows the html preview of the current section (or the whole project) next to the editor
+export function html_preview_listener(){
+    // @ts-ignore
+    let project_id : string = <string>globalThis.project_id;
+    // @ts-ignore
+    let section_path : string|undefined = globalThis.section_path;
+
+    let url = `/api/projects/`+project_id+`/preview/index.html`;
+    if(section_path){
+        url += `?section=`+encodeURIComponent(section_path);
+    }
+
+    show_rendering_col();
+    let viewer = document.getElementById("test");
+    viewer.innerHTML = "";
+    let frame = document.createElement("iframe");
+    frame.style.width = "100%";
+    frame.style.height = "100vh";
+    frame.style.border = "none";
+    frame.src = url;
+    viewer.appendChild(frame);
+}
+
+async function start_rendering(options: any){
+    if(status_events !== null){
         // Old rendering is still running, don't start a new one
         return;
     }
@@ -15,35 +71,59 @@ export async function render_project_listener(){
     let project_id : string = <string>globalThis.project_id;
 
     show_rendering_col();
-    let id : string = (await send_render_project(project_id)).data;
+    let id : string = (await send_render_project(project_id, options)).data;
     console.log("Rendering id is: ", id);
 
-    status_timer = setTimeout(check_rendering_status, 250, id);
+    current_rendering = id;
+    document.getElementById("editor_cancel_rendering_btn").classList.remove("hide");
+    follow_rendering(id);
 
 }
 
-async function check_rendering_status(render_id: string){
-    let status = await send_get_rendering_status(render_id);
-    console.log(status);
-    if(status.data === "Queued"){
-        console.log("Rendering is still queued");
-    }else if(status.data === "Preparing"){
-        console.log("Rendering is being prepared");
-    }else if(status.data === "Running"){
-        console.log("Rendering is running");
-    }else if(status.data === "Finished"){
-        console.log("Rendering finished");
-    }else if(status.data.hasOwnProperty("Failed")){
-        console.log("Rendering failed");
-        console.log(status.data);
-    }
-
-    if(status.data !== "Finished" && !status.data.hasOwnProperty("Failed")) { //TODO: fix failed status
-        status_timer = setTimeout(check_rendering_status, 200, render_id);
-    }else{
-        status_timer = null;
-        await show_pdf(render_id); //TODO only show pdf if it was successful
-    }
+function follow_rendering(render_id: string){
+    status_events = open_rendering_events(render_id);
+    status_events.addEventListener("log", function(event: MessageEvent){
+        console.log(JSON.parse(event.data).line);
+    });
+    status_events.addEventListener("status", async function(event: MessageEvent){
+        let data = JSON.parse(event.data);
+        let status = data.status;
+        if(status === "Queued"){
+            console.log("Rendering is still queued");
+        }else if(status === "Preparing"){
+            console.log("Rendering is being prepared");
+        }else if(status === "Running"){
+            console.log("Rendering is running");
+        }else if(status === "Finished"){
+            console.log("Rendering finished");
+        }else if(status === "Cancelled"){
+            console.log("Rendering was cancelled");
+        }else if(status === "TimedOut"){
+            console.log("Rendering timed out");
+        }else if(status.hasOwnProperty("Failed")){
+            console.log("Rendering failed");
+            console.log(status);
+        }
+
+        if(data.finished){
+            status_events.close();
+            status_events = null;
+            current_rendering = null;
+            document.getElementById("editor_cancel_rendering_btn").classList.add("hide");
+            if(status === "Finished"){
+                await show_pdf(render_id);
+            }
+        }
+    });
+    status_events.onerror = function(){
+        // The stream ends after the final status, anything else means the rendering is unknown
+        if(status_events !== null && status_events.readyState === EventSource.CLOSED){
+            console.error("Couldn't follow rendering "+render_id);
+            status_events = null;
+            current_rendering = null;
+            document.getElementById("editor_cancel_rendering_btn").classList.add("hide");
+        }
+    };
 }
 
 async function show_pdf(rendering_id: string){
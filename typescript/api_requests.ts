@@ -1,11 +1,37 @@
+/// Last known ETag of the content blocks of each section, sent as If-Match when saving
+const content_blocks_etags: Map<string, string> = new Map();
+
+/// Thrown if a resource was changed by someone else in the meantime (412 Precondition Failed)
+/// Holds the current state of the resource
+export class ConflictError extends Error{
+    current: any;
+
+    constructor(message: string, current: any){
+        super(message);
+        this.current = current;
+    }
+}
+
 export async function send_update_content_blocks(project_id: string, section_path: string, data: any){
+    // Without the version of the loaded content changes of other users could be overwritten
+    if(!content_blocks_etags.has(section_path)){
+        throw new Error("Failed to update content blocks: the loaded version is unknown, reload the section");
+    }
     const response = await fetch(`/api/projects/`+project_id+`/sections/`+section_path+"/content_blocks/", {
         method: 'PUT',
         headers: {
-            'Content-Type': 'application/json'
+            'Content-Type': 'application/json',
+            'If-Match': content_blocks_etags.get(section_path)
         },
         body: JSON.stringify(data)
     });
+    if(response.headers.has("ETag")){
+        content_blocks_etags.set(section_path, response.headers.get("ETag"));
+    }
+    if(response.status === 412){
+        let response_data = await response.json();
+        throw new ConflictError("Content blocks were changed by someone else", response_data.data);
+    }
     if(!response.ok){
         throw new Error(`Failed to update content block: ${response.status}`);
     }else{
@@ -33,18 +59,22 @@ export async function send_get_content_blocks(project_id: string, section_path:
         if(response_data.hasOwnProperty("error")) {
             throw new Error(`Failed to get content blocks: ${response_data["error"]}`);
         }else{
+            if(response.headers.has("ETag")){
+                content_blocks_etags.set(section_path, response.headers.get("ETag"));
+            }
             return response_data;
         }
     }
 }
 
 
-export async function send_render_project(project_id: string){
+export async function send_render_project(project_id: string, options: any = {}){
     const response = await fetch(`/api/projects/`+project_id+`/render`, {
         method: 'POST',
         headers: {
             'Content-Type': 'application/json'
-        }
+        },
+        body: JSON.stringify(options)
     });
     if(!response.ok){
         throw new Error(`Failed to render project: ${response.status}`);
@@ -58,6 +88,146 @@ export async function send_render_project(project_id: string){
     }
 }
 
+export async function send_cancel_rendering(render_id: string){
+    const response = await fetch(`/api/renderings/`+render_id+`/cancel`, {
+        method: 'POST',
+        headers: {
+            'Content-Type': 'application/json'
+        }
+    });
+    if(!response.ok){
+        throw new Error(`Failed to cancel rendering: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to cancel rendering: ${response_data["error"]}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+export async function send_list_renderings(project_id: string){
+    const response = await fetch(`/api/projects/`+project_id+`/renderings`, {
+        method: 'GET',
+        headers: {
+            'Content-Type': 'application/json'
+        }
+    });
+    if(!response.ok){
+        throw new Error(`Failed to list renderings: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to list renderings: ${response_data["error"]}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+export async function send_get_rendering_queue(){
+    const response = await fetch(`/api/renderings/queue`, {
+        method: 'GET',
+        headers: {
+            'Content-Type': 'application/json'
+        }
+    });
+    if(!response.ok){
+        throw new Error(`Failed to get rendering queue: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to get rendering queue: ${response_data["error"]}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+export async function send_move_queued_rendering(render_id: string, position: number){
+    const response = await fetch(`/api/renderings/queue/`+render_id+`/move`, {
+        method: 'POST',
+        headers: {
+            'Content-Type': 'application/json'
+        },
+        body: JSON.stringify({position: position})
+    });
+    if(!response.ok){
+        throw new Error(`Failed to move rendering: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to move rendering: ${response_data["error"]}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+export async function send_get_rendering_log(render_id: string){
+    const response = await fetch(`/api/renderings/`+render_id+`/log`, {
+        method: 'GET',
+        headers: {
+            'Content-Type': 'application/json'
+        }
+    });
+    if(!response.ok){
+        throw new Error(`Failed to get rendering log: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to get rendering log: ${response_data["error"]}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+export async function send_get_rendering_pages(render_id: string){
+    const response = await fetch(`/api/renderings/`+render_id+`/pages`, {
+        method: 'GET',
+        headers: {
+            'Content-Type': 'application/json'
+        }
+    });
+    if(!response.ok){
+        throw new Error(`Failed to get rendering pages: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to get rendering pages: ${response_data["error"]}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+/// Page count and section start pages of the latest rendering of the whole project
+export async function send_get_project_pages(project_id: string){
+    const response = await fetch(`/api/projects/`+project_id+`/pages`, {
+        method: 'GET',
+        headers: {
+            'Content-Type': 'application/json'
+        }
+    });
+    if(!response.ok){
+        throw new Error(`Failed to get project pages: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to get project pages: ${response_data["error"]}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+/// Opens the server-sent event stream of a rendering, with "status" and "log" events
+export function open_rendering_events(render_id: string): EventSource{
+    return new EventSource(`/api/renderings/`+render_id+`/events`);
+}
+
 export async function send_get_rendering_status(render_id: string){
     const response = await fetch(`/api/renderings/`+render_id+`/status`, {
         method: 'GET',
@@ -235,6 +405,11 @@ export async function update_bib_entry(data: any, key: string, project_id: strin
     }
 }
 
+/// Opens the server-sent event stream of an import, with "status", "progress" and "log" events
+export function open_import_events(id: string): EventSource{
+    return new EventSource(`/api/import/events/`+id);
+}
+
 export async function send_poll_import_status(id: string){
     const response = await fetch(`/api/import/status/`+id, {
         method: 'GET',
@@ -272,4 +447,183 @@ export async function send_import_from_wordpress(data: any){
             return response_data;
         }
     }
-}
\ No newline at end of file
+}
+
+export async function send_get_section_workflow(project_id: string, section_path: string){
+    const response = await fetch(`/api/projects/`+project_id+`/sections/`+section_path+`/workflow`, {
+        method: 'GET',
+        headers: {
+            'Content-Type': 'application/json'
+        }
+    });
+    if(!response.ok){
+        throw new Error(`Failed to get workflow state: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to get workflow state: ${response_data["error"]}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+export async function send_transition_section(project_id: string, section_path: string, to: string, comment: string|null = null){
+    const response = await fetch(`/api/projects/`+project_id+`/sections/`+section_path+`/workflow`, {
+        method: 'POST',
+        headers: {
+            'Content-Type': 'application/json'
+        },
+        body: JSON.stringify({to: to, comment: comment})
+    });
+    if(!response.ok){
+        throw new Error(`Failed to change workflow state: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to change workflow state: ${JSON.stringify(response_data["error"])}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+export async function send_list_section_states(project_id: string, state: string|null = null){
+    let url = `/api/projects/`+project_id+`/workflow/sections`;
+    if(state !== null){
+        url += `?state=`+encodeURIComponent(state);
+    }
+    const response = await fetch(url, {
+        method: 'GET',
+        headers: {
+            'Content-Type': 'application/json'
+        }
+    });
+    if(!response.ok){
+        throw new Error(`Failed to list section states: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to list section states: ${response_data["error"]}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+export async function send_list_tasks(include_completed: boolean = false){
+    const response = await fetch(`/api/tasks?include_completed=`+include_completed, {
+        method: 'GET',
+        headers: {
+            'Content-Type': 'application/json'
+        }
+    });
+    if(!response.ok){
+        throw new Error(`Failed to list tasks: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to list tasks: ${response_data["error"]}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+export async function send_set_section_assignment(project_id: string, section_path: string, assignment: any){
+    const response = await fetch(`/api/projects/`+project_id+`/sections/`+section_path+`/assignment`, {
+        method: 'PUT',
+        headers: {
+            'Content-Type': 'application/json'
+        },
+        body: JSON.stringify(assignment)
+    });
+    if(!response.ok){
+        throw new Error(`Failed to set assignment: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to set assignment: ${JSON.stringify(response_data["error"])}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+export async function send_list_contributor_sections(){
+    const response = await fetch(`/api/contributor/sections`, {
+        method: 'GET',
+        headers: {
+            'Content-Type': 'application/json'
+        }
+    });
+    if(!response.ok){
+        throw new Error(`Failed to list chapters: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to list chapters: ${JSON.stringify(response_data["error"])}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+export async function send_render_section_preview(project_id: string, section_path: string){
+    const response = await fetch(`/api/projects/`+project_id+`/sections/`+section_path+`/preview`, {
+        method: 'POST',
+        headers: {
+            'Content-Type': 'application/json'
+        }
+    });
+    if(!response.ok){
+        throw new Error(`Failed to render preview: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to render preview: ${JSON.stringify(response_data["error"])}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+export async function send_approve_section_proof(project_id: string, section_path: string, comment: string|null = null){
+    const response = await fetch(`/api/projects/`+project_id+`/sections/`+section_path+`/approve`, {
+        method: 'POST',
+        headers: {
+            'Content-Type': 'application/json'
+        },
+        body: JSON.stringify({comment: comment})
+    });
+    if(!response.ok){
+        throw new Error(`Failed to approve proof: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to approve proof: ${JSON.stringify(response_data["error"])}`);
+        }else{
+            return response_data;
+        }
+    }
+}
+
+export async function send_set_user_person(user_id: string, person_id: string|null){
+    const response = await fetch(`/api/users/`+user_id+`/person`, {
+        method: 'PUT',
+        headers: {
+            'Content-Type': 'application/json'
+        },
+        body: JSON.stringify({person_id: person_id})
+    });
+    if(!response.ok){
+        throw new Error(`Failed to link user: ${response.status}`);
+    }else{
+        let response_data = await response.json();
+        if(response_data.hasOwnProperty("error")) {
+            throw new Error(`Failed to link user: ${JSON.stringify(response_data["error"])}`);
+        }else{
+            return response_data;
+        }
+    }
+}
@@ -14,21 +14,32 @@ import * as Export from "./Export";
 import {CustomStyleTool} from "./CustomStyleTool";
 import {CitationTool} from "./CitationTool";
 import {BlockStyleTune} from "./BlockStyleTune";
+import {MathInlineTool, MathTool} from "./MathTool";
+import {CodeTool} from "./CodeTool";
+import {ContainerTool} from "./ContainerTool";
+import {Collaboration} from "./Collaboration";
 
 let typing_timer: number | null = null;
 let editor: EditorJS | null = null;
+let collaboration: Collaboration | null = null;
 
 export async function show_editor(){
     let first_change = true;
-    document.getElementById("editor_render_project_btn").addEventListener("click", RenderPDF.render_project_listener);
-    document.getElementById("editor_export_project_btn").addEventListener("click", Export.export_project_listener);
+    // Rendering and export buttons aren't shown to contributors
+    document.getElementById("editor_render_project_btn")?.addEventListener("click", RenderPDF.render_project_listener);
+    document.getElementById("editor_render_review_btn")?.addEventListener("click", RenderPDF.render_review_listener);
+    document.getElementById("editor_render_changes_btn")?.addEventListener("click", RenderPDF.render_changes_listener);
+    document.getElementById("editor_render_proof_btn")?.addEventListener("click", RenderPDF.render_proof_listener);
+    document.getElementById("editor_html_preview_btn")?.addEventListener("click", RenderPDF.html_preview_listener);
+    document.getElementById("editor_cancel_rendering_btn")?.addEventListener("click", RenderPDF.cancel_rendering_listener);
+    document.getElementById("editor_export_project_btn")?.addEventListener("click", Export.export_project_listener);
     try {
         // @ts-ignore
         let data = (await API.send_get_content_blocks(globalThis.project_id, globalThis.section_path)).data;
         console.log(data);
 
         // @ts-ignore
-        let by_file_upload_endpoint = '/api/projects/'+globalThis.project_id+'/uploads';
+        let by_file_upload_endpoint = '/api/projects/'+globalThis.project_id+'/uploads?section='+encodeURIComponent(globalThis.section_path);
 
         editor = new EditorJS({
             holder: "section_content_blocks_inner",
@@ -53,6 +64,24 @@ export async function show_editor(){
                 },
                 custom_style_tool: CustomStyleTool,
                 citation: CitationTool,
+                math: MathTool,
+                math_inline: MathInlineTool,
+                code: CodeTool,
+                box: {
+                    class: ContainerTool,
+                    config: {kind: 'box'},
+                    toolbox: {title: 'Box'},
+                },
+                epigraph: {
+                    class: ContainerTool,
+                    config: {kind: 'epigraph'},
+                    toolbox: {title: 'Epigraph'},
+                },
+                abstract: {
+                    class: ContainerTool,
+                    config: {kind: 'abstract'},
+                    toolbox: {title: 'Abstract'},
+                },
                 image: {
                     class: ImageTool,
                     config: {
@@ -68,7 +97,12 @@ export async function show_editor(){
             data: {blocks: data},
             onChange: (api, event) => {
                 if(!first_change){ // Don't save the first change, as it's just the initial load
-                    save_changes();
+                    if(collaboration !== null && collaboration.is_connected()){
+                        // Send block-level operations, so concurrent edits of other users aren't overwritten
+                        collaboration.handle_editor_change(event);
+                    }else{
+                        save_changes();
+                    }
                 }else{
                     first_change = false;
                 }
@@ -81,6 +115,12 @@ export async function show_editor(){
 
         await editor.isReady;
 
+        if(collaboration !== null){
+            collaboration.close();
+        }
+        // @ts-ignore
+        collaboration = new Collaboration(globalThis.project_id, globalThis.section_path, editor);
+
         document.getElementById("section_content_blocks_inner").addEventListener("input", typing_handler);
 
         // Make all existing notes clickable
@@ -101,11 +141,20 @@ export async function save_changes(){
         //Tools.show_alert("Saved Changes.", "success");
     }catch(e){
         console.error(e);
-        Tools.show_alert("Couldn't save content.", "danger");
+        if(e instanceof API.ConflictError){
+            Tools.show_alert("This section was changed by someone else, loaded the current version.", "warning");
+            await editor.render({blocks: e.current});
+        }else{
+            Tools.show_alert("Couldn't save content.", "danger");
+        }
     }
 }
 
 function typing_handler(){
+    if(collaboration !== null && collaboration.is_connected()){
+        // Changes are already sent block by block
+        return;
+    }
     if (typing_timer) {
         clearTimeout(typing_timer);
     }
@@ -7,6 +7,7 @@ module.exports = {
         import: './Import.ts',
         bibliography_editor: './BibliographyEditor.ts',
         user_tools: './UserTools.ts',
+        contributor: './Contributor.ts',
     },
     devtool: 'inline-source-map',
     module: {
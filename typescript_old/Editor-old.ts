@@ -13,6 +13,15 @@ namespace Editor{
     export async function init() {
         let project_id = extract_project_id_from_url();
         globalThis.project_id = project_id;
+
+        // Contributors only edit the section they opened from the contributor portal
+        let contributor_section_path = document.body.getAttribute("data-section-path");
+        if(contributor_section_path){
+            globalThis.section_path = contributor_section_path;
+            globalThis.section_id = contributor_section_path.split(":").pop();
+            await SectionView.show_section_view();
+            return;
+        }
         ProjectOverview.show_overview();
     }
 
@@ -1,6 +1,9 @@
 /// <reference path="Editor-old.ts" />
 namespace Editor{
     export namespace ProjectOverview{
+        /// ETag of the loaded project metadata, sent as If-Match on updates
+        let project_metadata_etag: string | null = null;
+
         export function show_overview() {
             console.log("Loading overview for project "+globalThis.project_id);
 
@@ -903,16 +906,30 @@ namespace Editor{
 
             console.log("new data: "+JSON.stringify(data));
 
+            // Without the version of the loaded metadata changes of other users could be overwritten
+            if(project_metadata_etag === null){
+                Tools.show_alert("The metadata wasn't loaded completely. Please reload the page.", "warning");
+                return;
+            }
+
             try {
                 Tools.start_loading_spinner();
                 const response = await fetch(`/api/projects/${globalThis.project_id}/metadata`, {
                     method: 'PATCH',
                     body: JSON.stringify(data),
                     headers: {
-                        'Content-Type': 'application/json'
+                        'Content-Type': 'application/json',
+                        'If-Match': project_metadata_etag
                     }
                 });
                 Tools.stop_loading_spinner();
+                if (response.status === 412) {
+                    Tools.show_alert("The metadata was changed by someone else. Please reload the page to see the current version.", "warning");
+                    return;
+                }
+                if (response.headers.has("ETag")) {
+                    project_metadata_etag = response.headers.get("ETag");
+                }
                 if (!response.ok) {
                     throw new Error(`Failed to update project metadata ${globalThis.project_id}`);
                 } else {
@@ -936,6 +953,7 @@ namespace Editor{
             if(!response.ok){
                 throw new Error(`Failed to load project metadata ${project_id}`);
             }else{
+                project_metadata_etag = response.headers.get("ETag");
                 return response.json();
             }
         }
@@ -7,6 +7,8 @@ namespace Editor{
         declare var section_data: object | null;
         declare var typing_timer: number | null;
         declare var pending_content_block_changes: Array<HTMLElement>;
+        /// ETags of the loaded sections, sent as If-Match on updates
+        let section_etags: {[section_path: string]: string} = {};
 
         // @ts-ignore
         export async function show_section_view(){
@@ -909,19 +911,33 @@ namespace Editor{
                 if(response_data.hasOwnProperty("error")) {
                     throw new Error(`Failed to get section data: ${response_data["error"]}`);
                 }else{
+                    section_etags[section_path] = response.headers.get("ETag");
                     return response_data.data;
                 }
             }
         }
 
         async function send_patch_section(section_path: string, section_data: object){
+            // Without the version of the loaded section changes of other users could be overwritten
+            if(!section_etags[section_path]){
+                Tools.show_alert("The section wasn't loaded completely. Please reload the page.", "warning");
+                throw new Error(`Failed to patch section data: section version unknown`);
+            }
             const response = await fetch(`/api/projects/${globalThis.project_id}/sections/`+section_path, {
                 method: 'PATCH',
                 headers: {
-                    'Content-Type': 'application/json'
+                    'Content-Type': 'application/json',
+                    'If-Match': section_etags[section_path]
                 },
                 body: JSON.stringify(section_data)
             });
+            if(response.status === 412){
+                Tools.show_alert("The section was changed by someone else. Please reload the page to see the current version.", "warning");
+                throw new Error(`Failed to patch section data: section was changed concurrently`);
+            }
+            if(response.headers.has("ETag")){
+                section_etags[section_path] = response.headers.get("ETag");
+            }
             if(!response.ok){
                 throw new Error(`Failed to patch section data: ${response.status}`);
             }else{